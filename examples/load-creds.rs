@@ -12,13 +12,13 @@ fn main() {
 
     let start = Instant::now();
 
-    let creds = badtouch::utils::load_creds(&path)
+    let creds = badtouch::utils::CredsList::count(&path)
                                     .expect("failed to load creds");
 
     let elapsed = start.elapsed();
-    let average = elapsed / creds.len() as u32;
+    let average = elapsed / creds as u32;
     println!("loaded {} records in {}, on average {}",
-            creds.len(),
+            creds,
             humantime::format_duration(elapsed),
             humantime::format_duration(average),
     );
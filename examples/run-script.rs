@@ -0,0 +1,33 @@
+extern crate badtouch;
+extern crate env_logger;
+
+use badtouch::{Config, Runtime};
+use std::env;
+
+// run-script <script> <user> <passwords>
+fn main() {
+    env_logger::init();
+
+    let mut args = env::args().skip(1);
+    let path = args.next().expect("missing script");
+    let user = args.next().expect("missing user");
+    let passwords = args.next().expect("missing password list");
+
+    let mut runtime = Runtime::new(Config::default(), 16);
+    runtime.load(&path)
+        .expect("failed to load script");
+    let passwords = badtouch::utils::load_list(&passwords)
+                                .expect("failed to load passwords");
+
+    for password in &passwords {
+        runtime.submit(&user, password);
+    }
+
+    runtime.run(|attempt, result| {
+        match *result {
+            Ok(true) => println!("valid: {:?}:{:?}", attempt.user(), attempt.password()),
+            Ok(false) => (),
+            Err(ref err) => println!("error: {:?}:{:?}: {}", attempt.user(), attempt.password(), err),
+        }
+    });
+}
@@ -1,12 +1,14 @@
 descr = "local mysql"
 
 function verify(user, password)
-    mysql_connect("127.0.0.1", 3306, user, password)
+    mysql_connect("127.0.0.1", 3306, user, password, {connect_timeout=3000})
 
     if last_err() then
-        clear_err()
-        return false
-    else
-        return true
+        if last_err():find('authentication failed') then
+            clear_err()
+            return false
+        end
+        return
     end
+    return true
 end
@@ -1,5 +1,11 @@
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
+use proxy::Rotation;
+use report::Format;
+use sandbox::Capability;
+use humantime;
+use log::LevelFilter;
+use std::time::Duration;
 
 #[derive(StructOpt, Debug)]
 #[structopt(author = "",
@@ -9,12 +15,130 @@ pub struct Args {
                 raw(global = "true"), parse(from_occurrences),
                 help="Verbose output")]
     pub verbose: u8,
-    #[structopt(short = "n", long = "workers", default_value = "16",
-                help="Concurrent workers")]
-    pub workers: usize,
+    #[structopt(short = "q", long = "quiet",
+                help="Only print valid credentials")]
+    pub quiet: bool,
+    #[structopt(long = "config",
+                help="Read the config from this file instead of ~/.config/badtouch/config.toml")]
+    pub config: Option<String>,
+    #[structopt(short = "n", long = "workers",
+                help="Concurrent workers (default: 16)")]
+    pub workers: Option<usize>,
+    #[structopt(long = "connections-per-target",
+                help="Limit the concurrent attempts against a single target")]
+    pub connections_per_target: Option<usize>,
     #[structopt(short = "o", long = "output",
                 help="Write results to file")]
     pub output: Option<String>,
+    #[structopt(long = "format",
+                help="Format of the output file [text, jsonl, csv] (default: text)")]
+    pub format: Option<Format>,
+    #[structopt(long = "log-attempts",
+                help="Also write invalid attempts and errors to the output file (jsonl and csv)")]
+    pub log_attempts: bool,
+    #[structopt(long = "proxy",
+                help="Route traffic through a proxy (http, https, socks5 or socks5h)")]
+    pub proxy: Option<String>,
+    #[structopt(long = "proxy-list",
+                help="Rotate through a list of proxies")]
+    pub proxy_list: Option<String>,
+    #[structopt(long = "proxy-rotation",
+                help="Pick a new proxy per attempt or per worker [attempt, worker]")]
+    pub proxy_rotation: Option<Rotation>,
+    #[structopt(long = "dns-server", number_of_values = 1,
+                help="Resolve names with this dns server (ip or ip:port), can be used multiple times")]
+    pub dns_servers: Vec<String>,
+    #[structopt(long = "fresh-connections",
+                help="Don't reuse http connections between attempts")]
+    pub fresh_connections: bool,
+    #[structopt(long = "user-agents",
+                help="Pick a random user agent from this list for every attempt")]
+    pub user_agents: Option<String>,
+    #[structopt(long = "retries",
+                help="Retry failed attempts this many times, network errors are retried with a backoff (default: 5)")]
+    pub retries: Option<u8>,
+    #[structopt(long = "script-timeout",
+                help="Give up on a verify() call after this many seconds and record a timeout error")]
+    pub script_timeout: Option<u64>,
+    #[structopt(long = "sandbox",
+                help="Remove exec, execve, file and raw socket functions from scripts")]
+    pub sandbox: bool,
+    #[structopt(long = "deny", raw(use_delimiter = "true", number_of_values = "1"),
+                help="Remove these groups of functions from scripts [exec, fs, raw-sockets]")]
+    pub deny: Vec<Capability>,
+    #[structopt(long = "trust",
+                help="Don't ask before running scripts that declare exec or fs")]
+    pub trust: bool,
+    #[structopt(long = "memory-limit",
+                help="Fail scripts that allocate more than this many megabytes")]
+    pub memory_limit: Option<usize>,
+    #[structopt(long = "instruction-limit",
+                help="Fail scripts that execute more than this many lua instructions")]
+    pub instruction_limit: Option<u64>,
+    #[structopt(long = "quarantine", default_value = "30m",
+                parse(try_from_str = "humantime::parse_duration"),
+                help="Skip locked accounts and throttling targets for this time")]
+    pub quarantine: Duration,
+    #[structopt(long = "skip", default_value = "0",
+                help="Skip the first n attempts")]
+    pub skip: usize,
+    #[structopt(long = "limit",
+                help="Only run n attempts")]
+    pub limit: Option<usize>,
+    #[structopt(long = "shard",
+                parse(try_from_str = "parse_shard"),
+                help="Only run every n-th attempt, starting at the i-th (i/n)")]
+    pub shard: Option<(usize, usize)>,
+    #[structopt(long = "shuffle",
+                help="Try the credentials and targets in random order")]
+    pub shuffle: bool,
+    #[structopt(long = "stop-on-success-per-user",
+                help="Stop trying a user on a script after the first valid password")]
+    pub stop_on_success_per_user: bool,
+    #[structopt(long = "max-successes",
+                help="Stop after this many valid credentials")]
+    pub max_successes: Option<usize>,
+    #[structopt(long = "max-success-rate",
+                help="Abort if more than this percentage of attempts is valid")]
+    pub max_success_rate: Option<f64>,
+    #[structopt(long = "shutdown-timeout", default_value = "30s",
+                parse(try_from_str = "humantime::parse_duration"),
+                help="Wait this long for running attempts on ctrl-c")]
+    pub shutdown_timeout: Duration,
+    #[structopt(long = "notify-webhook",
+                help="Post every valid credential as json to this url")]
+    pub notify_webhook: Option<String>,
+    #[structopt(long = "notify-on-finish",
+                help="Also send a notification when the run is done")]
+    pub notify_on_finish: bool,
+    #[structopt(long = "metrics-addr",
+                help="Serve prometheus metrics on this address, eg. 127.0.0.1:9000")]
+    pub metrics_addr: Option<String>,
+    #[structopt(long = "status-json",
+                help="Write the progress as json every second to stderr or a unix socket [stderr, PATH]")]
+    pub status_json: Option<String>,
+    #[structopt(long = "log-file",
+                help="Write a structured log of the runtime and every attempt to this file")]
+    pub log_file: Option<String>,
+    #[structopt(long = "log-level", default_value = "info",
+                help="Level of the log file [error, warn, info, debug, trace]")]
+    pub log_level: LevelFilter,
+    #[structopt(long = "sqlite",
+                help="Log all attempts and findings to a sqlite database, skip attempts it already has")]
+    pub sqlite: Option<String>,
+    #[structopt(long = "resume",
+                help="Save progress to a session file, resume from it if it exists")]
+    pub resume: Option<String>,
+    #[structopt(long = "allow-fs",
+                help="Allow scripts to read and write files")]
+    pub allow_fs: bool,
+    #[structopt(long = "kv-file",
+                help="Persist the kv_set store to a file")]
+    pub kv_file: Option<String>,
+    #[structopt(short = "X", long = "option", number_of_values = 1,
+                parse(try_from_str = "parse_option"),
+                help="Pass an option to the scripts (key=value), can be used multiple times")]
+    pub options: Vec<(String, String)>,
     #[structopt(subcommand)]
     pub subcommand: SubCommand,
 }
@@ -29,10 +153,34 @@ pub enum SubCommand {
                 name="creds",
                 about="Credential confirmation attack")]
     Creds(Creds),
+    #[structopt(author = "",
+                name="combo",
+                about="Credential stuffing with a combo list")]
+    Combo(Combo),
+    #[structopt(author = "",
+                name="mask",
+                about="Brute force passwords generated from a mask")]
+    Mask(Mask),
+    #[structopt(author = "",
+                name="defaults",
+                about="Try the vendor default credentials of a product")]
+    Defaults(Defaults),
     #[structopt(author = "",
                 name="enum",
                 about="Enumerate users")]
     Enum(Enum),
+    #[structopt(author = "",
+                name="serve",
+                about="Hand out attempts to workers in distributed mode")]
+    Serve(Serve),
+    #[structopt(author = "",
+                name="worker",
+                about="Run attempts for a coordinator in distributed mode")]
+    Worker(Worker),
+    #[structopt(author = "",
+                name="daemon",
+                about="Run jobs submitted over an http api")]
+    Daemon(Daemon),
     #[structopt(author = "",
                 name="oneshot",
                 about="Test a single username-password combination")]
@@ -41,10 +189,140 @@ pub enum SubCommand {
                 name="fsck",
                 about="Verify and fix encoding of a list")]
     Fsck(Fsck),
+    #[structopt(author = "",
+                name="list-scripts",
+                about="Show the name, protocol, options and caps of scripts")]
+    ListScripts(ListScripts),
+    #[structopt(author = "",
+                name="check",
+                about="Check scripts for problems without running them")]
+    Check(Check),
+    #[structopt(author = "",
+                name="test",
+                about="Run the test cases of scripts against a mock http server")]
+    Test(Test),
+    #[structopt(author = "",
+                name="repl",
+                about="Interactive prompt with the runtime functions loaded")]
+    Repl(Repl),
+    #[structopt(author = "",
+                name="scripts",
+                about="Install and update repositories of scripts")]
+    Scripts(Scripts),
+}
+
+// the subcommands that can run in distributed mode
+#[derive(StructOpt, Debug)]
+pub enum Attack {
+    #[structopt(author = "",
+                name="dict",
+                about="Dictionary attack")]
+    Dict(Dict),
+    #[structopt(author = "",
+                name="creds",
+                about="Credential confirmation attack")]
+    Creds(Creds),
+    #[structopt(author = "",
+                name="combo",
+                about="Credential stuffing with a combo list")]
+    Combo(Combo),
+    #[structopt(author = "",
+                name="mask",
+                about="Brute force passwords generated from a mask")]
+    Mask(Mask),
+    #[structopt(author = "",
+                name="defaults",
+                about="Try the vendor default credentials of a product")]
+    Defaults(Defaults),
+    #[structopt(author = "",
+                name="enum",
+                about="Enumerate users")]
+    Enum(Enum),
+}
+
+impl From<Attack> for SubCommand {
+    fn from(attack: Attack) -> SubCommand {
+        match attack {
+            Attack::Dict(x) => SubCommand::Dict(x),
+            Attack::Creds(x) => SubCommand::Creds(x),
+            Attack::Combo(x) => SubCommand::Combo(x),
+            Attack::Mask(x) => SubCommand::Mask(x),
+            Attack::Defaults(x) => SubCommand::Defaults(x),
+            Attack::Enum(x) => SubCommand::Enum(x),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Serve {
+    #[structopt(short = "l", long = "listen", default_value = "127.0.0.1:7878",
+                help="Address the workers connect to")]
+    pub listen: String,
+    #[structopt(long = "secret",
+                help="Shared secret the workers have to send")]
+    pub secret: String,
+    #[structopt(long = "chunk", default_value = "1000",
+                help="Number of attempts that are handed out at once")]
+    pub chunk: usize,
+    #[structopt(long = "worker-threads",
+                help="Set the concurrent workers of every worker")]
+    pub worker_threads: Option<usize>,
+    #[structopt(long = "agent-rate",
+                help="Limit the attempts per second of every worker")]
+    pub agent_rate: Option<f64>,
+    #[structopt(subcommand)]
+    pub attack: Attack,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Worker {
+    #[structopt(short = "c", long = "connect",
+                help="Address of the coordinator")]
+    pub connect: String,
+    #[structopt(long = "secret",
+                help="Shared secret of the coordinator")]
+    pub secret: String,
+    #[structopt(subcommand)]
+    pub attack: Attack,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Daemon {
+    #[structopt(short = "l", long = "listen", default_value = "127.0.0.1:8765",
+                help="Address of the http api")]
+    pub listen: String,
+    #[structopt(long = "dir", default_value = "badtouch-jobs",
+                help="Directory for the output of the jobs")]
+    pub dir: String,
+    #[structopt(long = "token",
+                help="Bearer token that every request has to send")]
+    pub token: String,
 }
 
 #[derive(StructOpt, Debug)]
 pub struct Dict {
+    #[structopt(short = "U", long = "users", number_of_values = 1,
+                help="Additional username list, can be used multiple times")]
+    pub more_users: Vec<String>,
+    #[structopt(long = "names",
+                help="Generate usernames from a list of full names")]
+    pub names: bool,
+    #[structopt(long = "domain", raw(requires = r#""names""#),
+                help="Also generate usernames with this domain suffix")]
+    pub domain: Option<String>,
+    #[structopt(short = "P", long = "passwords", number_of_values = 1,
+                help="Additional password list, can be used multiple times")]
+    pub more_passwords: Vec<String>,
+    #[structopt(short = "r", long = "rules",
+                help="Mangle passwords with hashcat-style rules from a file")]
+    pub rules: Option<String>,
+    #[structopt(long = "spray",
+                help="Try one password against all users before moving on to the next")]
+    pub spray: bool,
+    #[structopt(long = "lockout-window", default_value = "30m",
+                parse(try_from_str = "humantime::parse_duration"),
+                help="Time to wait between passwords with --spray")]
+    pub lockout_window: Duration,
     #[structopt(help="Username list path")]
     pub users: String,
     #[structopt(help="Password list path")]
@@ -63,8 +341,67 @@ pub struct Creds {
     pub scripts: Vec<String>,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct Combo {
+    #[structopt(short = "s", long = "separator", default_value = ":",
+                help="Separator between user and password")]
+    pub separator: String,
+    #[structopt(short = "e", long = "escape",
+                help="Allow escaping the separator with a backslash")]
+    pub escape: bool,
+    #[structopt(help="Combo list path")]
+    pub combos: String,
+    #[structopt(raw(required="true"),
+                help="Scripts to run")]
+    pub scripts: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Mask {
+    #[structopt(short = "1", long = "custom-charset1",
+                help="Custom charset ?1")]
+    pub charset1: Option<String>,
+    #[structopt(short = "2", long = "custom-charset2",
+                help="Custom charset ?2")]
+    pub charset2: Option<String>,
+    #[structopt(short = "3", long = "custom-charset3",
+                help="Custom charset ?3")]
+    pub charset3: Option<String>,
+    #[structopt(short = "4", long = "custom-charset4",
+                help="Custom charset ?4")]
+    pub charset4: Option<String>,
+    #[structopt(help="Username list path")]
+    pub users: String,
+    #[structopt(help="Password mask, eg. ?u?l?l?l?d?d")]
+    pub mask: String,
+    #[structopt(raw(required="true"),
+                help="Scripts to run")]
+    pub scripts: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Defaults {
+    #[structopt(long = "db",
+                help="Use a csv file with product,user,password instead of the builtin database")]
+    pub db: Option<String>,
+    #[structopt(help="Product to test, eg. tomcat")]
+    pub product: String,
+    #[structopt(raw(required="true"),
+                help="Scripts to run")]
+    pub scripts: Vec<String>,
+}
+
 #[derive(StructOpt, Debug)]
 pub struct Enum {
+    #[structopt(short = "U", long = "users", number_of_values = 1,
+                help="Additional username list, can be used multiple times")]
+    pub more_users: Vec<String>,
+    #[structopt(long = "names",
+                help="Generate usernames from a list of full names")]
+    pub names: bool,
+    #[structopt(long = "domain", raw(requires = r#""names""#),
+                help="Also generate usernames with this domain suffix")]
+    pub domain: Option<String>,
     #[structopt(help="Username list path")]
     pub users: String,
     #[structopt(raw(required="true"),
@@ -80,6 +417,8 @@ pub struct Oneshot {
     pub user: String,
     #[structopt(help="Password to test")]
     pub password: Option<String>,
+    #[structopt(help="Target passed to the script, available with getopt(\"target\")")]
+    pub target: Option<String>,
     #[structopt(short = "x", long = "exitcode",
                 help="Set the exitcode to 2 if the credentials are invalid")]
     pub exitcode: bool,
@@ -100,6 +439,90 @@ pub struct Fsck {
     pub paths: Vec<String>,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct ListScripts {
+    #[structopt(help="Scripts or directories to show, defaults to the current directory")]
+    pub scripts: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Check {
+    #[structopt(raw(required="true"),
+                help="Scripts to check")]
+    pub scripts: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Test {
+    #[structopt(raw(required="true"),
+                help="Scripts to test")]
+    pub scripts: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Repl {
+    #[structopt(help="Script to load before the prompt")]
+    pub script: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Scripts {
+    #[structopt(subcommand)]
+    pub subcommand: ScriptsCmd,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ScriptsCmd {
+    #[structopt(author = "",
+                name="install",
+                about="Clone a repository of scripts into ~/.config/badtouch/scripts")]
+    Install(Install),
+    #[structopt(author = "",
+                name="update",
+                about="Update the installed repositories")]
+    Update(Update),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Install {
+    #[structopt(help="Git url or owner/repo on github")]
+    pub source: String,
+    #[structopt(long = "name",
+                help="Install the repository under this name")]
+    pub name: Option<String>,
+    #[structopt(long = "pin",
+                help="Check out this commit and never update it")]
+    pub pin: Option<String>,
+    #[structopt(long = "signed",
+                help="Require a valid signature on every commit that is checked out")]
+    pub signed: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Update {
+    #[structopt(help="Repositories to update, all of them if none are given")]
+    pub names: Vec<String>,
+}
+
+fn parse_option(x: &str) -> Result<(String, String), String> {
+    let idx = x.find('=')
+        .ok_or_else(|| format!("expected key=value: {:?}", x))?;
+    let (key, value) = x.split_at(idx);
+    Ok((key.to_string(), value[1..].to_string()))
+}
+
+fn parse_shard(x: &str) -> Result<(usize, usize), String> {
+    let idx = x.find('/')
+        .ok_or_else(|| format!("expected i/n: {:?}", x))?;
+    let (i, n) = x.split_at(idx);
+    let i = i.parse::<usize>().map_err(|err| err.to_string())?;
+    let n = n[1..].parse::<usize>().map_err(|err| err.to_string())?;
+    if i == 0 || i > n {
+        return Err(format!("shard has to be between 1/{} and {}/{}", n, n, n));
+    }
+    Ok((i, n))
+}
+
 pub fn parse() -> Args {
     Args::from_args()
 }
@@ -1,25 +1,136 @@
 use errors::*;
 
 use dirs;
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
+use std::time::Duration;
 use libc::rlim_t;
+use http::ClientPool;
+use limits::Limits;
+use proxy::Rotation;
+use report::Format;
+use sandbox::Capability;
+use kv;
 use toml;
 
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub general: GeneralConfig,
     #[serde(default)]
     pub runtime: RuntimeConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(skip)]
+    pub kv: kv::Store,
+    #[serde(skip)]
+    pub http_clients: ClientPool,
+}
+
+// defaults of command line options, the command line always wins
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeneralConfig {
+    #[serde(default)]
+    pub workers: Option<usize>,
+    #[serde(default)]
+    pub retries: Option<u8>,
+    // in seconds
+    #[serde(default)]
+    pub script_timeout: Option<u64>,
+    #[serde(default)]
+    pub format: Option<Format>,
+    // scripts that aren't found are looked up in this folder
+    #[serde(default)]
+    pub scripts: Option<String>,
+}
+
+impl GeneralConfig {
+    pub fn scripts_dir(&self) -> Option<PathBuf> {
+        let dir = self.scripts.as_ref()?;
+        if dir.starts_with("~/") {
+            dirs::home_dir().map(|home| home.join(&dir[2..]))
+        } else {
+            Some(PathBuf::from(dir))
+        }
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct RuntimeConfig {
     #[serde(default)]
     pub user_agent: Option<String>,
+    // every attempt picks one of these, see --user-agents
+    #[serde(default)]
+    pub user_agents: Vec<String>,
     #[serde(default)]
     pub rlimit_nofile: Option<rlim_t>,
+    #[serde(default)]
+    pub proxies: Vec<String>,
+    #[serde(default)]
+    pub proxy_rotation: Rotation,
+    // resolve names with these servers instead of the system resolver
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    // build new http clients for every attempt instead of sharing them
+    #[serde(default)]
+    pub fresh_connections: bool,
+    // enables file_read and file_write
+    #[serde(default)]
+    pub allow_fs: bool,
+    // backing file for kv_get and kv_set
+    #[serde(default)]
+    pub kv_file: Option<String>,
+    // script options, read with getopt
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+    // in megabytes, per script
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+    #[serde(default)]
+    pub instruction_limit: Option<u64>,
+    // set from --script-timeout or general.script_timeout
+    #[serde(skip)]
+    pub script_timeout: Option<Duration>,
+    // functions that are removed from the lua environment
+    #[serde(default)]
+    pub deny: Vec<Capability>,
+    // don't ask before running scripts that declare exec or fs
+    #[serde(default)]
+    pub trust: bool,
+}
+
+impl RuntimeConfig {
+    #[inline]
+    pub fn limits(&self) -> Limits {
+        Limits {
+            memory: self.memory_limit,
+            instructions: self.instruction_limit,
+            timeout: self.script_timeout,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub slack: Option<ChatConfig>,
+    #[serde(default)]
+    pub discord: Option<ChatConfig>,
+    #[serde(default)]
+    pub telegram: Option<ChatConfig>,
+    // also notify when the run is done
+    #[serde(default)]
+    pub on_finish: bool,
+}
+
+// the channel is the chat id for telegram
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatConfig {
+    pub token: String,
+    pub channel: String,
 }
 
 impl Config {
@@ -27,23 +138,30 @@ impl Config {
         let home = dirs::home_dir()
                         .ok_or_else(|| format_err!("home folder not found"))?;
 
-        let path = home.join(".config/badtouch.toml");
+        // ~/.config/badtouch.toml is still read if there's no config.toml
+        let paths = [
+            home.join(".config/badtouch/config.toml"),
+            home.join(".config/badtouch.toml"),
+        ];
 
-        if path.exists() {
-            Config::from_file(path)
-        } else {
-            Ok(Config::default())
+        match paths.iter().find(|path| path.exists()) {
+            Some(path) => Config::from_file(path),
+            None => Ok(Config::default()),
         }
     }
 
     #[inline]
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-        let mut file = File::open(path)?;
+        let path = path.as_ref();
+        let mut file = File::open(path)
+            .context(format!("Failed to open config {:?}", path))?;
 
         let mut buf = String::new();
         file.read_to_string(&mut buf)?;
 
-        Config::try_from_str(&buf)
+        let config = Config::try_from_str(&buf)
+            .context(format!("Invalid config {:?}", path))?;
+        Ok(config)
     }
 
     #[inline]
@@ -62,4 +180,43 @@ mod tests {
         let config = Config::try_from_str("").unwrap();
         assert_eq!(config, Config::default());
     }
+
+    #[test]
+    fn verify_notify() {
+        let config = Config::try_from_str(r##"
+        [notify]
+        on_finish = true
+
+        [notify.slack]
+        token = "xoxb-1234"
+        channel = "#pentest"
+        "##).unwrap();
+        assert!(config.notify.on_finish);
+        assert_eq!(config.notify.slack, Some(ChatConfig {
+            token: "xoxb-1234".to_string(),
+            channel: "#pentest".to_string(),
+        }));
+        assert_eq!(config.notify.discord, None);
+    }
+
+    #[test]
+    fn verify_general() {
+        let config = Config::try_from_str(r#"
+        [general]
+        workers = 64
+        script_timeout = 10
+        format = "jsonl"
+        scripts = "/opt/badtouch-scripts"
+        "#).unwrap();
+        assert_eq!(config.general.workers, Some(64));
+        assert_eq!(config.general.retries, None);
+        assert_eq!(config.general.script_timeout, Some(10));
+        assert_eq!(config.general.format, Some(Format::Jsonl));
+        assert_eq!(config.general.scripts_dir(), Some(PathBuf::from("/opt/badtouch-scripts")));
+    }
+
+    #[test]
+    fn verify_invalid_format() {
+        assert!(Config::try_from_str("[general]\nformat = \"xml\"").is_err());
+    }
 }
@@ -0,0 +1,572 @@
+// daemon mode, a small http api to run jobs in the background
+//
+// every job is a badtouch process with its own directory, the progress is
+// read from its session file and the findings from its jsonl output. A job is
+// cancelled with SIGINT, so it shuts down gracefully.
+//
+// The command line of a job is built from typed fields, the script and lists
+// are paths relative to the jobs directory and jobs never get exec or fs.
+//
+//   POST   /jobs                {"mode": "dict", "script": "ssh.lua", "users": "users.txt", ...}
+//   GET    /jobs
+//   GET    /jobs/:id
+//   GET    /jobs/:id/findings   jsonl, streamed until the job is done
+//   DELETE /jobs/:id
+
+use errors::*;
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde_json::{self, Value};
+use session::Session;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use utils;
+
+
+const MAX_BODY: usize = 1024 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next()
+        .ok_or_else(|| format_err!("Missing method"))?
+        .to_string();
+    let target = parts.next()
+        .ok_or_else(|| format_err!("Missing path"))?;
+    // the query string isn't used
+    let path = target.split('?').next().unwrap_or("").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("Unexpected end of request");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let len = match headers.get("content-length") {
+        Some(len) => len.parse::<usize>()
+            .context("Invalid content-length")?,
+        None => 0,
+    };
+    if len > MAX_BODY {
+        bail!("Request body is too large");
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        307 => "Temporary Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &Value) -> Result<()> {
+    let body = serde_json::to_string_pretty(body)? + "\n";
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, reason(status), body.len(), body)?;
+    Ok(())
+}
+
+fn error(stream: &mut TcpStream, status: u16, msg: &str) -> Result<()> {
+    respond(stream, status, &json!({ "error": msg }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Dict,
+    Creds,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Submit {
+    pub mode: Mode,
+    pub script: String,
+    pub users: Option<String>,
+    pub passwords: Option<String>,
+    pub creds: Option<String>,
+    pub threads: Option<usize>,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+// paths of a job have to stay inside the jobs directory, symlinks that point
+// outside of it are rejected as well
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    if path.is_empty() || !relative.components().all(|x| match x {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    }) {
+        bail!("Path {:?} has to be relative to the jobs directory", path);
+    }
+
+    let full = root.join(relative);
+    if let Ok(real) = full.canonicalize() {
+        if !real.starts_with(root.canonicalize()?) {
+            bail!("Path {:?} points outside of the jobs directory", path);
+        }
+    }
+    Ok(full)
+}
+
+fn required<'a>(field: &'a Option<String>, name: &str) -> Result<&'a str> {
+    match *field {
+        Some(ref x) => Ok(x.as_str()),
+        None => bail!("{} is required in this mode", name),
+    }
+}
+
+impl Submit {
+    // arguments of the subcommand, the lists and the script
+    pub fn args(&self, root: &Path) -> Result<Vec<String>> {
+        let path = |x: &str| resolve(root, x).map(|x| x.to_string_lossy().into_owned());
+
+        let mut args = Vec::new();
+        match self.mode {
+            Mode::Dict => {
+                args.push("dict".to_string());
+                args.push(path(required(&self.users, "users")?)?);
+                args.push(path(required(&self.passwords, "passwords")?)?);
+            },
+            Mode::Creds => {
+                args.push("creds".to_string());
+                args.push(path(required(&self.creds, "creds")?)?);
+            },
+        }
+        args.push(path(&self.script)?);
+        Ok(args)
+    }
+
+    // arguments of the badtouch process, global options have to come before
+    // the subcommand
+    pub fn command(&self, root: &Path, dir: &Path) -> Result<Vec<String>> {
+        let mut command = vec![
+            "--resume".to_string(),
+            dir.join("session.json").to_string_lossy().into_owned(),
+            "--output".to_string(),
+            dir.join("findings.jsonl").to_string_lossy().into_owned(),
+            "--format".to_string(),
+            "jsonl".to_string(),
+            "--deny".to_string(),
+            "exec,fs".to_string(),
+        ];
+        if let Some(threads) = self.threads {
+            if threads == 0 {
+                bail!("threads has to be at least 1");
+            }
+            command.push("-n".to_string());
+            command.push(threads.to_string());
+        }
+        for (key, value) in &self.options {
+            if key.is_empty() || key.contains('=') {
+                bail!("Invalid option name: {:?}", key);
+            }
+            command.push("-X".to_string());
+            command.push(format!("{}={}", key, value));
+        }
+        command.extend(self.args(root)?);
+        Ok(command)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Running,
+    Cancelling,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub id: usize,
+    pub args: Vec<String>,
+    pub state: State,
+    pub done: usize,
+    pub total: usize,
+    pub valid: usize,
+    pub exit_code: Option<i32>,
+}
+
+struct Job {
+    args: Vec<String>,
+    dir: PathBuf,
+    child: Child,
+    // stdin is kept open so the keyboard thread of the job blocks
+    _stdin: Option<ChildStdin>,
+    state: State,
+    exit_code: Option<i32>,
+}
+
+impl Job {
+    fn update(&mut self) -> Result<()> {
+        if let Some(status) = self.child.try_wait()? {
+            if self.state == State::Running || self.state == State::Cancelling {
+                self.state = if self.state == State::Cancelling {
+                    State::Cancelled
+                } else if status.success() {
+                    State::Finished
+                } else {
+                    State::Failed
+                };
+                self.exit_code = status.code();
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.state == State::Running || self.state == State::Cancelling
+    }
+
+    fn status(&mut self, id: usize) -> Result<Status> {
+        self.update()?;
+
+        // the session is only written every few seconds
+        let session = Session::load(self.dir.join("session.json")).unwrap_or_default();
+        let valid = match File::open(self.dir.join("findings.jsonl")) {
+            Ok(f) => BufReader::new(f).lines().count(),
+            Err(_) => 0,
+        };
+
+        Ok(Status {
+            id,
+            args: self.args.clone(),
+            state: self.state,
+            done: session.progress(),
+            total: session.total,
+            valid,
+            exit_code: self.exit_code,
+        })
+    }
+
+    fn cancel(&mut self) -> Result<()> {
+        self.update()?;
+        if self.state == State::Running {
+            let pid = Pid::from_raw(self.child.id() as i32);
+            signal::kill(pid, Signal::SIGINT)?;
+            self.state = State::Cancelling;
+        }
+        Ok(())
+    }
+}
+
+pub struct Daemon {
+    dir: PathBuf,
+    exe: PathBuf,
+    token: String,
+    jobs: Mutex<BTreeMap<usize, Job>>,
+}
+
+impl Daemon {
+    pub fn new<P: Into<PathBuf>>(dir: P, token: String) -> Result<Daemon> {
+        if token.is_empty() {
+            bail!("The token can't be empty");
+        }
+
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .context("Failed to create jobs directory")?;
+        let exe = env::current_exe()?;
+
+        Ok(Daemon {
+            dir,
+            exe,
+            token,
+            jobs: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    // serve requests until the process is killed
+    pub fn serve(self, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr)
+            .context(format!("Failed to listen on {:?}", addr))?;
+        let daemon = Arc::new(self);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("failed to accept connection: {}", err);
+                    continue;
+                },
+            };
+
+            let daemon = daemon.clone();
+            thread::spawn(move || {
+                if let Err(err) = daemon.handle(stream) {
+                    info!("request failed: {}", err);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let req = {
+            let mut reader = BufReader::new(stream.try_clone()?);
+            match read_request(&mut reader) {
+                Ok(req) => req,
+                Err(err) => return error(&mut stream, 400, &err.to_string()),
+            }
+        };
+        debug!("{} {}", req.method, req.path);
+
+        let token = req.headers.get("authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if !utils::constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+            return error(&mut stream, 401, "invalid token");
+        }
+
+        let segments = req.path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let id = segments.get(1).and_then(|x| x.parse::<usize>().ok());
+
+        match (req.method.as_str(), segments.as_slice(), id) {
+            ("GET", ["jobs"], _) => {
+                let mut jobs = self.jobs.lock().unwrap();
+                let mut list = Vec::new();
+                for (id, job) in jobs.iter_mut() {
+                    list.push(job.status(*id)?);
+                }
+                respond(&mut stream, 200, &serde_json::to_value(list)?)
+            },
+            ("POST", ["jobs"], _) => {
+                let submit = match serde_json::from_slice::<Submit>(&req.body) {
+                    Ok(submit) => submit,
+                    Err(err) => return error(&mut stream, 400, &err.to_string()),
+                };
+                match self.submit(submit) {
+                    Ok(status) => respond(&mut stream, 201, &serde_json::to_value(status)?),
+                    Err(err) => error(&mut stream, 400, &err.to_string()),
+                }
+            },
+            ("GET", ["jobs", _], Some(id)) => {
+                let mut jobs = self.jobs.lock().unwrap();
+                match jobs.get_mut(&id) {
+                    Some(job) => respond(&mut stream, 200, &serde_json::to_value(job.status(id)?)?),
+                    None => error(&mut stream, 404, "job not found"),
+                }
+            },
+            ("DELETE", ["jobs", _], Some(id)) => {
+                let mut jobs = self.jobs.lock().unwrap();
+                match jobs.get_mut(&id) {
+                    Some(job) => {
+                        job.cancel()?;
+                        respond(&mut stream, 202, &serde_json::to_value(job.status(id)?)?)
+                    },
+                    None => error(&mut stream, 404, "job not found"),
+                }
+            },
+            ("GET", ["jobs", _, "findings"], Some(id)) => self.stream_findings(stream, id),
+            (_, ["jobs"], _) | (_, ["jobs", _], Some(_)) | (_, ["jobs", _, "findings"], Some(_)) => {
+                error(&mut stream, 405, "method not allowed")
+            },
+            _ => error(&mut stream, 404, "not found"),
+        }
+    }
+
+    fn submit(&self, submit: Submit) -> Result<Status> {
+        // invalid jobs are rejected before a directory is created
+        let args = submit.args(&self.dir)?;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(1, |x| x + 1);
+
+        // directories of an earlier daemon are not reused
+        let mut id = id;
+        while self.dir.join(id.to_string()).exists() {
+            id += 1;
+        }
+        let dir = self.dir.join(id.to_string());
+        fs::create_dir(&dir)?;
+
+        let log = File::create(dir.join("output.log"))?;
+        let mut child = Command::new(&self.exe)
+            .args(submit.command(&self.dir, &dir)?)
+            .stdin(Stdio::piped())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .context("Failed to start job")?;
+        let stdin = child.stdin.take();
+
+        let mut job = Job {
+            args,
+            dir,
+            child,
+            _stdin: stdin,
+            state: State::Running,
+            exit_code: None,
+        };
+        let status = job.status(id)?;
+        jobs.insert(id, job);
+        Ok(status)
+    }
+
+    // writes the findings as they are found until the job is done
+    fn stream_findings(&self, mut stream: TcpStream, id: usize) -> Result<()> {
+        let path = match self.jobs.lock().unwrap().get(&id) {
+            Some(job) => job.dir.join("findings.jsonl"),
+            None => return error(&mut stream, 404, "job not found"),
+        };
+
+        // the length is unknown, the body ends when the connection is closed
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n")?;
+
+        let mut reader = None;
+        let mut line = String::new();
+        loop {
+            let running = {
+                let mut jobs = self.jobs.lock().unwrap();
+                match jobs.get_mut(&id) {
+                    Some(job) => {
+                        job.update()?;
+                        job.is_running()
+                    },
+                    None => false,
+                }
+            };
+
+            if reader.is_none() {
+                reader = File::open(&path).ok().map(BufReader::new);
+            }
+
+            if let Some(ref mut reader) = reader {
+                // lines that are still being written are completed on the next read
+                while reader.read_line(&mut line)? > 0 {
+                    if line.ends_with('\n') {
+                        stream.write_all(line.as_bytes())?;
+                        line.clear();
+                    }
+                }
+            }
+
+            if !running {
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_request() {
+        let mut r = Cursor::new(&b"POST /jobs?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 13\r\n\r\n{\"args\": []}\n"[..]);
+        let req = read_request(&mut r).unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/jobs");
+        assert_eq!(req.headers.get("host").map(|x| x.as_str()), Some("localhost"));
+        assert_eq!(req.body, b"{\"args\": []}\n");
+    }
+
+    #[test]
+    fn test_read_request_truncated() {
+        let mut r = Cursor::new(&b"GET /jobs HTTP/1.1\r\nHost: localhost\r\n"[..]);
+        assert!(read_request(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_command() {
+        let submit: Submit = serde_json::from_str(r#"{"mode": "creds", "creds": "creds.txt", "script": "scripts/ssh.lua", "threads": 4, "options": {"target": "10.0.0.1"}}"#).unwrap();
+        let command = submit.command(Path::new("/jobs"), Path::new("/jobs/1")).unwrap();
+        assert_eq!(command, vec![
+            "--resume", "/jobs/1/session.json",
+            "--output", "/jobs/1/findings.jsonl",
+            "--format", "jsonl",
+            "--deny", "exec,fs",
+            "-n", "4",
+            "-X", "target=10.0.0.1",
+            "creds", "/jobs/creds.txt", "/jobs/scripts/ssh.lua",
+        ]);
+    }
+
+    #[test]
+    fn test_command_missing_list() {
+        let submit: Submit = serde_json::from_str(r#"{"mode": "dict", "users": "users.txt", "script": "ssh.lua"}"#).unwrap();
+        assert!(submit.command(Path::new("/jobs"), Path::new("/jobs/1")).is_err());
+    }
+
+    #[test]
+    fn test_submit_rejects_raw_args() {
+        let submit = serde_json::from_str::<Submit>(r#"{"mode": "creds", "creds": "creds.txt", "script": "ssh.lua", "args": ["--trust"]}"#);
+        assert!(submit.is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("/jobs");
+        assert_eq!(resolve(root, "lists/users.txt").unwrap(), PathBuf::from("/jobs/lists/users.txt"));
+        assert!(resolve(root, "/etc/passwd").is_err());
+        assert!(resolve(root, "../passwords.txt").is_err());
+        assert!(resolve(root, "lists/../../passwords.txt").is_err());
+        assert!(resolve(root, "").is_err());
+    }
+}
@@ -1,11 +1,73 @@
+use errors::*;
+
 use hlua::{AnyHashableLuaValue, AnyLuaValue};
+use json::LuaJsonValue;
+use limits;
 use mysql;
+use serde_json;
 
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
+use std::time::Duration;
 use twox_hash::XxHash;
 use structs::LuaMap;
 
+// ER_ACCESS_DENIED_ERROR, the server rejected the credentials
+const ER_ACCESS_DENIED: u16 = 1045;
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MysqlOptions {
+    // path to the ca certificate, enables tls
+    pub tls_ca: Option<String>,
+    #[serde(default)]
+    pub disable_tls_verify: bool,
+    // in milliseconds
+    pub connect_timeout: Option<u64>,
+    pub db: Option<String>,
+}
+
+impl MysqlOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<MysqlOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(MysqlOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+pub fn connect(ip: &str, port: u16, user: &str, password: &str, options: &MysqlOptions) -> Result<mysql::Conn> {
+    let mut builder = mysql::OptsBuilder::new();
+    builder.ip_or_hostname(Some(ip))
+           .tcp_port(port)
+           .prefer_socket(false)
+           .user(Some(user))
+           .pass(Some(password))
+           .db_name(options.db.as_ref())
+           .tcp_connect_timeout(limits::timeout(options.connect_timeout.map(Duration::from_millis)))
+           .read_timeout(limits::remaining())
+           .write_timeout(limits::remaining());
+
+    if let Some(ref ca) = options.tls_ca {
+        builder.ssl_opts(Some((ca.as_str(), None::<(String, String)>)))
+               .verify_peer(!options.disable_tls_verify);
+    }
+
+    // wrong credentials are reported differently than a server that can't be
+    // reached, so scripts can tell them apart with last_err()
+    match mysql::Conn::new(builder) {
+        Ok(conn) => Ok(conn),
+        Err(mysql::Error::MySqlError(ref err)) if err.code == ER_ACCESS_DENIED => {
+            bail!("mysql authentication failed: {}", err.message)
+        },
+        Err(err) => Err(err.context("mysql connection failed").into()),
+    }
+}
+
 
 impl From<mysql::Params> for LuaMap {
     fn from(params: mysql::Params) -> LuaMap {
@@ -0,0 +1,115 @@
+// database of vendor default credentials
+//
+// the format is `product,user,password` with one entry per line, the password
+// may contain commas. Empty lines and lines starting with `#` are ignored.
+
+use errors::*;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+
+const BUILTIN: &str = include_str!("../data/default-creds.csv");
+
+#[derive(Debug, PartialEq)]
+pub struct DefaultCreds {
+    pub product: String,
+    pub user: Arc<String>,
+    pub password: Arc<String>,
+}
+
+pub fn parse(x: &str) -> Result<Vec<DefaultCreds>> {
+    let mut db = Vec::new();
+
+    for (i, line) in x.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(3, ',');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(product), Some(user), Some(password)) => db.push(DefaultCreds {
+                product: product.trim().to_lowercase(),
+                user: Arc::new(user.to_string()),
+                password: Arc::new(password.to_string()),
+            }),
+            _ => bail!("Invalid entry in line {}: {:?}", i + 1, line),
+        }
+    }
+
+    Ok(db)
+}
+
+pub fn builtin() -> Vec<DefaultCreds> {
+    parse(BUILTIN).expect("builtin database is invalid")
+}
+
+pub fn load(path: &str) -> Result<Vec<DefaultCreds>> {
+    let mut file = File::open(path)?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    parse(&buf)
+}
+
+// products are matched case insensitive
+pub fn lookup<'a>(db: &'a [DefaultCreds], product: &str) -> Vec<&'a DefaultCreds> {
+    let product = product.to_lowercase();
+    db.iter()
+        .filter(|x| x.product == product)
+        .collect()
+}
+
+pub fn products(db: &[DefaultCreds]) -> Vec<&str> {
+    let mut products = db.iter()
+        .map(|x| x.product.as_str())
+        .collect::<Vec<_>>();
+    products.sort();
+    products.dedup();
+    products
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let db = builtin();
+        assert!(!db.is_empty());
+        assert!(products(&db).contains(&"tomcat"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let db = parse("# comment\r\nFooCam,admin,a,b\r\n\r\nfoocam,root,\n").unwrap();
+        assert_eq!(db, vec![
+            DefaultCreds {
+                product: "foocam".to_string(),
+                user: Arc::new("admin".to_string()),
+                password: Arc::new("a,b".to_string()),
+            },
+            DefaultCreds {
+                product: "foocam".to_string(),
+                user: Arc::new("root".to_string()),
+                password: Arc::new("".to_string()),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("foocam,admin").is_err());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = parse("foocam,admin,admin\nbarcam,root,root\n").unwrap();
+        let creds = lookup(&db, "FooCam");
+        assert_eq!(creds.len(), 1);
+        assert_eq!(creds[0].user.as_str(), "admin");
+        assert!(lookup(&db, "cam").is_empty());
+    }
+}
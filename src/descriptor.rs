@@ -0,0 +1,105 @@
+// optional metadata that scripts can define in a top-level `descriptor` table
+//
+// descriptor = {
+//     name = "example.com",
+//     author = "kpcyrd",
+//     protocol = "http",
+//     options = {
+//         domain = "the windows domain",
+//     },
+//     caps = {"http"},
+// }
+
+use errors::*;
+
+use hlua::AnyLuaValue;
+use json::LuaJsonValue;
+use sandbox::Capability;
+use serde_json;
+use std::collections::BTreeMap;
+
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Descriptor {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub protocol: Option<String>,
+    // option name and its description
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    // the script can only use these capabilities if they are declared
+    pub caps: Option<Vec<Capability>>,
+}
+
+impl Descriptor {
+    pub fn try_from(x: AnyLuaValue) -> Result<Descriptor> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(Descriptor::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(x: &str) -> AnyLuaValue {
+        AnyLuaValue::LuaString(x.to_string())
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let x = AnyLuaValue::LuaArray(vec![
+            (s("name"), s("example.com")),
+            (s("protocol"), s("http")),
+            (s("options"), AnyLuaValue::LuaArray(vec![
+                (s("domain"), s("the windows domain")),
+            ])),
+            (s("caps"), AnyLuaValue::LuaArray(vec![
+                (AnyLuaValue::LuaNumber(1.0), s("http")),
+                (AnyLuaValue::LuaNumber(2.0), s("raw-sockets")),
+            ])),
+        ]);
+        let x = Descriptor::try_from(x).unwrap();
+
+        let mut options = BTreeMap::new();
+        options.insert("domain".to_string(), "the windows domain".to_string());
+        assert_eq!(x, Descriptor {
+            name: Some("example.com".to_string()),
+            author: None,
+            protocol: Some("http".to_string()),
+            options,
+            caps: Some(vec![Capability::Http, Capability::RawSockets]),
+        });
+    }
+
+    #[test]
+    fn test_descriptor_nil() {
+        let x = Descriptor::try_from(AnyLuaValue::LuaNil).unwrap();
+        assert_eq!(x, Descriptor::default());
+    }
+
+    #[test]
+    fn test_descriptor_invalid() {
+        let x = AnyLuaValue::LuaArray(vec![
+            (s("options"), s("domain")),
+        ]);
+        assert!(Descriptor::try_from(x).is_err());
+    }
+
+    #[test]
+    fn test_descriptor_unknown_cap() {
+        let x = AnyLuaValue::LuaArray(vec![
+            (s("caps"), AnyLuaValue::LuaArray(vec![
+                (AnyLuaValue::LuaNumber(1.0), s("root")),
+            ])),
+        ]);
+        assert!(Descriptor::try_from(x).is_err());
+    }
+}
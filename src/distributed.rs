@@ -0,0 +1,586 @@
+// distributed mode, a coordinator hands out ranges of attempts to workers
+//
+// every worker generates the attempts from the same command, attempts are
+// numbered in the order they are generated, so the coordinator only has to
+// hand out ranges of ids. Workers report valid credentials back to the
+// coordinator, ranges of workers that disconnect are handed out again.
+//
+// the protocol is one json message per line over tcp. Workers have to know the
+// shared secret and run an attack with the same fingerprint. A worker only
+// keeps a bounded window of attempts it skipped, it tells the coordinator the
+// lowest id it can still run.
+
+use errors::*;
+
+use args::SubCommand;
+use config::Config;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use session::Valid;
+use sha2::{Digest, Sha256};
+use shutdown;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use utils;
+
+
+// workers without a range ask again after this time
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+// ids from start to end, excluding end
+pub type Range = (usize, usize);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    Hello {
+        secret: String,
+        fingerprint: String,
+        total: usize,
+    },
+    // the worker can't run attempts with a lower id than floor anymore
+    Work {
+        floor: usize,
+    },
+    Done {
+        start: usize,
+        end: usize,
+    },
+    Finding(Valid),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Welcome {
+        // overrides the number of workers of the worker
+        workers: Option<usize>,
+        // attempts per second of the worker
+        rate: Option<f64>,
+    },
+    Work {
+        start: usize,
+        end: usize,
+    },
+    // everything is handed out, but ranges might come back
+    Wait,
+    Finished,
+    Error {
+        msg: String,
+    },
+}
+
+// what happens on the coordinator, displayed by the main thread
+#[derive(Debug)]
+pub enum Event {
+    Connected(SocketAddr),
+    Rejected(SocketAddr, String),
+    // the number of ranges that are handed out again
+    Disconnected(SocketAddr, usize),
+    Finding(Valid),
+    // number of attempts in a range that is done
+    Progress(usize),
+    Finished,
+}
+
+// workers have to run the same attack as the coordinator. The attack is
+// compared by its options and the content of its lists, scripts, rules and
+// defaults database, the paths can be different on every machine.
+pub fn fingerprint(attack: &SubCommand, config: &Config) -> Result<String> {
+    let (params, files, scripts) = match *attack {
+        SubCommand::Dict(ref x) => (format!("dict names={} domain={:?}", x.names, x.domain),
+                                    Some(&x.users).into_iter()
+                                        .chain(&x.more_users)
+                                        .chain(Some(&x.passwords))
+                                        .chain(&x.more_passwords)
+                                        .chain(&x.rules)
+                                        .collect::<Vec<_>>(), &x.scripts),
+        SubCommand::Creds(ref x) => ("creds".to_string(), vec![&x.creds], &x.scripts),
+        SubCommand::Combo(ref x) => (format!("combo separator={:?} escape={}", x.separator, x.escape),
+                                     vec![&x.combos], &x.scripts),
+        SubCommand::Mask(ref x) => (format!("mask {:?} charsets={:?}", x.mask,
+                                            (&x.charset1, &x.charset2, &x.charset3, &x.charset4)),
+                                    vec![&x.users], &x.scripts),
+        SubCommand::Defaults(ref x) => (format!("defaults {:?}", x.product),
+                                        x.db.iter().collect::<Vec<_>>(), &x.scripts),
+        SubCommand::Enum(ref x) => (format!("enum names={} domain={:?}", x.names, x.domain),
+                                    Some(&x.users).into_iter()
+                                        .chain(&x.more_users)
+                                        .collect::<Vec<_>>(), &x.scripts),
+        _ => bail!("This subcommand can't be distributed"),
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.input(params.as_bytes());
+    for path in files {
+        let digest = file_digest(path)
+            .context(format!("Failed to read {:?}", path))?;
+        hasher.input(&digest);
+    }
+    for script in scripts {
+        let path = PathBuf::from(utils::find_script(script, config));
+        let mut paths = Vec::new();
+        if path.is_dir() {
+            utils::find_scripts(&path, &mut paths)?;
+            paths.sort();
+        } else {
+            paths.push(path);
+        }
+        for path in paths {
+            let code = fs::read(&path)
+                .context(format!("Failed to read script {:?}", path))?;
+            hasher.input(&Sha256::digest(&code));
+        }
+    }
+    Ok(format!("{:x}", hasher.result()))
+}
+
+// lists can be a few gigabytes, they are hashed without reading them into memory
+fn file_digest(path: &str) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+    }
+    Ok(hasher.result().to_vec())
+}
+
+fn send<T: Serialize>(stream: &mut TcpStream, msg: &T) -> Result<()> {
+    // write the line at once so it's never cut in half
+    let mut line = serde_json::to_string(msg)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// returns None if the connection was closed
+fn recv<T: DeserializeOwned>(reader: &mut BufReader<TcpStream>) -> Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let msg = serde_json::from_str(&line)
+        .context("Failed to parse message")?;
+    Ok(Some(msg))
+}
+
+#[derive(Debug, Default)]
+struct Work {
+    ranges: VecDeque<Range>,
+    // ranges that are handed out, by worker
+    assigned: HashMap<SocketAddr, HashSet<Range>>,
+    findings: HashSet<Valid>,
+    finished: bool,
+}
+
+impl Work {
+    fn new(total: usize, chunk: usize) -> Work {
+        let chunk = cmp::max(chunk, 1);
+        let ranges = (0..total).step_by(chunk)
+            .map(|start| (start, cmp::min(start + chunk, total)))
+            .collect();
+
+        Work {
+            ranges,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn is_done(&self) -> bool {
+        self.ranges.is_empty() && self.assigned.values().all(|x| x.is_empty())
+    }
+
+    // the first range the worker can still run
+    fn next(&mut self, worker: SocketAddr, floor: usize) -> Response {
+        let pos = self.ranges.iter().position(|&(start, _)| start >= floor);
+        match pos.and_then(|pos| self.ranges.remove(pos)) {
+            Some((start, end)) => {
+                self.assigned.entry(worker)
+                    .or_default()
+                    .insert((start, end));
+                Response::Work { start, end }
+            },
+            None if self.is_done() => Response::Finished,
+            // ranges that all connected workers moved past wait for a new one
+            None => Response::Wait,
+        }
+    }
+
+    #[inline]
+    fn has_range(&self, worker: SocketAddr) -> bool {
+        self.assigned.get(&worker).map_or(false, |x| !x.is_empty())
+    }
+
+    // returns false if the range wasn't handed out to this worker
+    fn done(&mut self, worker: SocketAddr, range: Range) -> bool {
+        match self.assigned.get_mut(&worker) {
+            Some(assigned) => assigned.remove(&range),
+            None => false,
+        }
+    }
+
+    // true the first time everything is done
+    fn finish(&mut self) -> bool {
+        if self.finished || !self.is_done() {
+            return false;
+        }
+        self.finished = true;
+        true
+    }
+
+    // hands out the ranges of the worker again, returns the number of ranges
+    fn release(&mut self, worker: SocketAddr) -> usize {
+        let assigned = self.assigned.remove(&worker).unwrap_or_default();
+        let num = assigned.len();
+        for range in assigned {
+            self.ranges.push_front(range);
+        }
+        num
+    }
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    pub secret: String,
+    pub fingerprint: String,
+    pub total: usize,
+    pub chunk: usize,
+    // overrides the number of workers of every worker
+    pub workers: Option<usize>,
+    // attempts per second of every worker
+    pub rate: Option<f64>,
+}
+
+struct Shared {
+    settings: Settings,
+    work: Mutex<Work>,
+}
+
+pub struct Coordinator {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl Coordinator {
+    pub fn bind(addr: &str, settings: Settings) -> Result<Coordinator> {
+        if settings.secret.is_empty() {
+            bail!("The secret can't be empty");
+        }
+        let listener = TcpListener::bind(addr)
+            .context(format!("Failed to listen on {:?}", addr))?;
+
+        let work = Work::new(settings.total, settings.chunk);
+        Ok(Coordinator {
+            listener,
+            shared: Arc::new(Shared {
+                settings,
+                work: Mutex::new(work),
+            }),
+        })
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        let addr = self.listener.local_addr()?;
+        Ok(addr)
+    }
+
+    // accept workers in the background, the events end with Event::Finished
+    pub fn spawn(self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+
+        if self.shared.work.lock().unwrap().finish() {
+            tx.send(Event::Finished).ok();
+        }
+
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("failed to accept worker: {}", err);
+                        continue;
+                    },
+                };
+
+                let shared = self.shared.clone();
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    let addr = match stream.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(_) => return,
+                    };
+
+                    if let Err(err) = handle(stream, addr, &shared, &tx) {
+                        info!("worker {} failed: {}", addr, err);
+                    }
+
+                    let released = shared.work.lock().unwrap().release(addr);
+                    tx.send(Event::Disconnected(addr, released)).ok();
+                });
+            }
+        });
+
+        rx
+    }
+}
+
+fn handle(mut stream: TcpStream, addr: SocketAddr, shared: &Shared, tx: &mpsc::Sender<Event>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let settings = &shared.settings;
+    match recv(&mut reader)? {
+        Some(Request::Hello { secret, fingerprint, total }) => {
+            let msg = if !utils::constant_time_eq(secret.as_bytes(), settings.secret.as_bytes()) {
+                Some("invalid secret".to_string())
+            } else if fingerprint != settings.fingerprint || total != settings.total {
+                Some(format!("the worker runs a different attack ({} attempts, expected {})",
+                             total, settings.total))
+            } else {
+                None
+            };
+            if let Some(msg) = msg {
+                send(&mut stream, &Response::Error { msg: msg.clone() })?;
+                tx.send(Event::Rejected(addr, msg)).ok();
+                return Ok(());
+            }
+            send(&mut stream, &Response::Welcome {
+                workers: settings.workers,
+                rate: settings.rate,
+            })?;
+        },
+        Some(_) => bail!("Expected hello"),
+        None => return Ok(()),
+    }
+    tx.send(Event::Connected(addr)).ok();
+
+    while let Some(request) = recv(&mut reader)? {
+        match request {
+            Request::Work { floor } => {
+                let response = shared.work.lock().unwrap().next(addr, floor);
+                send(&mut stream, &response)?;
+            },
+            Request::Done { start, end } => {
+                let mut work = shared.work.lock().unwrap();
+                if work.done(addr, (start, end)) {
+                    tx.send(Event::Progress(end - start)).ok();
+                }
+                if work.finish() {
+                    tx.send(Event::Finished).ok();
+                }
+            },
+            Request::Finding(finding) => {
+                let mut work = shared.work.lock().unwrap();
+                // findings are only accepted while the worker runs a range
+                if !work.has_range(addr) {
+                    bail!("Finding outside of a range");
+                }
+                if work.findings.insert(finding.clone()) {
+                    tx.send(Event::Finding(finding)).ok();
+                }
+            },
+            Request::Hello { .. } => bail!("Unexpected hello"),
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Agent {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    current: Option<Range>,
+    // set by the coordinator
+    pub workers: Option<usize>,
+    pub rate: Option<f64>,
+}
+
+impl Agent {
+    pub fn connect(addr: &str, secret: String, fingerprint: String, total: usize) -> Result<Agent> {
+        let mut stream = TcpStream::connect(addr)
+            .context(format!("Failed to connect to coordinator {:?}", addr))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        send(&mut stream, &Request::Hello { secret, fingerprint, total })?;
+        let (workers, rate) = match recv(&mut reader)? {
+            Some(Response::Welcome { workers, rate }) => (workers, rate),
+            Some(Response::Error { msg }) => bail!("Coordinator rejected the worker: {}", msg),
+            Some(response) => bail!("Unexpected response: {:?}", response),
+            None => bail!("Coordinator closed the connection"),
+        };
+
+        Ok(Agent {
+            stream,
+            reader,
+            current: None,
+            workers,
+            rate,
+        })
+    }
+
+    #[inline]
+    pub fn report(&mut self, valid: Valid) -> Result<()> {
+        send(&mut self.stream, &Request::Finding(valid))
+    }
+
+    // marks the current range as done and fetches the next one that starts
+    // at floor or later, returns None if there is no work left
+    pub fn next_range(&mut self, floor: usize) -> Result<Option<Range>> {
+        if let Some((start, end)) = self.current.take() {
+            send(&mut self.stream, &Request::Done { start, end })?;
+        }
+
+        loop {
+            send(&mut self.stream, &Request::Work { floor })?;
+            match recv(&mut self.reader)? {
+                Some(Response::Work { start, end }) => {
+                    self.current = Some((start, end));
+                    return Ok(self.current);
+                },
+                Some(Response::Wait) => {
+                    if shutdown::requested() {
+                        return Ok(None);
+                    }
+                    thread::sleep(WAIT_INTERVAL);
+                },
+                Some(Response::Finished) | None => return Ok(None),
+                Some(Response::Error { msg }) => bail!("Coordinator error: {}", msg),
+                Some(response) => bail!("Unexpected response: {:?}", response),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_fingerprint_lists() {
+        let dir = ::std::env::temp_dir().join(format!("badtouch-fingerprint-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("script.lua"), "descr = \"x\"").unwrap();
+        fs::write(path("a.txt"), "admin:123456\n").unwrap();
+        fs::write(path("b.txt"), "admin:hunter2\n").unwrap();
+        fs::write(path("c.txt"), "admin:123456\n").unwrap();
+
+        let config = Config::default();
+        let creds = |list: &str| fingerprint(&SubCommand::Creds(::args::Creds {
+            creds: path(list),
+            scripts: vec![path("script.lua")],
+        }), &config).unwrap();
+        let (a, b, c) = (creds("a.txt"), creds("b.txt"), creds("c.txt"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // same number of lines, different credentials
+        assert_ne!(a, b);
+        // the path doesn't matter
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let work = Work::new(25, 10);
+        assert_eq!(work.ranges, vec![(0, 10), (10, 20), (20, 25)]);
+        assert!(Work::new(0, 10).is_done());
+    }
+
+    #[test]
+    fn test_release() {
+        let mut work = Work::new(20, 10);
+        assert_eq!(work.next(addr(1), 0), Response::Work { start: 0, end: 10 });
+        assert_eq!(work.next(addr(2), 0), Response::Work { start: 10, end: 20 });
+        assert_eq!(work.next(addr(2), 0), Response::Wait);
+        assert!(work.done(addr(2), (10, 20)));
+        assert!(!work.done(addr(2), (0, 10)));
+
+        assert_eq!(work.release(addr(1)), 1);
+        assert!(!work.finish());
+        assert_eq!(work.next(addr(2), 0), Response::Work { start: 0, end: 10 });
+        assert!(work.done(addr(2), (0, 10)));
+        assert_eq!(work.next(addr(2), 0), Response::Finished);
+        assert!(work.finish());
+        assert!(!work.finish());
+    }
+
+    #[test]
+    fn test_floor() {
+        let mut work = Work::new(30, 10);
+        assert_eq!(work.next(addr(1), 0), Response::Work { start: 0, end: 10 });
+        assert_eq!(work.release(addr(1)), 1);
+        // the worker moved past the released range
+        assert_eq!(work.next(addr(2), 15), Response::Work { start: 20, end: 30 });
+        assert_eq!(work.next(addr(2), 15), Response::Wait);
+        assert_eq!(work.next(addr(3), 0), Response::Work { start: 0, end: 10 });
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            secret: "secret".to_string(),
+            fingerprint: "dict".to_string(),
+            total: 25,
+            chunk: 10,
+            workers: Some(3),
+            rate: Some(50.0),
+        }
+    }
+
+    #[test]
+    fn test_coordinator() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", settings()).unwrap();
+        let addr = coordinator.local_addr().unwrap().to_string();
+        let events = coordinator.spawn();
+
+        assert!(Agent::connect(&addr, "secret".to_string(), "creds".to_string(), 25).is_err());
+        assert!(Agent::connect(&addr, "wrong".to_string(), "dict".to_string(), 25).is_err());
+
+        let mut agent = Agent::connect(&addr, "secret".to_string(), "dict".to_string(), 25).unwrap();
+        assert_eq!(agent.workers, Some(3));
+        assert_eq!(agent.rate, Some(50.0));
+
+        let mut ranges = Vec::new();
+        while let Some(range) = agent.next_range(0).unwrap() {
+            agent.report(Valid {
+                script: "example.com".to_string(),
+                user: "admin".to_string(),
+                password: Some("admin".to_string()),
+            }).unwrap();
+            ranges.push(range);
+        }
+        assert_eq!(ranges, vec![(0, 10), (10, 20), (20, 25)]);
+
+        let mut findings = 0;
+        let mut progress = 0;
+        for event in events {
+            match event {
+                Event::Finding(_) => findings += 1,
+                Event::Progress(n) => progress += n,
+                Event::Finished => break,
+                _ => (),
+            }
+        }
+        assert_eq!(findings, 1);
+        assert_eq!(progress, 25);
+    }
+}
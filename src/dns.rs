@@ -0,0 +1,415 @@
+// lookups are cached for the whole process until the ttl of their records
+// runs out, names that don't exist are cached as well. The resolver of the
+// system is used unless servers are set with --dns-server. Http clients
+// resolve through HttpResolver, so they use the cache too.
+
+use errors::*;
+
+use hlua::AnyLuaValue;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use structs::LuaMap;
+use tokio::task::{spawn_blocking, JoinHandle};
+use trust_dns_resolver::Resolver;
+use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::rr::{RData, RecordType};
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+
+// if the server doesn't say how long a name doesn't exist
+const NEGATIVE_TTL: Duration = Duration::from_secs(60);
+// expired entries are removed once the cache is this big
+const MAX_ENTRIES: usize = 10_000;
+
+// the record type is None for the addresses of a host, see lookup_ip
+type Key = (String, Option<RecordType>);
+
+struct Entry {
+    records: Vec<RData>,
+    valid_until: Instant,
+}
+
+static SERVERS: Mutex<Vec<SocketAddr>> = Mutex::new(Vec::new());
+static CACHE: Mutex<Option<HashMap<Key, Entry>>> = Mutex::new(None);
+// a resolver blocks on a lock while it runs a query, so every lookup takes one
+// from here and a new one is created if they're all in use
+static RESOLVERS: Mutex<Vec<Resolver>> = Mutex::new(Vec::new());
+
+// an ip address, port 53 is used if it doesn't have one
+pub fn parse_server(x: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = x.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = x.parse::<IpAddr>()
+                .context(format!("Invalid dns server: {:?}", x))?;
+    Ok(SocketAddr::new(ip, 53))
+}
+
+// has to be called before the first lookup
+pub fn set_servers(servers: &[String]) -> Result<()> {
+    let servers = servers.iter()
+        .map(|x| parse_server(x))
+        .collect::<Result<Vec<_>>>()?;
+    *SERVERS.lock().unwrap() = servers;
+    RESOLVERS.lock().unwrap().clear();
+    Ok(())
+}
+
+fn new_resolver() -> Result<Resolver> {
+    let servers = SERVERS.lock().unwrap().clone();
+    if servers.is_empty() {
+        return Ok(Resolver::from_system_conf()?);
+    }
+
+    let mut config = ResolverConfig::new();
+    for addr in servers {
+        for protocol in &[Protocol::Udp, Protocol::Tcp] {
+            config.add_name_server(NameServerConfig {
+                socket_addr: addr,
+                protocol: *protocol,
+                tls_dns_name: None,
+            });
+        }
+    }
+    Ok(Resolver::new(config, ResolverOpts::default())?)
+}
+
+fn cached(key: &Key) -> Option<Vec<RData>> {
+    let cache = CACHE.lock().unwrap();
+    let entry = cache.as_ref()?.get(key)?;
+    if entry.valid_until > Instant::now() {
+        Some(entry.records.clone())
+    } else {
+        None
+    }
+}
+
+fn cache(key: Key, records: Vec<RData>, valid_until: Instant) {
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if cache.len() >= MAX_ENTRIES {
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.valid_until > now);
+    }
+    cache.insert(key, Entry {
+        records,
+        valid_until,
+    });
+}
+
+fn with_resolver<T, F>(f: F) -> Result<T>
+    where
+        F: FnOnce(&Resolver) -> T,
+{
+    let resolver = RESOLVERS.lock().unwrap().pop();
+    let resolver = match resolver {
+        Some(resolver) => resolver,
+        None => new_resolver()?,
+    };
+    let x = f(&resolver);
+    RESOLVERS.lock().unwrap().push(resolver);
+    Ok(x)
+}
+
+// an empty list if the name doesn't exist
+fn lookup(key: Key) -> Result<Vec<RData>> {
+    if let Some(records) = cached(&key) {
+        return Ok(records);
+    }
+
+    let (name, rtype) = (&key.0, key.1);
+    let result = with_resolver(|resolver| match rtype {
+        Some(rtype) => resolver.lookup(name, rtype)
+            .map(|lookup| (lookup.iter()
+                    .filter(|rdata| rdata.to_record_type() == rtype)
+                    .cloned()
+                    .collect::<Vec<_>>(), lookup.valid_until())),
+        None => resolver.lookup_ip(name)
+            .map(|lookup| (lookup.iter()
+                    .map(|ip| match ip {
+                        IpAddr::V4(ip) => RData::A(ip),
+                        IpAddr::V6(ip) => RData::AAAA(ip),
+                    })
+                    .collect::<Vec<_>>(), lookup.valid_until())),
+    })?;
+
+    let (records, valid_until) = match result {
+        Ok(x) => x,
+        Err(err) => match no_records(&err) {
+            Some(valid_until) => (Vec::new(), valid_until),
+            None => return Err(err.into()),
+        },
+    };
+
+    cache(key, records.clone(), valid_until);
+    Ok(records)
+}
+
+// the addresses of a host, ip addresses are returned as they are
+pub fn lookup_ip(host: &str) -> Result<Vec<IpAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    let records = lookup((host.to_lowercase(), None))?;
+    Ok(records.into_iter()
+        .flat_map(|x| match x {
+            RData::A(ip) => Some(IpAddr::V4(ip)),
+            RData::AAAA(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        })
+        .collect())
+}
+
+// replaces ToSocketAddrs so connections use the cache
+pub fn socket_addrs(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let ips = lookup_ip(host)?;
+    Ok(ips.into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+// the resolver of the http clients. A lookup that isn't cached blocks, so it
+// runs on the blocking pool of the client's runtime
+#[derive(Debug, Default)]
+pub struct HttpResolver;
+
+impl Resolve for HttpResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(HttpLookup(spawn_blocking(move || lookup_ip(&host))))
+    }
+}
+
+struct HttpLookup(JoinHandle<Result<Vec<IpAddr>>>);
+
+impl Future for HttpLookup {
+    type Output = ::std::result::Result<Addrs, BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ips = match Pin::new(&mut self.0).poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Ok(Ok(ips))) => ips,
+            Poll::Ready(Ok(Err(err))) => return Poll::Ready(Err(err.to_string().into())),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(Box::new(err) as BoxError)),
+        };
+
+        if ips.is_empty() {
+            return Poll::Ready(Err("no dns records found".into()));
+        }
+
+        // the port is set by the client
+        let addrs: Addrs = Box::new(ips.into_iter()
+            .map(|ip| SocketAddr::new(ip, 0))
+            .collect::<Vec<_>>()
+            .into_iter());
+        Poll::Ready(Ok(addrs))
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum DnsRecord {
+    // A, AAAA, CNAME, NS, PTR and TXT
+    Text(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+}
+
+impl Into<AnyLuaValue> for DnsRecord {
+    fn into(self) -> AnyLuaValue {
+        match self {
+            DnsRecord::Text(x) => AnyLuaValue::LuaString(x),
+            DnsRecord::Mx { preference, exchange } => {
+                let mut map = LuaMap::new();
+                map.insert_num("preference", f64::from(preference));
+                map.insert_str("exchange", exchange);
+                map.into()
+            },
+            DnsRecord::Srv { priority, weight, port, target } => {
+                let mut map = LuaMap::new();
+                map.insert_num("priority", f64::from(priority));
+                map.insert_num("weight", f64::from(weight));
+                map.insert_num("port", f64::from(port));
+                map.insert_str("target", target);
+                map.into()
+            },
+        }
+    }
+}
+
+// names are returned without the trailing dot
+fn format_name<T: ToString>(name: &T) -> String {
+    let name = name.to_string();
+    name.trim_end_matches('.').to_string()
+}
+
+fn transform(rdata: &RData) -> Option<DnsRecord> {
+    let record = match *rdata {
+        RData::A(ref ip) => DnsRecord::Text(ip.to_string()),
+        RData::AAAA(ref ip) => DnsRecord::Text(ip.to_string()),
+        RData::CNAME(ref name) |
+        RData::NS(ref name) |
+        RData::PTR(ref name) => DnsRecord::Text(format_name(name)),
+        RData::MX(ref mx) => DnsRecord::Mx {
+            preference: mx.preference(),
+            exchange: format_name(mx.exchange()),
+        },
+        RData::SRV(ref srv) => DnsRecord::Srv {
+            priority: srv.priority(),
+            weight: srv.weight(),
+            port: srv.port(),
+            target: format_name(srv.target()),
+        },
+        RData::TXT(ref txt) => DnsRecord::Text(txt.txt_data().iter()
+            .map(|x| String::from_utf8_lossy(x))
+            .collect()),
+        _ => return None,
+    };
+    Some(record)
+}
+
+fn record_type(x: &str) -> Result<RecordType> {
+    let x = match x.to_uppercase().as_str() {
+        "A" => RecordType::A,
+        "AAAA" => RecordType::AAAA,
+        "CNAME" => RecordType::CNAME,
+        "MX" => RecordType::MX,
+        "NS" => RecordType::NS,
+        "PTR" => RecordType::PTR,
+        "SRV" => RecordType::SRV,
+        "TXT" => RecordType::TXT,
+        _ => bail!("Unsupported record type: {:?}", x),
+    };
+    Ok(x)
+}
+
+// the name that is used for the PTR lookup of an ip address
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match *ip {
+        IpAddr::V4(ref ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa.", o[3], o[2], o[1], o[0])
+        },
+        IpAddr::V6(ref ip) => {
+            let mut name = String::new();
+            for b in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", b & 0xf, b >> 4));
+            }
+            name.push_str("ip6.arpa.");
+            name
+        },
+    }
+}
+
+// a name without records is not an error, an empty list is returned instead
+pub fn resolve(name: &str, rtype: &str) -> Result<Vec<DnsRecord>> {
+    let rtype = record_type(rtype)?;
+    let records = lookup((name.to_lowercase(), Some(rtype)))?;
+
+    Ok(records.iter()
+        .flat_map(transform)
+        .collect())
+}
+
+// the time until the name should be looked up again
+fn no_records(err: &ResolveError) -> Option<Instant> {
+    match *err.kind() {
+        ResolveErrorKind::NoRecordsFound { valid_until, .. } => {
+            Some(valid_until.unwrap_or_else(|| Instant::now() + NEGATIVE_TTL))
+        },
+        _ => None,
+    }
+}
+
+pub fn reverse(ip: &str) -> Result<Vec<String>> {
+    let ip = IpAddr::from_str(ip)
+                .context("Invalid ip address")?;
+
+    let records = resolve(&reverse_name(&ip), "PTR")?;
+    Ok(records.into_iter()
+        .flat_map(|x| match x {
+            DnsRecord::Text(x) => Some(x),
+            _ => None,
+        })
+        .collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_name() {
+        let ip = IpAddr::from_str("192.0.2.1").unwrap();
+        assert_eq!(reverse_name(&ip), "1.2.0.192.in-addr.arpa.");
+
+        let ip = IpAddr::from_str("2001:db8::1").unwrap();
+        assert_eq!(reverse_name(&ip), "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa.");
+    }
+
+    #[test]
+    fn test_record_type() {
+        assert_eq!(record_type("srv").unwrap(), RecordType::SRV);
+        assert!(record_type("AXFR").is_err());
+    }
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server("192.0.2.53").unwrap(), "192.0.2.53:53".parse().unwrap());
+        assert_eq!(parse_server("192.0.2.53:5353").unwrap(), "192.0.2.53:5353".parse().unwrap());
+        assert_eq!(parse_server("[2001:db8::53]:53").unwrap(), "[2001:db8::53]:53".parse().unwrap());
+        assert!(parse_server("ns1.example.com").is_err());
+    }
+
+    #[test]
+    fn test_lookup_ip_literal() {
+        assert_eq!(lookup_ip("192.0.2.1").unwrap(), vec![IpAddr::from_str("192.0.2.1").unwrap()]);
+        assert_eq!(socket_addrs("::1", 22).unwrap(), vec!["[::1]:22".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_cache() {
+        let key = ("cached.example.com".to_string(), None);
+        let ip = "192.0.2.7".parse().unwrap();
+        cache(key.clone(), vec![RData::A(ip)], Instant::now() + Duration::from_secs(60));
+        assert_eq!(lookup_ip("Cached.Example.com").unwrap(), vec![IpAddr::V4(ip)]);
+
+        let key = ("expired.example.com".to_string(), None);
+        cache(key.clone(), vec![RData::A(ip)], Instant::now());
+        assert_eq!(cached(&key), None);
+    }
+
+    #[test]
+    fn test_http_resolver() {
+        let key = ("http.example.com".to_string(), None);
+        let ip = "192.0.2.8".parse().unwrap();
+        cache(key, vec![RData::A(ip)], Instant::now() + Duration::from_secs(60));
+
+        let rt = ::tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let name = "http.example.com".parse::<Name>().unwrap();
+        let addrs = rt.block_on(HttpResolver.resolve(name)).unwrap();
+        assert_eq!(addrs.collect::<Vec<_>>(), vec![SocketAddr::new(IpAddr::V4(ip), 0)]);
+    }
+}
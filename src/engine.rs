@@ -0,0 +1,133 @@
+// the engine for tools that embed badtouch. A Runtime owns the config, the
+// scripts that are loaded with it and the scheduler that runs the attempts,
+// everything the cli sets up by hand.
+
+use errors::*;
+
+use config::Config;
+use ctx::Script;
+use scheduler::{Attempt, Msg, Scheduler};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use utils;
+
+
+pub struct Runtime {
+    config: Arc<Config>,
+    pool: Scheduler,
+    scripts: Vec<Arc<Script>>,
+}
+
+impl Runtime {
+    pub fn new(config: Config, workers: usize) -> Runtime {
+        Runtime {
+            config: Arc::new(config),
+            pool: Scheduler::new(workers),
+            scripts: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    // a script or a folder of scripts, paths that don't exist are looked up
+    // in the scripts folder of the config. Returns the number of scripts.
+    pub fn load(&mut self, path: &str) -> Result<usize> {
+        let scripts = utils::load_scripts(vec![path.to_string()], &self.config)?;
+        let num = scripts.len();
+        self.scripts.extend(scripts);
+        Ok(num)
+    }
+
+    pub fn load_from(&mut self, code: &[u8]) -> Result<()> {
+        let script = Script::load_from(code, self.config.clone())?;
+        self.scripts.push(Arc::new(script));
+        Ok(())
+    }
+
+    #[inline]
+    pub fn scripts(&self) -> &[Arc<Script>] {
+        &self.scripts
+    }
+
+    // the scheduler for everything the runtime doesn't wrap
+    #[inline]
+    pub fn scheduler(&mut self) -> &mut Scheduler {
+        &mut self.pool
+    }
+
+    // tries the credentials with every script
+    pub fn submit(&mut self, user: &str, password: &str) {
+        let user = Arc::new(user.to_string());
+        let password = Arc::new(password.to_string());
+        for script in &self.scripts {
+            self.pool.submit(Attempt::new(&user, &password, script));
+        }
+    }
+
+    // checks if the user exists with every script
+    pub fn submit_user(&mut self, user: &str) {
+        let user = Arc::new(user.to_string());
+        for script in &self.scripts {
+            self.pool.submit(Attempt::enumerate(&user, script));
+        }
+    }
+
+    // runs the submitted attempts and calls f with every result. Attempts of
+    // a user or target in quarantine run once the quarantine is over.
+    pub fn run<F: FnMut(&Attempt, &Result<bool>)>(&mut self, mut f: F) {
+        self.pool.resume();
+        loop {
+            self.pool.run_delayed();
+            if !self.pool.has_work() {
+                break;
+            }
+
+            if let Some(until) = self.pool.next_delayed() {
+                let now = Instant::now();
+                if until > now {
+                    thread::sleep(until - now);
+                }
+                continue;
+            }
+
+            match self.pool.recv() {
+                Msg::Attempt(attempt, result, _) => f(&attempt, &result),
+                Msg::Delayed(attempt, until) => self.pool.delay(*attempt, until),
+                _ => (),
+            }
+        }
+        self.pool.pause();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime() {
+        let mut runtime = Runtime::new(Config::default(), 2);
+        runtime.load_from(br#"
+        descr = "runtime"
+
+        function verify(user, password)
+            return password == "hunter2"
+        end
+        "#).unwrap();
+
+        runtime.submit("admin", "admin");
+        runtime.submit("admin", "hunter2");
+        let mut valid = Vec::new();
+        runtime.run(|attempt, result| {
+            if *result.as_ref().unwrap() {
+                valid.push(attempt.password().to_string());
+            }
+        });
+        assert_eq!(valid, vec!["hunter2"]);
+    }
+}
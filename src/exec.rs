@@ -0,0 +1,224 @@
+use errors::*;
+
+use hlua::{AnyLuaString, AnyLuaValue};
+use json::LuaJsonValue;
+use serde_json;
+use structs::LuaMap;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExecOptions {
+    // written to the stdin of the program, stdin is closed afterwards
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub cwd: Option<String>,
+    // kill the program after this many milliseconds
+    pub timeout: Option<u64>,
+}
+
+impl ExecOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<ExecOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(ExecOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+#[derive(Debug)]
+pub struct ExecOutput {
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Into<AnyLuaValue> for ExecOutput {
+    fn into(self: ExecOutput) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+        map.insert_num("status", f64::from(self.status));
+        map.insert("stdout", AnyLuaValue::LuaAnyString(AnyLuaString(self.stdout)));
+        map.insert("stderr", AnyLuaValue::LuaAnyString(AnyLuaString(self.stderr)));
+        map.into()
+    }
+}
+
+fn lua_type(x: &AnyLuaValue) -> &'static str {
+    match *x {
+        AnyLuaValue::LuaString(_) | AnyLuaValue::LuaAnyString(_) => "string",
+        AnyLuaValue::LuaNumber(_) => "number",
+        AnyLuaValue::LuaBoolean(_) => "boolean",
+        AnyLuaValue::LuaArray(_) => "table",
+        AnyLuaValue::LuaNil => "nil",
+        AnyLuaValue::LuaOther => "userdata",
+    }
+}
+
+// numbers are passed like lua would print them, everything else that isn't a
+// string is rejected instead of being dropped from the argument list
+pub fn args_from_lua(args: Vec<AnyLuaValue>) -> Result<Vec<String>> {
+    args.into_iter()
+        .enumerate()
+        .map(|(i, x)| match x {
+            AnyLuaValue::LuaString(x) => Ok(x),
+            AnyLuaValue::LuaAnyString(x) => String::from_utf8(x.0)
+                .map_err(|_| format_err!("Argument #{} is not valid utf8", i + 1)),
+            AnyLuaValue::LuaNumber(x) if x % 1.0 == 0.0 => Ok(format!("{}", x as i64)),
+            AnyLuaValue::LuaNumber(x) => Ok(format!("{}", x)),
+            x => bail!("Argument #{} has invalid type {}, expected string or number", i + 1, lua_type(&x)),
+        })
+        .collect()
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            // a failed read only truncates the output
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn wait(child: &mut Child, timeout: Option<u64>) -> Result<ExitStatus> {
+    let timeout = match timeout {
+        Some(timeout) => Duration::from_millis(timeout),
+        None => return Ok(child.wait()?),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            child.wait()?;
+            bail!("Process timed out");
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub fn exec(prog: &str, args: &[String], options: &ExecOptions) -> Result<ExecOutput> {
+    let mut cmd = Command::new(prog);
+    cmd.args(args)
+       .envs(&options.env)
+       .stdin(if options.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+       .stdout(Stdio::piped())
+       .stderr(Stdio::piped());
+
+    if let Some(ref cwd) = options.cwd {
+        cmd.current_dir(cwd);
+    }
+
+    let mut child = cmd.spawn()
+        .context("Failed to spawn program")?;
+
+    // the pipes are drained on their own threads so a chatty program can't
+    // block on a full pipe while we are still writing stdin or waiting
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let stdin = match (child.stdin.take(), options.stdin.clone()) {
+        (Some(mut pipe), Some(data)) => Some(thread::spawn(move || {
+            // the program may exit without reading all of stdin
+            let _ = pipe.write_all(data.as_bytes());
+        })),
+        _ => None,
+    };
+
+    let status = wait(&mut child, options.timeout)?;
+
+    if let Some(stdin) = stdin {
+        let _ = stdin.join();
+    }
+    let stdout = stdout.join()
+        .map_err(|_| format_err!("Failed to read stdout"))?;
+    let stderr = stderr.join()
+        .map_err(|_| format_err!("Failed to read stderr"))?;
+
+    let status = match status.code() {
+        Some(code) => code,
+        None => bail!("Process didn't return exit code"),
+    };
+
+    Ok(ExecOutput {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_from_lua() {
+        let args = args_from_lua(vec![
+            AnyLuaValue::LuaString("--port".to_string()),
+            AnyLuaValue::LuaNumber(3306.0),
+            AnyLuaValue::LuaNumber(1.5),
+        ]).unwrap();
+        assert_eq!(args, vec!["--port", "3306", "1.5"]);
+    }
+
+    #[test]
+    fn test_args_from_lua_invalid() {
+        let err = args_from_lua(vec![
+            AnyLuaValue::LuaString("--verbose".to_string()),
+            AnyLuaValue::LuaBoolean(true),
+        ]).unwrap_err();
+        assert_eq!(err.to_string(), "Argument #2 has invalid type boolean, expected string or number");
+    }
+
+    fn sh(script: &str, options: &ExecOptions) -> Result<ExecOutput> {
+        exec("sh", &["-c".to_string(), script.to_string()], options)
+    }
+
+    #[test]
+    fn test_exec_output() {
+        let out = sh("echo foo; echo bar >&2; exit 3", &ExecOptions::default()).unwrap();
+        assert_eq!(out.status, 3);
+        assert_eq!(out.stdout, b"foo\n");
+        assert_eq!(out.stderr, b"bar\n");
+    }
+
+    #[test]
+    fn test_exec_stdin_env_cwd() {
+        let mut env = HashMap::new();
+        env.insert("BADTOUCH_TEST".to_string(), "1337".to_string());
+        let out = sh("cat; echo $BADTOUCH_TEST; pwd", &ExecOptions {
+            stdin: Some("hello\n".to_string()),
+            env,
+            cwd: Some("/".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(out.status, 0);
+        assert_eq!(out.stdout, b"hello\n1337\n/\n");
+    }
+
+    #[test]
+    fn test_exec_timeout() {
+        let err = sh("sleep 5", &ExecOptions {
+            timeout: Some(100),
+            ..Default::default()
+        });
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// printf-style string formatting, a subset of lua's string.format
+//
+// %s  string, numbers and booleans are converted
+// %d  integer (also `%i`)
+// %x  lowercase hex, %X uppercase hex
+// %f  float, the precision defaults to 6
+// %q  quoted string
+// %%  a literal percent sign
+//
+// the `-` and `0` flags, a width and a precision (`%08.3f`) are supported.
+
+use errors::*;
+
+use hlua::AnyLuaValue;
+use std::str::Chars;
+use std::iter::Peekable;
+
+
+#[derive(Debug, Default, PartialEq)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, x: String) -> String {
+        let len = x.chars().count();
+        if len >= self.width {
+            return x;
+        }
+
+        let fill = self.width - len;
+        if self.left {
+            format!("{}{}", x, " ".repeat(fill))
+        } else if self.zero {
+            // keep the sign in front of the zeros
+            let (sign, digits) = if x.starts_with('-') {
+                x.split_at(1)
+            } else {
+                x.split_at(0)
+            };
+            format!("{}{}{}", sign, "0".repeat(fill), digits)
+        } else {
+            format!("{}{}", " ".repeat(fill), x)
+        }
+    }
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut num = None;
+    while let Some(c) = chars.peek().and_then(|c| c.to_digit(10)) {
+        num = Some(num.unwrap_or(0) * 10 + c as usize);
+        chars.next();
+    }
+    num
+}
+
+fn parse_spec(chars: &mut Peekable<Chars>) -> Spec {
+    let mut spec = Spec::default();
+
+    loop {
+        match chars.peek() {
+            Some('-') => spec.left = true,
+            Some('0') => spec.zero = true,
+            _ => break,
+        }
+        chars.next();
+    }
+
+    spec.width = parse_number(chars).unwrap_or(0);
+
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        spec.precision = Some(parse_number(chars).unwrap_or(0));
+    }
+
+    spec
+}
+
+fn to_string(x: &AnyLuaValue) -> Result<String> {
+    match *x {
+        AnyLuaValue::LuaString(ref x) => Ok(x.to_string()),
+        AnyLuaValue::LuaAnyString(ref x) => Ok(String::from_utf8_lossy(&x.0).to_string()),
+        AnyLuaValue::LuaNumber(x) if x % 1.0 == 0.0 => Ok(format!("{}", x as i64)),
+        AnyLuaValue::LuaNumber(x) => Ok(format!("{}", x)),
+        AnyLuaValue::LuaBoolean(x) => Ok(x.to_string()),
+        AnyLuaValue::LuaNil => Ok(String::from("nil")),
+        _ => bail!("can not format {:?} as string", x),
+    }
+}
+
+fn to_number(x: &AnyLuaValue) -> Result<f64> {
+    match *x {
+        AnyLuaValue::LuaNumber(x) => Ok(x),
+        AnyLuaValue::LuaString(ref s) => s.trim().parse()
+            .map_err(|_| format_err!("can not format {:?} as number", s)),
+        _ => bail!("can not format {:?} as number", x),
+    }
+}
+
+fn to_integer(x: &AnyLuaValue) -> Result<i64> {
+    let num = to_number(x)?;
+    if num % 1.0 != 0.0 {
+        bail!("number has no integer representation: {:?}", num);
+    }
+    Ok(num as i64)
+}
+
+pub fn format(fmt: &str, args: &[AnyLuaValue]) -> Result<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+
+        let spec = parse_spec(&mut chars);
+        let code = chars.next()
+                    .ok_or_else(|| format_err!("format string ends with incomplete specifier"))?;
+        let arg = args.next()
+                    .ok_or_else(|| format_err!("missing argument for %{}", code))?;
+
+        let x = match code {
+            's' => {
+                let x = to_string(arg)?;
+                match spec.precision {
+                    Some(p) => x.chars().take(p).collect(),
+                    None => x,
+                }
+            },
+            'd' | 'i' => to_integer(arg)?.to_string(),
+            'x' => format!("{:x}", to_integer(arg)?),
+            'X' => format!("{:X}", to_integer(arg)?),
+            'f' => format!("{:.*}", spec.precision.unwrap_or(6), to_number(arg)?),
+            'q' => format!("{:?}", to_string(arg)?),
+            _ => bail!("unsupported format specifier: %{}", code),
+        };
+        out.push_str(&spec.pad(x));
+    }
+
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(x: &str) -> AnyLuaValue {
+        AnyLuaValue::LuaString(x.to_string())
+    }
+
+    fn n(x: f64) -> AnyLuaValue {
+        AnyLuaValue::LuaNumber(x)
+    }
+
+    #[test]
+    fn test_format() {
+        let x = format("%s:%s@%s:%d", &[s("admin"), s("hunter2"), s("127.0.0.1"), n(22.0)]).unwrap();
+        assert_eq!(x, "admin:hunter2@127.0.0.1:22");
+    }
+
+    #[test]
+    fn test_format_numbers() {
+        let x = format("%05d|%-4d|%x|%X|%.2f|%f", &[n(42.0), n(7.0), n(255.0), n(255.0), n(3.14159), n(1.5)]).unwrap();
+        assert_eq!(x, "00042|7   |ff|FF|3.14|1.500000");
+    }
+
+    #[test]
+    fn test_format_string() {
+        let x = format("[%5s][%-5s][%.2s][%q][%s]", &[s("ab"), s("ab"), s("abc"), s("a\"b"), n(1.0)]).unwrap();
+        assert_eq!(x, "[   ab][ab   ][ab][\"a\\\"b\"][1]");
+    }
+
+    #[test]
+    fn test_format_percent() {
+        assert_eq!(format("100%%", &[]).unwrap(), "100%");
+    }
+
+    #[test]
+    fn test_format_negative_zero_pad() {
+        assert_eq!(format("%05d", &[n(-42.0)]).unwrap(), "-0042");
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert!(format("%d", &[]).is_err());
+        assert!(format("%d", &[n(1.5)]).is_err());
+        assert!(format("%z", &[n(1.0)]).is_err());
+        assert!(format("%", &[]).is_err());
+    }
+}
@@ -59,6 +59,92 @@ pub fn html_select_list(html: &str, selector: &str) -> Result<Vec<Element>> {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct Form {
+    action: Option<String>,
+    method: String,
+    fields: HashMap<String, String>,
+}
+
+impl Into<AnyLuaValue> for Form {
+    fn into(self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        if let Some(action) = self.action {
+            map.insert_str("action", action);
+        }
+        map.insert_str("method", self.method);
+        map.insert("fields", LuaMap::from(self.fields));
+
+        map.into()
+    }
+}
+
+// returns the value that would be submitted for this field, if any
+fn field_value(field: &kuchiki::NodeDataRef<kuchiki::ElementData>) -> Option<String> {
+    let attrs = field.attributes.borrow();
+
+    match &*field.name.local {
+        "input" => {
+            let kind = attrs.get("type").unwrap_or("text").to_lowercase();
+            match kind.as_str() {
+                "submit" | "button" | "reset" | "image" | "file" => None,
+                "checkbox" | "radio" if attrs.get("checked").is_none() => None,
+                "checkbox" | "radio" => Some(attrs.get("value").unwrap_or("on").to_string()),
+                _ => Some(attrs.get("value").unwrap_or("").to_string()),
+            }
+        },
+        "textarea" => Some(field.text_contents()),
+        "select" => {
+            let options = field.as_node().select("option").ok()?.collect::<Vec<_>>();
+            let option = options.iter()
+                            .find(|x| x.attributes.borrow().get("selected").is_some())
+                            .or_else(|| options.first())?;
+
+            let value = option.attributes.borrow().get("value").map(|x| x.to_string());
+            Some(value.unwrap_or_else(|| option.text_contents()))
+        },
+        _ => None,
+    }
+}
+
+pub fn html_form(html: &str, selector: &str) -> Result<Form> {
+    let doc = kuchiki::parse_html().one(html);
+    let form = match doc.select_first(selector) {
+        Ok(x) => x,
+        Err(_) => bail!("css selector failed"),
+    };
+
+    let (action, method) = {
+        let attrs = form.attributes.borrow();
+        let action = attrs.get("action").map(|x| x.to_string());
+        let method = attrs.get("method").unwrap_or("GET").to_uppercase();
+        (action, method)
+    };
+
+    let mut fields = HashMap::new();
+    let elements = match form.as_node().select("input, textarea, select") {
+        Ok(x) => x,
+        Err(_) => bail!("css selector failed"),
+    };
+    for field in elements {
+        let name = match field.attributes.borrow().get("name") {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+
+        if let Some(value) = field_value(&field) {
+            fields.insert(name, value);
+        }
+    }
+
+    Ok(Form {
+        action,
+        method,
+        fields,
+    })
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -85,4 +171,29 @@ mod tests {
             }
         ]);
     }
+
+    #[test]
+    fn test_html_form() {
+        let form = html_form(r#"<html><form action="/login" method="post">
+            <input type="hidden" name="csrf" value="abc123">
+            <input type="text" name="user">
+            <input type="checkbox" name="remember" checked>
+            <input type="checkbox" name="newsletter" value="yes">
+            <select name="lang"><option value="en">English</option><option value="de" selected>Deutsch</option></select>
+            <textarea name="comment">hello</textarea>
+            <input type="submit" name="login" value="Login">
+        </form></html>"#, "form").unwrap();
+
+        assert_eq!(form, Form {
+            action: Some("/login".into()),
+            method: "POST".into(),
+            fields: vec![
+                (String::from("csrf"), String::from("abc123")),
+                (String::from("user"), String::from("")),
+                (String::from("remember"), String::from("on")),
+                (String::from("lang"), String::from("de")),
+                (String::from("comment"), String::from("hello")),
+            ].into_iter().collect(),
+        });
+    }
 }
@@ -2,45 +2,239 @@ use errors::*;
 use structs::LuaMap;
 
 use reqwest;
-use reqwest::Method;
-use reqwest::header::{HeaderName, HeaderValue, COOKIE, SET_COOKIE, USER_AGENT};
-use hlua::AnyLuaValue;
+use reqwest::{Method, StatusCode};
+use reqwest::header::{HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, COOKIE, LOCATION, SET_COOKIE, USER_AGENT};
+use hlua::{AnyLuaValue, AnyLuaString};
 use serde_json;
 use json::LuaJsonValue;
-use std::collections::HashMap;
+use serde::de::{Deserialize, Deserializer};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use rand::{Rng, thread_rng};
 use rand::distributions::Alphanumeric;
 use config::Config;
 use ctx::State;
+use dns;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+
+
+// a default user agent of a browser is sent so requests don't stand out, the
+// first one is used unless user agents are rotated
+pub const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 Edg/124.0.0.0",
+];
+
+// sent with every request unless the script sets them
+const DEFAULT_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+const DEFAULT_ACCEPT_LANGUAGE: &str = "en-US,en;q=0.5";
+
+// a random user agent from the list, or from USER_AGENTS if it's empty
+pub fn random_user_agent(user_agents: &[String]) -> String {
+    let mut rng = thread_rng();
+    match rng.choose(user_agents) {
+        Some(agent) => agent.clone(),
+        None => rng.choose(USER_AGENTS).unwrap().to_string(),
+    }
+}
+
+// every client resolves with the dns cache and --dns-server
+pub fn client_builder() -> reqwest::blocking::ClientBuilder {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(dns::HttpResolver))
+        .into()
+}
+
+// clients are shared by all attempts, so connections to a host are kept alive
+// and tls handshakes are skipped. The key is everything the client is built
+// from, requests with different options get different clients.
+#[derive(Debug, Default)]
+pub struct ClientPool {
+    clients: Mutex<HashMap<String, reqwest::blocking::Client>>,
+}
 
+impl ClientPool {
+    pub fn get<F>(&self, key: &str, build: F) -> Result<reqwest::blocking::Client>
+        where
+            F: FnOnce() -> Result<reqwest::blocking::Client>,
+    {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(key) {
+            return Ok(client.clone());
+        }
+
+        let client = build()?;
+        clients.insert(key.to_string(), client.clone());
+        Ok(client)
+    }
 
-#[derive(Debug)]
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// the pool is a cache, it doesn't make two configs different
+impl PartialEq for ClientPool {
+    fn eq(&self, _other: &ClientPool) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct HttpSession {
     id: String,
     pub cookies: CookieJar,
+    options: SessionOptions,
+    user_agent: Option<String>,
 }
 
 impl HttpSession {
-    pub fn new() -> (String, HttpSession) {
+    pub fn new(options: SessionOptions, user_agents: &[String]) -> (String, HttpSession) {
         let id: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+        let user_agent = if options.rotate_user_agent {
+            Some(random_user_agent(user_agents))
+        } else {
+            None
+        };
         (id.clone(), HttpSession {
             id,
             cookies: CookieJar::default(),
+            options,
+            user_agent,
         })
     }
+
+    pub fn proxy(&self) -> Option<&String> {
+        self.options.proxy.as_ref()
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SessionOptions {
+    // defaults for every request of the session
+    headers: Option<Headers>,
+    user_agent: Option<String>,
+    proxy: Option<String>,
+    tls: Option<TlsOptions>,
+    timeout: Option<u64>,
+    // http/2 is negotiated with https servers unless a version is forced
+    http_version: Option<HttpVersion>,
+    // pick a random user agent for this session
+    #[serde(default)]
+    rotate_user_agent: bool,
+}
+
+impl SessionOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<SessionOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(SessionOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct RequestOptions {
     query: Option<HashMap<String, String>>,
-    headers: Option<HashMap<String, String>>,
+    headers: Option<Headers>,
     basic_auth: Option<(String, String)>,
     user_agent: Option<String>,
+    proxy: Option<String>,
+    tls: Option<TlsOptions>,
+    follow_redirects: Option<Redirects>,
+    timeout: Option<u64>,
+    http_version: Option<HttpVersion>,
     json: Option<serde_json::Value>,
     form: Option<serde_json::Value>,
-    body: Option<String>,
+    multipart: Option<HashMap<String, MultipartField>>,
+    body: Option<Bytes>,
+    binary: Option<bool>,
+}
+
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    H1,
+    H2,
+}
+
+// either `follow_redirects = true` or the maximum number of redirects
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Redirects {
+    Enabled(bool),
+    Limit(usize),
+}
+
+// native-tls can't require tls 1.3
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+}
+
+impl From<TlsVersion> for reqwest::tls::Version {
+    fn from(x: TlsVersion) -> reqwest::tls::Version {
+        match x {
+            TlsVersion::Tls10 => reqwest::tls::Version::TLS_1_0,
+            TlsVersion::Tls11 => reqwest::tls::Version::TLS_1_1,
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+        }
+    }
+}
+
+// unknown keys are rejected instead of being ignored
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsOptions {
+    verify: Option<bool>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    min_version: Option<TlsVersion>,
+}
+
+impl TlsOptions {
+    fn identity(&self) -> Result<Option<reqwest::Identity>> {
+        let (cert, key) = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => bail!("client_cert and client_key need to be set together"),
+        };
+
+        let cert = X509::from_pem(cert.as_bytes())
+                        .context("Invalid client_cert")?;
+        let key = PKey::private_key_from_pem(key.as_bytes())
+                        .context("Invalid client_key")?;
+
+        // native-tls only accepts pkcs12, so we have to bundle cert and key first
+        let der = Pkcs12::builder()
+                    .build("", "", &key, &cert)?
+                    .to_der()?;
+        let identity = reqwest::Identity::from_pkcs12_der(&der, "")?;
+
+        Ok(Some(identity))
+    }
 }
 
 impl RequestOptions {
@@ -59,17 +253,50 @@ pub struct HttpRequest {
     method: String,
     url: String,
     query: Option<HashMap<String, String>>,
-    headers: Option<HashMap<String, String>>,
+    headers: Option<Headers>,
     basic_auth: Option<(String, String)>,
     user_agent: Option<String>,
+    proxy: Option<String>,
+    tls: Option<TlsOptions>,
+    follow_redirects: Option<Redirects>,
+    timeout: Option<u64>,
+    http_version: Option<HttpVersion>,
+    #[serde(default)]
+    binary: bool,
     body: Option<Body>,
 }
 
 impl HttpRequest {
-    pub fn new(config: &Arc<Config>, session: &HttpSession, method: String, url: String, options: RequestOptions) -> HttpRequest {
+    pub fn new(config: &Arc<Config>, session: &HttpSession, default_proxy: Option<&String>, default_user_agent: Option<&String>, method: String, url: String, options: RequestOptions) -> HttpRequest {
         let cookies = session.cookies.clone();
 
-        let user_agent = options.user_agent.or_else(|| config.runtime.user_agent.clone());
+        let user_agent = options.user_agent
+                        .or_else(|| session.options.user_agent.clone())
+                        .or_else(|| session.user_agent.clone())
+                        .or_else(|| default_user_agent.cloned())
+                        .or_else(|| config.runtime.user_agent.clone())
+                        .unwrap_or_else(|| USER_AGENTS[0].to_string());
+        let proxy = options.proxy
+                        .or_else(|| session.options.proxy.clone())
+                        .or_else(|| default_proxy.cloned());
+        let tls = options.tls.or_else(|| session.options.tls.clone());
+        // a request doesn't take longer than --script-timeout
+        let max = config.runtime.script_timeout.map(|x| x.as_secs() * 1000 + u64::from(x.subsec_millis()));
+        let timeout = match (options.timeout.or(session.options.timeout), max) {
+            (Some(timeout), Some(max)) => Some(cmp::min(timeout, max)),
+            (timeout, max) => timeout.or(max),
+        };
+
+        // request headers replace session headers with the same name
+        let headers = match (&session.options.headers, options.headers) {
+            (Some(defaults), Some(headers)) => {
+                let mut defaults = defaults.clone();
+                defaults.merge(headers);
+                Some(defaults)
+            },
+            (defaults, headers) => headers.or_else(|| defaults.clone()),
+        };
+        let http_version = options.http_version.or(session.options.http_version);
 
         let mut request = HttpRequest {
             session: session.id.clone(),
@@ -77,9 +304,15 @@ impl HttpRequest {
             method,
             url,
             query: options.query,
-            headers: options.headers,
+            headers,
             basic_auth: options.basic_auth,
-            user_agent,
+            user_agent: Some(user_agent),
+            proxy,
+            tls,
+            follow_redirects: options.follow_redirects,
+            timeout,
+            http_version,
+            binary: options.binary.unwrap_or(false),
             body: None,
         };
 
@@ -91,8 +324,12 @@ impl HttpRequest {
             request.body = Some(Body::Form(form));
         }
 
-        if let Some(text) = options.body {
-            request.body = Some(Body::Raw(text));
+        if let Some(multipart) = options.multipart {
+            request.body = Some(Body::Multipart(multipart));
+        }
+
+        if let Some(bytes) = options.body {
+            request.body = Some(Body::Raw(bytes));
         }
 
         request
@@ -101,58 +338,68 @@ impl HttpRequest {
     pub fn send(&self, state: &State) -> Result<LuaMap> {
         debug!("http send: {:?}", self);
 
-        let client = reqwest::Client::builder()
-            .redirect(reqwest::RedirectPolicy::none()) // TODO: this should be configurable
-            .build().unwrap();
-        let method = self.method.parse::<Method>()
+        // clients are shared between attempts, see ClientPool
+        let key = serde_json::to_string(&(&self.proxy, &self.tls, &self.timeout, &self.http_version))?;
+        let client = state.http_client(&key, || self.build_client())?;
+
+        let mut method = self.method.parse::<Method>()
                         .context("Invalid http method")?;
-        let mut req = client.request(method, &self.url);
+        let mut url = self.url.clone();
+        let mut body = self.body.as_ref();
+        let mut cookies = self.cookies.clone();
+        let mut redirects = Vec::new();
+
+        let max_redirects = match self.follow_redirects {
+            None | Some(Redirects::Enabled(false)) => 0,
+            Some(Redirects::Enabled(true)) => DEFAULT_MAX_REDIRECTS,
+            Some(Redirects::Limit(n)) => n,
+        };
 
-        if let Some(cookies) = self.cookies.assemble_cookie_header() {
-            debug!("Adding cookies to request: {:?}", cookies);
-            req = req.header(COOKIE, HeaderValue::from_str(&cookies)?);
-        }
+        let mut res = loop {
+            let req = self.build_request(&client, method.clone(), &url, &cookies, body, redirects.is_empty())?;
 
-        if let Some(ref agent) = self.user_agent {
-            req = req.header(USER_AGENT, agent.as_str());
-        }
+            info!("http req: {:?}", req);
+            let res = match req.send() {
+                Ok(res) => res,
+                Err(ref err) if err.is_timeout() => bail!("http request timed out"),
+                Err(err) => return Err(err.into()),
+            };
+            info!("http res: {:?}", res);
 
-        if let Some(ref auth) = self.basic_auth {
-            let &(ref user, ref password) = auth;
-            req = req.basic_auth(user, Some(password));
-        }
+            let jar = HttpRequest::parse_cookies(&res.headers().get_all(SET_COOKIE))
+                        .context("Failed to process http response cookies")?;
+            cookies.register_in_jar(jar.clone());
+            state.register_in_jar(&self.session, jar);
 
-        if let Some(ref headers) = self.headers {
-            for (k, v) in headers {
-                let k = HeaderName::from_bytes(k.as_bytes())?;
-                req = req.header(k, HeaderValue::from_str(v)?);
+            if !res.status().is_redirection() || redirects.len() >= max_redirects {
+                break res;
             }
-        }
 
-        if let Some(ref query) = self.query {
-            req = req.query(query);
-        }
+            let next = match res.headers().get(LOCATION) {
+                Some(location) => res.url().join(location.to_str()?)
+                                    .context("Invalid redirect location")?,
+                None => break res,
+            };
 
-        req = match self.body {
-            Some(Body::Raw(ref x))  => { req.body(x.clone()) },
-            Some(Body::Form(ref x)) => { req.form(x) },
-            Some(Body::Json(ref x)) => { req.json(x) },
-            None => req,
-        };
+            let status = res.status();
+            if status != StatusCode::TEMPORARY_REDIRECT && status != StatusCode::PERMANENT_REDIRECT && method != Method::HEAD {
+                method = Method::GET;
+                body = None;
+            }
 
-        info!("http req: {:?}", req);
-        let mut res = req.send()?;
-        info!("http res: {:?}", res);
+            debug!("following redirect to {:?}", next);
+            redirects.push(AnyLuaValue::LuaString(res.url().to_string()));
+            url = next.into();
+        };
 
         let mut resp = LuaMap::new();
         let status = res.status();
         resp.insert_num("status", f64::from(status.as_u16()));
-
-        {
-            let cookies = res.headers().get_all(SET_COOKIE);
-            HttpRequest::register_cookies_on_state(&self.session, state, &cookies)
-                .context("Failed to process http response cookies")?;
-        }
+        resp.insert_str("url", res.url().as_str());
+        resp.insert("redirects", AnyLuaValue::LuaArray(redirects.into_iter()
+            .enumerate()
+            .map(|(i, x)| (AnyLuaValue::LuaNumber((i + 1) as f64), x))
+            .collect()));
 
         let mut headers = LuaMap::new();
         for (name, value) in res.headers().iter() {
@@ -160,14 +407,112 @@ impl HttpRequest {
         }
         resp.insert("headers", headers);
 
-        if let Ok(text) = res.text() {
+        if self.binary {
+            let mut body = Vec::new();
+            res.copy_to(&mut body)?;
+            resp.insert("body", AnyLuaValue::LuaAnyString(AnyLuaString(body)));
+        } else if let Ok(text) = res.text() {
             resp.insert_str("text", text);
         }
 
         Ok(resp)
     }
 
-    fn register_cookies_on_state(session: &str, state: &State, cookies: &reqwest::header::GetAll<HeaderValue>) -> Result<()> {
+    fn build_client(&self) -> Result<reqwest::blocking::Client> {
+        // redirects are followed manually so we see the cookies of every hop
+        let mut client = client_builder()
+            .redirect(reqwest::redirect::Policy::none());
+
+        if let Some(ref proxy) = self.proxy {
+            let proxy = reqwest::Proxy::all(proxy.as_str())
+                            .context("Invalid proxy")?;
+            client = client.proxy(proxy);
+        }
+
+        if let Some(timeout) = self.timeout {
+            let timeout = Duration::from_millis(timeout);
+            client = client.timeout(timeout)
+                           .connect_timeout(timeout);
+        }
+
+        if let Some(ref tls) = self.tls {
+            if tls.verify == Some(false) {
+                client = client.danger_accept_invalid_certs(true)
+                               .danger_accept_invalid_hostnames(true);
+            }
+
+            if let Some(identity) = tls.identity()? {
+                client = client.identity(identity);
+            }
+
+            if let Some(version) = tls.min_version {
+                client = client.min_tls_version(version.into());
+            }
+        }
+
+        // without a version, https servers are offered h2 and http/1.1 with
+        // alpn, plain http uses http/1.1
+        match self.http_version {
+            Some(HttpVersion::H1) => client = client.http1_only(),
+            Some(HttpVersion::H2) => client = client.http2_prior_knowledge(),
+            None => (),
+        }
+
+        Ok(client.build()?)
+    }
+
+    fn build_request(&self, client: &reqwest::blocking::Client, method: Method, url: &str, cookies: &CookieJar, body: Option<&Body>, first: bool) -> Result<reqwest::blocking::RequestBuilder> {
+        let mut req = client.request(method, url);
+
+        if let Some(cookies) = cookies.assemble_cookie_header() {
+            debug!("Adding cookies to request: {:?}", cookies);
+            req = req.header(COOKIE, HeaderValue::from_str(&cookies)?);
+        }
+
+        // headers are sent in the order the script set them, the defaults
+        // that it didn't set are sent after them
+        let headers = self.headers.clone().unwrap_or_default();
+        for (k, v) in headers.iter() {
+            let k = HeaderName::from_bytes(k.as_bytes())?;
+            req = req.header(k, HeaderValue::from_str(v)?);
+        }
+
+        if let Some(ref agent) = self.user_agent {
+            if !headers.contains("user-agent") {
+                req = req.header(USER_AGENT, agent.as_str());
+            }
+        }
+        if !headers.contains("accept") {
+            req = req.header(ACCEPT, DEFAULT_ACCEPT);
+        }
+        if !headers.contains("accept-language") {
+            req = req.header(ACCEPT_LANGUAGE, DEFAULT_ACCEPT_LANGUAGE);
+        }
+
+        if let Some(ref auth) = self.basic_auth {
+            let &(ref user, ref password) = auth;
+            req = req.basic_auth(user, Some(password));
+        }
+
+        // the redirect location already contains the query
+        if first {
+            if let Some(ref query) = self.query {
+                req = req.query(query);
+            }
+        }
+
+        req = match body {
+            Some(&Body::Raw(ref x))  => { req.body(x.clone().into_bytes()) },
+            Some(&Body::Form(ref x)) => { req.form(x) },
+            Some(&Body::Json(ref x)) => { req.json(x) },
+            Some(&Body::Multipart(ref x)) => { req.multipart(multipart_form(x)?) },
+            None => req,
+        };
+
+        Ok(req)
+    }
+
+    fn parse_cookies(cookies: &reqwest::header::GetAll<HeaderValue>) -> Result<Vec<(String, String)>> {
         let mut jar = Vec::new();
 
         for cookie in cookies.iter() {
@@ -189,9 +534,7 @@ impl HttpRequest {
             jar.push((key, value));
         }
 
-        state.register_in_jar(session, jar);
-
-        Ok(())
+        Ok(jar)
     }
 }
 
@@ -210,6 +553,48 @@ impl Into<AnyLuaValue> for HttpRequest {
     }
 }
 
+// a list of {name, value} pairs keeps the order of the headers, the keys of a
+// table are sorted
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Headers(Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for Headers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Headers, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            List(Vec<(String, String)>),
+            Map(BTreeMap<String, String>),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::List(x) => Ok(Headers(x)),
+            Raw::Map(x) => Ok(Headers(x.into_iter().collect())),
+        }
+    }
+}
+
+impl Headers {
+    // header names are case insensitive
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(k, _)| k.eq_ignore_ascii_case(name))
+    }
+
+    // replaces headers with the same name in place, new headers are appended
+    pub fn merge(&mut self, other: Headers) {
+        for (name, value) in other.0 {
+            match self.0.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&name)) {
+                Some(header) => *header = (name, value),
+                None => self.0.push((name, value)),
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&(String, String)> {
+        self.0.iter()
+    }
+}
+
 // see https://github.com/seanmonstar/reqwest/issues/14 for proper cookie jars
 // maybe change this to reqwest::header::Cookie
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -265,7 +650,201 @@ impl Deref for CookieJar {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Body {
-    Raw(String), // TODO: maybe Vec<u8>
+    Raw(Bytes),
     Form(serde_json::Value),
     Json(serde_json::Value),
+    Multipart(HashMap<String, MultipartField>),
+}
+
+// lua strings that aren't valid utf8 are passed to us as a list of bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Bytes {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Bytes {
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Bytes::Text(x) => x.into_bytes(),
+            Bytes::Binary(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MultipartField {
+    Text(String),
+    File {
+        filename: Option<String>,
+        content_type: Option<String>,
+        bytes: Bytes,
+    },
+}
+
+fn multipart_form(fields: &HashMap<String, MultipartField>) -> Result<reqwest::blocking::multipart::Form> {
+    let mut form = reqwest::blocking::multipart::Form::new();
+
+    for (name, field) in fields {
+        form = match *field {
+            MultipartField::Text(ref text) => form.text(name.clone(), text.clone()),
+            MultipartField::File { ref filename, ref content_type, ref bytes } => {
+                let mut part = reqwest::blocking::multipart::Part::bytes(bytes.clone().into_bytes());
+                if let Some(ref filename) = *filename {
+                    part = part.file_name(filename.clone());
+                }
+                if let Some(ref content_type) = *content_type {
+                    part = part.mime_str(content_type)
+                                .context("Invalid content_type")?;
+                }
+                form.part(name.clone(), part)
+            },
+        };
+    }
+
+    Ok(form)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(req: &HttpRequest) -> reqwest::header::HeaderMap {
+        let client = reqwest::blocking::Client::new();
+        req.build_request(&client, Method::GET, "http://127.0.0.1/", &CookieJar::default(), None, true)
+            .unwrap()
+            .build()
+            .unwrap()
+            .headers()
+            .clone()
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let config = Arc::new(Config::default());
+        let (_, session) = HttpSession::new(SessionOptions::default(), &[]);
+        let req = HttpRequest::new(&config, &session, None, None, "GET".to_string(), "http://127.0.0.1/".to_string(), RequestOptions::default());
+
+        let headers = headers(&req);
+        assert_eq!(headers.get(USER_AGENT).unwrap(), USER_AGENTS[0]);
+        assert_eq!(headers.get(ACCEPT).unwrap(), DEFAULT_ACCEPT);
+        assert_eq!(headers.get(ACCEPT_LANGUAGE).unwrap(), DEFAULT_ACCEPT_LANGUAGE);
+    }
+
+    #[test]
+    fn test_script_headers() {
+        let config = Arc::new(Config::default());
+        let (_, session) = HttpSession::new(SessionOptions::default(), &[]);
+        let mut options = RequestOptions::default();
+        options.headers = Some(Headers(vec![("Accept".to_string(), "application/json".to_string())]));
+        let agent = "curl/7.64.0".to_string();
+        let req = HttpRequest::new(&config, &session, None, Some(&agent), "GET".to_string(), "http://127.0.0.1/".to_string(), options);
+
+        let headers = headers(&req);
+        assert_eq!(headers.get(USER_AGENT).unwrap(), "curl/7.64.0");
+        assert_eq!(headers.get_all(ACCEPT).iter().collect::<Vec<_>>(), vec!["application/json"]);
+    }
+
+    #[test]
+    fn test_rotate_user_agent() {
+        let options = SessionOptions {
+            rotate_user_agent: true,
+            ..Default::default()
+        };
+        let agents = vec!["a".to_string(), "b".to_string()];
+        let (_, session) = HttpSession::new(options, &agents);
+        assert!(agents.contains(session.user_agent.as_ref().unwrap()));
+
+        assert!(USER_AGENTS.contains(&random_user_agent(&[]).as_str()));
+    }
+
+    #[test]
+    fn test_session_defaults() {
+        let config = Arc::new(Config::default());
+        let options = SessionOptions::try_from(LuaJsonValue::from(json!({
+            "headers": [["X-Requested-With", "XMLHttpRequest"], ["Accept", "*/*"], ["DNT", "1"]],
+            "user_agent": "badtouch",
+        })).into()).unwrap();
+        let (_, session) = HttpSession::new(options, &[]);
+
+        let options = RequestOptions::try_from(LuaJsonValue::from(json!({
+            "headers": {"accept": "application/json", "Origin": "http://127.0.0.1"},
+        })).into()).unwrap();
+        let req = HttpRequest::new(&config, &session, None, None, "GET".to_string(), "http://127.0.0.1/".to_string(), options);
+
+        let headers = headers(&req);
+        let names = headers.keys().map(|x| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(&names[..5], &["x-requested-with", "accept", "dnt", "origin", "user-agent"]);
+        assert_eq!(headers.get(ACCEPT).unwrap(), "application/json");
+        assert_eq!(headers.get(USER_AGENT).unwrap(), "badtouch");
+    }
+
+    #[test]
+    fn test_tls_options() {
+        let options = RequestOptions::try_from(LuaJsonValue::from(json!({
+            "tls": {"verify": false},
+        })).into()).unwrap();
+        assert_eq!(options.tls.unwrap().verify, Some(false));
+
+        let options = RequestOptions::try_from(LuaJsonValue::from(json!({
+            "tls": {"min_version": "tls12"},
+        })).into()).unwrap();
+        let tls = options.tls.unwrap();
+        assert_eq!(tls.min_version, Some(TlsVersion::Tls12));
+
+        let config = Arc::new(Config::default());
+        let (_, session) = HttpSession::new(SessionOptions::default(), &[]);
+        let mut options = RequestOptions::default();
+        options.tls = Some(tls);
+        let req = HttpRequest::new(&config, &session, None, None, "GET".to_string(), "https://127.0.0.1/".to_string(), options);
+        assert!(req.build_client().is_ok());
+
+        for tls in &[json!({"min_version": "ssl3"}), json!({"max_version": "tls12"})] {
+            let err = RequestOptions::try_from(LuaJsonValue::from(json!({
+                "tls": tls,
+            })).into());
+            assert!(err.is_err());
+        }
+    }
+
+    #[test]
+    fn test_http_version() {
+        let options = SessionOptions::try_from(LuaJsonValue::from(json!({
+            "http_version": "h2",
+        })).into()).unwrap();
+        assert_eq!(options.http_version, Some(HttpVersion::H2));
+
+        let err = RequestOptions::try_from(LuaJsonValue::from(json!({
+            "http_version": "h3",
+        })).into());
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_headers_roundtrip() {
+        let headers = Headers(vec![
+            ("b".to_string(), "1".to_string()),
+            ("a".to_string(), "2".to_string()),
+        ]);
+        let value = serde_json::to_value(&headers).unwrap();
+        assert_eq!(serde_json::from_value::<Headers>(value).unwrap(), headers);
+    }
+
+    #[test]
+    fn test_client_pool() {
+        let pool = ClientPool::default();
+        let mut built = 0;
+        for _ in 0..3 {
+            pool.get("a", || {
+                built += 1;
+                Ok(reqwest::blocking::Client::new())
+            }).unwrap();
+        }
+        pool.get("b", || Ok(reqwest::blocking::Client::new())).unwrap();
+        assert_eq!(built, 1);
+        assert_eq!(pool.len(), 2);
+    }
 }
@@ -35,6 +35,8 @@ impl Keyboard {
                 Ok(43)  => return Key::Plus,
                 Ok(45)  => return Key::Minus,
                 Ok(104) => return Key::H,
+                Ok(115) => return Key::S,
+                Ok(110) => return Key::N,
                 _ => (),
             }
         }
@@ -57,6 +59,8 @@ pub enum Key {
     H,
     P,
     R,
+    S,
+    N,
     Plus,
     Minus,
 }
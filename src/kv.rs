@@ -0,0 +1,107 @@
+// key-value store that is shared by all workers, eg. to cache csrf tokens
+//
+// if a path is set the store is loaded from this file on startup and
+// written back after every change, so it survives restarts.
+
+use errors::*;
+
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Mutex;
+
+
+#[derive(Debug, Default)]
+pub struct Store {
+    map: Mutex<HashMap<String, Value>>,
+    path: Option<PathBuf>,
+}
+
+impl Store {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Store> {
+        let path = path.into();
+
+        let map = if path.exists() {
+            let file = File::open(&path)?;
+            serde_json::from_reader(file)
+                .context("Failed to parse kv file")?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Store {
+            map: Mutex::new(map),
+            path: Some(path),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let map = self.map.lock().unwrap();
+        map.get(key).cloned()
+    }
+
+    // setting a key to null removes it
+    pub fn set(&self, key: &str, value: Value) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+
+        if value.is_null() {
+            map.remove(key);
+        } else {
+            map.insert(key.to_string(), value);
+        }
+
+        if let Some(ref path) = self.path {
+            // keep holding the lock so writes don't interleave
+            let tmp = path.with_extension("tmp");
+            let file = File::create(&tmp)?;
+            serde_json::to_writer(file, &*map)?;
+            fs::rename(&tmp, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for Store {
+    fn eq(&self, other: &Store) -> bool {
+        if ptr::eq(self, other) {
+            return true;
+        }
+
+        self.path == other.path &&
+            *self.map.lock().unwrap() == *other.map.lock().unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_get_set() {
+        let store = Store::default();
+        assert_eq!(store.get("foo"), None);
+        store.set("foo", json!("bar")).unwrap();
+        assert_eq!(store.get("foo"), Some(json!("bar")));
+        store.set("foo", Value::Null).unwrap();
+        assert_eq!(store.get("foo"), None);
+    }
+
+    #[test]
+    fn test_persist() {
+        let path = env::temp_dir().join(format!("badtouch-kv-{}.json", process::id()));
+
+        let store = Store::open(&path).unwrap();
+        store.set("token", json!({"csrf": "abc", "n": 3})).unwrap();
+
+        let store = Store::open(&path).unwrap();
+        assert_eq!(store.get("token"), Some(json!({"csrf": "abc", "n": 3})));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,13 +1,111 @@
+//! badtouch is a scriptable network authentication cracker, the engine can be
+//! embedded in other tools without shelling out to the cli.
+//!
+//! A [`Runtime`] wraps the config, the scripts and the scheduler:
+//!
+//! ```
+//! extern crate badtouch;
+//!
+//! use badtouch::{Config, Runtime};
+//!
+//! let mut runtime = Runtime::new(Config::default(), 4);
+//! runtime.load_from(br#"
+//! descr = "example"
+//! function verify(user, password)
+//!     return password == "hunter2"
+//! end
+//! "#).unwrap();
+//!
+//! for password in &["admin", "hunter2", "password"] {
+//!     runtime.submit("admin", password);
+//! }
+//!
+//! let mut valid = Vec::new();
+//! runtime.run(|attempt, result| {
+//!     if let Ok(true) = *result {
+//!         valid.push(attempt.password().to_string());
+//!     }
+//! });
+//! assert_eq!(valid, vec!["hunter2"]);
+//! ```
+//!
+//! The parts of the runtime can also be used on their own.
+//! Scripts are loaded into a [`Script`] with a [`Config`], the runtime of the
+//! scripts is configured with [`RuntimeConfig`]. A script can be run directly:
+//!
+//! ```
+//! extern crate badtouch;
+//!
+//! use badtouch::{Config, Script};
+//! use std::sync::Arc;
+//!
+//! let code = r#"
+//! descr = "example"
+//! function verify(user, password)
+//!     return password == "hunter2"
+//! end
+//! "#;
+//!
+//! let config = Arc::new(Config::default());
+//! let script = Script::load_from(code.as_bytes(), config).unwrap();
+//! assert!(script.run_creds("admin", "hunter2").unwrap());
+//! assert!(!script.run_creds("admin", "admin").unwrap());
+//! ```
+//!
+//! The [`Scheduler`] runs [`Attempt`]s on a threadpool, the results are
+//! received as [`Msg`]s. The workers start paused until `resume` is called:
+//!
+//! ```
+//! extern crate badtouch;
+//!
+//! use badtouch::{Attempt, Config, Msg, Scheduler, Script};
+//! use std::sync::Arc;
+//!
+//! let code = r#"
+//! descr = "example"
+//! function verify(user, password)
+//!     return password == "hunter2"
+//! end
+//! "#;
+//! let script = Arc::new(Script::load_from(code.as_bytes(), Arc::new(Config::default())).unwrap());
+//!
+//! let mut pool = Scheduler::new(4);
+//! let user = Arc::new("admin".to_string());
+//! for password in &["admin", "hunter2", "password"] {
+//!     pool.submit(Attempt::new(&user, &Arc::new(password.to_string()), &script));
+//! }
+//! pool.resume();
+//!
+//! let mut valid = Vec::new();
+//! while pool.has_work() {
+//!     if let Msg::Attempt(attempt, result, _latency) = pool.recv() {
+//!         if result.unwrap() {
+//!             valid.push(attempt.password().to_string());
+//!         }
+//!     }
+//! }
+//! assert_eq!(valid, vec!["hunter2"]);
+//! ```
+//!
+//! [`Runtime`]: engine/struct.Runtime.html
+//! [`Script`]: ctx/struct.Script.html
+//! [`Config`]: config/struct.Config.html
+//! [`RuntimeConfig`]: config/struct.RuntimeConfig.html
+//! [`Scheduler`]: scheduler/struct.Scheduler.html
+//! [`Attempt`]: scheduler/struct.Attempt.html
+//! [`Msg`]: scheduler/enum.Msg.html
+
 #![warn(unused_extern_crates)]
 extern crate hlua_badtouch as hlua;
+extern crate env_logger;
 extern crate pbr;
 extern crate threadpool;
 extern crate colored;
 extern crate time;
+extern crate humantime;
 extern crate atty;
 extern crate rand;
 extern crate getch;
-extern crate serde_json;
 extern crate kuchiki;
 extern crate toml;
 extern crate nix;
@@ -15,44 +113,108 @@ extern crate libc;
 extern crate bufstream;
 extern crate regex;
 extern crate dirs;
+extern crate openssl;
+extern crate socks;
+extern crate tungstenite;
+extern crate trust_dns_resolver;
+extern crate sxd_document;
+extern crate sxd_xpath;
+extern crate flate2;
+extern crate serde;
+#[macro_use] extern crate rusqlite;
 #[macro_use] extern crate log;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate failure;
 #[macro_use] extern crate structopt;
+#[macro_use] extern crate serde_json;
 
+extern crate md4;
 extern crate md5;
 extern crate sha1;
 extern crate sha2;
 extern crate sha3;
+extern crate blake2;
+extern crate crc32fast;
 extern crate digest;
 extern crate hmac;
 extern crate base64;
+extern crate base32;
 extern crate bcrypt;
+extern crate argon2;
+extern crate des;
+extern crate pwhash as crypt;
 
 #[cfg(not(windows))]
 extern crate termios;
 
 extern crate reqwest;
+extern crate hyper;
+extern crate tokio;
 extern crate mysql;
 extern crate ldap3;
 extern crate twox_hash;
 
 pub mod args;
 pub mod config;
+pub(crate) mod crypto;
 pub mod ctx;
-pub mod db;
+pub mod daemon;
+pub(crate) mod db;
+pub mod defaults;
+pub mod descriptor;
+pub mod distributed;
+pub mod dns;
+pub mod engine;
+pub(crate) mod exec;
+pub(crate) mod format;
 pub mod fsck;
-pub mod html;
-pub mod http;
-pub mod json;
+pub(crate) mod html;
+pub(crate) mod http;
+pub(crate) mod json;
+pub(crate) mod jwt;
 pub mod keyboard;
+pub mod kv;
+pub mod library;
+pub(crate) mod limits;
+pub mod logfile;
+pub mod mask;
+pub mod metrics;
+pub mod mock;
+pub mod names;
+pub mod notify;
+pub(crate) mod ntlm;
+pub(crate) mod oauth;
+pub(crate) mod otp;
+pub(crate) mod pack;
 pub mod pb;
-pub mod runtime;
+pub(crate) mod proxy;
+pub(crate) mod pwhash;
+pub mod repl;
+pub mod report;
+pub mod results;
+pub mod retry;
+pub mod rules;
+pub(crate) mod runtime;
+pub(crate) mod saml;
+pub mod sandbox;
 pub mod scheduler;
-pub mod sockets;
-pub mod structs;
+pub mod session;
+pub mod shutdown;
+pub mod status;
+pub(crate) mod sockets;
+pub(crate) mod structs;
 pub mod ulimit;
+pub(crate) mod url;
 pub mod utils;
+pub(crate) mod websocket;
+pub mod wordlist;
+pub(crate) mod x509;
+pub(crate) mod xml;
+
+pub use config::{Config, RuntimeConfig};
+pub use ctx::Script;
+pub use engine::Runtime;
+pub use scheduler::{Attempt, Msg, Scheduler};
 
 
 pub mod errors {
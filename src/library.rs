@@ -0,0 +1,296 @@
+// script library under ~/.config/badtouch/scripts, see `badtouch scripts`
+//
+// every repository is cloned into a folder of its own and the commit that is
+// checked out is recorded in library.json. Before a repository is updated
+// it's verified that it's still on that commit and wasn't modified. With
+// --pin a repository stays on a commit, with --signed the commit that is
+// checked out has to have a valid signature (git verify-commit).
+
+use errors::*;
+
+use colored::Colorize;
+use dirs;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+
+pub const MANIFEST: &str = "library.json";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Library {
+    pub repos: BTreeMap<String, Repo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Repo {
+    pub url: String,
+    pub commit: String,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub signed: bool,
+}
+
+impl Library {
+    pub fn load(dir: &Path) -> Result<Library> {
+        let path = dir.join(MANIFEST);
+        if !path.exists() {
+            return Ok(Library::default());
+        }
+
+        let file = File::open(&path)?;
+        let library = serde_json::from_reader(file)
+            .context(format!("Invalid script library {:?}", path))?;
+        Ok(library)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let file = File::create(dir.join(MANIFEST))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+pub fn dir() -> Result<PathBuf> {
+    let home = dirs::home_dir()
+                    .ok_or_else(|| format_err!("home folder not found"))?;
+    Ok(home.join(".config/badtouch/scripts"))
+}
+
+// a git url or owner/repo on github, returns the name and the url
+pub fn resolve(source: &str) -> Result<(String, String)> {
+    // git would read it as an option
+    if source.starts_with('-') {
+        bail!("expected a git url or owner/repo: {:?}", source);
+    }
+    let is_url = source.contains("://") || source.starts_with("git@") || Path::new(source).exists();
+
+    let url = if is_url {
+        source.to_string()
+    } else {
+        let parts = source.split('/').collect::<Vec<_>>();
+        if parts.len() != 2 || parts.iter().any(|x| !is_valid_name(x)) {
+            bail!("expected a git url or owner/repo: {:?}", source);
+        }
+        format!("https://github.com/{}.git", source)
+    };
+
+    let name = url.trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|x| x.trim_end_matches(".git"))
+        .unwrap_or("");
+    if !is_valid_name(name) {
+        bail!("can't use {:?} as a name, set one with --name", name);
+    }
+
+    Ok((name.to_string(), url))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// branches and tags can be moved, so only commits can be pinned
+pub fn is_commit_hash(x: &str) -> bool {
+    x.len() >= 7 && x.len() <= 40 && x.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn git(repo: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new("git");
+    if let Some(repo) = repo {
+        cmd.arg("-C").arg(repo);
+    }
+    let output = cmd.args(args)
+        .output()
+        .context("Failed to run git")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args[0], stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// unsigned commits fail without a message
+fn verify_signature(path: &Path, commit: &str) -> Result<()> {
+    if git(Some(path), &["verify-commit", commit]).is_err() {
+        bail!("{} has no valid signature", short(commit));
+    }
+    Ok(())
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(10)]
+}
+
+// the checkout has to be on the recorded commit without local changes
+fn verify(path: &Path, repo: &Repo) -> Result<()> {
+    let head = git(Some(path), &["rev-parse", "HEAD"])?;
+    if head != repo.commit {
+        bail!("expected commit {}, found {}", short(&repo.commit), short(&head));
+    }
+    if !git(Some(path), &["status", "--porcelain"])?.is_empty() {
+        bail!("scripts were modified, reinstall the repository");
+    }
+    Ok(())
+}
+
+pub fn install(dir: &Path, source: &str, name: Option<&str>, pin: Option<&str>, signed: bool) -> Result<()> {
+    let (default_name, url) = resolve(source)?;
+    let name = match name {
+        Some(name) if !is_valid_name(name) => bail!("invalid name: {:?}", name),
+        Some(name) => name.to_string(),
+        None => default_name,
+    };
+    if let Some(pin) = pin {
+        if !is_commit_hash(pin) {
+            bail!("--pin expects a commit hash: {:?}", pin);
+        }
+    }
+
+    let mut library = Library::load(dir)?;
+    let path = dir.join(&name);
+    if library.repos.contains_key(&name) || path.exists() {
+        bail!("{:?} is already installed, see scripts update", name);
+    }
+
+    fs::create_dir_all(dir)?;
+    let path_str = path.to_str()
+        .ok_or_else(|| format_err!("invalid path: {:?}", path))?;
+    git(None, &["clone", "--quiet", "--", &url, path_str])?;
+
+    let commit = match checkout(&path, pin, signed) {
+        Ok(commit) => commit,
+        Err(err) => {
+            fs::remove_dir_all(&path)?;
+            return Err(err);
+        },
+    };
+
+    println!("{} installed {} at {}", "[+]".bold(), name.green(), short(&commit).yellow());
+    library.repos.insert(name, Repo {
+        url,
+        commit,
+        pinned: pin.is_some(),
+        signed,
+    });
+    library.save(dir)
+}
+
+fn checkout(path: &Path, pin: Option<&str>, signed: bool) -> Result<String> {
+    if let Some(pin) = pin {
+        let commit = git(Some(path), &["rev-parse", "--verify", &format!("{}^{{commit}}", pin)])
+            .context(format!("commit {} not found", pin))?;
+        if !commit.starts_with(&pin.to_lowercase()) {
+            bail!("{} doesn't match commit {}", pin, commit);
+        }
+        git(Some(path), &["-c", "advice.detachedHead=false", "checkout", "--quiet", &commit])?;
+    }
+    let commit = git(Some(path), &["rev-parse", "HEAD"])?;
+    if signed {
+        verify_signature(path, &commit)?;
+    }
+    Ok(commit)
+}
+
+fn update_repo(path: &Path, repo: &mut Repo) -> Result<Option<String>> {
+    verify(path, repo)?;
+    if repo.pinned {
+        return Ok(None);
+    }
+
+    git(Some(path), &["fetch", "--quiet", "origin"])?;
+    let commit = git(Some(path), &["rev-parse", "@{upstream}"])?;
+    if commit == repo.commit {
+        return Ok(None);
+    }
+    if repo.signed {
+        verify_signature(path, &commit)?;
+    }
+    git(Some(path), &["merge", "--quiet", "--ff-only", &commit])?;
+
+    let old = repo.commit.clone();
+    repo.commit = commit;
+    Ok(Some(old))
+}
+
+pub fn update(dir: &Path, names: &[String]) -> Result<()> {
+    let mut library = Library::load(dir)?;
+    for name in names {
+        if !library.repos.contains_key(name) {
+            bail!("{:?} is not installed", name);
+        }
+    }
+
+    let mut failed = 0;
+    for (name, repo) in library.repos.iter_mut() {
+        if !names.is_empty() && !names.contains(name) {
+            continue;
+        }
+
+        match update_repo(&dir.join(name), repo) {
+            Ok(Some(old)) => println!("{} updated {}: {}..{}", "[+]".bold(),
+                name.green(), short(&old), short(&repo.commit).yellow()),
+            Ok(None) if repo.pinned => println!("{} {} is pinned to {}", "[*]".bold(),
+                name, short(&repo.commit).yellow()),
+            Ok(None) => println!("{} {} is up to date", "[*]".bold(), name),
+            Err(err) => {
+                failed += 1;
+                println!("{} {}({}): {}", "[-]".bold(), "failed".red(), name.yellow(), err);
+            },
+        }
+    }
+
+    library.save(dir)?;
+    if failed > 0 {
+        bail!("{} repositories failed to update", failed);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("kpcyrd/badtouch-scripts").unwrap(),
+            ("badtouch-scripts".to_string(), "https://github.com/kpcyrd/badtouch-scripts.git".to_string()));
+        assert_eq!(resolve("https://git.example.com/team/scripts.git/").unwrap(),
+            ("scripts".to_string(), "https://git.example.com/team/scripts.git/".to_string()));
+        assert_eq!(resolve("git@example.com:scripts").unwrap(),
+            ("scripts".to_string(), "git@example.com:scripts".to_string()));
+        assert!(resolve("not-a-repo").is_err());
+        assert!(resolve("../not-a-repo").is_err());
+        assert!(resolve("a/b/c").is_err());
+        assert!(resolve("--upload-pack=touch /tmp/pwned").is_err());
+        assert!(resolve("-u/x").is_err());
+    }
+
+    #[test]
+    fn test_is_commit_hash() {
+        assert!(is_commit_hash("bc31f89"));
+        assert!(is_commit_hash("bc31f89d0c0f7c4bd2a7e1b5a8f5bfae8d0c1f2a"));
+        assert!(!is_commit_hash("master"));
+        assert!(!is_commit_hash("bc31f"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut library = Library::default();
+        library.repos.insert("scripts".to_string(), Repo {
+            url: "https://git.example.com/scripts.git".to_string(),
+            commit: "bc31f89d0c0f7c4bd2a7e1b5a8f5bfae8d0c1f2a".to_string(),
+            pinned: true,
+            signed: false,
+        });
+        let json = serde_json::to_string(&library).unwrap();
+        assert_eq!(serde_json::from_str::<Library>(&json).unwrap(), library);
+    }
+}
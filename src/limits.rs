@@ -0,0 +1,219 @@
+// memory, instruction and time limits for scripts
+//
+// the limits are tracked per thread, a script always runs on the thread that
+// created its lua state. Lua allocates through a counting allocator that
+// refuses allocations over the limit, lua raises a memory error for them. A
+// count hook raises an error once the instructions or the time are used up.
+// All of them fail the script with a lua error that is replaced with the limit
+// that was hit.
+//
+// Lua can only report a failed allocation inside of a protected call, so
+// allocations are only refused while the script runs in protected().
+//
+// The hook doesn't run while a rust function blocks, sockets, http and ldap
+// use the time that is left as their timeout.
+
+use hlua::{self, ffi, AsMutLua};
+use libc::{self, c_void, size_t};
+use std::cell::Cell;
+use std::cmp;
+use std::fmt;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+use humantime;
+
+
+// the hook runs every this many instructions
+const HOOK_INTERVAL: u64 = 1000;
+
+thread_local! {
+    // bytes that are allocated by limited lua states on this thread
+    static USED: Cell<usize> = const { Cell::new(0) };
+    static MEMORY_LIMIT: Cell<usize> = const { Cell::new(0) };
+    static INSTRUCTION_LIMIT: Cell<Option<u64>> = const { Cell::new(None) };
+    static INSTRUCTIONS_LEFT: Cell<u64> = const { Cell::new(0) };
+    static TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    static EXCEEDED: Cell<Option<Exceeded>> = const { Cell::new(None) };
+    // set while a limited script runs in a protected call
+    static PROTECTED: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    // in megabytes
+    pub memory: Option<usize>,
+    pub instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exceeded {
+    // in bytes
+    Memory(usize),
+    Instructions(u64),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exceeded::Memory(bytes) => write!(f, "script exceeded the memory limit of {}MB", bytes / 1024 / 1024),
+            Exceeded::Instructions(n) => write!(f, "script exceeded the limit of {} instructions", n),
+            Exceeded::Timeout(timeout) => write!(f, "script timed out after {}", humantime::format_duration(timeout)),
+        }
+    }
+}
+
+extern "C" fn alloc(_: *mut c_void, ptr: *mut c_void, osize: size_t, nsize: size_t) -> *mut c_void {
+    // for new blocks osize is the type of the object
+    let osize = if ptr.is_null() { 0 } else { osize };
+    let used = USED.with(|x| x.get()).saturating_sub(osize);
+
+    if nsize == 0 {
+        unsafe { libc::free(ptr) };
+        USED.with(|x| x.set(used));
+        return ptr::null_mut();
+    }
+
+    // shrinking a block must not fail
+    let limit = MEMORY_LIMIT.with(|x| x.get());
+    let grows = nsize > osize;
+    if grows && used.saturating_add(nsize) > limit && PROTECTED.with(|x| x.get()) {
+        EXCEEDED.with(|x| x.set(Some(Exceeded::Memory(limit))));
+        return ptr::null_mut();
+    }
+
+    let new = unsafe { libc::realloc(ptr, nsize) };
+    if new.is_null() {
+        return new;
+    }
+    USED.with(|x| x.set(used + nsize));
+
+    // lua retries a refused allocation after a full collection, the limit
+    // wasn't hit if that freed enough
+    if grows {
+        EXCEEDED.with(|x| if let Some(Exceeded::Memory(_)) = x.get() {
+            x.set(None);
+        });
+    }
+    new
+}
+
+fn instructions_exceeded() -> Option<Exceeded> {
+    let limit = INSTRUCTION_LIMIT.with(|x| x.get())?;
+    let left = INSTRUCTIONS_LEFT.with(|x| x.get());
+    if left > HOOK_INTERVAL {
+        INSTRUCTIONS_LEFT.with(|x| x.set(left - HOOK_INTERVAL));
+        None
+    } else {
+        Some(Exceeded::Instructions(limit))
+    }
+}
+
+fn timed_out() -> Option<Exceeded> {
+    let deadline = DEADLINE.with(|x| x.get())?;
+    if Instant::now() < deadline {
+        None
+    } else {
+        TIMEOUT.with(|x| x.get()).map(Exceeded::Timeout)
+    }
+}
+
+// raising an error jumps out of this function, nothing in here may need drop
+extern "C" fn hook(lua: *mut ffi::lua_State, _: *mut ffi::lua_Debug) {
+    if EXCEEDED.with(|x| x.get()).is_none() {
+        match instructions_exceeded().or_else(timed_out) {
+            Some(exceeded) => EXCEEDED.with(|x| x.set(Some(exceeded))),
+            None => return,
+        }
+    }
+
+    unsafe {
+        ffi::lua_pushstring(lua, b"script limit exceeded\0".as_ptr() as *const _);
+        ffi::lua_error(lua);
+    }
+}
+
+// the counters are restarted and the hook is installed if a limit needs it
+fn start(state: *mut ffi::lua_State, limits: Limits) {
+    EXCEEDED.with(|x| x.set(None));
+    INSTRUCTION_LIMIT.with(|x| x.set(limits.instructions));
+    INSTRUCTIONS_LEFT.with(|x| x.set(limits.instructions.unwrap_or(0)));
+    TIMEOUT.with(|x| x.set(limits.timeout));
+    DEADLINE.with(|x| x.set(limits.timeout.map(|timeout| Instant::now() + timeout)));
+
+    if limits.instructions.is_some() || limits.timeout.is_some() {
+        unsafe { ffi::lua_sethook(state, hook, ffi::LUA_MASKCOUNT, HOOK_INTERVAL as i32) };
+    } else {
+        unsafe { ffi::lua_sethook(state, hook, 0, 0) };
+    }
+}
+
+// has to be called on a new state, before it is used
+pub fn apply(lua: &mut hlua::Lua, limits: Limits) {
+    let state = lua.as_mut_lua().state_ptr();
+
+    if let Some(mb) = limits.memory {
+        MEMORY_LIMIT.with(|x| x.set(mb.saturating_mul(1024 * 1024)));
+        unsafe { ffi::lua_setallocf(state, alloc, ptr::null_mut()) };
+    }
+
+    start(state, limits);
+}
+
+// has to be called before a state that was set up with apply is used for the
+// next attempt. The garbage of the last attempt is collected, so it doesn't
+// count against the memory limit.
+pub fn reset(lua: &mut hlua::Lua, limits: Limits) {
+    let state = lua.as_mut_lua().state_ptr();
+
+    if let Some(mb) = limits.memory {
+        MEMORY_LIMIT.with(|x| x.set(mb.saturating_mul(1024 * 1024)));
+        unsafe { ffi::lua_gc(state, ffi::LUA_GCCOLLECT, 0) };
+    }
+
+    start(state, limits);
+}
+
+// runs f with allocations over the memory limit refused, f has to call lua
+// with lua_pcall
+pub fn protected<T, F: FnOnce() -> T>(f: F) -> T {
+    PROTECTED.with(|x| x.set(true));
+    let result = f();
+    PROTECTED.with(|x| x.set(false));
+    result
+}
+
+// the time that is left until the script on this thread times out, at least
+// a millisecond so it can be used as a socket timeout
+pub fn remaining() -> Option<Duration> {
+    let deadline = DEADLINE.with(|x| x.get())?;
+    let left = deadline.saturating_duration_since(Instant::now());
+    Some(cmp::max(left, Duration::from_millis(1)))
+}
+
+// sleeps at most until the script times out, the hook fails the script on its
+// next instruction
+pub fn sleep(duration: Duration) {
+    let duration = match remaining() {
+        Some(left) => cmp::min(duration, left),
+        None => duration,
+    };
+    thread::sleep(duration);
+}
+
+// caps a timeout of an operation to the time that is left
+pub fn timeout(timeout: Option<Duration>) -> Option<Duration> {
+    match (timeout, remaining()) {
+        (Some(timeout), Some(left)) => Some(cmp::min(timeout, left)),
+        (timeout, left) => timeout.or(left),
+    }
+}
+
+// the limit that made the last script on this thread fail
+#[inline]
+pub fn take_exceeded() -> Option<Exceeded> {
+    EXCEEDED.with(|x| x.take())
+}
@@ -0,0 +1,335 @@
+// structured log file with --log-file
+//
+// every line is a json object. Log records are written if they pass
+// --log-level, independent of -v, and the outcome of every attempt is logged
+// as well, so a script that misbehaves somewhere in a long run can be
+// debugged afterwards.
+//
+// Lines that scripts log with info, warn or debug are sent to the main loop,
+// so they don't break the progress bar, and are written to the log file with
+// the worker and the attempt that logged them.
+
+use errors::*;
+
+use env_logger;
+use humantime;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use report::{self, BatchWriter};
+use scheduler::Msg;
+use serde_json;
+use std::cell::RefCell;
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::SystemTime;
+
+
+// the log target of lines that are logged by scripts
+pub const SCRIPT: &str = "script";
+
+static NEXT_WORKER: AtomicUsize = AtomicUsize::new(1);
+// the main loop that script lines are sent to
+static TERMINAL: Mutex<Option<mpsc::Sender<Msg>>> = Mutex::new(None);
+
+thread_local! {
+    static WORKER: usize = NEXT_WORKER.fetch_add(1, Ordering::Relaxed);
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+// the attempt that the current thread is running
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Context {
+    pub worker: usize,
+    pub attempt: usize,
+    pub script: String,
+    pub user: String,
+}
+
+// set by the worker thread while an attempt is running
+pub fn set_context(attempt: usize, script: &str, user: &str) {
+    let worker = WORKER.with(|worker| *worker);
+    CONTEXT.with(|ctx| {
+        *ctx.borrow_mut() = Some(Context {
+            worker,
+            attempt,
+            script: script.to_string(),
+            user: user.to_string(),
+        });
+    });
+}
+
+#[inline]
+pub fn clear_context() {
+    CONTEXT.with(|ctx| ctx.borrow_mut().take());
+}
+
+#[inline]
+fn context() -> Option<Context> {
+    CONTEXT.with(|ctx| ctx.borrow().clone())
+}
+
+#[derive(Debug)]
+pub struct ScriptLine {
+    pub level: Level,
+    pub context: Option<Context>,
+    pub message: String,
+}
+
+// script lines are sent to the main loop as Msg::Log from now on
+pub fn set_terminal(tx: mpsc::Sender<Msg>) {
+    *TERMINAL.lock().unwrap() = Some(tx);
+}
+
+// called by the info, warn, debug and print functions of scripts. Without a
+// main loop, eg. with oneshot, info and warn lines are printed to stdout.
+pub fn script(level: Level, message: String) {
+    log!(target: SCRIPT, level, "{}", message);
+
+    let line = ScriptLine {
+        level,
+        context: context(),
+        message,
+    };
+    match *TERMINAL.lock().unwrap() {
+        Some(ref tx) => {
+            tx.send(Msg::Log(line)).ok();
+        },
+        None => if level <= Level::Info {
+            println!("{}", line.message);
+        },
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    timestamp: String,
+    level: &'a str,
+    // the module that logged the line, or script
+    module: &'a str,
+    message: String,
+    #[serde(flatten)]
+    context: Option<Context>,
+}
+
+#[derive(Debug, Serialize)]
+struct AttemptLine<'a> {
+    level: &'a str,
+    id: usize,
+    #[serde(flatten)]
+    record: &'a report::Record<'a>,
+}
+
+#[inline]
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+pub struct LogFile {
+    file: Mutex<BatchWriter<File>>,
+    level: LevelFilter,
+}
+
+impl LogFile {
+    // appends to the file if it already exists
+    pub fn open(path: &str, level: LevelFilter) -> Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open log file: {:?}", path))?;
+
+        Ok(LogFile {
+            file: Mutex::new(BatchWriter::new(file)),
+            level,
+        })
+    }
+
+    #[inline]
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    fn write<T: ::serde::Serialize>(&self, line: &T) {
+        let mut line = match serde_json::to_string(line) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        line.push('\n');
+        // there's nowhere to report a failed write to
+        self.file.lock().unwrap()
+            .write_line(line.as_bytes()).ok();
+    }
+
+    pub fn flush(&self) {
+        self.file.lock().unwrap().flush().ok();
+    }
+
+    pub fn log(&self, level: Level, target: &str, message: String) {
+        if !self.enabled(level) {
+            return;
+        }
+        // script lines are logged on the thread that runs the attempt
+        let context = if target == SCRIPT {
+            context()
+        } else {
+            None
+        };
+        self.write(&Line {
+            timestamp: humantime::format_rfc3339(SystemTime::now()).to_string(),
+            level: level_name(level),
+            module: target,
+            message,
+            context,
+        });
+    }
+
+    // errors are logged as warnings, everything else as info
+    pub fn attempt(&self, id: usize, record: &report::Record) {
+        let level = if record.error.is_some() {
+            Level::Warn
+        } else {
+            Level::Info
+        };
+        if !self.enabled(level) {
+            return;
+        }
+        self.write(&AttemptLine {
+            level: level_name(level),
+            id,
+            record,
+        });
+        if record.valid {
+            self.flush();
+        }
+    }
+}
+
+// sends log records to stderr, filtered by -v and RUST_LOG, and to the log
+// file, filtered by --log-level
+pub struct Logger {
+    stderr: env_logger::Logger,
+    file: Option<Arc<LogFile>>,
+}
+
+impl Logger {
+    pub fn init(stderr: env_logger::Logger, file: Option<Arc<LogFile>>) -> Result<()> {
+        let mut max = stderr.filter();
+        if let Some(ref file) = file {
+            max = cmp::max(max, file.level);
+        }
+        log::set_boxed_logger(Box::new(Logger {
+            stderr,
+            file,
+        }))?;
+        log::set_max_level(max);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) ||
+            self.file.as_ref().is_some_and(|file| file.enabled(metadata.level()))
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target() != SCRIPT {
+            self.stderr.log(record);
+        }
+        if let Some(ref file) = self.file {
+            file.log(record.level(), record.target(), record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(ref file) = self.file {
+            file.flush();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use std::time::Duration;
+
+    fn tmp(name: &str) -> String {
+        let path = env::temp_dir().join(format!("badtouch-{}-{}.log", name, process::id()));
+        fs::remove_file(&path).ok();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_log_level() {
+        let path = tmp("level");
+        let file = LogFile::open(&path, LevelFilter::Info).unwrap();
+        file.log(Level::Warn, "badtouch::ctx", "foo".to_string());
+        file.log(Level::Debug, "badtouch::ctx", "bar".to_string());
+        drop(file);
+
+        let out = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["module"], "badtouch::ctx");
+        assert_eq!(line["message"], "foo");
+        assert!(line.get("worker").is_none());
+    }
+
+    #[test]
+    fn test_log_script_context() {
+        let path = tmp("script");
+        let file = LogFile::open(&path, LevelFilter::Debug).unwrap();
+        set_context(40_000, "example.com", "admin");
+        file.log(Level::Debug, SCRIPT, "csrf token: abc".to_string());
+        let ctx = context().unwrap();
+        clear_context();
+        file.log(Level::Debug, SCRIPT, "done".to_string());
+        drop(file);
+
+        let out = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let lines = out.lines()
+            .map(|x| serde_json::from_str::<serde_json::Value>(x).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0]["module"], "script");
+        assert_eq!(lines[0]["worker"], ctx.worker);
+        assert_eq!(lines[0]["attempt"], 40_000);
+        assert_eq!(lines[0]["script"], "example.com");
+        assert_eq!(lines[0]["user"], "admin");
+        assert!(lines[1].get("attempt").is_none());
+    }
+
+    #[test]
+    fn test_log_attempt() {
+        let path = tmp("attempt");
+        let file = LogFile::open(&path, LevelFilter::Info).unwrap();
+        let mut record = report::Record::new("example.com", "admin", Some("hunter2"))
+            .latency(Duration::from_millis(120));
+        record.error = Some("connection refused".to_string());
+        file.attempt(40_000, &record);
+        drop(file);
+
+        let out = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let line: serde_json::Value = serde_json::from_str(out.trim()).unwrap();
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["id"], 40_000);
+        assert_eq!(line["script"], "example.com");
+        assert_eq!(line["password"], "hunter2");
+        assert_eq!(line["latency_ms"], 120);
+        assert_eq!(line["error"], "connection refused");
+    }
+}
@@ -5,136 +5,386 @@ extern crate colored;
 extern crate humantime;
 extern crate atty;
 #[macro_use] extern crate log;
+#[macro_use] extern crate failure;
 
 use badtouch::args;
-use badtouch::ctx::Script;
+use badtouch::ctx::{ErrorKind, Script, Lockout};
+use badtouch::daemon::Daemon;
+use badtouch::defaults;
+use badtouch::distributed::{self, Agent, Coordinator, Event, Settings};
+use badtouch::dns;
 use badtouch::fsck;
-use badtouch::utils;
+use badtouch::utils::{self, ComboList, CredsList};
 use badtouch::config::Config;
+use badtouch::kv;
+use badtouch::library;
+use badtouch::logfile::{self, LogFile, ScriptLine};
+use badtouch::mask::Mask;
+use badtouch::metrics::{self, Metrics, Outcome};
+use badtouch::mock::{self, Expect, MockServer};
+use badtouch::names;
+use badtouch::notify::{self, Notifier, Webhook};
 use badtouch::pb::ProgressBar;
-use badtouch::scheduler::{Scheduler, Attempt, Creds, Msg};
+use badtouch::repl;
+use badtouch::report::{Format, Report, Record};
+use badtouch::results::ResultsDb;
+use badtouch::retry;
+use badtouch::rules::{self, Rule};
+use badtouch::sandbox;
+use badtouch::scheduler::{Scheduler, Attempt, Creds, Msg, Slice};
+use badtouch::session::{Session, Valid};
+use badtouch::shutdown;
+use badtouch::status::{Status, StatusWriter};
 use badtouch::keyboard::{Keyboard, Key};
 use badtouch::ulimit::{Resource, getrlimit, setrlimit};
+use badtouch::wordlist::{self, Wordlist};
 
 use colored::*;
+use log::Level;
+use std::io;
 use std::thread;
-use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
-use std::io::prelude::*;
-use badtouch::errors::{Result, ResultExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeMap, HashSet};
+use std::cmp;
+use std::time::{Duration, Instant, SystemTime};
+use badtouch::errors::{Error, Result, ResultExt};
 
 
-enum Report {
-    Some(File),
-    None
+macro_rules! tinfof {
+    ($arg1:tt, $fmt:expr, $($arg:tt)*) => (
+        $arg1.bold().to_string() + " " + &(format!($fmt, $($arg)*).dimmed().to_string())
+    );
 }
 
-impl Report {
-    pub fn open(path: Option<String>) -> Result<Report> {
-        match path {
-            Some(path) => Ok(Report::Some(File::create(path)?)),
-            None => Ok(Report::None),
+macro_rules! tinfo {
+    ($arg1:tt, $fmt:expr, $($arg:tt)*) => (
+        if !QUIET.load(Ordering::Relaxed) {
+            println!("{}", tinfof!($arg1, $fmt, $($arg)*));
         }
+    );
+}
+
+// --quiet, only valid credentials are printed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// a single list is read from disk while the attempts are generated, multiple
+// lists are merged in memory to remove duplicates
+fn load_lists(kind: &str, first: String, more: Vec<String>) -> Result<Wordlist> {
+    let mut paths = vec![first];
+    paths.extend(more);
+
+    if paths.len() == 1 {
+        let list = Wordlist::open(&paths[0])
+            .context(format!("Failed to load {}", kind))?;
+        tinfo!("[+]", "loaded {} {}", list.len(), kind);
+        return Ok(list);
     }
 
-    pub fn write_creds(&mut self, user: &str, password: &str, script: &str) -> Result<()> {
-        if let Report::Some(ref mut f) = *self {
-            writeln!(f, "{}:{}:{}", script, user, password)?;
-        }
-        Ok(())
+    let mut lists = Vec::new();
+    let mut total = 0;
+    for path in &paths {
+        let list = utils::load_list(path)
+            .context(format!("Failed to load {} from {:?}", kind, path))?;
+        tinfo!("[+]", "loaded {} {} from {:?}", list.len(), kind, path);
+        total += list.len();
+        lists.push(list);
+    }
+
+    let list = utils::merge_lists(lists);
+    tinfo!("[+]", "merged {} {}, removed {} duplicates", list.len(), kind, total - list.len());
+    Ok(Wordlist::from(list))
+}
+
+fn load_users(first: String, more: Vec<String>, names: bool, domain: Option<String>) -> Result<Wordlist> {
+    if !names {
+        return load_lists("users", first, more);
     }
 
-    pub fn write_enum(&mut self, user: &str, script: &str) -> Result<()> {
-        if let Report::Some(ref mut f) = *self {
-            writeln!(f, "{}:{}", script, user)?;
+    let names = load_lists("names", first, more)?.load()?;
+    let users = names::usernames(&names, domain.as_ref().map(|x| x.as_str()));
+    tinfo!("[+]", "generated {} users", users.len());
+    Ok(Wordlist::from(users))
+}
+
+// scripts that declare exec or fs in their descriptor, or don't declare caps
+// at all, are only run if the user confirms them
+fn confirm_capabilities(scripts: &[Arc<Script>], config: &Config) -> Result<()> {
+    if config.runtime.trust {
+        return Ok(());
+    }
+
+    for script in scripts {
+        let declared = script.descriptor().caps.as_ref();
+        let caps = sandbox::needs_confirmation(declared.map(|x| &x[..]), &config.runtime.deny);
+        if caps.is_empty() {
+            continue;
+        }
+        let caps = caps.iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let caps = match declared {
+            Some(_) => caps,
+            None => format!("{} (no caps declared)", caps),
+        };
+
+        if atty::isnt(atty::Stream::Stdin) {
+            bail!("Script {:?} requests {}, run with --trust to allow it", script.descr(), caps);
+        }
+        eprint!("{} script {:?} requests {}, allow it? [y/N] ", "[?]".bold(), script.descr(), caps);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !["y", "yes"].contains(&answer.trim().to_lowercase().as_str()) {
+            bail!("Script {:?} wasn't allowed to use {}", script.descr(), caps);
         }
-        Ok(())
     }
+
+    Ok(())
 }
 
-macro_rules! tinfof {
-    ($arg1:tt, $fmt:expr, $($arg:tt)*) => (
-        $arg1.bold().to_string() + " " + &(format!($fmt, $($arg)*).dimmed().to_string())
-    );
+fn load_scripts(paths: Vec<String>, config: &Arc<Config>) -> Result<Vec<Arc<Script>>> {
+    let scripts = utils::load_scripts(paths, config)
+        .context("Failed to load scripts")?;
+    confirm_capabilities(&scripts, config)?;
+    tinfo!("[+]", "loaded {} scripts", scripts.len());
+    Ok(scripts)
 }
 
-macro_rules! tinfo {
-    ($arg1:tt, $fmt:expr, $($arg:tt)*) => (
-        println!("{}", tinfof!($arg1, $fmt, $($arg)*));
-    );
+// the passwords that are tried for a line of the password list
+fn mangle(rules: Option<&Vec<Rule>>, password: Arc<String>) -> Vec<Arc<String>> {
+    match rules {
+        Some(rules) => rules::candidates(rules, &password),
+        None => vec![password],
+    }
 }
 
 fn setup_dictionary_attack(pool: &mut Scheduler, args: args::Dict, config: &Arc<Config>) -> Result<usize> {
-    let users = utils::load_list(&args.users)
-        .context("Failed to load users")?;
-    tinfo!("[+]", "loaded {} users", users.len());
-    let passwords = utils::load_list(&args.passwords)
-        .context("Failed to load passwords")?;
-    tinfo!("[+]", "loaded {} passwords", passwords.len());
-    let scripts = utils::load_scripts(args.scripts, &config)
-        .context("Failed to load scripts")?;
-    tinfo!("[+]", "loaded {} scripts", scripts.len());
+    let users = load_users(args.users, args.more_users, args.names, args.domain)?;
+    let passwords = load_lists("passwords", args.passwords, args.more_passwords)?;
+    let rules = match args.rules {
+        Some(path) => {
+            let rules = rules::load(&path)
+                .context("Failed to load rules")?;
+            tinfo!("[+]", "loaded {} rules", rules.len());
+            Some(Arc::new(rules))
+        },
+        None => None,
+    };
+    // the rules are applied while the attempts are generated, the candidates
+    // are only counted up front
+    let candidates = match rules {
+        Some(ref rules) => {
+            let mut num = 0;
+            for password in passwords.iter()? {
+                num += rules::candidates(rules, &password?).len();
+            }
+            tinfo!("[+]", "generated {} candidates", num);
+            num
+        },
+        None => passwords.len(),
+    };
+    let scripts = load_scripts(args.scripts, config)?;
 
-    let attempts = users.len() * passwords.len() * scripts.len();
+    let attempts = users.len() * candidates * scripts.len();
     tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
 
-    for user in &users {
-        for password in &passwords {
-            for script in &scripts {
-                let attempt = Attempt::new(user, password, script);
-                pool.run(attempt);
-            }
-        }
+    if args.spray {
+        tinfo!("[*]", "spraying {} passwords with a lockout window of {}", candidates,
+            humantime::format_duration(args.lockout_window));
+        // the rounds are generated while the previous one runs, the user
+        // list is read again for every round
+        let rounds = passwords.iter()?
+            .flat_map(move |password| -> Vec<Result<Arc<String>>> {
+                match password {
+                    Ok(password) => mangle(rules.as_ref().map(|x| &**x), password)
+                        .into_iter()
+                        .map(Ok)
+                        .collect(),
+                    Err(err) => vec![Err(err)],
+                }
+            })
+            .map(move |password| -> Result<Vec<Attempt>> {
+                let password = password?;
+                let mut round = Vec::new();
+                for user in users.iter()? {
+                    let user = user?;
+                    for script in &scripts {
+                        round.push(Attempt::new(&user, &password, script));
+                    }
+                }
+                Ok(round)
+            });
+        pool.set_rounds(Box::new(rounds))?;
+        pool.start_round();
+    } else {
+        let feed = wordlist::Product::new(&users, &passwords)?
+            .flat_map(move |creds| -> Vec<Result<Attempt>> {
+                let (user, password) = match creds {
+                    Ok(creds) => creds,
+                    Err(err) => return vec![Err(err)],
+                };
+                let mut attempts = Vec::new();
+                for password in mangle(rules.as_ref().map(|x| &**x), password) {
+                    for script in &scripts {
+                        attempts.push(Ok(Attempt::new(&user, &password, script)));
+                    }
+                }
+                attempts
+            });
+        pool.set_feed(Box::new(feed))?;
     }
 
     Ok(attempts)
 }
 
 fn setup_credential_confirmation(pool: &mut Scheduler, args: args::Creds, config: &Arc<Config>) -> Result<usize> {
-    let creds = utils::load_creds(&args.creds)?;
-    tinfo!("[+]", "loaded {} credentials", creds.len());
-    let scripts = utils::load_scripts(args.scripts, &config)
-        .context("Failed to load scripts")?;
-    tinfo!("[+]", "loaded {} scripts", scripts.len());
+    // the list is validated up front and read again while the attempts are generated
+    let creds = CredsList::count(&args.creds)?;
+    tinfo!("[+]", "loaded {} credentials", creds);
+    let scripts = load_scripts(args.scripts, config)?;
 
-    let attempts = creds.len() * scripts.len();
+    let attempts = creds * scripts.len();
     tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
 
-    for cred in creds {
-        // TODO: optimization if we only have once script
-        for script in &scripts {
-            let attempt = Attempt::bytes(&cred, script);
-            pool.run(attempt);
-        }
+    let feed = CredsList::open(&args.creds)?
+        .flat_map(move |cred| -> Vec<Result<Attempt>> {
+            match cred {
+                Ok(cred) => scripts.iter()
+                    .map(|script| Ok(Attempt::bytes(&cred, script)))
+                    .collect(),
+                Err(err) => vec![Err(err)],
+            }
+        });
+    pool.set_feed(Box::new(feed))?;
+
+    Ok(attempts)
+}
+
+fn setup_combo_attack(pool: &mut Scheduler, args: args::Combo, config: &Arc<Config>) -> Result<usize> {
+    let (combos, skipped) = ComboList::count(&args.combos, &args.separator, args.escape)
+        .context("Failed to load combo list")?;
+    tinfo!("[+]", "loaded {} credentials", combos);
+    if skipped > 0 {
+        tinfo!("[!]", "skipped {} invalid lines", skipped);
     }
+    let scripts = load_scripts(args.scripts, config)?;
+
+    let attempts = combos * scripts.len();
+    tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
+
+    let feed = ComboList::open(&args.combos, &args.separator, args.escape)?
+        .flat_map(move |combo| -> Vec<Result<Attempt>> {
+            match combo {
+                Ok((user, password)) => scripts.iter()
+                    .map(|script| Ok(Attempt::new(&user, &password, script)))
+                    .collect(),
+                Err(err) => vec![Err(err)],
+            }
+        });
+    pool.set_feed(Box::new(feed))?;
 
     Ok(attempts)
 }
 
-fn setup_enum_attack(pool: &mut Scheduler, args: args::Enum, config: &Arc<Config>) -> Result<usize> {
+fn setup_mask_attack(pool: &mut Scheduler, args: args::Mask, config: &Arc<Config>) -> Result<usize> {
     let users = utils::load_list(&args.users)
         .context("Failed to load users")?;
     tinfo!("[+]", "loaded {} users", users.len());
-    let scripts = utils::load_scripts(args.scripts, &config)
-        .context("Failed to load scripts")?;
-    tinfo!("[+]", "loaded {} scripts", scripts.len());
+    let custom = [args.charset1, args.charset2, args.charset3, args.charset4];
+    let mask = Mask::parse(&args.mask, &custom)
+        .context("Failed to parse mask")?;
+    let scripts = load_scripts(args.scripts, config)?;
 
-    let attempts = users.len() * scripts.len();
+    // the passwords are generated while the run progresses, the number of
+    // attempts still has to fit
+    let keyspace = mask.keyspace()
+        .filter(|keyspace| *keyspace <= usize::MAX as u64)
+        .ok_or_else(|| format_err!("Keyspace of mask is too large"))?;
+    tinfo!("[+]", "mask has a keyspace of {} passwords", keyspace);
+    let attempts = (keyspace as usize).checked_mul(users.len())
+        .and_then(|x| x.checked_mul(scripts.len()))
+        .ok_or_else(|| format_err!("Keyspace of mask is too large"))?;
     tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
 
-    for user in &users {
-        for script in &scripts {
-            let attempt = Attempt::enumerate(user, script);
-            pool.run(attempt);
-        }
+    let feed = mask.iter()
+        .flat_map(move |password| -> Vec<Result<Attempt>> {
+            let password = Arc::new(password);
+            let mut attempts = Vec::new();
+            for user in &users {
+                for script in &scripts {
+                    attempts.push(Ok(Attempt::new(user, &password, script)));
+                }
+            }
+            attempts
+        });
+    pool.set_feed(Box::new(feed))?;
+
+    Ok(attempts)
+}
+
+fn setup_defaults_attack(pool: &mut Scheduler, args: args::Defaults, config: &Arc<Config>) -> Result<usize> {
+    let db = match args.db {
+        Some(path) => defaults::load(&path)
+            .context("Failed to load default credentials")?,
+        None => defaults::builtin(),
+    };
+    let creds = defaults::lookup(&db, &args.product).into_iter()
+        .map(|cred| (cred.user.clone(), cred.password.clone()))
+        .collect::<Vec<_>>();
+    if creds.is_empty() {
+        bail!("No default credentials for {:?}, known products: {}", args.product,
+            defaults::products(&db).join(", "));
     }
+    tinfo!("[+]", "loaded {} default credentials for {:?}", creds.len(), args.product);
+    let scripts = load_scripts(args.scripts, config)?;
+
+    let attempts = creds.len() * scripts.len();
+    tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
+
+    let feed = creds.into_iter()
+        .flat_map(move |(user, password)| -> Vec<Result<Attempt>> {
+            scripts.iter()
+                .map(|script| Ok(Attempt::new(&user, &password, script)))
+                .collect()
+        });
+    pool.set_feed(Box::new(feed))?;
+
+    Ok(attempts)
+}
+
+fn setup_enum_attack(pool: &mut Scheduler, args: args::Enum, config: &Arc<Config>) -> Result<usize> {
+    let users = load_users(args.users, args.more_users, args.names, args.domain)?;
+    let scripts = load_scripts(args.scripts, config)?;
+
+    let attempts = users.len() * scripts.len();
+    tinfo!("[*]", "submitting {} jobs to threadpool with {} workers", attempts, pool.max_count());
+
+    let feed = users.iter()?
+        .flat_map(move |user| -> Vec<Result<Attempt>> {
+            match user {
+                Ok(user) => scripts.iter()
+                    .map(|script| Ok(Attempt::enumerate(&user, script)))
+                    .collect(),
+                Err(err) => vec![Err(err)],
+            }
+        });
+    pool.set_feed(Box::new(feed))?;
 
     Ok(attempts)
 }
 
+fn run_daemon(args: args::Daemon) -> Result<()> {
+    let daemon = Daemon::new(&args.dir, args.token)?;
+    tinfo!("[+]", "listening on {}, writing jobs to {:?}", args.listen, args.dir);
+    daemon.serve(&args.listen)
+}
+
 fn run_oneshot(oneshot: args::Oneshot, config: Arc<Config>) -> Result<()> {
-    let script = Script::load(&oneshot.script, config)?;
+    let path = utils::find_script(&oneshot.script, &config);
+    let script = Arc::new(Script::load(&path, config.clone())?);
+    confirm_capabilities(&[script.clone()], &config)?;
     let user = oneshot.user;
 
     let valid = match oneshot.password {
@@ -147,13 +397,215 @@ fn run_oneshot(oneshot: args::Oneshot, config: Arc<Config>) -> Result<()> {
             Some(ref password) => println!("{}", format_valid_creds(script.descr(), &user, &password)),
             None => println!("{}", format_valid_enum(script.descr(), &user)),
         }
-    } else if oneshot.exitcode {
-        std::process::exit(2);
+    } else {
+        match oneshot.password {
+            Some(ref password) => println!("{}", format_invalid_creds(script.descr(), &user, &password)),
+            None => println!("{}", format_invalid_enum(script.descr(), &user)),
+        }
+
+        if oneshot.exitcode {
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
+
+// an error and its causes on one line
+fn format_error(err: &Error) -> String {
+    err.iter_chain()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+// the columns are padded before they are colored
+fn print_table(rows: &[Vec<String>]) {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let len = cell.chars().count();
+            if i >= widths.len() {
+                widths.push(len);
+            } else {
+                widths[i] = cmp::max(widths[i], len);
+            }
+        }
+    }
+
+    for (i, row) in rows.iter().enumerate() {
+        let line = row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width=width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let line = line.trim_end();
+        if i == 0 {
+            println!("{}", line.bold());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+fn list_scripts(args: args::ListScripts, config: &Arc<Config>) -> Result<()> {
+    let dirs = if args.scripts.is_empty() {
+        let dir = config.general.scripts_dir()
+            .unwrap_or_else(|| PathBuf::from("."));
+        vec![dir.to_string_lossy().into_owned()]
+    } else {
+        args.scripts
+    };
+
+    let mut paths = Vec::new();
+    for dir in &dirs {
+        let path = PathBuf::from(dir);
+        if path.is_dir() {
+            utils::find_scripts(&path, &mut paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut rows = vec![
+        ["NAME", "PROTOCOL", "OPTIONS", "CAPS", "PATH"].iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>(),
+    ];
+    let mut errors = Vec::new();
+    for path in &paths {
+        let path = path.to_string_lossy();
+        // a broken script doesn't hide the others
+        let script = match Script::load(&path, config.clone()) {
+            Ok(script) => script,
+            Err(err) => {
+                errors.push(format!("{}: {}", path, format_error(&err)));
+                continue;
+            },
+        };
+        let descriptor = script.descriptor();
+
+        let options = descriptor.options.keys()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        // scripts that don't declare caps can use everything
+        let caps = match descriptor.caps {
+            Some(ref caps) => caps.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => "any".to_string(),
+        };
+
+        rows.push(vec![
+            script.descr().to_string(),
+            descriptor.protocol.clone().unwrap_or_else(|| "-".to_string()),
+            if options.is_empty() { "-".to_string() } else { options },
+            caps,
+            path.into_owned(),
+        ]);
+    }
+
+    print_table(&rows);
+    for err in errors {
+        eprintln!("{} {}", "[!]".bold(), err.red());
     }
 
     Ok(())
 }
 
+// loads the scripts without setup or verify, so nothing is sent
+fn run_check(args: args::Check, config: &Arc<Config>) -> Result<()> {
+    let mut failed = 0;
+
+    for path in &args.scripts {
+        let problems = match Script::load(path, config.clone()) {
+            Ok(script) => {
+                let problems = script.check();
+                if problems.is_empty() {
+                    println!("{} {}({}): {}", "[+]".bold(), "ok".green(), script.descr().yellow(), path);
+                    continue;
+                }
+                problems
+            },
+            Err(err) => vec![format_error(&err)],
+        };
+
+        failed += 1;
+        for problem in problems {
+            println!("{} {}({}): {}", "[-]".bold(), "failed".red(), path.yellow(), problem);
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} scripts have problems", failed, args.scripts.len());
+    }
+    Ok(())
+}
+
+fn run_scripts(args: args::Scripts) -> Result<()> {
+    let dir = library::dir()?;
+    match args.subcommand {
+        args::ScriptsCmd::Install(install) => library::install(&dir, &install.source,
+            install.name.as_deref(), install.pin.as_deref(), install.signed),
+        args::ScriptsCmd::Update(update) => library::update(&dir, &update.names),
+    }
+}
+
+fn run_tests(args: args::Test, config: &Arc<Config>, server: &MockServer) -> Result<()> {
+    let mut total = 0;
+    let mut failed = 0;
+
+    for path in &args.scripts {
+        let script = Arc::new(Script::load(path, config.clone())
+            .context(format!("Failed to load script {:?}", path))?);
+        confirm_capabilities(&[script.clone()], config)?;
+
+        let mut tests = script.tests().to_vec();
+        tests.extend(mock::load_sidecar(path)?);
+        if tests.is_empty() {
+            tinfo!("[*]", "{} has no tests", script.descr());
+            continue;
+        }
+
+        for test in tests {
+            server.set_routes(test.routes.clone());
+            // a fresh script, so setup runs again with these routes
+            let script = Script::load(path, config.clone())?;
+            let result = match test.password {
+                Some(ref password) => script.run_creds(&test.user, password),
+                None => script.run_enum(&test.user),
+            };
+            total += 1;
+
+            let got = Expect::from(&result);
+            if got == test.expect {
+                println!("{} {}({}): {}", "[+]".bold(), "ok".green(), script.descr().yellow(), test.name());
+                continue;
+            }
+
+            failed += 1;
+            let got = match result {
+                Err(err) => format!("{}: {}", got, err),
+                _ => got.to_string(),
+            };
+            println!("{} {}({}): {}, expected {}, got {}", "[-]".bold(), "failed".red(),
+                script.descr().yellow(), test.name(), test.expect, got);
+            for request in server.unmatched() {
+                println!("    {}", format!("no route for {}", request).dimmed());
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} tests failed", failed, total);
+    }
+    tinfo!("[+]", "{} tests passed", total);
+    Ok(())
+}
+
 fn format_valid_creds(script: &str, user: &str, password: &str) -> String {
     format!("{} {}({}) => {:?}:{:?}", "[+]".bold(), "valid".green(),
         script.yellow(), user, password)
@@ -164,6 +616,192 @@ fn format_valid_enum(script: &str, user: &str) -> String {
         script.yellow(), user)
 }
 
+fn wait_lockout_window(pb: &mut ProgressBar, window: Duration) {
+    pb.writeln(tinfof!("[*]", "waiting {} for the lockout window", humantime::format_duration(window)));
+
+    let deadline = Instant::now() + window;
+    loop {
+        let now = Instant::now();
+        if now >= deadline || shutdown::requested() {
+            break;
+        }
+
+        let left = Duration::from_secs((deadline - now).as_secs());
+        pb.message(&format!("next password in {} ", humantime::format_duration(left)));
+        thread::sleep(cmp::min(deadline - now, Duration::from_secs(1)));
+    }
+
+    pb.message("");
+}
+
+fn wait_delayed(pb: &mut ProgressBar, until: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= until || shutdown::requested() {
+            break;
+        }
+
+        let left = Duration::from_secs((until - now).as_secs());
+        pb.message(&format!("next attempt in {} ", humantime::format_duration(left)));
+        thread::sleep(cmp::min(until - now, Duration::from_secs(1)));
+    }
+
+    pb.message("");
+}
+
+fn format_invalid_creds(script: &str, user: &str, password: &str) -> String {
+    format!("{} {}({}) => {:?}:{:?}", "[-]".bold(), "invalid".red(),
+        script.yellow(), user, password)
+}
+
+fn format_invalid_enum(script: &str, user: &str) -> String {
+    format!("{} {}({}) => {:?}", "[-]".bold(), "invalid".red(),
+        script.yellow(), user)
+}
+
+// stop dispatching new attempts, returns the deadline for the running attempts
+fn drain(pool: &mut Scheduler, pb: &mut ProgressBar, reason: &str, timeout: Duration) -> Instant {
+    pb.writeln(tinfof!("[*]", "{}, waiting up to {} for running attempts",
+        reason, humantime::format_duration(timeout)));
+    pool.drain();
+    Instant::now() + timeout
+}
+
+#[derive(Debug, Default)]
+struct TargetStats {
+    attempts: usize,
+    valid: usize,
+    errors: usize,
+    latency: Duration,
+}
+
+impl TargetStats {
+    fn add(&mut self, record: &Record, latency: Duration) {
+        self.attempts += 1;
+        if record.valid {
+            self.valid += 1;
+        }
+        if record.error.is_some() {
+            self.errors += 1;
+        }
+        self.latency += latency;
+    }
+
+    fn summary(&self, target: &str) -> String {
+        let average = self.latency / cmp::max(self.attempts, 1) as u32;
+        let average = average.as_secs() * 1000 + u64::from(average.subsec_millis());
+        format!("{}: {} attempts, {} valid, {} errors, {}ms average latency",
+            target, self.attempts, self.valid, self.errors, average)
+    }
+}
+
+fn print_targets(targets: &BTreeMap<String, TargetStats>) {
+    for (target, stats) in targets {
+        tinfo!("[*]", "{}", stats.summary(target));
+    }
+}
+
+fn format_script_line(line: &ScriptLine) -> String {
+    let level = match line.level {
+        Level::Error | Level::Warn => "warn".yellow(),
+        Level::Info => "info".cyan(),
+        Level::Debug | Level::Trace => "debug".dimmed(),
+    };
+    match line.context {
+        Some(ref ctx) => format!("{} {}({}, {}): {}", "[~]".bold(), level, ctx.script.yellow(), format!("{:?}", ctx.user).dimmed(), line.message),
+        None => format!("{} {}: {}", "[~]".bold(), level, line.message),
+    }
+}
+
+// the detailed status that is printed with [s]
+fn format_status(status: &Status, paused: bool) -> String {
+    let eta = match status.eta_secs {
+        Some(secs) => humantime::format_duration(Duration::from_secs(secs)).to_string(),
+        None => "unknown".to_string(),
+    };
+    tinfof!("[*]", "{}{} of {} attempts after {}, {} valid, {} errors, {} retries, {} queued, {}/s, eta {}",
+        if paused { "paused, " } else { "" },
+        status.done, status.total,
+        humantime::format_duration(Duration::from_secs(status.elapsed_secs)),
+        status.valid, status.errors, status.retries, status.queued,
+        status.rate, eta)
+}
+
+// --max-success-rate is only checked after this many attempts
+const MIN_SUCCESS_RATE_SAMPLE: usize = 50;
+
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+fn save_session(pool: &Scheduler, path: &Option<String>) -> Result<()> {
+    if let (Some(session), Some(path)) = (pool.session(), path) {
+        session.save(path)
+            .context("Failed to save session")?;
+    }
+    Ok(())
+}
+
+enum Distributed {
+    Serve {
+        listen: String,
+        secret: String,
+        chunk: usize,
+        worker_threads: Option<usize>,
+        agent_rate: Option<f64>,
+    },
+    Worker {
+        connect: String,
+        secret: String,
+    },
+}
+
+// hand out the attempts to workers and collect their results
+fn run_coordinator(listen: &str, settings: Settings, mut report: Report, target: Option<String>) -> Result<()> {
+    let attempts = settings.total;
+    let chunk = settings.chunk;
+    let coordinator = Coordinator::bind(listen, settings)?;
+    tinfo!("[+]", "listening on {}, handing out {} attempts in ranges of {}",
+        coordinator.local_addr()?, attempts, chunk);
+    let events = coordinator.spawn();
+
+    let mut pb = ProgressBar::new(attempts as u64);
+    pb.set_quiet(QUIET.load(Ordering::Relaxed));
+    pb.tick();
+
+    let start = Instant::now();
+    let mut valid = 0;
+    for event in events {
+        match event {
+            Event::Connected(addr) => pb.writeln(tinfof!("[+]", "worker {} connected", addr)),
+            Event::Rejected(addr, err) => pb.writeln(tinfof!("[!]", "rejected worker {}: {}", addr, err)),
+            Event::Disconnected(addr, 0) => pb.writeln(tinfof!("[*]", "worker {} disconnected", addr)),
+            Event::Disconnected(addr, ranges) => {
+                pb.writeln(tinfof!("[!]", "worker {} disconnected, handing out {} ranges again", addr, ranges));
+            },
+            Event::Finding(found) => {
+                match found.password {
+                    Some(ref password) => pb.finding(format_valid_creds(&found.script, &found.user, password)),
+                    None => pb.finding(format_valid_enum(&found.script, &found.user)),
+                }
+
+                let mut record = Record::new(&found.script, &found.user, found.password.as_ref().map(|x| x.as_str()));
+                record.target = target.as_ref().map(|x| x.as_str());
+                record.valid = true;
+                report.write(&record)?;
+                valid += 1;
+                pb.set_counts(valid, 0);
+            },
+            Event::Progress(num) => pb.add(num as u64),
+            Event::Finished => break,
+        }
+    }
+
+    report.flush()?;
+    pb.finish_replace(tinfof!("[+]", "found {} valid credentials with {} attempts after {}\n",
+            valid, attempts, humantime::format_duration(start.elapsed())));
+
+    Ok(())
+}
+
 fn set_nofile(config: &Config) -> Result<()> {
     let (soft_limit, hard_limit) = getrlimit(Resource::RLIMIT_NOFILE)?;
     debug!("soft_limit={:?}, hard_limit={:?}", soft_limit, hard_limit);
@@ -180,6 +818,7 @@ fn set_nofile(config: &Config) -> Result<()> {
 
 fn run() -> Result<()> {
     let args = args::parse();
+    QUIET.store(args.quiet, Ordering::Relaxed);
 
     let env = env_logger::Env::default();
     let env = match args.verbose {
@@ -187,27 +826,305 @@ fn run() -> Result<()> {
         1 => env.filter_or("RUST_LOG", "info"),
         _ => env.filter_or("RUST_LOG", "debug"),
     };
-    env_logger::init_from_env(env);
+    let log_file = match args.log_file {
+        Some(ref path) => Some(Arc::new(LogFile::open(path, args.log_level)?)),
+        None => None,
+    };
+    logfile::Logger::init(env_logger::Builder::from_env(env).build(), log_file.clone())?;
 
     if atty::isnt(atty::Stream::Stdout) {
         colored::control::SHOULD_COLORIZE.set_override(false);
     }
 
-    let config = Arc::new(Config::load()?);
+    let mut config = match args.config {
+        Some(ref path) => Config::from_file(path)?,
+        None => Config::load()?,
+    };
+    if let Some(proxy) = args.proxy {
+        config.runtime.proxies.push(proxy);
+    }
+    if let Some(path) = args.proxy_list {
+        let proxies = utils::load_list(&path)
+                        .context("Failed to load proxy list")?;
+        config.runtime.proxies.extend(proxies.iter()
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string()));
+    }
+    config.runtime.dns_servers.extend(args.dns_servers);
+    dns::set_servers(&config.runtime.dns_servers)?;
+    if args.fresh_connections {
+        config.runtime.fresh_connections = true;
+    }
+    if let Some(path) = args.user_agents {
+        let user_agents = utils::load_list(&path)
+                        .context("Failed to load user agent list")?;
+        config.runtime.user_agents.extend(user_agents.iter()
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string()));
+    }
+    if let Some(rotation) = args.proxy_rotation {
+        config.runtime.proxy_rotation = rotation;
+    }
+    if args.sandbox {
+        config.runtime.deny.extend(sandbox::DANGEROUS);
+    }
+    config.runtime.deny.extend(args.deny);
+    if args.trust {
+        config.runtime.trust = true;
+    }
+    if args.memory_limit.is_some() {
+        config.runtime.memory_limit = args.memory_limit;
+    }
+    if args.instruction_limit.is_some() {
+        config.runtime.instruction_limit = args.instruction_limit;
+    }
+    if let Some(secs) = args.script_timeout.or(config.general.script_timeout) {
+        config.runtime.script_timeout = Some(Duration::from_secs(secs));
+    }
+    if args.allow_fs {
+        config.runtime.allow_fs = true;
+    }
+    config.runtime.options.extend(args.options);
+    if let Some(path) = args.kv_file {
+        config.runtime.kv_file = Some(path);
+    }
+    if let Some(ref path) = config.runtime.kv_file {
+        config.kv = kv::Store::open(path)
+                        .context("Failed to open kv file")?;
+    }
+    if let args::SubCommand::Oneshot(ref oneshot) = args.subcommand {
+        if let Some(ref target) = oneshot.target {
+            config.runtime.options.insert("target".to_string(), target.to_string());
+        }
+    }
+    // plain http requests are sent through the mock server as a proxy
+    let mock = match args.subcommand {
+        args::SubCommand::Test(_) => {
+            let server = MockServer::start()?;
+            config.runtime.proxies = vec![server.url()];
+            config.runtime.options.insert("target".to_string(), server.url());
+            Some(server)
+        },
+        _ => None,
+    };
+    let (subcommand, distributed) = match args.subcommand {
+        args::SubCommand::Serve(serve) => {
+            let distributed = Distributed::Serve {
+                listen: serve.listen,
+                secret: serve.secret,
+                chunk: serve.chunk,
+                worker_threads: serve.worker_threads,
+                agent_rate: serve.agent_rate,
+            };
+            (serve.attack.into(), Some(distributed))
+        },
+        args::SubCommand::Worker(worker) => {
+            let distributed = Distributed::Worker {
+                connect: worker.connect,
+                secret: worker.secret,
+            };
+            (worker.attack.into(), Some(distributed))
+        },
+        subcommand => (subcommand, None),
+    };
+    if distributed.is_some() {
+        if args.skip > 0 || args.limit.is_some() || args.shard.is_some() {
+            bail!("--skip, --limit and --shard can't be used in distributed mode");
+        }
+        if let args::SubCommand::Dict(ref dict) = subcommand {
+            if dict.spray {
+                bail!("--spray can't be used in distributed mode");
+            }
+        }
+    }
+    if let Some(Distributed::Serve { agent_rate, .. }) = distributed {
+        if args.resume.is_some() || args.sqlite.is_some() {
+            bail!("--resume and --sqlite can only be used on the workers");
+        }
+        if let Some(rate) = agent_rate {
+            if !(rate > 0.0) {
+                bail!("--agent-rate has to be greater than 0");
+            }
+        }
+    }
+    let config = Arc::new(config);
     #[cfg(target_os="linux")]
     set_nofile(&config)
         .context("Failed to set RLIMIT_NOFILE")?;
 
-    let mut pool = Scheduler::new(args.workers);
-    let mut report = Report::open(args.output)?;
+    let mut pool = Scheduler::new(args.workers.or(config.general.workers).unwrap_or(16));
+    pool.set_retries(args.retries.or(config.general.retries).unwrap_or(5));
+    pool.set_shuffle(args.shuffle);
+    match distributed {
+        // the coordinator only counts the attempts
+        Some(Distributed::Serve { .. }) => pool.set_slice(Slice {
+            limit: Some(0),
+            ..Default::default()
+        }),
+        // the attempts are started when their range is handed out
+        Some(Distributed::Worker { .. }) => pool.set_hold(true),
+        None => pool.set_slice(Slice {
+            skip: args.skip,
+            limit: args.limit,
+            shard: args.shard,
+        }),
+    }
+    if let Some(limit) = args.connections_per_target {
+        if limit == 0 {
+            bail!("--connections-per-target must be at least 1");
+        }
+        pool.set_per_target(limit);
+    }
+    let format = args.format.or(config.general.format).unwrap_or(Format::Text);
+    let mut report = Report::open(args.output, format, args.log_attempts)?;
+    let mut notifiers = notify::from_config(&config.notify)?;
+    if let Some(ref url) = args.notify_webhook {
+        notifiers.push(Box::new(Webhook::new(url)?));
+    }
+    let notifier = if notifiers.is_empty() {
+        None
+    } else {
+        Some(Notifier::spawn(notifiers))
+    };
+    let notify_on_finish = args.notify_on_finish || config.notify.on_finish;
+    let mut status = match args.status_json {
+        Some(ref dest) => Some(StatusWriter::open(dest)?),
+        None => None,
+    };
+    let metrics = match args.metrics_addr {
+        Some(ref addr) => {
+            let metrics = Arc::new(Metrics::default());
+            metrics::serve(metrics.clone(), addr)
+                .context("Failed to start metrics endpoint")?;
+            Some(metrics)
+        },
+        None => None,
+    };
+
+    let quarantine = args.quarantine;
+    let stop_per_user = args.stop_on_success_per_user;
+    let max_successes = args.max_successes;
+    let max_success_rate = args.max_success_rate;
+    if let Some(rate) = max_success_rate {
+        if rate < 0.0 || rate > 100.0 {
+            bail!("--max-success-rate has to be between 0 and 100");
+        }
+    }
+    let shutdown_timeout = args.shutdown_timeout;
+    let lockout_window = match subcommand {
+        args::SubCommand::Dict(ref dict) if dict.spray => dict.lockout_window,
+        _ => Duration::from_secs(0),
+    };
+
+    let target = config.runtime.options.get("target").cloned();
+
+    let command = format!("{:?}", subcommand);
+    let fingerprint = match distributed {
+        Some(_) => Some(distributed::fingerprint(&subcommand, &config)?),
+        None => None,
+    };
+
+    let results = match args.sqlite {
+        Some(ref path) => {
+            let started = humantime::format_rfc3339(SystemTime::now()).to_string();
+            let db = ResultsDb::open(path, &started, &command)
+                .context("Failed to open results database")?;
+            let findings = db.findings(target.as_ref().map(|x| x.as_str()))?;
+            if findings > 0 {
+                tinfo!("[+]", "{} credentials were found in previous runs", findings);
+            }
+            pool.skip_known(db.known(target.as_ref().map(|x| x.as_str()))?);
+            Some(db)
+        },
+        None => None,
+    };
+
+    if let Some(ref path) = args.resume {
+        let session = if Path::new(path).exists() {
+            let session = Session::load(path)
+                .context("Failed to load session")?;
+            if session.command != command {
+                bail!("Session {:?} was started with a different command", path);
+            }
+            tinfo!("[+]", "resuming session with {} finished attempts", session.progress());
+            // the output file is truncated, so the previous results are written again
+            for valid in &session.valid {
+                match valid.password {
+                    Some(ref password) => println!("{}", format_valid_creds(&valid.script, &valid.user, password)),
+                    None => println!("{}", format_valid_enum(&valid.script, &valid.user)),
+                }
+
+                let mut record = Record::new(&valid.script, &valid.user, valid.password.as_ref().map(|x| x.as_str()));
+                record.target = target.as_ref().map(|x| x.as_str());
+                record.valid = true;
+                report.write(&record)?;
+            }
+            session
+        } else {
+            Session::new(command.clone())
+        };
+        pool.set_session(session);
+    }
 
-    let attempts = match args.subcommand {
+    let attempts = match subcommand {
         args::SubCommand::Dict(dict) => setup_dictionary_attack(&mut pool, dict, &config)?,
         args::SubCommand::Creds(creds) => setup_credential_confirmation(&mut pool, creds, &config)?,
+        args::SubCommand::Combo(combo) => setup_combo_attack(&mut pool, combo, &config)?,
+        args::SubCommand::Mask(mask) => setup_mask_attack(&mut pool, mask, &config)?,
+        args::SubCommand::Defaults(defaults) => setup_defaults_attack(&mut pool, defaults, &config)?,
         args::SubCommand::Enum(enumerate) => setup_enum_attack(&mut pool, enumerate, &config)?,
+        args::SubCommand::Daemon(daemon) => return run_daemon(daemon),
         args::SubCommand::Oneshot(oneshot) => return run_oneshot(oneshot, config),
         args::SubCommand::Fsck(fsck) => return fsck::run_fsck(&fsck),
+        args::SubCommand::ListScripts(list) => return list_scripts(list, &config),
+        args::SubCommand::Check(check) => return run_check(check, &config),
+        args::SubCommand::Repl(args) => return repl::run(&config, args.script.as_ref().map(|x| x.as_str())),
+        args::SubCommand::Test(test) => return run_tests(test, &config, mock.as_ref().unwrap()),
+        args::SubCommand::Scripts(scripts) => return run_scripts(scripts),
+        args::SubCommand::Serve(_) | args::SubCommand::Worker(_) => unreachable!(),
     };
+    let mut agent = match distributed {
+        Some(Distributed::Serve { listen, secret, chunk, worker_threads, agent_rate }) => {
+            let settings = Settings {
+                secret,
+                fingerprint: fingerprint.unwrap(),
+                total: attempts,
+                chunk,
+                workers: worker_threads,
+                rate: agent_rate,
+            };
+            return run_coordinator(&listen, settings, report, target);
+        },
+        Some(Distributed::Worker { connect, secret }) => {
+            let agent = Agent::connect(&connect, secret, fingerprint.unwrap(), attempts)?;
+            tinfo!("[+]", "connected to coordinator {}", connect);
+            if let Some(workers) = agent.workers {
+                tinfo!("[*]", "the coordinator set {} workers", workers);
+                pool.set_workers(workers);
+            }
+            if let Some(rate) = agent.rate {
+                tinfo!("[*]", "the coordinator set a rate of {} attempts per second", rate);
+                pool.set_rate(rate)?;
+            }
+            Some(agent)
+        },
+        None => {
+            pool.flush();
+            None
+        },
+    };
+    pool.set_total(attempts);
+    let included = pool.included(attempts);
+    if included < attempts {
+        tinfo!("[*]", "running {} of {} attempts in this slice", included, attempts);
+    }
+    if pool.skipped() > 0 {
+        tinfo!("[*]", "skipped {} attempts that are already done", pool.skipped());
+    }
+    let attempts = included - pool.skipped();
+    // attempts from a wordlist are only skipped when they are generated
+    let skipped_before = pool.skipped();
+    let mut skipped_later = 0;
 
     let tx = pool.tx();
     thread::spawn(move || {
@@ -217,19 +1134,115 @@ fn run() -> Result<()> {
             tx.send(Msg::Key(key)).expect("failed to send key");
         }
     });
+    logfile::set_terminal(pool.tx());
 
     let mut pb = ProgressBar::new(attempts as u64);
+    pb.set_quiet(QUIET.load(Ordering::Relaxed));
     pb.print_help();
     pb.tick();
 
+    shutdown::setup(pool.tx())
+        .context("Failed to setup signal handlers")?;
+
     pool.resume();
     let start = Instant::now();
 
+    let mut last_save = Instant::now();
+    let mut deadline = None;
+    let mut aborted = None;
+    let mut finished = 0;
     let mut valid = 0;
     let mut retries = 0;
     let mut expired = 0;
-    while pool.has_work() {
-        match pool.recv() {
+    let mut errors = 0;
+    let mut targets: BTreeMap<String, TargetStats> = BTreeMap::new();
+    // the target of the last attempt, this is skipped with [n]
+    let mut current: Option<String> = None;
+    let mut skipped = HashSet::new();
+    loop {
+        if deadline.is_none() && shutdown::requested() {
+            deadline = Some(drain(&mut pool, &mut pb, "shutting down", shutdown_timeout));
+        }
+        pb.set_counts(valid, errors);
+
+        if let Some(ref mut status) = status {
+            if status.is_due() {
+                status.write(&Status {
+                    valid,
+                    errors,
+                    retries,
+                    queued: pool.queued(),
+                    ..Status::new(start.elapsed(), finished + expired + pool.dropped() + skipped_later, attempts)
+                })?;
+            }
+        }
+
+        let msg = if let Some(deadline) = deadline {
+            if !pool.has_work() {
+                break;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                pb.writeln(tinfof!("[!]", "giving up on {} running attempts", pool.inflight()));
+                break;
+            }
+
+            match pool.recv_timeout(deadline - now) {
+                Some(msg) => msg,
+                None => continue,
+            }
+        } else {
+            pool.run_delayed();
+            pool.refill()?;
+            let done = pool.skipped() - skipped_before;
+            if done > skipped_later {
+                pb.add((done - skipped_later) as u64);
+                skipped_later = done;
+            }
+
+            if !pool.has_work() {
+                if pool.has_rounds() {
+                    wait_lockout_window(&mut pb, lockout_window);
+                    pool.start_round();
+                    continue;
+                }
+
+                // ask the coordinator for the next range
+                if let Some(ref mut agent) = agent {
+                    if let Some((start, end)) = agent.next_range(pool.floor())? {
+                        pool.run_range(start, end)?;
+                        continue;
+                    }
+                }
+                break;
+            }
+
+            if let Some(until) = pool.next_delayed() {
+                wait_delayed(&mut pb, until);
+                continue;
+            }
+
+            if last_save.elapsed() >= SESSION_SAVE_INTERVAL {
+                save_session(&pool, &args.resume)?;
+                last_save = Instant::now();
+            }
+
+            match status {
+                // wake up for the next status
+                Some(ref status) => match pool.recv_timeout(status.timeout()) {
+                    Some(msg) => msg,
+                    None => continue,
+                },
+                None => pool.recv(),
+            }
+        };
+
+        if let Some(ref metrics) = metrics {
+            metrics.set_queued(pool.queued());
+        }
+
+        match msg {
             Msg::Key(key) => {
                 match key {
                     Key::H => pb.print_help(),
@@ -241,6 +1254,27 @@ fn run() -> Result<()> {
                         pb.writeln(format!("{} {}", "[*]".bold(), "resuming threads".dimmed()));
                         pool.resume();
                     },
+                    Key::S => {
+                        let status = Status {
+                            valid,
+                            errors,
+                            retries,
+                            queued: pool.queued(),
+                            ..Status::new(start.elapsed(), finished + expired + pool.dropped() + skipped_later, attempts)
+                        };
+                        pb.writeln(format_status(&status, pool.is_paused()));
+                        for (target, stats) in &targets {
+                            pb.writeln(tinfof!("[*]", "{}", stats.summary(target)));
+                        }
+                    },
+                    Key::N => match current.take() {
+                        Some(target) => {
+                            pb.writeln(format!("{} {}", "[*]".bold(), format!("skipping the remaining attempts on {}", target).dimmed()));
+                            pool.drop_target(&target);
+                            skipped.insert(target);
+                        },
+                        None => pb.writeln(format!("{} {}", "[*]".bold(), "no target to skip".dimmed())),
+                    },
                     Key::Plus => {
                         let num = pool.incr();
                         pb.writeln(format!("{} {}", "[*]".bold(), format!("increased to {} threads", num).dimmed()));
@@ -252,43 +1286,180 @@ fn run() -> Result<()> {
                 }
                 pb.tick();
             },
-            Msg::Attempt(mut attempt, result) => {
+            Msg::Delayed(attempt, until) => {
+                pool.delay(*attempt, until);
+            },
+            // the attempt is left for the next run
+            Msg::Cancelled(_) => (),
+            // debug lines are only shown with -v
+            Msg::Log(line) => if line.level <= Level::Info || args.verbose > 0 {
+                pb.writeln(format_script_line(&line));
+            },
+            // handled at the top of the loop
+            Msg::Shutdown => (),
+            Msg::Attempt(mut attempt, result, latency) => {
+                {
+                    let password = match attempt.creds {
+                        Creds::Enum(_) => None,
+                        _ => Some(attempt.password()),
+                    };
+                    let mut record = Record::new(attempt.script.descr(), attempt.user(), password)
+                        .latency(latency);
+                    record.target = target.as_ref().map(|x| x.as_str());
+                    match result {
+                        Ok(is_valid) => record.valid = is_valid,
+                        Err(ref err) => {
+                            record.error = Some(err.to_string());
+                            errors += 1;
+                        },
+                    }
+                    targets.entry(attempt.script.descr().to_string())
+                        .or_insert_with(TargetStats::default)
+                        .add(&record, latency);
+                    if !skipped.contains(attempt.script.descr()) {
+                        current = Some(attempt.script.descr().to_string());
+                    }
+                    report.write(&record)?;
+                    if let Some(ref log_file) = log_file {
+                        log_file.attempt(attempt.id, &record);
+                    }
+                    if let Some(ref db) = results {
+                        db.insert(&record)?;
+                    }
+                    if let Some(ref metrics) = metrics {
+                        let outcome = match result {
+                            Ok(true) => Outcome::Valid,
+                            Ok(false) => Outcome::Invalid,
+                            Err(ref err) => Outcome::Error(metrics::error_class(err)),
+                        };
+                        metrics.observe(attempt.script.descr(), latency, outcome);
+                    }
+                }
+
                 match result {
                     Ok(is_valid) => {
                         if is_valid {
-                            match attempt.creds {
+                            let password = match attempt.creds {
                                 Creds::Enum(_) => {
                                     let user = attempt.user();
                                     let script = attempt.script.descr();
 
-                                    pb.writeln(format_valid_enum(script, user));
-                                    report.write_enum(user, script)?;
+                                    pb.finding(format_valid_enum(script, user));
+                                    None
                                 },
                                 _ => {
                                     let user = attempt.user();
                                     let password = attempt.password();
                                     let script = attempt.script.descr();
 
-                                    pb.writeln(format_valid_creds(script, user, password));
-                                    report.write_creds(user, password, script)?;
+                                    pb.finding(format_valid_creds(script, user, password));
+                                    Some(password.to_string())
                                 },
                             };
+                            let found = Valid {
+                                script: attempt.script.descr().to_string(),
+                                user: attempt.user().to_string(),
+                                password,
+                            };
+                            if let Some(ref mut agent) = agent {
+                                agent.report(found.clone())?;
+                            }
+                            if let Some(ref notifier) = notifier {
+                                notifier.send(notify::Event::Valid {
+                                    timestamp: humantime::format_rfc3339(SystemTime::now()).to_string(),
+                                    script: found.script.clone(),
+                                    target: target.clone(),
+                                    user: found.user.clone(),
+                                    password: found.password.clone(),
+                                });
+                            }
+                            pool.add_valid(found);
                             valid += 1;
+
+                            if stop_per_user {
+                                pool.drop_user(attempt.script.descr(), attempt.user());
+                            }
+                        }
+                        pool.mark_done(attempt.id);
+                        finished += 1;
+                        pb.inc();
+
+                        if deadline.is_none() {
+                            if max_successes.map_or(false, |max| valid >= max) {
+                                let reason = format!("found {} valid credentials, stopping", valid);
+                                deadline = Some(drain(&mut pool, &mut pb, &reason, shutdown_timeout));
+                            } else if let Some(max) = max_success_rate {
+                                let rate = valid as f64 / finished as f64 * 100.0;
+                                if finished >= MIN_SUCCESS_RATE_SAMPLE && rate > max {
+                                    aborted = Some(format!("{:.1}% of the attempts are valid, the script is probably broken", rate));
+                                    deadline = Some(drain(&mut pool, &mut pb, "aborting", shutdown_timeout));
+                                }
+                            }
+                        }
+                    },
+                    Err(ref err) if err.downcast_ref::<Lockout>().is_some() => {
+                        let lockout = *err.downcast_ref::<Lockout>().unwrap();
+                        let until = pool.quarantine(lockout, &attempt, quarantine);
+                        if attempt.ttl > 0 {
+                            // every quarantine uses up a retry, a target that
+                            // is always locked would be tried forever
+                            attempt.ttl -= 1;
+                            pb.writeln(format!("{} {}({}, {}): {}, retrying in {}", "[!]".bold(), "locked".red(), attempt.script.descr().yellow(), format!("{:?}", attempt.user()).dimmed(), err, humantime::format_duration(quarantine)));
+                            pool.delay(*attempt, until);
+                            pb.tick();
+                        } else {
+                            // tried again when the session is resumed
+                            pb.writeln(format!("{} {}({}, {}): {}, giving up", "[!]".bold(), "locked".red(), attempt.script.descr().yellow(), format!("{:?}", attempt.user()).dimmed(), err));
+                            expired += 1;
+                            pb.inc();
                         }
+                    },
+                    Err(ref err) if retry::classify(err) == Some(ErrorKind::Fatal) => {
+                        pb.writeln(format!("{} {}({}, {}): {}", "[!]".bold(), "fatal".red(), attempt.script.descr().yellow(), format!("{:?}", attempt.user()).dimmed(), err));
+                        if deadline.is_none() {
+                            deadline = Some(drain(&mut pool, &mut pb, "aborting", shutdown_timeout));
+                            aborted = Some(format!("fatal error in {}: {}", attempt.script.descr(), err));
+                        }
+                        // counted like an attempt that ran out of retries
+                        expired += 1;
+                        pool.mark_done(attempt.id);
+                        pb.inc();
+                    },
+                    Err(ref err) if retry::classify(err) == Some(ErrorKind::TargetDead) => {
+                        pb.writeln(format!("{} {}({}): {}, dropping the remaining attempts", "[!]".bold(), "dead".red(), attempt.script.descr().yellow(), err));
+                        pool.drop_target(attempt.script.descr());
+                        // counted like an attempt that ran out of retries
+                        expired += 1;
+                        pool.mark_done(attempt.id);
                         pb.inc();
                     },
                     Err(err) => {
                         pb.writeln(format!("{} {}({}, {}): {:?}", "[!]".bold(), "error".red(), attempt.script.descr().yellow(), format!("{:?}:{:?}", attempt.user(), attempt.password()).dimmed(), err));
 
-                        if attempt.ttl > 0 {
+                        let transient = retry::is_transient(&err);
+
+                        if pool.is_draining() {
+                            // not retried, but not done either
+                            pb.tick();
+                        } else if attempt.ttl > 0 {
                             // we have retries left
                             retries += 1;
+                            let retry = pool.retries() - attempt.ttl;
                             attempt.ttl -= 1;
-                            pool.run(*attempt);
+                            if transient {
+                                let until = Instant::now() + retry::backoff(retry);
+                                pool.delay(*attempt, until);
+                            } else {
+                                pool.run(*attempt);
+                            }
                             pb.tick();
                         } else {
                             // giving up
                             expired += 1;
+                            // transient errors are tried again when the session is resumed
+                            if !transient {
+                                pool.mark_done(attempt.id);
+                            }
                             pb.inc();
                         }
                     }
@@ -297,8 +1468,22 @@ fn run() -> Result<()> {
         }
     }
 
+    report.flush()?;
+    save_session(&pool, &args.resume)?;
+
+    if let Some(ref mut status) = status {
+        status.write(&Status {
+            valid,
+            errors,
+            retries,
+            queued: pool.queued(),
+            ..Status::new(start.elapsed(), finished + expired + pool.dropped() + skipped_later, attempts)
+        })?;
+    }
+
+    let attempts = attempts - skipped_later;
     let elapsed = start.elapsed();
-    let average = elapsed / attempts as u32;
+    let average = elapsed / cmp::max(attempts, 1) as u32;
     pb.finish_replace(tinfof!("[+]", "found {} valid credentials with {} attempts and {} retries after {} and on average {} per attempt. {} attempts expired.\n",
             valid, attempts, retries,
             humantime::format_duration(elapsed),
@@ -306,13 +1491,56 @@ fn run() -> Result<()> {
             expired,
     ));
 
+    if targets.len() > 1 {
+        print_targets(&targets);
+    }
+
+    if skipped_later > 0 {
+        tinfo!("[*]", "skipped {} attempts that are already done", skipped_later);
+    }
+
+    if pool.dropped() > 0 {
+        tinfo!("[*]", "dropped {} attempts on dead or skipped targets and users that were found", pool.dropped());
+    }
+
+    if deadline.is_some() {
+        match args.resume {
+            Some(ref path) => {
+                tinfo!("[*]", "interrupted, continue with --resume {}", path);
+            },
+            None => {
+                tinfo!("[*]", "interrupted, use --resume to save the progress of the next run",);
+            },
+        }
+    }
+
+    if let Some(notifier) = notifier {
+        if notify_on_finish {
+            notifier.send(notify::Event::Finished {
+                valid,
+                attempts,
+                elapsed_secs: elapsed.as_secs(),
+                interrupted: deadline.is_some(),
+                aborted: aborted.clone(),
+            });
+        }
+        notifier.finish();
+    }
+
     Keyboard::reset();
 
+    if let Some(err) = aborted {
+        bail!("Aborted: {}", err);
+    }
+
     Ok(())
 }
 
 fn main() {
-    if let Err(err) = run() {
+    let result = run();
+    // the log file is written in batches
+    log::logger().flush();
+    if let Err(err) = result {
         eprintln!("Error: {}", err);
         for cause in err.iter_chain().skip(1) {
             eprintln!("Because: {}", cause);
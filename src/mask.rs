@@ -0,0 +1,211 @@
+// mask based candidate generation, similar to hashcat
+//
+// ?l  abcdefghijklmnopqrstuvwxyz
+// ?u  ABCDEFGHIJKLMNOPQRSTUVWXYZ
+// ?d  0123456789
+// ?h  0123456789abcdef
+// ?H  0123456789ABCDEF
+// ?s  special characters, including space
+// ?a  ?l?u?d?s
+// ?1  to ?4 custom charsets, they may contain other charsets like `?l?d`
+// ??  a literal question mark
+//
+// everything else is used as is, `admin?d?d` generates admin00 to admin99.
+
+use errors::*;
+
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const HEX_LOWER: &str = "0123456789abcdef";
+const HEX_UPPER: &str = "0123456789ABCDEF";
+const SPECIAL: &str = " !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+fn builtin(c: char) -> Option<String> {
+    let x = match c {
+        'l' => LOWER.to_string(),
+        'u' => UPPER.to_string(),
+        'd' => DIGITS.to_string(),
+        'h' => HEX_LOWER.to_string(),
+        'H' => HEX_UPPER.to_string(),
+        's' => SPECIAL.to_string(),
+        'a' => format!("{}{}{}{}", LOWER, UPPER, DIGITS, SPECIAL),
+        _ => return None,
+    };
+    Some(x)
+}
+
+// expands the builtin charsets in a custom charset
+fn expand_charset(x: &str) -> Result<Vec<char>> {
+    let mut charset = Vec::new();
+    let mut chars = x.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '?' {
+            charset.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('?') => charset.push('?'),
+            Some(c) => match builtin(c) {
+                Some(x) => charset.extend(x.chars()),
+                None => bail!("Invalid charset in custom charset: ?{}", c),
+            },
+            None => bail!("Custom charset ends with incomplete charset"),
+        }
+    }
+
+    charset.sort();
+    charset.dedup();
+    Ok(charset)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Mask {
+    positions: Vec<Vec<char>>,
+}
+
+impl Mask {
+    pub fn parse(mask: &str, custom: &[Option<String>]) -> Result<Mask> {
+        let mut positions = Vec::new();
+        let mut chars = mask.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '?' {
+                positions.push(vec![c]);
+                continue;
+            }
+
+            let c = chars.next()
+                        .ok_or_else(|| format_err!("Mask ends with incomplete charset"))?;
+            let charset = match c {
+                '?' => vec!['?'],
+                '1'..='4' => {
+                    let idx = c as usize - '1' as usize;
+                    match custom.get(idx) {
+                        Some(Some(x)) => expand_charset(x)?,
+                        _ => bail!("Custom charset ?{} is not defined", c),
+                    }
+                },
+                _ => match builtin(c) {
+                    Some(x) => x.chars().collect(),
+                    None => bail!("Invalid charset in mask: ?{}", c),
+                },
+            };
+
+            if charset.is_empty() {
+                bail!("Charset ?{} is empty", c);
+            }
+            positions.push(charset);
+        }
+
+        Ok(Mask {
+            positions,
+        })
+    }
+
+    // returns None if the keyspace doesn't fit into an u64
+    pub fn keyspace(&self) -> Option<u64> {
+        self.positions.iter()
+            .try_fold(1u64, |acc, x| acc.checked_mul(x.len() as u64))
+    }
+
+    pub fn iter(&self) -> MaskIter {
+        MaskIter {
+            positions: self.positions.clone(),
+            idx: vec![0; self.positions.len()],
+            done: false,
+        }
+    }
+}
+
+// owns the charsets, so the candidates can be generated while the run
+// progresses
+pub struct MaskIter {
+    positions: Vec<Vec<char>>,
+    idx: Vec<usize>,
+    done: bool,
+}
+
+impl Iterator for MaskIter {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+
+        let candidate = self.idx.iter()
+            .zip(self.positions.iter())
+            .map(|(i, charset)| charset[*i])
+            .collect();
+
+        // increment the last position first, like an odometer
+        self.done = true;
+        for (i, charset) in self.idx.iter_mut().zip(self.positions.iter()).rev() {
+            *i += 1;
+            if *i < charset.len() {
+                self.done = false;
+                break;
+            }
+            *i = 0;
+        }
+
+        Some(candidate)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(mask: &str, custom: &[Option<String>]) -> Vec<String> {
+        Mask::parse(mask, custom).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_mask() {
+        let x = generate("a?d", &[]);
+        assert_eq!(x, vec!["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "a8", "a9"]);
+    }
+
+    #[test]
+    fn test_mask_order() {
+        let x = generate("?1?1", &[Some("ab".to_string())]);
+        assert_eq!(x, vec!["aa", "ab", "ba", "bb"]);
+    }
+
+    #[test]
+    fn test_mask_literal() {
+        assert_eq!(generate("admin??", &[]), vec!["admin?"]);
+        assert_eq!(generate("", &[]), vec![""]);
+    }
+
+    #[test]
+    fn test_custom_charset() {
+        let x = generate("?2", &[None, Some("?dx?d".to_string())]);
+        assert_eq!(x, vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "x"]);
+    }
+
+    #[test]
+    fn test_keyspace() {
+        let mask = Mask::parse("?u?l?l?l?d?d", &[]).unwrap();
+        assert_eq!(mask.keyspace(), Some(26 * 26 * 26 * 26 * 10 * 10));
+        let mask = Mask::parse("?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a", &[]).unwrap();
+        assert_eq!(mask.keyspace(), None);
+        let mask = Mask::parse("?a", &[]).unwrap();
+        assert_eq!(mask.keyspace(), Some(95));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Mask::parse("?", &[]).is_err());
+        assert!(Mask::parse("?x", &[]).is_err());
+        assert!(Mask::parse("?1", &[]).is_err());
+        assert!(Mask::parse("?1", &[Some("".to_string())]).is_err());
+        assert!(Mask::parse("?1", &[Some("?z".to_string())]).is_err());
+    }
+}
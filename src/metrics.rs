@@ -0,0 +1,226 @@
+// prometheus metrics that are served with --metrics-addr
+//
+// attempts per second are not tracked here, use rate() on the counters.
+// Targets are identified by the descr of the script.
+
+use errors::*;
+
+use ctx::{ErrorKind, Lockout};
+use daemon::read_request;
+use retry;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+
+// upper bounds of the latency buckets in seconds
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // not cumulative, one more for +Inf
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len() + 1];
+        }
+        let idx = BUCKETS.iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Valid,
+    Invalid,
+    Error(&'static str),
+}
+
+// the label of an error in badtouch_errors_total
+pub fn error_class(err: &Error) -> &'static str {
+    if err.downcast_ref::<Lockout>().is_some() {
+        return "lockout";
+    }
+    match retry::classify(err) {
+        Some(ErrorKind::Transient) => "transient",
+        Some(ErrorKind::Fatal) => "fatal",
+        Some(ErrorKind::TargetDead) => "target_dead",
+        None => "other",
+    }
+}
+
+fn escape(x: &str) -> String {
+    x.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    attempts: AtomicUsize,
+    valid: AtomicUsize,
+    queued: AtomicUsize,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    latency: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn observe(&self, target: &str, latency: Duration, outcome: Outcome) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        match outcome {
+            Outcome::Valid => {
+                self.valid.fetch_add(1, Ordering::Relaxed);
+            },
+            Outcome::Invalid => (),
+            Outcome::Error(class) => {
+                *self.errors.lock().unwrap().entry(class).or_insert(0) += 1;
+            },
+        }
+
+        let secs = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+        self.latency.lock().unwrap()
+            .entry(target.to_string())
+            .or_default()
+            .observe(secs);
+    }
+
+    // attempts that are submitted, delayed or waiting for a connection
+    #[inline]
+    pub fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    // text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "# HELP badtouch_attempts_total Attempts that finished, including errors").unwrap();
+        writeln!(out, "# TYPE badtouch_attempts_total counter").unwrap();
+        writeln!(out, "badtouch_attempts_total {}", self.attempts.load(Ordering::Relaxed)).unwrap();
+
+        writeln!(out, "# HELP badtouch_valid_total Valid credentials").unwrap();
+        writeln!(out, "# TYPE badtouch_valid_total counter").unwrap();
+        writeln!(out, "badtouch_valid_total {}", self.valid.load(Ordering::Relaxed)).unwrap();
+
+        writeln!(out, "# HELP badtouch_errors_total Attempts that failed with an error").unwrap();
+        writeln!(out, "# TYPE badtouch_errors_total counter").unwrap();
+        for (class, num) in self.errors.lock().unwrap().iter() {
+            writeln!(out, "badtouch_errors_total{{class=\"{}\"}} {}", class, num).unwrap();
+        }
+
+        writeln!(out, "# HELP badtouch_queue_depth Attempts that are queued, delayed or waiting").unwrap();
+        writeln!(out, "# TYPE badtouch_queue_depth gauge").unwrap();
+        writeln!(out, "badtouch_queue_depth {}", self.queued.load(Ordering::Relaxed)).unwrap();
+
+        writeln!(out, "# HELP badtouch_attempt_duration_seconds Latency of the attempts").unwrap();
+        writeln!(out, "# TYPE badtouch_attempt_duration_seconds histogram").unwrap();
+        for (target, histogram) in self.latency.lock().unwrap().iter() {
+            let target = escape(target);
+            let mut cumulative = 0;
+            for (le, num) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += num;
+                writeln!(out, "badtouch_attempt_duration_seconds_bucket{{target=\"{}\",le=\"{}\"}} {}",
+                         target, le, cumulative).unwrap();
+            }
+            writeln!(out, "badtouch_attempt_duration_seconds_bucket{{target=\"{}\",le=\"+Inf\"}} {}",
+                     target, histogram.count).unwrap();
+            writeln!(out, "badtouch_attempt_duration_seconds_sum{{target=\"{}\"}} {}", target, histogram.sum).unwrap();
+            writeln!(out, "badtouch_attempt_duration_seconds_count{{target=\"{}\"}} {}", target, histogram.count).unwrap();
+        }
+
+        out
+    }
+}
+
+fn handle(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    let req = {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        read_request(&mut reader)?
+    };
+
+    if req.method == "GET" && (req.path == "/metrics" || req.path == "/") {
+        let body = metrics.render();
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               body.len(), body)?;
+    } else {
+        write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+    }
+    Ok(())
+}
+
+// serve the metrics in the background
+pub fn serve(metrics: Arc<Metrics>, addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .context(format!("Failed to listen on {:?}", addr))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("failed to accept connection: {}", err);
+                    continue;
+                },
+            };
+            if let Err(err) = handle(stream, &metrics) {
+                info!("metrics request failed: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.observe("example.com", Duration::from_millis(30), Outcome::Invalid);
+        metrics.observe("example.com", Duration::from_millis(200), Outcome::Valid);
+        metrics.observe("example.com", Duration::from_secs(60), Outcome::Error("transient"));
+        metrics.set_queued(12);
+
+        let out = metrics.render();
+        assert!(out.contains("badtouch_attempts_total 3\n"));
+        assert!(out.contains("badtouch_valid_total 1\n"));
+        assert!(out.contains("badtouch_errors_total{class=\"transient\"} 1\n"));
+        assert!(out.contains("badtouch_queue_depth 12\n"));
+        assert!(out.contains("badtouch_attempt_duration_seconds_bucket{target=\"example.com\",le=\"0.025\"} 0\n"));
+        assert!(out.contains("badtouch_attempt_duration_seconds_bucket{target=\"example.com\",le=\"0.05\"} 1\n"));
+        assert!(out.contains("badtouch_attempt_duration_seconds_bucket{target=\"example.com\",le=\"30\"} 2\n"));
+        assert!(out.contains("badtouch_attempt_duration_seconds_bucket{target=\"example.com\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("badtouch_attempt_duration_seconds_count{target=\"example.com\"} 3\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_error_class() {
+        assert_eq!(error_class(&Lockout::User.into()), "lockout");
+        let err: Error = ::std::io::Error::new(::std::io::ErrorKind::TimedOut, "timeout").into();
+        assert_eq!(error_class(&err), "transient");
+        assert_eq!(error_class(&format_err!("lua returned wrong type")), "other");
+    }
+}
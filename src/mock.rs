@@ -0,0 +1,390 @@
+// mock http server for `badtouch test`
+//
+// scripts can define test cases in a top-level tests table, or in a sidecar
+// file next to the script (example.lua and example.tests.json). Every test
+// case sets the responses of the mock server and the result that verify is
+// expected to return for the credentials.
+//
+// tests = {
+//     {
+//         name = "valid login",
+//         user = "admin",
+//         password = "hunter2",
+//         expect = true,
+//         routes = {
+//             {method = "POST", path = "/login", contains = "hunter2", status = 302},
+//             {path = "/login", status = 200, body = "invalid password"},
+//         },
+//     },
+// }
+//
+// expect is true, false or "error". The target option is set to the url of
+// the mock server, and plain http requests are sent through it as a proxy,
+// so scripts with a hardcoded http url can be tested as well.
+
+use errors::*;
+
+use daemon::{read_request, reason};
+use hlua::AnyLuaValue;
+use json::LuaJsonValue;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+
+// example.lua is tested with example.tests.json
+pub const SIDECAR: &str = ".tests.json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expect {
+    Valid,
+    Invalid,
+    Error,
+}
+
+impl<'de> Deserialize<'de> for Expect {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Expect, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Str(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(Expect::Valid),
+            Raw::Bool(false) => Ok(Expect::Invalid),
+            Raw::Str(ref x) if x == "error" => Ok(Expect::Error),
+            Raw::Str(x) => Err(de::Error::custom(format!("expected true, false or \"error\", got {:?}", x))),
+        }
+    }
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Expect::Valid => "valid",
+            Expect::Invalid => "invalid",
+            Expect::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<&Result<bool>> for Expect {
+    fn from(result: &Result<bool>) -> Expect {
+        match *result {
+            Ok(true) => Expect::Valid,
+            Ok(false) => Expect::Invalid,
+            Err(_) => Expect::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Route {
+    // any method if it isn't set
+    pub method: Option<String>,
+    pub path: String,
+    // only matches if the request body contains this
+    pub contains: Option<String>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl Route {
+    fn matches(&self, method: &str, path: &str, body: &[u8]) -> bool {
+        if let Some(ref expected) = self.method {
+            if !expected.eq_ignore_ascii_case(method) {
+                return false;
+            }
+        }
+        if self.path != path {
+            return false;
+        }
+        match self.contains {
+            Some(ref needle) => String::from_utf8_lossy(body).contains(needle.as_str()),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TestCase {
+    pub name: Option<String>,
+    pub user: String,
+    // scripts that enumerate users are tested without a password
+    pub password: Option<String>,
+    pub expect: Expect,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+}
+
+impl TestCase {
+    pub fn name(&self) -> String {
+        match (&self.name, &self.password) {
+            (Some(ref name), _) => name.clone(),
+            (None, Some(ref password)) => format!("{:?}:{:?}", self.user, password),
+            (None, None) => format!("{:?}", self.user),
+        }
+    }
+}
+
+// the tests table of a script
+pub fn from_lua(x: AnyLuaValue) -> Result<Vec<TestCase>> {
+    match x {
+        AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(Vec::new()),
+        _ => (),
+    }
+
+    let x = LuaJsonValue::from(x);
+    let x = serde_json::from_value(x.into())?;
+    Ok(x)
+}
+
+// the sidecar file of a script, if there is one
+pub fn load_sidecar(script: &str) -> Result<Vec<TestCase>> {
+    let path = Path::new(script).with_extension("tests.json");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = File::open(&path)?;
+    let tests = serde_json::from_reader(file)
+        .context(format!("Invalid tests in {:?}", path))?;
+    Ok(tests)
+}
+
+// requests through the proxy have an absolute url
+fn request_path(target: &str) -> &str {
+    match target.find("://") {
+        Some(idx) => {
+            let rest = &target[idx + 3..];
+            rest.find('/')
+                .map(|idx| &rest[idx..])
+                .unwrap_or("/")
+        },
+        None => target,
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    routes: Arc<Mutex<Vec<Route>>>,
+    // requests that didn't match any route
+    unmatched: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    // listens on a random port on localhost
+    pub fn start() -> Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .context("Failed to start mock server")?;
+        let addr = listener.local_addr()?;
+
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let unmatched = Arc::new(Mutex::new(Vec::new()));
+
+        let r = routes.clone();
+        let u = unmatched.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = r.clone();
+                let unmatched = u.clone();
+                thread::spawn(move || {
+                    if let Err(err) = handle(stream, &routes, &unmatched) {
+                        debug!("mock request failed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(MockServer {
+            addr,
+            routes,
+            unmatched,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    // the routes of the next test case
+    pub fn set_routes(&self, routes: Vec<Route>) {
+        *self.routes.lock().unwrap() = routes;
+        self.unmatched.lock().unwrap().clear();
+    }
+
+    pub fn unmatched(&self) -> Vec<String> {
+        self.unmatched.lock().unwrap().clone()
+    }
+}
+
+fn handle(mut stream: TcpStream, routes: &Mutex<Vec<Route>>, unmatched: &Mutex<Vec<String>>) -> Result<()> {
+    let req = {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        read_request(&mut reader)?
+    };
+    let path = request_path(&req.path);
+
+    // the first matching route is used
+    let route = routes.lock().unwrap().iter()
+        .find(|route| route.matches(&req.method, path, &req.body))
+        .cloned();
+
+    match route {
+        Some(route) => {
+            let mut head = format!("HTTP/1.1 {} {}\r\n", route.status, reason(route.status));
+            for (key, value) in &route.headers {
+                head += &format!("{}: {}\r\n", key, value);
+            }
+            head += &format!("Content-Length: {}\r\nConnection: close\r\n\r\n", route.body.len());
+            stream.write_all(head.as_bytes())?;
+            stream.write_all(route.body.as_bytes())?;
+        },
+        None => {
+            unmatched.lock().unwrap().push(format!("{} {}", req.method, path));
+            write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+        },
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    fn s(x: &str) -> AnyLuaValue {
+        AnyLuaValue::LuaString(x.to_string())
+    }
+
+    fn get(server: &MockServer, request: &str) -> String {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).ok();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_from_lua() {
+        let x = AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaArray(vec![
+                (s("user"), s("admin")),
+                (s("password"), s("hunter2")),
+                (s("expect"), AnyLuaValue::LuaBoolean(true)),
+                (s("routes"), AnyLuaValue::LuaArray(vec![
+                    (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaArray(vec![
+                        (s("path"), s("/login")),
+                        (s("status"), AnyLuaValue::LuaNumber(302.0)),
+                    ])),
+                ])),
+            ])),
+            (AnyLuaValue::LuaNumber(2.0), AnyLuaValue::LuaArray(vec![
+                (s("user"), s("root")),
+                (s("expect"), s("error")),
+            ])),
+        ]);
+        let tests = from_lua(x).unwrap();
+        assert_eq!(tests, vec![
+            TestCase {
+                name: None,
+                user: "admin".to_string(),
+                password: Some("hunter2".to_string()),
+                expect: Expect::Valid,
+                routes: vec![Route {
+                    method: None,
+                    path: "/login".to_string(),
+                    contains: None,
+                    status: 302,
+                    headers: BTreeMap::new(),
+                    body: String::new(),
+                }],
+            },
+            TestCase {
+                name: None,
+                user: "root".to_string(),
+                password: None,
+                expect: Expect::Error,
+                routes: vec![],
+            },
+        ]);
+        assert_eq!(tests[0].name(), r#""admin":"hunter2""#);
+    }
+
+    #[test]
+    fn test_from_lua_invalid_expect() {
+        let x = AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaArray(vec![
+                (s("user"), s("admin")),
+                (s("expect"), s("valid")),
+            ])),
+        ]);
+        assert!(from_lua(x).is_err());
+        assert_eq!(from_lua(AnyLuaValue::LuaNil).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_request_path() {
+        assert_eq!(request_path("/login"), "/login");
+        assert_eq!(request_path("http://example.com/login"), "/login");
+        assert_eq!(request_path("http://example.com"), "/");
+    }
+
+    #[test]
+    fn test_mock_server() {
+        let server = MockServer::start().unwrap();
+        let mut headers = BTreeMap::new();
+        headers.insert("Location".to_string(), "/home".to_string());
+        server.set_routes(vec![
+            Route {
+                method: Some("POST".to_string()),
+                path: "/login".to_string(),
+                contains: Some("hunter2".to_string()),
+                status: 302,
+                headers,
+                body: String::new(),
+            },
+            Route {
+                method: None,
+                path: "/login".to_string(),
+                contains: None,
+                status: 200,
+                headers: BTreeMap::new(),
+                body: "invalid password".to_string(),
+            },
+        ]);
+
+        let resp = get(&server, "POST /login HTTP/1.1\r\nContent-Length: 12\r\n\r\npass=hunter2");
+        assert!(resp.starts_with("HTTP/1.1 302 "));
+        assert!(resp.contains("Location: /home\r\n"));
+
+        let resp = get(&server, "POST http://example.com/login HTTP/1.1\r\nContent-Length: 10\r\n\r\npass=12345");
+        assert!(resp.starts_with("HTTP/1.1 200 "));
+        assert!(resp.ends_with("invalid password"));
+
+        let resp = get(&server, "GET /admin HTTP/1.1\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 "));
+        assert_eq!(server.unmatched(), vec!["GET /admin".to_string()]);
+    }
+}
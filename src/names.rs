@@ -0,0 +1,98 @@
+// generate common username formats from full names, eg. for password spraying
+//
+// "John Doe" becomes john.doe, jdoe, j.doe, doe.john, john and johndoe. If a
+// domain is set, every username is also generated with an @domain suffix.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+
+pub fn permutations(name: &str) -> Vec<String> {
+    let parts = name.split_whitespace()
+        .map(|x| x.to_lowercase())
+        .collect::<Vec<_>>();
+
+    let (first, last) = match parts.len() {
+        0 => return Vec::new(),
+        1 => return vec![parts[0].clone()],
+        // middle names are ignored
+        n => (&parts[0], &parts[n - 1]),
+    };
+    let f = first.chars().next().unwrap();
+
+    vec![
+        format!("{}.{}", first, last),
+        format!("{}{}", f, last),
+        format!("{}.{}", f, last),
+        format!("{}.{}", last, first),
+        first.to_string(),
+        format!("{}{}", first, last),
+    ]
+}
+
+pub fn usernames(names: &[Arc<String>], domain: Option<&str>) -> Vec<Arc<String>> {
+    let mut seen = HashSet::new();
+    let mut users = Vec::new();
+
+    for name in names {
+        for user in permutations(name) {
+            let mut candidates = Vec::new();
+            if let Some(domain) = domain {
+                candidates.push(format!("{}@{}", user, domain));
+            }
+            candidates.push(user);
+
+            for user in candidates {
+                if seen.insert(user.clone()) {
+                    users.push(Arc::new(user));
+                }
+            }
+        }
+    }
+
+    users
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(permutations("John Doe"), vec![
+            "john.doe",
+            "jdoe",
+            "j.doe",
+            "doe.john",
+            "john",
+            "johndoe",
+        ]);
+    }
+
+    #[test]
+    fn test_permutations_middle_name() {
+        assert_eq!(permutations(" Jane  Q. Public ")[0], "jane.public");
+    }
+
+    #[test]
+    fn test_permutations_single() {
+        assert_eq!(permutations("admin"), vec!["admin"]);
+        assert!(permutations("  ").is_empty());
+    }
+
+    #[test]
+    fn test_usernames() {
+        let names = vec![Arc::new("Jo Do".to_string()), Arc::new("jo".to_string())];
+        let users = usernames(&names, Some("example.com"));
+        let users = users.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(users, vec![
+            "jo.do@example.com", "jo.do",
+            "jdo@example.com", "jdo",
+            "j.do@example.com", "j.do",
+            "do.jo@example.com", "do.jo",
+            "jo@example.com", "jo",
+            "jodo@example.com", "jodo",
+        ]);
+    }
+}
@@ -0,0 +1,312 @@
+// notifications about valid credentials and finished runs
+//
+// events are sent from a background thread, so a slow notifier doesn't hold
+// up the attempts. Failed notifications are logged and dropped. A new backend
+// only has to implement Notify.
+
+use errors::*;
+
+use config::{ChatConfig, NotifyConfig};
+use reqwest;
+use serde_json::Value;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Valid {
+        timestamp: String,
+        script: String,
+        target: Option<String>,
+        user: String,
+        // None for user enumeration
+        password: Option<String>,
+    },
+    Finished {
+        valid: usize,
+        attempts: usize,
+        elapsed_secs: u64,
+        interrupted: bool,
+        aborted: Option<String>,
+    },
+}
+
+impl Event {
+    // human readable version for chat messages
+    pub fn text(&self) -> String {
+        match *self {
+            Event::Valid { ref script, ref target, ref user, ref password, .. } => {
+                let script = match *target {
+                    Some(ref target) => format!("{} ({})", script, target),
+                    None => script.to_string(),
+                };
+                match *password {
+                    Some(ref password) => format!("badtouch: valid credentials for {}: {:?}:{:?}", script, user, password),
+                    None => format!("badtouch: valid user for {}: {:?}", script, user),
+                }
+            },
+            Event::Finished { valid, attempts, interrupted, ref aborted, .. } => {
+                let mut text = format!("badtouch: found {} valid credentials with {} attempts", valid, attempts);
+                if let Some(ref err) = *aborted {
+                    text += &format!(", aborted: {}", err);
+                } else if interrupted {
+                    text += ", interrupted";
+                }
+                text
+            },
+        }
+    }
+}
+
+pub trait Notify: Send {
+    fn notify(&self, event: &Event) -> Result<()>;
+}
+
+fn client() -> Result<reqwest::blocking::Client> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?;
+    Ok(client)
+}
+
+// slack and telegram return errors with a 200 status
+fn check_ok(resp: reqwest::blocking::Response, service: &str) -> Result<()> {
+    let body: Value = resp.json()?;
+    if body["ok"] != Value::Bool(true) {
+        let err = body["error"].as_str()
+            .or_else(|| body["description"].as_str())
+            .unwrap_or("unknown error");
+        bail!("{} returned an error: {}", service, err);
+    }
+    Ok(())
+}
+
+pub struct Webhook {
+    client: reqwest::blocking::Client,
+    url: reqwest::Url,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Result<Webhook> {
+        let url = url.parse::<reqwest::Url>()
+            .context(format!("Invalid webhook url: {:?}", url))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            bail!("Webhook url has to be http or https: {:?}", url.as_str());
+        }
+        Ok(Webhook {
+            client: client()?,
+            url,
+        })
+    }
+}
+
+impl Notify for Webhook {
+    fn notify(&self, event: &Event) -> Result<()> {
+        self.client.post(self.url.clone())
+            .json(event)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct Slack {
+    client: reqwest::blocking::Client,
+    config: ChatConfig,
+}
+
+impl Slack {
+    pub fn new(config: ChatConfig) -> Result<Slack> {
+        Ok(Slack {
+            client: client()?,
+            config,
+        })
+    }
+}
+
+impl Notify for Slack {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let resp = self.client.post("https://slack.com/api/chat.postMessage")
+            .bearer_auth(&self.config.token)
+            .json(&json!({
+                "channel": self.config.channel,
+                "text": event.text(),
+            }))
+            .send()?
+            .error_for_status()?;
+        check_ok(resp, "slack")
+    }
+}
+
+pub struct Discord {
+    client: reqwest::blocking::Client,
+    config: ChatConfig,
+}
+
+impl Discord {
+    pub fn new(config: ChatConfig) -> Result<Discord> {
+        Ok(Discord {
+            client: client()?,
+            config,
+        })
+    }
+}
+
+impl Notify for Discord {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let url = format!("https://discord.com/api/v10/channels/{}/messages", self.config.channel);
+        self.client.post(&url)
+            .header("Authorization", format!("Bot {}", self.config.token))
+            .json(&json!({
+                "content": event.text(),
+            }))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct Telegram {
+    client: reqwest::blocking::Client,
+    config: ChatConfig,
+}
+
+impl Telegram {
+    pub fn new(config: ChatConfig) -> Result<Telegram> {
+        Ok(Telegram {
+            client: client()?,
+            config,
+        })
+    }
+}
+
+impl Notify for Telegram {
+    fn notify(&self, event: &Event) -> Result<()> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.config.token);
+        let resp = self.client.post(&url)
+            .json(&json!({
+                "chat_id": self.config.channel,
+                "text": event.text(),
+            }))
+            .send()?
+            .error_for_status()?;
+        check_ok(resp, "telegram")
+    }
+}
+
+// the chat backends that are set in the config file
+pub fn from_config(config: &NotifyConfig) -> Result<Vec<Box<dyn Notify>>> {
+    let mut notifiers: Vec<Box<dyn Notify>> = Vec::new();
+    if let Some(ref slack) = config.slack {
+        notifiers.push(Box::new(Slack::new(slack.clone())?));
+    }
+    if let Some(ref discord) = config.discord {
+        notifiers.push(Box::new(Discord::new(discord.clone())?));
+    }
+    if let Some(ref telegram) = config.telegram {
+        notifiers.push(Box::new(Telegram::new(telegram.clone())?));
+    }
+    Ok(notifiers)
+}
+
+pub struct Notifier {
+    tx: mpsc::Sender<Event>,
+    thread: JoinHandle<()>,
+}
+
+impl Notifier {
+    pub fn spawn(notifiers: Vec<Box<dyn Notify>>) -> Notifier {
+        let (tx, rx) = mpsc::channel::<Event>();
+        let thread = thread::spawn(move || {
+            for event in rx {
+                for notifier in &notifiers {
+                    if let Err(err) = notifier.notify(&event) {
+                        error!("failed to send notification: {}", err);
+                    }
+                }
+            }
+        });
+
+        Notifier {
+            tx,
+            thread,
+        }
+    }
+
+    #[inline]
+    pub fn send(&self, event: Event) {
+        self.tx.send(event).ok();
+    }
+
+    // waits until the pending notifications are sent
+    pub fn finish(self) {
+        drop(self.tx);
+        self.thread.join().ok();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::Valid {
+            timestamp: "2018-01-01T00:00:00Z".to_string(),
+            script: "example.com".to_string(),
+            target: None,
+            user: "admin".to_string(),
+            password: Some("hunter2".to_string()),
+        };
+        let x = serde_json::to_string(&event).unwrap();
+        assert_eq!(x, r#"{"event":"valid","timestamp":"2018-01-01T00:00:00Z","script":"example.com","target":null,"user":"admin","password":"hunter2"}"#);
+    }
+
+    #[test]
+    fn test_event_text() {
+        let event = Event::Valid {
+            timestamp: "2018-01-01T00:00:00Z".to_string(),
+            script: "example.com".to_string(),
+            target: Some("10.0.0.1".to_string()),
+            user: "admin".to_string(),
+            password: Some("hunter2".to_string()),
+        };
+        assert_eq!(event.text(), "badtouch: valid credentials for example.com (10.0.0.1): \"admin\":\"hunter2\"");
+
+        let event = Event::Finished {
+            valid: 1,
+            attempts: 200,
+            elapsed_secs: 60,
+            interrupted: true,
+            aborted: None,
+        };
+        assert_eq!(event.text(), "badtouch: found 1 valid credentials with 200 attempts, interrupted");
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = NotifyConfig {
+            telegram: Some(ChatConfig {
+                token: "123:abc".to_string(),
+                channel: "-100123".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(from_config(&config).unwrap().len(), 1);
+        assert!(from_config(&NotifyConfig::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        assert!(Webhook::new("not a url").is_err());
+        assert!(Webhook::new("ftp://example.com/").is_err());
+        assert!(Webhook::new("https://example.com/hook").is_ok());
+    }
+}
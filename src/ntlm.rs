@@ -0,0 +1,525 @@
+use errors::*;
+
+use base64;
+use des::Des;
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::cipher::generic_array::GenericArray;
+use hlua::{AnyLuaValue, AnyLuaString};
+use hmac::{Hmac, Mac};
+use http::Bytes;
+use json::LuaJsonValue;
+use md4::{Md4, Digest};
+use md5::Md5;
+use rand::{self, RngCore};
+use serde_json;
+use structs::LuaMap;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+const SIGNATURE: &[u8] = b"NTLMSSP\0";
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NEGOTIATE_VERSION: u32 = 0x0200_0000;
+const LM_MAGIC: &[u8] = b"KGS!@#$%";
+// seconds between 1601-01-01 and 1970-01-01
+const FILETIME_EPOCH: u64 = 11_644_473_600;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Challenge {
+    pub flags: u32,
+    pub server_challenge: Vec<u8>,
+    pub target_name: Option<String>,
+    pub nb_computer_name: Option<String>,
+    pub nb_domain_name: Option<String>,
+    pub dns_computer_name: Option<String>,
+    pub dns_domain_name: Option<String>,
+    pub dns_tree_name: Option<String>,
+    pub timestamp: Option<u64>,
+    pub version: Option<String>,
+    // raw target info, needed to compute an NTLMv2 response
+    pub target_info: Vec<u8>,
+}
+
+impl Into<AnyLuaValue> for Challenge {
+    fn into(self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        map.insert_num("flags", f64::from(self.flags));
+        map.insert("server_challenge", AnyLuaValue::LuaAnyString(AnyLuaString(self.server_challenge)));
+        map.insert("target_info", AnyLuaValue::LuaAnyString(AnyLuaString(self.target_info)));
+
+        let strings = vec![
+            ("target_name", self.target_name),
+            ("nb_computer_name", self.nb_computer_name),
+            ("nb_domain_name", self.nb_domain_name),
+            ("dns_computer_name", self.dns_computer_name),
+            ("dns_domain_name", self.dns_domain_name),
+            ("dns_tree_name", self.dns_tree_name),
+            ("version", self.version),
+        ];
+        for (k, v) in strings {
+            if let Some(v) = v {
+                map.insert_str(k, v);
+            }
+        }
+
+        if let Some(timestamp) = self.timestamp {
+            map.insert_num("timestamp", timestamp as f64);
+        }
+
+        map.into()
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16> {
+    match buf.get(offset..offset + 2) {
+        Some(x) => Ok(u16::from(x[0]) | u16::from(x[1]) << 8),
+        None => bail!("ntlm message is truncated"),
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32> {
+    let low = u32::from(read_u16(buf, offset)?);
+    let high = u32::from(read_u16(buf, offset + 2)?);
+    Ok(low | high << 16)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Result<u64> {
+    let low = u64::from(read_u32(buf, offset)?);
+    let high = u64::from(read_u32(buf, offset + 4)?);
+    Ok(low | high << 32)
+}
+
+// security buffers are stored as (length, allocated length, offset)
+fn read_buffer(buf: &[u8], offset: usize) -> Result<(&[u8], usize)> {
+    let len = read_u16(buf, offset)? as usize;
+    let start = read_u32(buf, offset + 4)? as usize;
+    match buf.get(start..start + len) {
+        Some(x) => Ok((x, start)),
+        None => bail!("ntlm security buffer is out of bounds"),
+    }
+}
+
+fn utf16(buf: &[u8]) -> Result<String> {
+    let chars = buf.chunks(2)
+        .map(|x| match x {
+            [low, high] => Ok(u16::from(*low) | u16::from(*high) << 8),
+            _ => bail!("Invalid utf16 string"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(String::from_utf16(&chars)?)
+}
+
+// accepts the raw base64 message or a WWW-Authenticate header value
+pub fn decode_challenge(msg: &str) -> Result<Challenge> {
+    let msg = msg.trim();
+    let msg = msg.rsplit(' ').next().unwrap();
+    let msg = base64::decode(msg)
+                .context("Invalid base64")?;
+    parse_challenge(&msg)
+}
+
+pub fn parse_challenge(msg: &[u8]) -> Result<Challenge> {
+    if !msg.starts_with(SIGNATURE) {
+        bail!("Invalid ntlmssp signature");
+    }
+    let kind = read_u32(msg, 8)?;
+    if kind != 2 {
+        bail!("Expected ntlm challenge message, got type {}", kind);
+    }
+
+    let flags = read_u32(msg, 20)?;
+    let server_challenge = match msg.get(24..32) {
+        Some(x) => x.to_vec(),
+        None => bail!("ntlm message is truncated"),
+    };
+
+    let mut challenge = Challenge {
+        flags,
+        server_challenge,
+        ..Default::default()
+    };
+
+    let (target_name, mut payload_start) = read_buffer(msg, 12)?;
+    if !target_name.is_empty() {
+        challenge.target_name = Some(if flags & NEGOTIATE_UNICODE != 0 {
+            utf16(target_name)?
+        } else {
+            String::from_utf8_lossy(target_name).to_string()
+        });
+    }
+
+    // the target info buffer is missing in very old implementations
+    if msg.len() >= 48 {
+        let (target_info, start) = read_buffer(msg, 40)?;
+        payload_start = payload_start.min(start);
+        parse_target_info(&mut challenge, target_info)?;
+        challenge.target_info = target_info.to_vec();
+    }
+
+    // some servers set the flag without sending the version field
+    if flags & NEGOTIATE_VERSION != 0 && payload_start >= 56 && msg.len() >= 56 {
+        challenge.version = Some(format!("{}.{}.{}", msg[48], msg[49], read_u16(msg, 50)?));
+    }
+
+    Ok(challenge)
+}
+
+fn parse_target_info(challenge: &mut Challenge, mut buf: &[u8]) -> Result<()> {
+    while buf.len() >= 4 {
+        let id = read_u16(buf, 0)?;
+        let len = read_u16(buf, 2)? as usize;
+        let value = match buf.get(4..4 + len) {
+            Some(x) => x,
+            None => bail!("ntlm target info is truncated"),
+        };
+
+        match id {
+            0 => break,
+            1 => challenge.nb_computer_name = Some(utf16(value)?),
+            2 => challenge.nb_domain_name = Some(utf16(value)?),
+            3 => challenge.dns_computer_name = Some(utf16(value)?),
+            4 => challenge.dns_domain_name = Some(utf16(value)?),
+            5 => challenge.dns_tree_name = Some(utf16(value)?),
+            7 => challenge.timestamp = Some(read_u64(value, 0)?),
+            _ => (),
+        }
+
+        buf = &buf[4 + len..];
+    }
+    Ok(())
+}
+
+pub fn md4(x: &[u8]) -> Vec<u8> {
+    Md4::digest(x).to_vec()
+}
+
+fn utf16le(x: &str) -> Vec<u8> {
+    x.encode_utf16()
+        .flat_map(|c| vec![c as u8, (c >> 8) as u8])
+        .collect()
+}
+
+pub fn ntlm_hash(password: &str) -> Vec<u8> {
+    md4(&utf16le(password))
+}
+
+// spread 56 bits over 8 bytes, the lowest bit of each byte is the parity bit
+// which is ignored by des
+fn des_key(x: &[u8]) -> [u8; 8] {
+    let mut key = [0; 8];
+    key[0] = x[0];
+    key[1] = (x[0] << 7) | (x[1] >> 1);
+    key[2] = (x[1] << 6) | (x[2] >> 2);
+    key[3] = (x[2] << 5) | (x[3] >> 3);
+    key[4] = (x[3] << 4) | (x[4] >> 4);
+    key[5] = (x[4] << 3) | (x[5] >> 5);
+    key[6] = (x[5] << 2) | (x[6] >> 6);
+    key[7] = x[6] << 1;
+    key
+}
+
+fn des_encrypt(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let cipher = Des::new(GenericArray::from_slice(&des_key(key)));
+    let mut block = GenericArray::clone_from_slice(data);
+    cipher.encrypt_block(&mut block);
+    block.to_vec()
+}
+
+// passwords longer than 14 characters can't be represented as lm hash
+pub fn lm_hash(password: &str) -> Result<Vec<u8>> {
+    let password = password.to_uppercase();
+    if !password.is_ascii() {
+        bail!("lm hash only supports ascii passwords");
+    }
+    if password.len() > 14 {
+        bail!("lm hash only supports passwords up to 14 characters");
+    }
+
+    let mut key = password.into_bytes();
+    key.resize(14, 0);
+
+    let mut hash = des_encrypt(&key[..7], LM_MAGIC);
+    hash.extend(des_encrypt(&key[7..], LM_MAGIC));
+    Ok(hash)
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseOptions {
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub domain: String,
+    pub password: Option<String>,
+    // either 16 bytes or 32 hex characters
+    pub nt_hash: Option<Bytes>,
+    pub server_challenge: Bytes,
+    pub target_info: Option<Bytes>,
+    pub client_challenge: Option<Bytes>,
+    pub timestamp: Option<u64>,
+}
+
+impl ResponseOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<ResponseOptions> {
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+
+    fn nt_hash(&self) -> Result<Vec<u8>> {
+        match (&self.password, &self.nt_hash) {
+            (_, Some(Bytes::Text(hash))) if hash.len() == 32 => {
+                (0..32).step_by(2)
+                    .map(|i| u8::from_str_radix(&hash[i..i + 2], 16)
+                        .map_err(|_| format_err!("Invalid nt hash")))
+                    .collect()
+            },
+            (_, Some(hash)) => {
+                let hash = hash.clone().into_bytes();
+                if hash.len() != 16 {
+                    bail!("Invalid nt hash");
+                }
+                Ok(hash)
+            },
+            (Some(password), None) => Ok(ntlm_hash(password)),
+            (None, None) => bail!("password or nt_hash is required"),
+        }
+    }
+
+    fn server_challenge(&self) -> Result<Vec<u8>> {
+        let challenge = self.server_challenge.clone().into_bytes();
+        if challenge.len() != 8 {
+            bail!("server challenge has to be 8 bytes");
+        }
+        Ok(challenge)
+    }
+
+    fn client_challenge(&self) -> Result<Vec<u8>> {
+        match self.client_challenge {
+            Some(ref challenge) => {
+                let challenge = challenge.clone().into_bytes();
+                if challenge.len() != 8 {
+                    bail!("client challenge has to be 8 bytes");
+                }
+                Ok(challenge)
+            },
+            None => {
+                let mut challenge = vec![0; 8];
+                rand::thread_rng().fill_bytes(&mut challenge);
+                Ok(challenge)
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub nt_response: Vec<u8>,
+    pub lm_response: Vec<u8>,
+    pub session_key: Vec<u8>,
+}
+
+impl Into<AnyLuaValue> for Response {
+    fn into(self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        map.insert("nt_response", AnyLuaValue::LuaAnyString(AnyLuaString(self.nt_response)));
+        map.insert("lm_response", AnyLuaValue::LuaAnyString(AnyLuaString(self.lm_response)));
+        map.insert("session_key", AnyLuaValue::LuaAnyString(AnyLuaString(self.session_key)));
+
+        map.into()
+    }
+}
+
+fn hmac_md5(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Md5>::new_varkey(key)
+                    .expect("hmac accepts any key length");
+    mac.input(msg);
+    mac.result().code().to_vec()
+}
+
+// encrypt the challenge with the 16 byte hash padded to three des keys
+fn desl(hash: &[u8], challenge: &[u8]) -> Vec<u8> {
+    let mut key = hash.to_vec();
+    key.resize(21, 0);
+
+    let mut out = des_encrypt(&key[..7], challenge);
+    out.extend(des_encrypt(&key[7..14], challenge));
+    out.extend(des_encrypt(&key[14..], challenge));
+    out
+}
+
+pub fn ntlmv1_response(options: &ResponseOptions) -> Result<Response> {
+    let nt_hash = options.nt_hash()?;
+    let server_challenge = options.server_challenge()?;
+
+    let nt_response = desl(&nt_hash, &server_challenge);
+
+    // without a lm hash windows sends the nt response twice
+    let lm_hash = options.password.as_ref()
+                    .and_then(|password| lm_hash(password).ok());
+    let lm_response = match lm_hash {
+        Some(lm_hash) => desl(&lm_hash, &server_challenge),
+        None => nt_response.clone(),
+    };
+
+    Ok(Response {
+        nt_response,
+        lm_response,
+        session_key: md4(&nt_hash),
+    })
+}
+
+fn filetime_now() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                .expect("system time is before 1970");
+    (now.as_secs() + FILETIME_EPOCH) * 10_000_000 + u64::from(now.subsec_nanos() / 100)
+}
+
+pub fn ntlmv2_response(options: &ResponseOptions) -> Result<Response> {
+    let nt_hash = options.nt_hash()?;
+    let server_challenge = options.server_challenge()?;
+    let client_challenge = options.client_challenge()?;
+    let target_info = options.target_info.clone()
+                        .map(|x| x.into_bytes())
+                        .unwrap_or_default();
+
+    // prefer the timestamp of the server if there is one
+    let timestamp = match options.timestamp {
+        Some(timestamp) => timestamp,
+        None => {
+            let mut challenge = Challenge::default();
+            parse_target_info(&mut challenge, &target_info)?;
+            challenge.timestamp.unwrap_or_else(filetime_now)
+        },
+    };
+
+    let identity = format!("{}{}", options.user.to_uppercase(), options.domain);
+    let key = hmac_md5(&nt_hash, &utf16le(&identity));
+
+    let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    blob.extend((0..8).map(|i| (timestamp >> (i * 8)) as u8));
+    blob.extend(&client_challenge);
+    blob.extend(&[0, 0, 0, 0]);
+    blob.extend(&target_info);
+    blob.extend(&[0, 0, 0, 0]);
+
+    let mut msg = server_challenge.clone();
+    msg.extend(&blob);
+    let nt_proof = hmac_md5(&key, &msg);
+
+    let mut msg = server_challenge.clone();
+    msg.extend(&client_challenge);
+    let mut lm_response = hmac_md5(&key, &msg);
+    lm_response.extend(&client_challenge);
+
+    let session_key = hmac_md5(&key, &nt_proof);
+
+    let mut nt_response = nt_proof;
+    nt_response.extend(blob);
+
+    Ok(Response {
+        nt_response,
+        lm_response,
+        session_key,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from http://davenport.sourceforge.net/ntlm.html
+    const CHALLENGE: &str = "TlRMTVNTUAACAAAADAAMADAAAAA1goriASNFZ4mrze8AAAAAAAAAAGIAYgA8AAAARABPAE0AQQBJAE4AAgAMAEQATwBNAEEASQBOAAEADABTAEUAUgBWAEUAUgAEABQAZABvAG0AYQBpAG4ALgBjAG8AbQADACIAcwBlAHIAdgBlAHIALgBkAG8AbQBhAGkAbgAuAGMAbwBtAAAAAAA=";
+
+    #[test]
+    fn test_decode_challenge() {
+        let challenge = decode_challenge(&format!("NTLM {}", CHALLENGE)).unwrap();
+        assert_eq!(challenge.server_challenge, vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(challenge.target_name, Some("DOMAIN".into()));
+        assert_eq!(challenge.nb_domain_name, Some("DOMAIN".into()));
+        assert_eq!(challenge.nb_computer_name, Some("SERVER".into()));
+        assert_eq!(challenge.dns_domain_name, Some("domain.com".into()));
+        assert_eq!(challenge.dns_computer_name, Some("server.domain.com".into()));
+        assert_eq!(challenge.version, None);
+        assert_eq!(challenge.target_info.len(), 98);
+    }
+
+    #[test]
+    fn test_decode_wrong_type() {
+        // type 1 negotiate message
+        assert!(decode_challenge("TlRMTVNTUAABAAAAB4IIogAAAAAAAAAAAAAAAAAAAAAKAO5CAAAADw==").is_err());
+    }
+
+    #[test]
+    fn test_ntlm_hash() {
+        assert_eq!(ntlm_hash("password"), vec![
+            0x88, 0x46, 0xf7, 0xea, 0xee, 0x8f, 0xb1, 0x17,
+            0xad, 0x06, 0xbd, 0xd8, 0x30, 0xb7, 0x58, 0x6c,
+        ]);
+    }
+
+    #[test]
+    fn test_lm_hash() {
+        assert_eq!(lm_hash("password").unwrap(), vec![
+            0xe5, 0x2c, 0xac, 0x67, 0x41, 0x9a, 0x9a, 0x22,
+            0x4a, 0x3b, 0x10, 0x8f, 0x3f, 0xa6, 0xcb, 0x6d,
+        ]);
+        assert!(lm_hash("this password is too long").is_err());
+    }
+
+    fn unhex(x: &str) -> Vec<u8> {
+        (0..x.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&x[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // test vectors from MS-NLMP 4.2
+    fn nlmp_options() -> ResponseOptions {
+        let mut target_info = vec![0x02, 0x00, 0x0c, 0x00];
+        target_info.extend(utf16le("Domain"));
+        target_info.extend(&[0x01, 0x00, 0x0c, 0x00]);
+        target_info.extend(utf16le("Server"));
+        target_info.extend(&[0x00, 0x00, 0x00, 0x00]);
+
+        ResponseOptions {
+            user: "User".into(),
+            domain: "Domain".into(),
+            password: Some("Password".into()),
+            nt_hash: None,
+            server_challenge: Bytes::Binary(unhex("0123456789abcdef")),
+            client_challenge: Some(Bytes::Binary(unhex("aaaaaaaaaaaaaaaa"))),
+            target_info: Some(Bytes::Binary(target_info)),
+            timestamp: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_ntlmv1_response() {
+        let response = ntlmv1_response(&nlmp_options()).unwrap();
+        assert_eq!(response.nt_response, unhex("67c43011f30298a2ad35ece64f16331c44bdbed927841f94"));
+        assert_eq!(response.lm_response, unhex("98def7b87f88aa5dafe2df779688a172def11c7d5ccdef13"));
+        assert_eq!(response.session_key, unhex("d87262b0cde4b1cb7499becccdf10784"));
+    }
+
+    #[test]
+    fn test_ntlmv2_response() {
+        let response = ntlmv2_response(&nlmp_options()).unwrap();
+        assert_eq!(&response.nt_response[..16], &unhex("68cd0ab851e51c96aabc927bebef6a1c")[..]);
+        assert_eq!(response.lm_response, unhex("86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa"));
+        assert_eq!(response.session_key, unhex("8de40ccadbc14a82f15cb0ad0de95ca3"));
+    }
+
+    #[test]
+    fn test_ntlmv2_response_with_hash() {
+        let options = ResponseOptions {
+            password: None,
+            nt_hash: Some(Bytes::Text("a4f49c406510bdcab6824ee7c30fd852".into())),
+            ..nlmp_options()
+        };
+        let response = ntlmv2_response(&options).unwrap();
+        assert_eq!(response, ntlmv2_response(&nlmp_options()).unwrap());
+    }
+}
@@ -0,0 +1,190 @@
+use errors::*;
+
+use base64;
+use hlua::AnyLuaValue;
+use hmac::{Hmac, Mac};
+use json::LuaJsonValue;
+use rand::{Rng, thread_rng};
+use rand::distributions::Alphanumeric;
+use reqwest::Url;
+use serde_json::{self, Value, Map};
+use sha1::Sha1;
+use http::SessionOptions;
+use ctx::State;
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+#[derive(Debug, Serialize)]
+struct PasswordGrant<'a> {
+    grant_type: &'a str,
+    username: &'a str,
+    password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<&'a str>,
+}
+
+// returns true and the token response if the credentials are valid, false and
+// the error response if the server rejected the credentials
+pub fn password_grant(state: &State, token_url: &str, client_id: &str, client_secret: &str, user: &str, password: &str) -> Result<(bool, AnyLuaValue)> {
+    let mut options = Map::new();
+
+    // public clients don't have a secret and send their id in the body instead
+    let form = if client_secret.is_empty() {
+        PasswordGrant {
+            grant_type: "password",
+            username: user,
+            password,
+            client_id: Some(client_id),
+        }
+    } else {
+        options.insert("basic_auth".into(), serde_json::to_value(&(client_id, client_secret))?);
+        PasswordGrant {
+            grant_type: "password",
+            username: user,
+            password,
+            client_id: None,
+        }
+    };
+    options.insert("form".into(), serde_json::to_value(&form)?);
+
+    let options = serde_json::from_value(Value::Object(options))?;
+    let session = state.http_mksession(SessionOptions::default());
+    let req = state.http_request(&session, "POST".into(), token_url.into(), options)?;
+    let resp = req.send(state)?;
+
+    let status = match resp.get("status") {
+        Some(&AnyLuaValue::LuaNumber(status)) => status as u16,
+        _ => bail!("response is missing a status"),
+    };
+    let text = match resp.get("text") {
+        Some(&AnyLuaValue::LuaString(ref text)) => text.as_str(),
+        _ => "",
+    };
+
+    let body: Value = serde_json::from_str(text)
+                        .context(format!("token endpoint returned invalid json (http {})", status))?;
+
+    let success = match status {
+        200 => {
+            if body.get("access_token").is_none() {
+                bail!("token response is missing access_token");
+            }
+            true
+        },
+        // rfc6749 uses 400 for invalid_grant, some servers use 401
+        400 | 401 if body.get("error").is_some() => false,
+        _ => bail!("unexpected response from token endpoint (http {})", status),
+    };
+
+    Ok((success, LuaJsonValue::from(body).into()))
+}
+
+// percent encoding as required by rfc5849, everything but unreserved characters
+fn encode(x: &str) -> String {
+    let mut out = String::new();
+    for b in x.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+pub struct OAuth1<'a> {
+    pub consumer_key: &'a str,
+    pub consumer_secret: &'a str,
+    pub token: &'a str,
+    pub token_secret: &'a str,
+}
+
+impl<'a> OAuth1<'a> {
+    // returns the value for the Authorization header
+    pub fn sign(&self, method: &str, url: &str, params: &HashMap<String, String>) -> Result<String> {
+        let nonce: String = thread_rng().sample_iter(&Alphanumeric).take(32).collect();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.sign_with(method, url, params, &nonce, timestamp)
+    }
+
+    fn sign_with(&self, method: &str, url: &str, params: &HashMap<String, String>, nonce: &str, timestamp: u64) -> Result<String> {
+        let mut url = Url::parse(url)
+                        .context("Invalid url")?;
+
+        let mut oauth = vec![
+            ("oauth_consumer_key", self.consumer_key.to_string()),
+            ("oauth_nonce", nonce.to_string()),
+            ("oauth_signature_method", "HMAC-SHA1".to_string()),
+            ("oauth_timestamp", timestamp.to_string()),
+        ];
+        if !self.token.is_empty() {
+            oauth.push(("oauth_token", self.token.to_string()));
+        }
+        oauth.push(("oauth_version", "1.0".to_string()));
+
+        let mut pairs = oauth.iter()
+            .map(|&(k, ref v)| (encode(k), encode(v)))
+            .chain(params.iter().map(|(k, v)| (encode(k), encode(v))))
+            .chain(url.query_pairs().map(|(k, v)| (encode(&k), encode(&v))))
+            .collect::<Vec<_>>();
+        pairs.sort();
+
+        let params = pairs.into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        url.set_query(None);
+        url.set_fragment(None);
+
+        let base = format!("{}&{}&{}", method.to_uppercase(), encode(url.as_str()), encode(&params));
+        let key = format!("{}&{}", encode(self.consumer_secret), encode(self.token_secret));
+
+        let mut mac = match Hmac::<Sha1>::new_varkey(key.as_bytes()) {
+            Ok(mac) => mac,
+            Err(_) => bail!("Invalid key length"),
+        };
+        mac.input(base.as_bytes());
+        let signature = base64::encode(&mac.result().code());
+
+        let header = oauth.iter()
+            .map(|&(k, ref v)| (k, v.as_str()))
+            .chain(Some(("oauth_signature", signature.as_str())))
+            .map(|(k, v)| format!("{}=\"{}\"", k, encode(v)))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Ok(format!("OAuth {}", header))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("Ladies + Gentlemen"), "Ladies%20%2B%20Gentlemen");
+        assert_eq!(encode("a-b.c_d~e"), "a-b.c_d~e");
+    }
+
+    #[test]
+    fn test_oauth1_sign() {
+        // example from https://developer.twitter.com/en/docs/basics/authentication/guides/creating-a-signature
+        let oauth = OAuth1 {
+            consumer_key: "xvz1evFS4wEEPTGEFPHBog",
+            consumer_secret: "kAcSOqF21Fu85e7zjz7ZN2U4ZRhfV3WpwPAoE3Z7kBw",
+            token: "370773112-GmHxMAgYyLbNEtIKZeRNFsMKPR9EyMZeS9weJAEb",
+            token_secret: "LswwdoUaIvS8ltyTt5jkRh4J50vUPVVHtR2YPi5kE",
+        };
+        let mut params = HashMap::new();
+        params.insert("status".to_string(), "Hello Ladies + Gentlemen, a signed OAuth request!".to_string());
+
+        let header = oauth.sign_with("POST", "https://api.twitter.com/1.1/statuses/update.json?include_entities=true",
+                                     &params, "kYjzVBB8Y0ZFabxSWbWovY3uYSQ2pTgmZeNu2VS4cg", 1318622958).unwrap();
+        assert!(header.starts_with("OAuth oauth_consumer_key=\"xvz1evFS4wEEPTGEFPHBog\", "));
+        assert!(header.ends_with("oauth_signature=\"hCtSmYh%2BiHYCEqBWrE7C7hYmtUk%3D\""));
+    }
+}
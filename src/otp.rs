@@ -0,0 +1,150 @@
+use errors::*;
+
+use base32::{self, Alphabet};
+use digest::{Input, BlockInput, FixedOutput, Reset};
+use digest::generic_array::ArrayLength;
+use hlua::AnyLuaValue;
+use hmac::{Hmac, Mac};
+use json::LuaJsonValue;
+use serde_json;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TotpOptions {
+    pub step: Option<u64>,
+    pub digits: Option<u32>,
+    // unix timestamp, defaults to the current time
+    pub time: Option<u64>,
+    pub algorithm: Option<String>,
+}
+
+impl TotpOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<TotpOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(TotpOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+// authenticator apps show secrets in groups and without padding
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+    let secret = secret.replace(' ', "")
+                    .to_uppercase();
+    let secret = secret.trim_end_matches('=');
+    base32::decode(Alphabet::RFC4648 { padding: false }, secret)
+        .ok_or_else(|| format_err!("Invalid base32 secret"))
+}
+
+fn hmac<D>(key: &[u8], msg: &[u8]) -> Result<Vec<u8>>
+    where
+        D: Input + BlockInput + FixedOutput + Reset + Default + Clone,
+        D::BlockSize: ArrayLength<u8> + Clone,
+        D::OutputSize: ArrayLength<u8>,
+{
+    let mut mac = match Hmac::<D>::new_varkey(key) {
+        Ok(mac) => mac,
+        Err(_) => bail!("Invalid key length"),
+    };
+    mac.input(msg);
+    Ok(mac.result().code().to_vec())
+}
+
+fn generate(key: &[u8], counter: u64, digits: u32, algorithm: &str) -> Result<String> {
+    if digits == 0 || digits > 9 {
+        bail!("Invalid number of digits: {}", digits);
+    }
+
+    let msg = counter.to_be_bytes();
+    let hash = match algorithm {
+        "sha1" => hmac::<Sha1>(key, &msg)?,
+        "sha256" => hmac::<Sha256>(key, &msg)?,
+        "sha512" => hmac::<Sha512>(key, &msg)?,
+        _ => bail!("Unsupported algorithm: {:?}", algorithm),
+    };
+
+    // dynamic truncation from rfc4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = (u32::from(hash[offset]) & 0x7f) << 24
+             | u32::from(hash[offset + 1]) << 16
+             | u32::from(hash[offset + 2]) << 8
+             | u32::from(hash[offset + 3]);
+
+    let code = code % 10u32.pow(digits);
+    Ok(format!("{:0width$}", code, width = digits as usize))
+}
+
+pub fn hotp(secret: &str, counter: u64) -> Result<String> {
+    let key = decode_secret(secret)?;
+    generate(&key, counter, 6, "sha1")
+}
+
+pub fn totp(secret: &str, options: &TotpOptions) -> Result<String> {
+    let key = decode_secret(secret)?;
+
+    let step = options.step.unwrap_or(30);
+    if step == 0 {
+        bail!("step can not be zero");
+    }
+
+    let time = match options.time {
+        Some(time) => time,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+
+    let digits = options.digits.unwrap_or(6);
+    let algorithm = options.algorithm.as_ref().map(|x| x.as_str()).unwrap_or("sha1");
+    generate(&key, time / step, digits, algorithm)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // base32 of the rfc4226/rfc6238 secrets
+    const SECRET_SHA1: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SECRET_SHA256: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+
+    #[test]
+    fn test_hotp() {
+        assert_eq!(hotp(SECRET_SHA1, 0).unwrap(), "755224");
+        assert_eq!(hotp(SECRET_SHA1, 1).unwrap(), "287082");
+        assert_eq!(hotp(SECRET_SHA1, 9).unwrap(), "520489");
+    }
+
+    #[test]
+    fn test_totp_sha1() {
+        let code = totp(SECRET_SHA1, &TotpOptions {
+            digits: Some(8),
+            time: Some(1111111109),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(code, "07081804");
+    }
+
+    #[test]
+    fn test_totp_sha256() {
+        let code = totp(SECRET_SHA256, &TotpOptions {
+            digits: Some(8),
+            time: Some(59),
+            algorithm: Some("sha256".into()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(code, "46119246");
+    }
+
+    #[test]
+    fn test_decode_secret() {
+        assert_eq!(decode_secret("gezd gnbv gy3t qojq").unwrap(), b"1234567890");
+        assert!(decode_secret("not base32!").is_err());
+    }
+}
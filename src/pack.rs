@@ -0,0 +1,289 @@
+// struct-style binary packing, loosely modeled after python's struct module
+//
+// <  little endian
+// >  big endian (also `!`)
+// x  pad byte
+// b  i8,  B  u8
+// h  i16, H  u16
+// i  i32, I  u32
+// q  i64, Q  u64
+// s  fixed length string, the count is the length (`4s`)
+//
+// every code can be prefixed with a count, `4B` is the same as `BBBB`.
+
+use errors::*;
+
+use hlua::{AnyLuaValue, AnyLuaString};
+use std::str::Chars;
+use std::iter::Peekable;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Code {
+    Pad,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    Bytes(usize),
+}
+
+impl Code {
+    fn size(&self) -> usize {
+        match *self {
+            Code::Pad | Code::I8 | Code::U8 => 1,
+            Code::I16 | Code::U16 => 2,
+            Code::I32 | Code::U32 => 4,
+            Code::I64 | Code::U64 => 8,
+            Code::Bytes(n) => n,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Format {
+    endian: Endian,
+    codes: Vec<Code>,
+}
+
+fn parse_count(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut count = None;
+    while let Some(c) = chars.peek().and_then(|c| c.to_digit(10)) {
+        count = Some(count.unwrap_or(0) * 10 + c as usize);
+        chars.next();
+    }
+    count
+}
+
+fn parse_format(fmt: &str) -> Result<Format> {
+    let mut chars = fmt.chars().peekable();
+
+    let endian = match chars.peek() {
+        Some('<') => Some(Endian::Little),
+        Some('>') | Some('!') => Some(Endian::Big),
+        _ => None,
+    };
+    if endian.is_some() {
+        chars.next();
+    }
+    let endian = endian.unwrap_or(Endian::Big);
+
+    let mut codes = Vec::new();
+    loop {
+        let count = parse_count(&mut chars);
+        let c = match chars.next() {
+            Some(c) => c,
+            None if count.is_some() => bail!("format ends with a count"),
+            None => break,
+        };
+
+        let code = match c {
+            ' ' => continue,
+            'x' => Code::Pad,
+            'b' => Code::I8,
+            'B' => Code::U8,
+            'h' => Code::I16,
+            'H' => Code::U16,
+            'i' => Code::I32,
+            'I' => Code::U32,
+            'q' => Code::I64,
+            'Q' => Code::U64,
+            's' => {
+                codes.push(Code::Bytes(count.unwrap_or(1)));
+                continue;
+            },
+            c => bail!("unknown format code: {:?}", c),
+        };
+
+        for _ in 0..count.unwrap_or(1) {
+            codes.push(code);
+        }
+    }
+
+    Ok(Format {
+        endian,
+        codes,
+    })
+}
+
+fn write_int(out: &mut Vec<u8>, endian: Endian, size: usize, num: u64) {
+    for i in 0..size {
+        let shift = match endian {
+            Endian::Little => i * 8,
+            Endian::Big => (size - i - 1) * 8,
+        };
+        out.push((num >> shift) as u8);
+    }
+}
+
+fn read_int(bytes: &[u8], endian: Endian) -> u64 {
+    let mut num = 0u64;
+    for i in 0..bytes.len() {
+        let b = match endian {
+            Endian::Little => bytes[bytes.len() - i - 1],
+            Endian::Big => bytes[i],
+        };
+        num = (num << 8) | u64::from(b);
+    }
+    num
+}
+
+fn value_to_int(value: &AnyLuaValue, min: f64, max: f64) -> Result<u64> {
+    match *value {
+        AnyLuaValue::LuaNumber(num) if num % 1.0 == 0.0 && num >= min && num <= max => Ok(num as i64 as u64),
+        AnyLuaValue::LuaNumber(num) => bail!("number is out of range: {:?}", num),
+        ref x => bail!("expected number, got: {:?}", x),
+    }
+}
+
+fn value_to_bytes(value: AnyLuaValue) -> Result<Vec<u8>> {
+    match value {
+        AnyLuaValue::LuaString(x) => Ok(x.into_bytes()),
+        AnyLuaValue::LuaAnyString(x) => Ok(x.0),
+        x => bail!("expected string, got: {:?}", x),
+    }
+}
+
+pub fn pack(fmt: &str, values: Vec<AnyLuaValue>) -> Result<Vec<u8>> {
+    let fmt = parse_format(fmt)?;
+    let mut values = values.into_iter();
+    let mut out = Vec::new();
+
+    for code in fmt.codes {
+        if code == Code::Pad {
+            out.push(0);
+            continue;
+        }
+
+        let value = values.next()
+                        .ok_or_else(|| format_err!("not enough arguments for format"))?;
+
+        let num = match code {
+            Code::Pad => unreachable!(),
+            Code::I8 => value_to_int(&value, f64::from(i8::min_value()), f64::from(i8::max_value()))?,
+            Code::U8 => value_to_int(&value, 0.0, f64::from(u8::max_value()))?,
+            Code::I16 => value_to_int(&value, f64::from(i16::min_value()), f64::from(i16::max_value()))?,
+            Code::U16 => value_to_int(&value, 0.0, f64::from(u16::max_value()))?,
+            Code::I32 => value_to_int(&value, f64::from(i32::min_value()), f64::from(i32::max_value()))?,
+            Code::U32 => value_to_int(&value, 0.0, f64::from(u32::max_value()))?,
+            Code::I64 => value_to_int(&value, i64::min_value() as f64, i64::max_value() as f64)?,
+            Code::U64 => value_to_int(&value, 0.0, u64::max_value() as f64)?,
+            Code::Bytes(n) => {
+                let mut bytes = value_to_bytes(value)?;
+                bytes.resize(n, 0);
+                out.extend(bytes);
+                continue;
+            },
+        };
+
+        write_int(&mut out, fmt.endian, code.size(), num);
+    }
+
+    if values.next().is_some() {
+        bail!("too many arguments for format");
+    }
+
+    Ok(out)
+}
+
+pub fn unpack(fmt: &str, bytes: &[u8]) -> Result<Vec<AnyLuaValue>> {
+    let fmt = parse_format(fmt)?;
+    let mut out = Vec::new();
+    let mut cursor = 0;
+
+    for code in fmt.codes {
+        let size = code.size();
+        if cursor + size > bytes.len() {
+            bail!("not enough bytes for format");
+        }
+        let slice = &bytes[cursor..cursor + size];
+        cursor += size;
+
+        let num = read_int(slice, fmt.endian);
+        let value = match code {
+            Code::Pad => continue,
+            Code::I8 => f64::from(num as i8),
+            Code::U8 => num as f64,
+            Code::I16 => f64::from(num as i16),
+            Code::U16 => num as f64,
+            Code::I32 => f64::from(num as i32),
+            Code::U32 => num as f64,
+            Code::I64 => num as i64 as f64,
+            Code::U64 => num as f64,
+            Code::Bytes(_) => {
+                out.push(AnyLuaValue::LuaAnyString(AnyLuaString(slice.to_vec())));
+                continue;
+            },
+        };
+
+        out.push(AnyLuaValue::LuaNumber(value));
+    }
+
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let fmt = parse_format("<2BH4s").unwrap();
+        assert_eq!(fmt, Format {
+            endian: Endian::Little,
+            codes: vec![Code::U8, Code::U8, Code::U16, Code::Bytes(4)],
+        });
+    }
+
+    #[test]
+    fn test_pack_big_endian() {
+        let bytes = pack(">BHI", vec![
+            AnyLuaValue::LuaNumber(1.0),
+            AnyLuaValue::LuaNumber(2.0),
+            AnyLuaValue::LuaNumber(3.0),
+        ]).unwrap();
+        assert_eq!(bytes, vec![1, 0, 2, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_pack_little_endian() {
+        let bytes = pack("<hx3s", vec![
+            AnyLuaValue::LuaNumber(-2.0),
+            AnyLuaValue::LuaString("ab".into()),
+        ]).unwrap();
+        assert_eq!(bytes, vec![0xfe, 0xff, 0, b'a', b'b', 0]);
+    }
+
+    #[test]
+    fn test_pack_out_of_range() {
+        let r = pack("B", vec![AnyLuaValue::LuaNumber(256.0)]);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_unpack() {
+        let values = unpack("<Hi2s", &[1, 2, 0xff, 0xff, 0xff, 0xff, b'o', b'k']).unwrap();
+        assert_eq!(values, vec![
+            AnyLuaValue::LuaNumber(513.0),
+            AnyLuaValue::LuaNumber(-1.0),
+            AnyLuaValue::LuaAnyString(AnyLuaString(b"ok".to_vec())),
+        ]);
+    }
+
+    #[test]
+    fn test_unpack_short() {
+        let r = unpack(">I", &[0, 0]);
+        assert!(r.is_err());
+    }
+}
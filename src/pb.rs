@@ -8,10 +8,14 @@
 // - using colored strings breaks pbr
 //
 // https://github.com/a8m/pb/pull/62
+//
+// the speed of pbr is the average of the whole run, the message in front of
+// the bar shows the rate of the last seconds and the valid and failed attempts.
 
 use pbr;
 use atty;
 use colored::Colorize;
+use std::collections::VecDeque;
 use std::fmt::Display;
 use std::io::prelude::*;
 use std::io::{self, Stdout};
@@ -31,13 +35,24 @@ pub struct ProgressBar {
     last_refresh_time: SteadyTime,
     max_refresh_rate: Option<time::Duration>,
     atty: bool,
+    // only print findings
+    quiet: bool,
+    // (time, current) for the rolling rate
+    samples: VecDeque<(SteadyTime, u64)>,
+    valid: u64,
+    errors: u64,
+    // eg. the time until the next attempt
+    status: String,
 }
 
+const RATE_WINDOW_SECS: i64 = 10;
+
 impl ProgressBar {
     #[inline]
     pub fn new(total: u64) -> ProgressBar {
         let mut pb = pbr::ProgressBar::new(total);
         pb.format("(=> )");
+        pb.show_speed = false;
 
         let now = SteadyTime::now();
         let refresh_rate = Duration::milliseconds(250);
@@ -49,30 +64,99 @@ impl ProgressBar {
             last_refresh_time: now - refresh_rate,
             max_refresh_rate: Some(refresh_rate),
             atty,
+            quiet: false,
+            samples: VecDeque::new(),
+            valid: 0,
+            errors: 0,
+            status: String::new(),
         }
     }
 
+    #[inline]
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
     #[inline]
     pub fn draw(&mut self) {
-        if !self.atty {
+        if !self.atty || self.quiet {
             return;
         }
 
+        self.update_message();
         self.pb.tick()
     }
 
+    // attempts per second over the last seconds
+    pub fn rate(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) => {
+                let ms = (last.0 - first.0).num_milliseconds();
+                if ms > 0 {
+                    (last.1 - first.1) as f64 * 1000.0 / ms as f64
+                } else {
+                    0.0
+                }
+            },
+            _ => 0.0,
+        }
+    }
+
+    fn sample(&mut self) {
+        let now = SteadyTime::now();
+        self.samples.push_back((now, self.current));
+        while let Some(first) = self.samples.front().cloned() {
+            if now - first.0 <= Duration::seconds(RATE_WINDOW_SECS) {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn update_message(&mut self) {
+        let msg = format!("{}{} valid, {} errors, {:.1}/s ",
+            self.status, self.valid, self.errors, self.rate());
+        self.pb.message(&msg);
+    }
+
+    // counters that are shown in front of the bar
+    #[inline]
+    pub fn set_counts(&mut self, valid: usize, errors: usize) {
+        self.valid = valid as u64;
+        self.errors = errors as u64;
+    }
+
     #[inline]
     pub fn print_help(&mut self) {
         self.writeln(format!("{} {}", "[+]".bold(),
-            "[h] help, [p] pause, [r] resume, [+] increase threads, [-] decrease threads".dimmed()));
+            "[h] help, [p] pause, [r] resume, [s] status, [n] skip target, [+] increase threads, [-] decrease threads".dimmed()));
     }
 
     #[inline]
     pub fn writeln<T: Display>(&mut self, s: T) {
+        if self.quiet {
+            return;
+        }
         printfl!(io::stderr(), "\r\x1B[2K{}\n", s);
         self.draw()
     }
 
+    // valid credentials are printed to stdout even with --quiet
+    #[inline]
+    pub fn finding<T: Display>(&mut self, s: T) {
+        if self.quiet {
+            println!("{}", s);
+        } else {
+            self.writeln(s);
+        }
+    }
+
+    #[inline]
+    pub fn message(&mut self, s: &str) {
+        self.status = s.to_string();
+        self.draw()
+    }
+
     #[inline]
     pub fn tick(&mut self) {
         let now = SteadyTime::now();
@@ -82,6 +166,7 @@ impl ProgressBar {
             }
         }
 
+        self.sample();
         self.draw();
 
         self.last_refresh_time = SteadyTime::now();
@@ -89,18 +174,24 @@ impl ProgressBar {
 
     #[inline]
     pub fn inc(&mut self) {
-        if !self.atty {
+        self.add(1);
+    }
+
+    pub fn add(&mut self, n: u64) {
+        self.current += n;
+        if !self.atty || self.quiet {
             return;
         }
 
         let now = SteadyTime::now();
         if let Some(mrr) = self.max_refresh_rate {
             if now - self.last_refresh_time < mrr {
-                self.current += 1;
                 return;
             }
         }
 
+        self.sample();
+        self.update_message();
         self.pb.set(self.current);
 
         self.last_refresh_time = SteadyTime::now();
@@ -108,6 +199,9 @@ impl ProgressBar {
 
     #[inline]
     pub fn finish_replace<T: Display>(&self, s: T) {
+        if self.quiet {
+            return;
+        }
         if self.atty {
             print!("\r\x1B[2K{}", s);
         } else {
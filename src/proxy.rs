@@ -0,0 +1,171 @@
+use errors::*;
+
+use base64;
+use dns;
+use reqwest::Url;
+use socks::Socks5Stream;
+
+use std::cell::Cell;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+static NEXT_PROXY: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static WORKER_PROXY: Cell<Option<usize>> = Cell::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Attempt,
+    Worker,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::Attempt
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rotation> {
+        match s {
+            "attempt" => Ok(Rotation::Attempt),
+            "worker" => Ok(Rotation::Worker),
+            _ => bail!("unknown proxy rotation: {:?}", s),
+        }
+    }
+}
+
+// select the proxy for the next attempt. With Rotation::Attempt the proxies
+// are used round-robin, with Rotation::Worker every worker thread keeps the
+// proxy it got assigned first.
+pub fn pick(proxies: &[String], rotation: Rotation) -> Option<String> {
+    if proxies.is_empty() {
+        return None;
+    }
+
+    let idx = match rotation {
+        Rotation::Attempt => NEXT_PROXY.fetch_add(1, Ordering::Relaxed),
+        Rotation::Worker => WORKER_PROXY.with(|cell| {
+            match cell.get() {
+                Some(idx) => idx,
+                None => {
+                    let idx = NEXT_PROXY.fetch_add(1, Ordering::Relaxed);
+                    cell.set(Some(idx));
+                    idx
+                },
+            }
+        }),
+    };
+
+    Some(proxies[idx % proxies.len()].clone())
+}
+
+pub fn is_socks(proxy: &str) -> bool {
+    proxy.starts_with("socks5://") || proxy.starts_with("socks5h://")
+}
+
+pub fn connect(proxy: &str, host: &str, port: u16) -> Result<TcpStream> {
+    let url = Url::parse(proxy)
+                .context("Invalid proxy")?;
+
+    match url.scheme() {
+        "socks5" => socks5_connect(&url, host, port, false),
+        "socks5h" => socks5_connect(&url, host, port, true),
+        "http" => http_connect(&url, host, port),
+        scheme => bail!("unsupported proxy scheme: {:?}", scheme),
+    }
+}
+
+// socks5h resolves the hostname on the proxy, socks5 resolves locally
+fn socks5_connect(url: &Url, host: &str, port: u16, remote_dns: bool) -> Result<TcpStream> {
+    let proxy_host = url.host_str()
+                        .ok_or_else(|| format_err!("proxy is missing a host"))?;
+    let proxy_port = url.port().unwrap_or(1080);
+
+    let target = if remote_dns {
+        (host.to_string(), port)
+    } else {
+        let ip = dns::lookup_ip(host)?
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| format_err!("no dns records found"))?;
+        (ip.to_string(), port)
+    };
+
+    debug!("connecting to {:?} through proxy {:?}", target, proxy_host);
+    let target = (target.0.as_str(), target.1);
+    let stream = if url.username().is_empty() {
+        Socks5Stream::connect((proxy_host, proxy_port), target)?
+    } else {
+        let password = url.password().unwrap_or("");
+        Socks5Stream::connect_with_password((proxy_host, proxy_port), target,
+                                            url.username(), password)?
+    };
+
+    Ok(stream.into_inner())
+}
+
+// open a tunnel with CONNECT, the proxy has to allow the target port
+fn http_connect(url: &Url, host: &str, port: u16) -> Result<TcpStream> {
+    let proxy_host = url.host_str()
+                        .ok_or_else(|| format_err!("proxy is missing a host"))?;
+    let proxy_port = url.port_or_known_default().unwrap_or(8080);
+
+    debug!("connecting to {:?} through proxy {:?}", (host, port), proxy_host);
+    let mut stream = TcpStream::connect((proxy_host, proxy_port))?;
+
+    let mut req = format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n", host, port);
+    if !url.username().is_empty() {
+        let auth = format!("{}:{}", url.username(), url.password().unwrap_or(""));
+        req.push_str(&format!("Proxy-Authorization: Basic {}\r\n", base64::encode(&auth)));
+    }
+    req.push_str("\r\n");
+    stream.write_all(req.as_bytes())?;
+
+    // read byte by byte so we don't consume anything after the header
+    let mut resp = Vec::new();
+    let mut buf = [0; 1];
+    while !resp.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut buf)? == 0 {
+            bail!("proxy closed the connection");
+        }
+        resp.push(buf[0]);
+    }
+
+    let resp = String::from_utf8_lossy(&resp);
+    let status = resp.split(' ').nth(1);
+    if status != Some("200") {
+        bail!("proxy rejected tunnel: {:?}", resp.lines().next().unwrap_or(""));
+    }
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_empty() {
+        assert_eq!(pick(&[], Rotation::Attempt), None);
+    }
+
+    #[test]
+    fn test_pick_worker_is_sticky() {
+        let proxies = vec![
+            String::from("socks5://127.0.0.1:1080"),
+            String::from("socks5://127.0.0.1:1081"),
+        ];
+        let first = pick(&proxies, Rotation::Worker);
+        assert_eq!(pick(&proxies, Rotation::Worker), first);
+        assert_eq!(pick(&proxies, Rotation::Worker), first);
+    }
+}
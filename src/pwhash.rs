@@ -0,0 +1,461 @@
+use errors::*;
+
+use argon2;
+use base64;
+use bcrypt;
+use crypt;
+use digest::{Input, FixedOutput};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5;
+use rand::{self, RngCore};
+use sha1::Sha1;
+use sha2::Sha256;
+
+
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u64 = 8;
+const SCRYPT_P: u64 = 1;
+const SCRYPT_LEN: usize = 32;
+const CRYPT_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn salt() -> Vec<u8> {
+    let mut salt = vec![0; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+fn scrypt_raw(password: &[u8], salt: &[u8], log_n: u8, r: u64, p: u64, len: usize) -> Result<Vec<u8>> {
+    if log_n >= 64 {
+        bail!("scrypt cost is too high");
+    }
+    let n = 1u64 << log_n;
+
+    // openssl limits scrypt to 32MB unless maxmem is raised
+    let maxmem = 128 * r * (n + p + 1) + 1024 * 1024;
+
+    let mut key = vec![0; len];
+    pkcs5::scrypt(password, salt, n, r, p, maxmem, &mut key)?;
+    Ok(key)
+}
+
+// hashes are encoded in the phc format: $scrypt$ln=15,r=8,p=1$<salt>$<hash>
+pub fn scrypt_hash(password: &str) -> Result<String> {
+    let salt = salt();
+    let hash = scrypt_raw(password.as_bytes(), &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, SCRYPT_LEN)?;
+
+    Ok(format!("$scrypt$ln={},r={},p={}${}${}",
+        SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P,
+        base64::encode_config(&salt, base64::STANDARD_NO_PAD),
+        base64::encode_config(&hash, base64::STANDARD_NO_PAD)))
+}
+
+pub fn scrypt_verify(password: &str, hashed: &str) -> Result<bool> {
+    let parts = hashed.split('$').collect::<Vec<_>>();
+    if parts.len() != 5 || !parts[0].is_empty() || parts[1] != "scrypt" {
+        bail!("Invalid scrypt hash");
+    }
+
+    let mut log_n = None;
+    let mut r = None;
+    let mut p = None;
+    for param in parts[2].split(',') {
+        let mut kv = param.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("ln"), Some(v)) => log_n = Some(v.parse()?),
+            (Some("r"), Some(v)) => r = Some(v.parse()?),
+            (Some("p"), Some(v)) => p = Some(v.parse()?),
+            _ => bail!("Invalid scrypt parameter: {:?}", param),
+        }
+    }
+
+    let (log_n, r, p) = match (log_n, r, p) {
+        (Some(log_n), Some(r), Some(p)) => (log_n, r, p),
+        _ => bail!("scrypt hash is missing parameters"),
+    };
+
+    let salt = base64::decode_config(parts[3], base64::STANDARD_NO_PAD)
+                .context("Invalid scrypt salt")?;
+    let expected = base64::decode_config(parts[4], base64::STANDARD_NO_PAD)
+                .context("Invalid scrypt hash")?;
+
+    let hash = scrypt_raw(password.as_bytes(), &salt, log_n, r, p, expected.len())?;
+    Ok(constant_time_eq(&hash, &expected))
+}
+
+pub fn pbkdf2(password: &[u8], salt: &[u8], iterations: usize, len: usize, hash: &str) -> Result<Vec<u8>> {
+    let digest = match hash {
+        "md5" => MessageDigest::md5(),
+        "sha1" => MessageDigest::sha1(),
+        "sha256" | "sha2_256" => MessageDigest::sha256(),
+        "sha512" | "sha2_512" => MessageDigest::sha512(),
+        _ => bail!("unsupported pbkdf2 hash: {:?}", hash),
+    };
+
+    let mut key = vec![0; len];
+    pkcs5::pbkdf2_hmac(password, salt, iterations, digest, &mut key)?;
+    Ok(key)
+}
+
+pub fn argon2_hash(password: &str) -> Result<String> {
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..Default::default()
+    };
+    let hash = argon2::hash_encoded(password.as_bytes(), &salt(), &config)?;
+    Ok(hash)
+}
+
+pub fn argon2_verify(password: &str, hashed: &str) -> Result<bool> {
+    let valid = argon2::verify_encoded(hashed, password.as_bytes())?;
+    Ok(valid)
+}
+
+fn md5(parts: &[&[u8]]) -> Vec<u8> {
+    let mut h = Md5::default();
+    for part in parts {
+        h.input(part);
+    }
+    h.fixed_result().to_vec()
+}
+
+fn crypt_encode(out: &mut String, mut v: u32, n: usize) {
+    for _ in 0..n {
+        out.push(CRYPT_ALPHABET[(v & 0x3f) as usize] as char);
+        v >>= 6;
+    }
+}
+
+// md5crypt as used by $1$ and apache's $apr1$, which only differ in the magic
+fn md5_crypt(password: &[u8], salt: &str, magic: &str) -> String {
+    let salt = salt.split('$').next().unwrap();
+    let salt = &salt[..salt.len().min(8)];
+
+    let alternate = md5(&[password, salt.as_bytes(), password]);
+
+    let mut h = Md5::default();
+    h.input(password);
+    h.input(magic.as_bytes());
+    h.input(salt.as_bytes());
+    for chunk in password.chunks(16) {
+        h.input(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            h.input(&[0]);
+        } else {
+            h.input(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut hash = h.fixed_result().to_vec();
+
+    for i in 0..1000 {
+        let mut h = Md5::default();
+        h.input(if i & 1 == 1 { password } else { &hash });
+        if i % 3 != 0 {
+            h.input(salt.as_bytes());
+        }
+        if i % 7 != 0 {
+            h.input(password);
+        }
+        h.input(if i & 1 == 1 { &hash } else { password });
+        hash = h.fixed_result().to_vec();
+    }
+
+    let mut out = format!("{}{}$", magic, salt);
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let v = u32::from(hash[a]) << 16 | u32::from(hash[b]) << 8 | u32::from(hash[c]);
+        crypt_encode(&mut out, v, 4);
+    }
+    crypt_encode(&mut out, u32::from(hash[11]), 2);
+    out
+}
+
+// supports des, $1$, $apr1$, $5$, $6$ and bcrypt hashes
+pub fn crypt_verify(password: &str, hashed: &str) -> Result<bool> {
+    const APR1: &str = "$apr1$";
+
+    let hash = if hashed.starts_with(APR1) {
+        md5_crypt(password.as_bytes(), &hashed[APR1.len()..], APR1)
+    } else {
+        crypt::unix::crypt(password, hashed)
+            .map_err(|err| format_err!("Invalid crypt hash: {}", err))?
+    };
+
+    Ok(constant_time_eq(hash.as_bytes(), hashed.as_bytes()))
+}
+
+fn phpass_verify(password: &str, hashed: &str) -> Result<bool> {
+    if hashed.len() != 34 {
+        bail!("Invalid phpass hash");
+    }
+
+    let count = CRYPT_ALPHABET.iter()
+                    .position(|x| *x == hashed.as_bytes()[3])
+                    .ok_or_else(|| format_err!("Invalid phpass hash"))?;
+    if !(7..=30).contains(&count) {
+        bail!("Invalid phpass iteration count");
+    }
+    let salt = &hashed.as_bytes()[4..12];
+
+    let mut hash = md5(&[salt, password.as_bytes()]);
+    for _ in 0..(1 << count) {
+        hash = md5(&[&hash, password.as_bytes()]);
+    }
+
+    let mut out = hashed[..12].to_string();
+    for chunk in hash.chunks(3) {
+        let v = chunk.iter()
+                    .rev()
+                    .fold(0, |acc, x| acc << 8 | u32::from(*x));
+        crypt_encode(&mut out, v, chunk.len() + 1);
+    }
+
+    Ok(constant_time_eq(out.as_bytes(), hashed.as_bytes()))
+}
+
+// algorithm$iterations$salt$hash
+fn django_pbkdf2_verify(password: &str, hashed: &str) -> Result<bool> {
+    let parts = hashed.split('$').collect::<Vec<_>>();
+    if parts.len() != 4 {
+        bail!("Invalid django hash");
+    }
+
+    let hash = match parts[0] {
+        "pbkdf2_sha256" => "sha256",
+        "pbkdf2_sha1" => "sha1",
+        _ => bail!("unsupported django hash: {:?}", parts[0]),
+    };
+    let iterations = parts[1].parse::<usize>()
+                        .context("Invalid django iteration count")?;
+    let expected = base64::decode(parts[3])
+                        .context("Invalid django hash")?;
+
+    let key = pbkdf2(password.as_bytes(), parts[2].as_bytes(), iterations, expected.len(), hash)?;
+    Ok(constant_time_eq(&key, &expected))
+}
+
+// detects the format of hashes from php and python web frameworks, the pepper
+// is appended to the password for bcrypt hashes like devise does
+pub fn framework_hash_verify(password: &str, hashed: &str, pepper: Option<&str>) -> Result<bool> {
+    if hashed.starts_with("$P$") || hashed.starts_with("$H$") {
+        phpass_verify(password, hashed)
+    } else if hashed.starts_with("pbkdf2_") {
+        django_pbkdf2_verify(password, hashed)
+    } else if hashed.starts_with("bcrypt_sha256$") {
+        let password = to_hex(&Sha256::default().chain(password.as_bytes()).fixed_result());
+        Ok(bcrypt::verify(&password, &hashed["bcrypt_sha256$".len()..])?)
+    } else if hashed.starts_with("bcrypt$") {
+        Ok(bcrypt::verify(password, &hashed["bcrypt$".len()..])?)
+    } else if hashed.starts_with("argon2$") {
+        argon2_verify(password, &hashed["argon2".len()..])
+    } else if hashed.starts_with("$2") {
+        let password = format!("{}{}", password, pepper.unwrap_or(""));
+        Ok(bcrypt::verify(&password, hashed)?)
+    } else {
+        bail!("unknown hash format")
+    }
+}
+
+const SCRAM_ITERATIONS: usize = 4096;
+
+fn to_hex(x: &[u8]) -> String {
+    x.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// the hash stored in mysql.user for mysql_native_password
+pub fn mysql_native_password(password: &str) -> String {
+    let stage1 = Sha1::default().chain(password.as_bytes()).fixed_result();
+    let stage2 = Sha1::default().chain(stage1).fixed_result();
+    format!("*{}", to_hex(&stage2).to_uppercase())
+}
+
+// the hash stored in pg_authid for md5 authentication
+pub fn postgres_md5(user: &str, password: &str) -> String {
+    format!("md5{}", to_hex(&md5(&[password.as_bytes(), user.as_bytes()])))
+}
+
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key)
+                    .expect("hmac accepts keys of any size");
+    mac.input(msg);
+    mac.result().code().to_vec()
+}
+
+fn scram_keys(password: &str, salt: &[u8], iterations: usize) -> Result<(Vec<u8>, Vec<u8>)> {
+    let salted = pbkdf2(password.as_bytes(), salt, iterations, 32, "sha256")?;
+    let client_key = hmac_sha256(&salted, b"Client Key");
+    let stored_key = Sha256::default().chain(&client_key).fixed_result().to_vec();
+    let server_key = hmac_sha256(&salted, b"Server Key");
+    Ok((stored_key, server_key))
+}
+
+// SCRAM-SHA-256$iterations:salt$StoredKey:ServerKey
+pub fn postgres_scram_hash(password: &str) -> Result<String> {
+    let salt = salt();
+    let (stored_key, server_key) = scram_keys(password, &salt, SCRAM_ITERATIONS)?;
+
+    Ok(format!("SCRAM-SHA-256${}:{}${}:{}",
+        SCRAM_ITERATIONS,
+        base64::encode(&salt),
+        base64::encode(&stored_key),
+        base64::encode(&server_key)))
+}
+
+pub fn postgres_scram_verify(password: &str, hashed: &str) -> Result<bool> {
+    let parts = hashed.split(&['$', ':'][..]).collect::<Vec<_>>();
+    if parts.len() != 5 || parts[0] != "SCRAM-SHA-256" {
+        bail!("Invalid scram hash");
+    }
+
+    let iterations = parts[1].parse::<usize>()
+                        .context("Invalid scram iteration count")?;
+    let salt = base64::decode(parts[2])
+                        .context("Invalid scram salt")?;
+    let expected = base64::decode(parts[3])
+                        .context("Invalid scram hash")?;
+
+    let (stored_key, _) = scram_keys(password, &salt, iterations)?;
+    Ok(constant_time_eq(&stored_key, &expected))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrypt() {
+        let hash = scrypt_hash("hunter2").unwrap();
+        assert!(scrypt_verify("hunter2", &hash).unwrap());
+        assert!(!scrypt_verify("hunter3", &hash).unwrap());
+    }
+
+    #[test]
+    fn test_scrypt_known_hash() {
+        // generated with python's hashlib.scrypt
+        let hash = "$scrypt$ln=4,r=8,p=1$c2FsdHNhbHQ$xdm4IMyPApeWQ+5AiPVw2L3OCnA4OBnnwWGIV2OM5+o";
+        assert!(scrypt_verify("password", hash).unwrap());
+    }
+
+    #[test]
+    fn test_scrypt_invalid() {
+        assert!(scrypt_verify("hunter2", "$2y$10$abc").is_err());
+    }
+
+    #[test]
+    fn test_pbkdf2_wpa() {
+        // IEEE 802.11i test vector
+        let key = pbkdf2(b"password", b"IEEE", 4096, 32, "sha1").unwrap();
+        assert_eq!(key, vec![
+            0xf4, 0x2c, 0x6f, 0xc5, 0x2d, 0xf0, 0xeb, 0xef,
+            0x9e, 0xbb, 0x4b, 0x90, 0xb3, 0x8a, 0x5f, 0x90,
+            0x2e, 0x83, 0xfe, 0x1b, 0x13, 0x5a, 0x70, 0xe2,
+            0x3a, 0xed, 0x76, 0x2e, 0x97, 0x10, 0xa1, 0x2e,
+        ]);
+    }
+
+    #[test]
+    fn test_pbkdf2_unknown_hash() {
+        assert!(pbkdf2(b"password", b"salt", 1, 32, "crc32").is_err());
+    }
+
+    #[test]
+    fn test_crypt_verify() {
+        // generated with openssl passwd and python's crypt module
+        let hashes = [
+            "abJnggxhB/yWI",
+            "$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/",
+            "$apr1$r31.....$ARC3pREO82RIm0aQ2zszC0",
+            "$5$saltsalt$gOjOtoMpVhru2uyjeJSEc/JaLQWOXMNmlOnj6T4AtC.",
+            "$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/",
+        ];
+        for hash in &hashes {
+            assert!(crypt_verify("password", hash).unwrap(), "{}", hash);
+            assert!(!crypt_verify("hunter2", hash).unwrap(), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn test_md5_crypt() {
+        assert_eq!(md5_crypt(b"password", "saltsalt", "$1$"), "$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/");
+    }
+
+    #[test]
+    fn test_crypt_verify_invalid() {
+        assert!(crypt_verify("password", "$9$foo$bar").is_err());
+    }
+
+    #[test]
+    fn test_phpass() {
+        // from the phpass test suite
+        let hash = "$P$9IQRaTwmfeRo7ud9Fh4E2PdI0S3r.L0";
+        assert!(framework_hash_verify("test12345", hash, None).unwrap());
+        assert!(!framework_hash_verify("test12346", hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_django_pbkdf2() {
+        // generated with python's hashlib.pbkdf2_hmac
+        let hash = "pbkdf2_sha256$1000$seasalt$YIWkt6M1JFXrHg5s0jZjBSc7C2Cz6QvchSJ0h8Y+i7c=";
+        assert!(framework_hash_verify("password", hash, None).unwrap());
+        assert!(!framework_hash_verify("hunter2", hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_django_bcrypt_sha256() {
+        let sha256 = "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8";
+        let hash = format!("bcrypt_sha256${}", bcrypt::hash(sha256, 4).unwrap());
+        assert!(framework_hash_verify("password", &hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_bcrypt_pepper() {
+        let hash = bcrypt::hash("passwordpepper", 4).unwrap();
+        assert!(framework_hash_verify("password", &hash, Some("pepper")).unwrap());
+        assert!(!framework_hash_verify("password", &hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_mysql_native_password() {
+        assert_eq!(mysql_native_password("password"), "*2470C0C06DEE42FD1618BB99005ADCA2EC9D1E19");
+    }
+
+    #[test]
+    fn test_postgres_md5() {
+        assert_eq!(postgres_md5("postgres", "password"), "md532e12f215ba27cb750c9e093ce4b5127");
+    }
+
+    #[test]
+    fn test_postgres_scram() {
+        // generated with python's hashlib and hmac
+        let hash = "SCRAM-SHA-256$4096:MDEyMzQ1Njc4OWFiY2RlZg==$wjGCKoCIcEWiPxSG7t/wnb/YICMEFr1JZNZSKNje12g=:6NG/vkzOjK2oyl11qeNEBeKuOY3QQ4atswXYbIBO79Q=";
+        assert!(postgres_scram_verify("password", hash).unwrap());
+        assert!(!postgres_scram_verify("hunter2", hash).unwrap());
+
+        let hash = postgres_scram_hash("hunter2").unwrap();
+        assert!(postgres_scram_verify("hunter2", &hash).unwrap());
+    }
+
+    #[test]
+    fn test_argon2() {
+        let hash = argon2_hash("hunter2").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(argon2_verify("hunter2", &hash).unwrap());
+        assert!(!argon2_verify("hunter3", &hash).unwrap());
+    }
+}
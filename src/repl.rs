@@ -0,0 +1,151 @@
+// interactive prompt with the runtime loaded, see `badtouch repl`
+//
+// every line is evaluated as an expression first and as a statement if that
+// fails, lines are joined until the code is complete. Globals are kept
+// between lines, so a script can be loaded and its functions called by hand.
+
+use errors::*;
+
+use atty;
+use colored::Colorize;
+use config::Config;
+use ctx::{self, Script, State};
+use hlua::{self, AnyLuaValue};
+use runtime::format_lua;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::sync::Arc;
+
+
+pub struct Repl<'a> {
+    lua: hlua::Lua<'a>,
+    state: State,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(config: &Arc<Config>) -> Repl<'a> {
+        let (lua, state) = Script::ctx(config, &config.runtime.deny);
+        Repl {
+            lua,
+            state,
+        }
+    }
+
+    // lua reports code that ends too early as an error near <eof>
+    pub fn is_incomplete(&mut self, code: &str) -> bool {
+        match ctx::compile(&mut self.lua, code) {
+            Err(err) => err.to_string().ends_with("<eof>"),
+            Ok(_) => false,
+        }
+    }
+
+    // the value of an expression, None for statements
+    pub fn eval(&mut self, code: &str) -> Result<Option<AnyLuaValue>> {
+        // the parentheses make sure there's exactly one value
+        let expr = format!("return ({})", code);
+        if ctx::compile(&mut self.lua, &expr).is_ok() {
+            let value = self.lua.execute::<AnyLuaValue>(&expr)
+                .map_err(|err| format_err!("execution failed: {:?}", err))?;
+            return Ok(Some(value));
+        }
+
+        ctx::compile(&mut self.lua, code)?;
+        self.lua.execute::<()>(code)
+            .map_err(|err| format_err!("execution failed: {:?}", err))?;
+        Ok(None)
+    }
+
+    // the error of a runtime function, it's cleared after every line
+    pub fn take_error(&self) -> Option<String> {
+        let err = self.state.last_error();
+        self.state.clear_error();
+        err
+    }
+}
+
+pub fn run(config: &Arc<Config>, script: Option<&str>) -> Result<()> {
+    let mut repl = Repl::new(config);
+
+    if let Some(path) = script {
+        let code = fs::read_to_string(path)
+            .context(format!("Failed to read script {:?}", path))?;
+        repl.eval(&code)
+            .context(format!("Failed to load script {:?}", path))?;
+    }
+
+    let interactive = atty::is(atty::Stream::Stdin);
+    let stdin = io::stdin();
+    let mut code = String::new();
+
+    loop {
+        if interactive {
+            print!("{}", if code.is_empty() { "> " } else { ">> " });
+            io::stdout().flush()?;
+        }
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        code.push_str(&line);
+        if code.trim().is_empty() {
+            code.clear();
+            continue;
+        }
+        if repl.is_incomplete(&code) {
+            continue;
+        }
+
+        match repl.eval(code.trim()) {
+            Ok(Some(AnyLuaValue::LuaNil)) | Ok(None) => (),
+            Ok(Some(value)) => {
+                let mut out = String::new();
+                format_lua(&mut out, &value);
+                println!("{}", out);
+            },
+            Err(err) => eprintln!("{} {}", "[!]".bold(), err.to_string().red()),
+        }
+        if let Some(err) = repl.take_error() {
+            eprintln!("{} {}", "[!]".bold(), format!("last_err: {}", err).dimmed());
+        }
+        code.clear();
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl<'static> {
+        Repl::new(&Arc::new(Config::default()))
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut repl = repl();
+        assert_eq!(repl.eval("x = hex(md5(\"a\"))").unwrap(), None);
+        assert_eq!(repl.eval("x").unwrap(),
+            Some(AnyLuaValue::LuaString("0cc175b9c0f1b6a831c399e269772661".to_string())));
+        assert_eq!(repl.eval("1 + 2").unwrap(), Some(AnyLuaValue::LuaNumber(3.0)));
+        assert!(repl.eval("x(").is_err());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let mut repl = repl();
+        assert!(repl.is_incomplete("function f(x)\n"));
+        assert!(!repl.is_incomplete("function f(x) return x end\n"));
+        assert!(!repl.is_incomplete("x = = 1\n"));
+    }
+
+    #[test]
+    fn test_take_error() {
+        let mut repl = repl();
+        assert_eq!(repl.eval("json_decode(\"{\")").unwrap(), Some(AnyLuaValue::LuaNil));
+        assert!(repl.take_error().is_some());
+        assert!(repl.take_error().is_none());
+    }
+}
@@ -0,0 +1,279 @@
+// results file that is written with --output
+//
+// text is the classic `script:user:password` format and only contains valid
+// credentials. jsonl and csv contain one record per line and can also log
+// every attempt, including errors.
+//
+// records are buffered and written in batches, findings are synced to disk
+// right away so they survive a crash.
+
+use errors::*;
+
+use humantime;
+use serde_json;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => bail!("unknown output format: {:?}", s),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "timestamp,script,target,user,password,valid,latency_ms,error";
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Record<'a> {
+    pub timestamp: String,
+    pub script: &'a str,
+    pub target: Option<&'a str>,
+    pub user: &'a str,
+    // None for user enumeration
+    pub password: Option<&'a str>,
+    pub valid: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(script: &'a str, user: &'a str, password: Option<&'a str>) -> Record<'a> {
+        Record {
+            timestamp: humantime::format_rfc3339(SystemTime::now()).to_string(),
+            script,
+            target: None,
+            user,
+            password,
+            valid: false,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    pub fn latency(mut self, latency: Duration) -> Record<'a> {
+        self.latency_ms = Some(latency.as_secs() * 1000 + u64::from(latency.subsec_millis()));
+        self
+    }
+
+    pub fn format(&self, format: Format) -> Result<String> {
+        let line = match format {
+            Format::Text => match self.password {
+                Some(password) => format!("{}:{}:{}\n", self.script, self.user, password),
+                None => format!("{}:{}\n", self.script, self.user),
+            },
+            Format::Jsonl => {
+                let mut line = serde_json::to_string(self)?;
+                line.push('\n');
+                line
+            },
+            Format::Csv => {
+                let fields = [
+                    csv_escape(&self.timestamp),
+                    csv_escape(self.script),
+                    csv_escape(self.target.unwrap_or("")),
+                    csv_escape(self.user),
+                    csv_escape(self.password.unwrap_or("")),
+                    self.valid.to_string(),
+                    self.latency_ms.map(|x| x.to_string()).unwrap_or_default(),
+                    csv_escape(self.error.as_deref().unwrap_or("")),
+                ];
+                fields.join(",") + "\n"
+            },
+        };
+        Ok(line)
+    }
+}
+
+fn csv_escape(x: &str) -> String {
+    if x.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", x.replace('"', "\"\""))
+    } else {
+        x.to_string()
+    }
+}
+
+// the buffer is written when it's full, or with the next line after this
+const BATCH_SIZE: usize = 64 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// lines are written in batches instead of one write per line, a line is
+// never split across batches
+#[derive(Debug)]
+pub struct BatchWriter<W: Write> {
+    inner: BufWriter<W>,
+    last_flush: Instant,
+}
+
+impl<W: Write> BatchWriter<W> {
+    pub fn new(inner: W) -> BatchWriter<W> {
+        BatchWriter {
+            inner: BufWriter::with_capacity(BATCH_SIZE, inner),
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.inner.write_all(line)?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    file: Option<BatchWriter<File>>,
+    format: Format,
+    // also write invalid attempts and errors
+    all: bool,
+}
+
+impl Report {
+    pub fn open(path: Option<String>, format: Format, all: bool) -> Result<Report> {
+        let mut file = match path {
+            Some(path) => Some(BatchWriter::new(File::create(path)?)),
+            None => None,
+        };
+
+        if format == Format::Csv {
+            if let Some(ref mut f) = file {
+                f.write_line(format!("{}\n", CSV_HEADER).as_bytes())?;
+            }
+        }
+
+        Ok(Report {
+            file,
+            format,
+            all,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        // the text format only has room for valid credentials
+        if !record.valid && (!self.all || self.format == Format::Text) {
+            return Ok(());
+        }
+
+        if let Some(ref mut f) = self.file {
+            f.write_line(record.format(self.format)?.as_bytes())?;
+            if record.valid {
+                f.flush()?;
+                f.get_ref().sync_data()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(ref mut f) = self.file {
+            f.flush()?;
+            f.get_ref().sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>() -> Record<'a> {
+        Record {
+            timestamp: "2018-01-01T00:00:00Z".to_string(),
+            script: "example.com",
+            target: Some("10.0.0.1"),
+            user: "admin",
+            password: Some("a,\"b\""),
+            valid: true,
+            latency_ms: Some(120),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_format_text() {
+        let x = record().format(Format::Text).unwrap();
+        assert_eq!(x, "example.com:admin:a,\"b\"\n");
+        let mut r = record();
+        r.password = None;
+        assert_eq!(r.format(Format::Text).unwrap(), "example.com:admin\n");
+    }
+
+    #[test]
+    fn test_format_jsonl() {
+        let x = record().format(Format::Jsonl).unwrap();
+        assert_eq!(x, "{\"timestamp\":\"2018-01-01T00:00:00Z\",\"script\":\"example.com\",\"target\":\"10.0.0.1\",\"user\":\"admin\",\"password\":\"a,\\\"b\\\"\",\"valid\":true,\"latency_ms\":120,\"error\":null}\n");
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let x = record().format(Format::Csv).unwrap();
+        assert_eq!(x, "2018-01-01T00:00:00Z,example.com,10.0.0.1,admin,\"a,\"\"b\"\"\",true,120,\n");
+    }
+
+    #[test]
+    fn test_latency() {
+        let r = Record::new("x", "y", None).latency(Duration::from_millis(1500));
+        assert_eq!(r.latency_ms, Some(1500));
+    }
+
+    #[test]
+    fn test_batch_writer() {
+        let mut w = BatchWriter::new(Vec::new());
+        w.write_line(b"a\n").unwrap();
+        w.write_line(b"b\n").unwrap();
+        assert!(w.get_ref().is_empty());
+        w.flush().unwrap();
+        assert_eq!(w.get_ref(), b"a\nb\n");
+    }
+
+    #[test]
+    fn test_findings_are_written() {
+        use std::{env, fs, process};
+
+        let path = env::temp_dir().join(format!("badtouch-report-{}.jsonl", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut report = Report::open(Some(path.clone()), Format::Jsonl, true).unwrap();
+
+        let mut r = record();
+        r.valid = false;
+        report.write(&r).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        report.write(&record()).unwrap();
+        let out = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!(out.lines().count(), 2);
+    }
+}
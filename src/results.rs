@@ -0,0 +1,192 @@
+// sqlite database that logs every run, attempt and finding
+//
+// the same database can be used for multiple runs, attempts that already
+// have a result for the same target are skipped so work isn't repeated.
+// findings are deduplicated across runs, attempts and errors are not.
+
+use errors::*;
+
+use report::Record;
+use rusqlite::Connection;
+use std::collections::HashSet;
+
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started TEXT NOT NULL,
+    command TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS attempts (
+    id INTEGER PRIMARY KEY,
+    run INTEGER NOT NULL REFERENCES runs(id),
+    timestamp TEXT NOT NULL,
+    script TEXT NOT NULL,
+    target TEXT NOT NULL,
+    user TEXT NOT NULL,
+    password TEXT,
+    valid INTEGER NOT NULL,
+    latency_ms INTEGER
+);
+CREATE INDEX IF NOT EXISTS attempts_target ON attempts (target, script);
+
+CREATE TABLE IF NOT EXISTS findings (
+    id INTEGER PRIMARY KEY,
+    run INTEGER NOT NULL REFERENCES runs(id),
+    timestamp TEXT NOT NULL,
+    script TEXT NOT NULL,
+    target TEXT NOT NULL,
+    user TEXT NOT NULL,
+    password TEXT NOT NULL,
+    UNIQUE (script, target, user, password)
+);
+
+CREATE TABLE IF NOT EXISTS errors (
+    id INTEGER PRIMARY KEY,
+    run INTEGER NOT NULL REFERENCES runs(id),
+    timestamp TEXT NOT NULL,
+    script TEXT NOT NULL,
+    target TEXT NOT NULL,
+    user TEXT NOT NULL,
+    password TEXT,
+    error TEXT NOT NULL,
+    latency_ms INTEGER
+);
+";
+
+// (script, user, password), the password is empty for user enumeration
+pub type Known = HashSet<(String, String, String)>;
+
+pub struct ResultsDb {
+    conn: Connection,
+    run: i64,
+}
+
+impl ResultsDb {
+    pub fn open(path: &str, started: &str, command: &str) -> Result<ResultsDb> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create tables")?;
+
+        conn.execute("INSERT INTO runs (started, command) VALUES (?1, ?2)",
+                     params![started, command])?;
+        let run = conn.last_insert_rowid();
+
+        Ok(ResultsDb {
+            conn,
+            run,
+        })
+    }
+
+    // the target is stored as an empty string if no target option is set
+    pub fn insert(&self, record: &Record) -> Result<()> {
+        let target = record.target.unwrap_or("");
+
+        if let Some(ref error) = record.error {
+            self.conn.execute("INSERT INTO errors (run, timestamp, script, target, user, password, error, latency_ms)
+                               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                              params![self.run, record.timestamp, record.script, target,
+                                      record.user, record.password, error,
+                                      record.latency_ms.map(|x| x as i64)])?;
+            return Ok(());
+        }
+
+        self.conn.execute("INSERT INTO attempts (run, timestamp, script, target, user, password, valid, latency_ms)
+                           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                          params![self.run, record.timestamp, record.script, target,
+                                  record.user, record.password, record.valid,
+                                  record.latency_ms.map(|x| x as i64)])?;
+
+        if record.valid {
+            self.conn.execute("INSERT OR IGNORE INTO findings (run, timestamp, script, target, user, password)
+                               VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                              params![self.run, record.timestamp, record.script, target,
+                                      record.user, record.password.unwrap_or("")])?;
+        }
+
+        Ok(())
+    }
+
+    // attempts that already have a result for this target
+    pub fn known(&self, target: Option<&str>) -> Result<Known> {
+        let mut stmt = self.conn.prepare("SELECT script, user, COALESCE(password, '') FROM attempts
+                                          WHERE target = ?1")?;
+        let rows = stmt.query_map(params![target.unwrap_or("")], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+
+        let mut known = HashSet::new();
+        for row in rows {
+            known.insert(row?);
+        }
+        Ok(known)
+    }
+
+    // findings of previous runs for this target
+    pub fn findings(&self, target: Option<&str>) -> Result<usize> {
+        let n: i64 = self.conn.query_row("SELECT COUNT(*) FROM findings WHERE target = ?1 AND run != ?2",
+                                         params![target.unwrap_or(""), self.run],
+                                         |row| row.get(0))?;
+        Ok(n as usize)
+    }
+
+    #[inline]
+    pub fn run(&self) -> i64 {
+        self.run
+    }
+
+    #[cfg(test)]
+    fn count(&self, table: &str) -> i64 {
+        self.conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get(0))
+            .unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>(user: &'a str, password: Option<&'a str>, valid: bool) -> Record<'a> {
+        let mut record = Record::new("example.com", user, password);
+        record.target = Some("10.0.0.1");
+        record.valid = valid;
+        record
+    }
+
+    #[test]
+    fn test_insert() {
+        let db = ResultsDb::open(":memory:", "2018-01-01T00:00:00Z", "dict").unwrap();
+        db.insert(&record("admin", Some("admin"), false)).unwrap();
+        db.insert(&record("admin", Some("hunter2"), true)).unwrap();
+        db.insert(&record("admin", Some("hunter2"), true)).unwrap();
+        let mut err = record("root", Some("toor"), false);
+        err.error = Some("connection refused".to_string());
+        db.insert(&err).unwrap();
+
+        assert_eq!(db.count("attempts"), 3);
+        assert_eq!(db.count("findings"), 1);
+        assert_eq!(db.count("errors"), 1);
+        assert_eq!(db.run(), 1);
+    }
+
+    #[test]
+    fn test_known() {
+        let db = ResultsDb::open(":memory:", "2018-01-01T00:00:00Z", "dict").unwrap();
+        db.insert(&record("admin", Some("admin"), false)).unwrap();
+        db.insert(&record("guest", None, true)).unwrap();
+        let mut err = record("root", Some("toor"), false);
+        err.error = Some("connection refused".to_string());
+        db.insert(&err).unwrap();
+
+        let known = db.known(Some("10.0.0.1")).unwrap();
+        assert_eq!(known.len(), 2);
+        assert!(known.contains(&("example.com".to_string(), "admin".to_string(), "admin".to_string())));
+        assert!(known.contains(&("example.com".to_string(), "guest".to_string(), "".to_string())));
+        assert!(db.known(None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+// decide how the engine reacts to a failed attempt
+//
+// scripts can classify their errors with transient_error(), fatal_error() and
+// target_dead(). Timeouts and refused or reset connections are transient even
+// if the script didn't say so. Transient errors are retried with an
+// exponential backoff.
+
+use errors::*;
+
+use ctx::{ErrorKind, ScriptError};
+use reqwest;
+use std::cmp;
+use std::error::Error as StdError;
+use std::io;
+use std::time::Duration;
+
+
+const BACKOFF_BASE: u64 = 1;
+const BACKOFF_MAX: u64 = 120;
+
+fn is_transient_io(err: &io::Error) -> bool {
+    matches!(err.kind(),
+        io::ErrorKind::ConnectionRefused |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::TimedOut |
+        io::ErrorKind::WouldBlock)
+}
+
+fn is_transient_std(err: &(dyn StdError + 'static)) -> bool {
+    let mut cause = Some(err);
+    while let Some(err) = cause {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if is_transient_io(err) {
+                return true;
+            }
+        }
+        cause = err.source();
+    }
+    false
+}
+
+pub fn classify(err: &Error) -> Option<ErrorKind> {
+    // the script knows best
+    for cause in err.iter_chain() {
+        if let Some(err) = cause.downcast_ref::<ScriptError>() {
+            return Some(err.kind);
+        }
+    }
+
+    if is_network_error(err) {
+        Some(ErrorKind::Transient)
+    } else {
+        None
+    }
+}
+
+#[inline]
+pub fn is_transient(err: &Error) -> bool {
+    classify(err) == Some(ErrorKind::Transient)
+}
+
+fn is_network_error(err: &Error) -> bool {
+    for cause in err.iter_chain() {
+
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            if is_transient_io(err) {
+                return true;
+            }
+        }
+
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            if err.is_timeout() {
+                return true;
+            }
+            if let Some(source) = err.source() {
+                if is_transient_std(source) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// 1s, 2s, 4s, ... up to 2 minutes
+pub fn backoff(retry: u8) -> Duration {
+    let secs = 1u64.checked_shl(u32::from(retry))
+        .map(|x| x * BACKOFF_BASE)
+        .unwrap_or(BACKOFF_MAX);
+    Duration::from_secs(cmp::min(secs, BACKOFF_MAX))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        let err: Error = ScriptError {
+            kind: ErrorKind::Transient,
+            msg: "server error".to_string(),
+        }.into();
+        assert!(is_transient(&err));
+        let err: Error = io::Error::new(io::ErrorKind::ConnectionRefused, "refused").into();
+        assert!(is_transient(&err));
+        let err: Error = io::Error::new(io::ErrorKind::ConnectionRefused, "refused").into();
+        assert!(is_transient(&err.context("Failed to connect").into()));
+    }
+
+    #[test]
+    fn test_is_not_transient() {
+        let err: Error = io::Error::new(io::ErrorKind::PermissionDenied, "denied").into();
+        assert!(!is_transient(&err));
+        assert!(!is_transient(&format_err!("lua returned wrong type")));
+    }
+
+    #[test]
+    fn test_classify() {
+        let err: Error = ScriptError {
+            kind: ErrorKind::TargetDead,
+            msg: "gone".to_string(),
+        }.into();
+        assert_eq!(classify(&err), Some(ErrorKind::TargetDead));
+        let err: Error = io::Error::new(io::ErrorKind::TimedOut, "timeout").into();
+        assert_eq!(classify(&err), Some(ErrorKind::Transient));
+        assert_eq!(classify(&format_err!("lua returned wrong type")), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(10), Duration::from_secs(120));
+        assert_eq!(backoff(200), Duration::from_secs(120));
+    }
+}
@@ -0,0 +1,258 @@
+// password mangling with a subset of the hashcat rule syntax
+//
+// :   do nothing           l   lowercase           u   uppercase
+// c   capitalize           C   invert capitalize   t   toggle case
+// TN  toggle case at N     r   reverse             d   duplicate
+// $X  append X             ^X  prepend X           [   delete first
+// ]   delete last          DN  delete at N         'N  truncate at N
+// sXY replace X with Y     @X  purge X
+//
+// positions are 0-9 and A-Z for 10-35. every line of a rules file is one rule,
+// empty lines and lines starting with `#` are ignored.
+
+use errors::*;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::str::Chars;
+use std::sync::Arc;
+
+
+#[derive(Debug, PartialEq)]
+pub enum Op {
+    Noop,
+    Lowercase,
+    Uppercase,
+    Capitalize,
+    InvertCapitalize,
+    ToggleCase,
+    ToggleAt(usize),
+    Reverse,
+    Duplicate,
+    Append(char),
+    Prepend(char),
+    DeleteFirst,
+    DeleteLast,
+    DeleteAt(usize),
+    Truncate(usize),
+    Replace(char, char),
+    Purge(char),
+}
+
+fn toggle(c: char) -> String {
+    if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    }
+}
+
+impl Op {
+    fn apply(&self, x: &str) -> String {
+        match *self {
+            Op::Noop => x.to_string(),
+            Op::Lowercase => x.to_lowercase(),
+            Op::Uppercase => x.to_uppercase(),
+            Op::Capitalize => {
+                let mut chars = x.chars();
+                match chars.next() {
+                    Some(c) => c.to_uppercase().chain(chars.as_str().to_lowercase().chars()).collect(),
+                    None => String::new(),
+                }
+            },
+            Op::InvertCapitalize => {
+                let mut chars = x.chars();
+                match chars.next() {
+                    Some(c) => c.to_lowercase().chain(chars.as_str().to_uppercase().chars()).collect(),
+                    None => String::new(),
+                }
+            },
+            Op::ToggleCase => x.chars().map(toggle).collect(),
+            Op::ToggleAt(n) => x.chars().enumerate()
+                .map(|(i, c)| if i == n { toggle(c) } else { c.to_string() })
+                .collect(),
+            Op::Reverse => x.chars().rev().collect(),
+            Op::Duplicate => x.repeat(2),
+            Op::Append(c) => format!("{}{}", x, c),
+            Op::Prepend(c) => format!("{}{}", c, x),
+            Op::DeleteFirst => x.chars().skip(1).collect(),
+            Op::DeleteLast => {
+                let mut x = x.to_string();
+                x.pop();
+                x
+            },
+            Op::DeleteAt(n) => x.chars().enumerate()
+                .filter(|&(i, _)| i != n)
+                .map(|(_, c)| c)
+                .collect(),
+            Op::Truncate(n) => x.chars().take(n).collect(),
+            Op::Replace(a, b) => x.chars()
+                .map(|c| if c == a { b } else { c })
+                .collect(),
+            Op::Purge(a) => x.chars()
+                .filter(|c| *c != a)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rule(Vec<Op>);
+
+fn next_char(chars: &mut Chars, op: char) -> Result<char> {
+    chars.next()
+        .ok_or_else(|| format_err!("Missing argument for rule function {:?}", op))
+}
+
+fn next_position(chars: &mut Chars, op: char) -> Result<usize> {
+    let c = next_char(chars, op)?;
+    match c {
+        '0'..='9' => Ok(c as usize - '0' as usize),
+        'A'..='Z' => Ok(c as usize - 'A' as usize + 10),
+        _ => bail!("Invalid position for rule function {:?}: {:?}", op, c),
+    }
+}
+
+impl Rule {
+    pub fn parse(x: &str) -> Result<Rule> {
+        let mut ops = Vec::new();
+        let mut chars = x.chars();
+
+        while let Some(c) = chars.next() {
+            let op = match c {
+                ' ' | '\t' => continue,
+                ':' => Op::Noop,
+                'l' => Op::Lowercase,
+                'u' => Op::Uppercase,
+                'c' => Op::Capitalize,
+                'C' => Op::InvertCapitalize,
+                't' => Op::ToggleCase,
+                'T' => Op::ToggleAt(next_position(&mut chars, c)?),
+                'r' => Op::Reverse,
+                'd' => Op::Duplicate,
+                '$' => Op::Append(next_char(&mut chars, c)?),
+                '^' => Op::Prepend(next_char(&mut chars, c)?),
+                '[' => Op::DeleteFirst,
+                ']' => Op::DeleteLast,
+                'D' => Op::DeleteAt(next_position(&mut chars, c)?),
+                '\'' => Op::Truncate(next_position(&mut chars, c)?),
+                's' => {
+                    let a = next_char(&mut chars, c)?;
+                    let b = next_char(&mut chars, c)?;
+                    Op::Replace(a, b)
+                },
+                '@' => Op::Purge(next_char(&mut chars, c)?),
+                _ => bail!("Unsupported rule function: {:?}", c),
+            };
+            ops.push(op);
+        }
+
+        Ok(Rule(ops))
+    }
+
+    pub fn apply(&self, x: &str) -> String {
+        self.0.iter()
+            .fold(x.to_string(), |x, op| op.apply(&x))
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<Rule>> {
+    let f = File::open(path)?;
+    let file = BufReader::new(&f);
+
+    let mut rules = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let rule = Rule::parse(&line)
+            .context(format!("Invalid rule in line {}", i + 1))?;
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+// the candidates that are generated from one password, they are generated
+// while the attempts are generated. Duplicates are only removed within the
+// candidates of the same password.
+pub fn candidates(rules: &[Rule], password: &str) -> Vec<Arc<String>> {
+    let mut candidates: Vec<Arc<String>> = Vec::with_capacity(rules.len());
+    for rule in rules {
+        let candidate = rule.apply(password);
+        if !candidates.iter().any(|x| **x == candidate) {
+            candidates.push(Arc::new(candidate));
+        }
+    }
+    candidates
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(rule: &str, x: &str) -> String {
+        Rule::parse(rule).unwrap().apply(x)
+    }
+
+    #[test]
+    fn test_candidates() {
+        let rules = vec![Rule::parse(":").unwrap(), Rule::parse("l").unwrap(), Rule::parse("$1").unwrap()];
+        let x = candidates(&rules, "password");
+        assert_eq!(x.iter().map(|x| x.as_str()).collect::<Vec<_>>(), vec!["password", "password1"]);
+        assert_eq!(candidates(&rules, "Admin").len(), 3);
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(apply(":", "pAssword"), "pAssword");
+        assert_eq!(apply("l", "pAssword"), "password");
+        assert_eq!(apply("u", "pAssword"), "PASSWORD");
+        assert_eq!(apply("c", "pAssword"), "Password");
+        assert_eq!(apply("C", "pAssword"), "pASSWORD");
+        assert_eq!(apply("t", "pAssword"), "PaSSWORD");
+        assert_eq!(apply("T1", "pAssword"), "password");
+        assert_eq!(apply("T9", "pAssword"), "pAssword");
+    }
+
+    #[test]
+    fn test_append_prepend() {
+        assert_eq!(apply("$1 $2 $3", "password"), "password123");
+        assert_eq!(apply("^1 ^2", "password"), "21password");
+        assert_eq!(apply("c $2 $0 $1 $8 $!", "password"), "Password2018!");
+    }
+
+    #[test]
+    fn test_delete() {
+        assert_eq!(apply("[", "password"), "assword");
+        assert_eq!(apply("]", "password"), "passwor");
+        assert_eq!(apply("D3", "password"), "pasword");
+        assert_eq!(apply("'4", "password"), "pass");
+        assert_eq!(apply("'A", "password"), "password");
+        assert_eq!(apply("@s", "password"), "paword");
+    }
+
+    #[test]
+    fn test_misc() {
+        assert_eq!(apply("r", "password"), "drowssap");
+        assert_eq!(apply("d", "pw"), "pwpw");
+        assert_eq!(apply("sa@ so0 ss$", "password"), "p@$$w0rd");
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(apply("c ] [ D0 T0", ""), "");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Rule::parse("x").is_err());
+        assert!(Rule::parse("$").is_err());
+        assert!(Rule::parse("sa").is_err());
+        assert!(Rule::parse("T!").is_err());
+    }
+}
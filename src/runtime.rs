@@ -4,34 +4,75 @@ use hlua::AnyLuaValue::LuaString;
 use structs::LuaMap;
 use errors::*;
 use json;
+use ntlm;
+use jwt;
+use limits;
+use logfile;
+use log::Level;
+use serde_json;
 use db;
+use dns;
+use exec::{self, ExecOptions};
+use format;
 
 use md5;
 use sha1;
 use sha2;
 use sha3::{self, Digest};
+use blake2;
+use crc32fast;
 use digest::{Input, BlockInput, FixedOutput, Reset};
 use digest::generic_array::ArrayLength;
 use hmac::{Hmac, Mac};
 use base64;
+use base32::{self, Alphabet};
 use bcrypt;
 
 use reqwest;
 use reqwest::header::WWW_AUTHENTICATE;
 use ldap3;
-use mysql;
 use rand;
-use rand::RngCore;
+use time;
+use rand::{Rng, RngCore};
+use rand::distributions::Alphanumeric;
+use regex::{Captures, Regex};
 
-use std::thread;
+use std::fs;
+use std::net::IpAddr;
 use std::time::Duration;
 use std::process::Command;
 use std::collections::HashMap;
-use ctx::State;
-use http::HttpRequest;
+use crypto::{self, RsaKey};
+use ctx::{ErrorKind, Lockout, State};
+use http::{self, HttpRequest};
 use http::RequestOptions;
+use http::SessionOptions;
 use html;
-
+use oauth;
+use otp::{self, TotpOptions};
+use pack;
+use pwhash;
+use saml;
+use sockets::{self, SocketOptions};
+use tungstenite::Message;
+use url::{self, UrlParts};
+use websocket::WebSocketOptions;
+use x509::Certificate;
+use xml;
+
+
+// hlua reads a fixed number of arguments from the top of the stack, a
+// function that is called without its optional trailing arguments would read
+// the wrong values. The function is wrapped in a lua function with a fixed
+// parameter list, so missing arguments are passed as nil.
+fn optional_args(lua: &mut hlua::Lua, name: &str, params: usize) {
+    let params = (1..=params)
+        .map(|i| format!("a{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    lua.execute::<()>(&format!("do local f = {0}; function {0}({1}) return f({1}) end end", name, params))
+        .expect("failed to wrap function");
+}
 
 fn byte_array(bytes: AnyLuaValue) -> Result<Vec<u8>> {
     match bytes {
@@ -57,6 +98,81 @@ fn lua_bytes(bytes: &[u8]) -> AnyLuaValue {
     AnyLuaValue::LuaAnyString(bytes)
 }
 
+pub fn account_locked(lua: &mut hlua::Lua, state: State) {
+    lua.set("account_locked", hlua::function0(move || {
+        state.set_lockout(Lockout::User)
+    }))
+}
+
+pub fn aes_decrypt(lua: &mut hlua::Lua, state: State) {
+    lua.set("aes_decrypt", hlua::function4(move |mode: String, key: AnyLuaValue, iv: AnyLuaValue, data: AnyLuaValue| -> Result<AnyLuaValue> {
+        let key = byte_array(key)
+            .map_err(|err| state.set_error(err))?;
+        let iv = byte_array(iv)
+            .map_err(|err| state.set_error(err))?;
+        let data = byte_array(data)
+            .map_err(|err| state.set_error(err))?;
+
+        crypto::aes_decrypt(&mode, &key, &iv, &data)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+pub fn aes_encrypt(lua: &mut hlua::Lua, state: State) {
+    lua.set("aes_encrypt", hlua::function4(move |mode: String, key: AnyLuaValue, iv: AnyLuaValue, data: AnyLuaValue| -> Result<AnyLuaValue> {
+        let key = byte_array(key)
+            .map_err(|err| state.set_error(err))?;
+        let iv = byte_array(iv)
+            .map_err(|err| state.set_error(err))?;
+        let data = byte_array(data)
+            .map_err(|err| state.set_error(err))?;
+
+        crypto::aes_encrypt(&mode, &key, &iv, &data)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+pub fn argon2_hash(lua: &mut hlua::Lua, state: State) {
+    lua.set("argon2_hash", hlua::function1(move |password: String| -> Result<String> {
+        pwhash::argon2_hash(&password)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn argon2_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("argon2_verify", hlua::function2(move |password: String, hashed: String| -> Result<bool> {
+        pwhash::argon2_verify(&password, &hashed)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn banner_grab(lua: &mut hlua::Lua, state: State) {
+    lua.set("banner_grab", hlua::function3(move |host: String, port: u16, timeout: u32| -> Result<AnyLuaValue> {
+        sockets::banner_grab(&host, port, timeout)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+pub fn base32_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("base32_decode", hlua::function1(move |bytes: String| -> Result<AnyLuaValue> {
+        let bytes = bytes.to_uppercase();
+        base32::decode(Alphabet::RFC4648 { padding: false }, bytes.trim_end_matches('='))
+            .ok_or_else(|| state.set_error(format_err!("Invalid base32")))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+pub fn base32_encode(lua: &mut hlua::Lua, state: State) {
+    lua.set("base32_encode", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| base32::encode(Alphabet::RFC4648 { padding: true }, &bytes))
+    }))
+}
+
 pub fn base64_decode(lua: &mut hlua::Lua, state: State) {
     lua.set("base64_decode", hlua::function1(move |bytes: String| -> Result<AnyLuaValue> {
         base64::decode(&bytes)
@@ -80,6 +196,13 @@ pub fn bcrypt(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn bcrypt_hash(lua: &mut hlua::Lua, state: State) {
+    lua.set("bcrypt_hash", hlua::function2(move |password: String, cost: u32| -> Result<String> {
+        bcrypt::hash(&password, cost)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn bcrypt_verify(lua: &mut hlua::Lua, state: State) {
     lua.set("bcrypt_verify", hlua::function2(move |password: String, hashed: String| -> Result<bool> {
         bcrypt::verify(&password, &hashed)
@@ -87,20 +210,108 @@ pub fn bcrypt_verify(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn blake2b(lua: &mut hlua::Lua, state: State) {
+    lua.set("blake2b", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&blake2::Blake2b::digest(&bytes)))
+    }))
+}
+
+pub fn blake2s(lua: &mut hlua::Lua, state: State) {
+    lua.set("blake2s", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&blake2::Blake2s::digest(&bytes)))
+    }))
+}
+
 pub fn clear_err(lua: &mut hlua::Lua, state: State) {
     lua.set("clear_err", hlua::function0(move || {
         state.clear_error()
     }))
 }
 
+pub fn clock_ms(lua: &mut hlua::Lua, _: State) {
+    lua.set("clock_ms", hlua::function0(move || -> f64 {
+        (time::precise_time_ns() / 1_000_000) as f64
+    }))
+}
+
+pub fn crc32(lua: &mut hlua::Lua, state: State) {
+    lua.set("crc32", hlua::function1(move |bytes: AnyLuaValue| -> Result<u32> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| crc32fast::hash(&bytes))
+    }))
+}
+
+pub fn crypt_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("crypt_verify", hlua::function2(move |password: String, hashed: String| -> Result<bool> {
+        pwhash::crypt_verify(&password, &hashed)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn debug(lua: &mut hlua::Lua, _: State) {
+    lua.set("debug", hlua::function1(move |val: AnyLuaValue| {
+        logfile::script(Level::Debug, log_message(&val));
+    }));
+    optional_args(lua, "debug", 1)
+}
+
+pub fn dns_resolve(lua: &mut hlua::Lua, state: State) {
+    lua.set("dns_resolve", hlua::function2(move |name: String, rtype: AnyLuaValue| -> Result<Vec<AnyLuaValue>> {
+        let rtype = match rtype {
+            AnyLuaValue::LuaString(rtype) => rtype,
+            _ => String::from("A"),
+        };
+
+        dns::resolve(&name, &rtype)
+            .map_err(|err| state.set_error(err))
+            .map(|records| records.into_iter()
+                .map(|x| x.into())
+                .collect())
+    }));
+    optional_args(lua, "dns_resolve", 2)
+}
+
+pub fn dns_reverse(lua: &mut hlua::Lua, state: State) {
+    lua.set("dns_reverse", hlua::function1(move |ip: String| -> Result<Vec<AnyLuaValue>> {
+        dns::reverse(&ip)
+            .map_err(|err| state.set_error(err))
+            .map(|names| names.into_iter()
+                .map(LuaString)
+                .collect())
+    }))
+}
+
+pub fn endswith(lua: &mut hlua::Lua, _: State) {
+    lua.set("endswith", hlua::function2(move |x: String, suffix: String| -> bool {
+        x.ends_with(&suffix)
+    }))
+}
+
+pub fn exec(lua: &mut hlua::Lua, state: State) {
+    lua.set("exec", hlua::function3(move |prog: String, args: Vec<AnyLuaValue>, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let args = exec::args_from_lua(args)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = ExecOptions::try_from(options)
+            .context("invalid exec options")
+            .map_err(|err| state.set_error(err))?;
+
+        exec::exec(&prog, &args, &options)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }));
+    optional_args(lua, "exec", 3)
+}
+
 pub fn execve(lua: &mut hlua::Lua, state: State) {
     lua.set("execve", hlua::function2(move |prog: String, args: Vec<AnyLuaValue>| -> Result<i32> {
-        let args: Vec<_> = args.into_iter()
-                    .flat_map(|x| match x {
-                        LuaString(x) => Some(x),
-                        _ => None, // TODO: error
-                    })
-                    .collect();
+        let args = exec::args_from_lua(args)
+            .map_err(|err| state.set_error(err))?;
 
         let status = match Command::new(prog)
                         .args(&args)
@@ -119,6 +330,68 @@ pub fn execve(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn fatal_error(lua: &mut hlua::Lua, state: State) {
+    lua.set("fatal_error", hlua::function1(move |msg: String| -> Result<()> {
+        Err(state.set_error_kind(ErrorKind::Fatal, msg))
+    }))
+}
+
+pub fn file_read(lua: &mut hlua::Lua, state: State) {
+    lua.set("file_read", hlua::function1(move |path: String| -> Result<AnyLuaValue> {
+        state.fs_allowed()
+            .map_err(|err| state.set_error(err))?;
+
+        fs::read(&path)
+            .context(format_err!("Failed to read {:?}", path))
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+pub fn file_write(lua: &mut hlua::Lua, state: State) {
+    lua.set("file_write", hlua::function2(move |path: String, bytes: AnyLuaValue| -> Result<()> {
+        state.fs_allowed()
+            .map_err(|err| state.set_error(err))?;
+
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        fs::write(&path, &bytes)
+            .context(format_err!("Failed to write {:?}", path))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn format(lua: &mut hlua::Lua, state: State) {
+    lua.set("format", hlua::function2(move |fmt: String, args: Vec<AnyLuaValue>| -> Result<String> {
+        format::format(&fmt, &args)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn framework_hash_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("framework_hash_verify", hlua::function3(move |password: String, hashed: String, pepper: AnyLuaValue| -> Result<bool> {
+        // the pepper is optional
+        let pepper = match pepper {
+            AnyLuaValue::LuaString(pepper) => Some(pepper),
+            _ => None,
+        };
+
+        pwhash::framework_hash_verify(&password, &hashed, pepper.as_ref().map(|x| x.as_str()))
+            .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "framework_hash_verify", 3)
+}
+
+pub fn getopt(lua: &mut hlua::Lua, state: State) {
+    lua.set("getopt", hlua::function1(move |key: String| -> AnyLuaValue {
+        match state.getopt(&key) {
+            Some(value) => AnyLuaValue::LuaString(value.to_string()),
+            None => AnyLuaValue::LuaNil,
+        }
+    }))
+}
+
 pub fn hex(lua: &mut hlua::Lua, state: State) {
     lua.set("hex", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
         byte_array(bytes)
@@ -153,6 +426,28 @@ fn hmac<D>(secret: AnyLuaValue, msg: AnyLuaValue) -> Result<AnyLuaValue>
     Ok(lua_bytes(&result.code()))
 }
 
+pub fn hex_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("hex_decode", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
+        let x = x.chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+
+        if x.len() % 2 != 0 {
+            return Err(state.set_error(format_err!("Invalid hex: odd number of digits")));
+        }
+
+        let mut out = Vec::new();
+        for i in (0..x.len()).step_by(2) {
+            let b = x.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| state.set_error(format_err!("Invalid hex: {:?}", x)))?;
+            out.push(b);
+        }
+
+        Ok(lua_bytes(&out))
+    }))
+}
+
 pub fn hmac_md5(lua: &mut hlua::Lua, state: State) {
     lua.set("hmac_md5", hlua::function2(move |secret: AnyLuaValue, msg: AnyLuaValue| -> Result<AnyLuaValue> {
         hmac::<md5::Md5>(secret, msg)
@@ -167,6 +462,13 @@ pub fn hmac_sha1(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn hmac_sha256(lua: &mut hlua::Lua, state: State) {
+    lua.set("hmac_sha256", hlua::function2(move |secret: AnyLuaValue, msg: AnyLuaValue| -> Result<AnyLuaValue> {
+        hmac::<sha2::Sha256>(secret, msg)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn hmac_sha2_256(lua: &mut hlua::Lua, state: State) {
     lua.set("hmac_sha2_256", hlua::function2(move |secret: AnyLuaValue, msg: AnyLuaValue| -> Result<AnyLuaValue> {
         hmac::<sha2::Sha256>(secret, msg)
@@ -195,6 +497,28 @@ pub fn hmac_sha3_512(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn hmac_sha512(lua: &mut hlua::Lua, state: State) {
+    lua.set("hmac_sha512", hlua::function2(move |secret: AnyLuaValue, msg: AnyLuaValue| -> Result<AnyLuaValue> {
+        hmac::<sha2::Sha512>(secret, msg)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn hotp(lua: &mut hlua::Lua, state: State) {
+    lua.set("hotp", hlua::function2(move |secret: String, counter: u32| -> Result<String> {
+        otp::hotp(&secret, u64::from(counter))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn html_form(lua: &mut hlua::Lua, state: State) {
+    lua.set("html_form", hlua::function2(move |html: String, selector: String| -> Result<AnyLuaValue> {
+        html::html_form(&html, &selector)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
 pub fn html_select(lua: &mut hlua::Lua, state: State) {
     lua.set("html_select", hlua::function2(move |html: String, selector: String| -> Result<AnyLuaValue> {
         html::html_select(&html, &selector)
@@ -213,7 +537,8 @@ pub fn html_select_list(lua: &mut hlua::Lua, state: State) {
 
 pub fn http_basic_auth(lua: &mut hlua::Lua, state: State) {
     lua.set("http_basic_auth", hlua::function3(move |url: String, user: String, password: String| -> Result<bool> {
-        let client = reqwest::Client::new();
+        let client = http::client_builder().build()
+            .map_err(|err| state.set_error(err))?;
 
         client.get(&url)
             .basic_auth(user, Some(password))
@@ -228,21 +553,74 @@ pub fn http_basic_auth(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
-pub fn http_mksession(lua: &mut hlua::Lua, state: State) {
-    lua.set("http_mksession", hlua::function0(move || -> String {
-        state.http_mksession()
+pub fn http_cookies(lua: &mut hlua::Lua, state: State) {
+    lua.set("http_cookies", hlua::function1(move |session: String| -> Result<AnyLuaValue> {
+        state.http_cookies(&session)
+            .map_err(|err| state.set_error(err))
+            .map(|cookies| LuaMap::from(cookies).into())
     }))
 }
 
+fn http_shortcut(state: &State, session: &str, method: &str, url: String, options: AnyLuaValue, body: Option<AnyLuaValue>) -> Result<LuaMap> {
+    let mut options = match options {
+        AnyLuaValue::LuaArray(options) => options,
+        AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => Vec::new(),
+        x => bail!("Invalid request options: {:?}", x),
+    };
+
+    // tables are form encoded, everything else is sent as is
+    if let Some(body) = body {
+        let key = match body {
+            AnyLuaValue::LuaArray(_) => "form",
+            _ => "body",
+        };
+        options.push((AnyLuaValue::LuaString(key.into()), body));
+    }
+
+    let options = RequestOptions::try_from(AnyLuaValue::LuaArray(options))
+        .context("Invalid request options")?;
+    let req = state.http_request(session, method.into(), url, options)?;
+    req.send(state)
+}
+
+pub fn http_get(lua: &mut hlua::Lua, state: State) {
+    lua.set("http_get", hlua::function3(move |session: String, url: String, options: AnyLuaValue| -> Result<HashMap<AnyHashableLuaValue, AnyLuaValue>> {
+        http_shortcut(&state, &session, "GET", url, options, None)
+            .map_err(|err| state.set_error(err))
+            .map(|resp| resp.into())
+    }));
+    optional_args(lua, "http_get", 3)
+}
+
+pub fn http_mksession(lua: &mut hlua::Lua, state: State) {
+    lua.set("http_mksession", hlua::function1(move |options: AnyLuaValue| -> Result<String> {
+        SessionOptions::try_from(options)
+            .context("Invalid session options")
+            .map_err(|err| state.set_error(err))
+            .map(|options| state.http_mksession(options))
+    }));
+    optional_args(lua, "http_mksession", 1)
+}
+
+pub fn http_post(lua: &mut hlua::Lua, state: State) {
+    lua.set("http_post", hlua::function4(move |session: String, url: String, body: AnyLuaValue, options: AnyLuaValue| -> Result<HashMap<AnyHashableLuaValue, AnyLuaValue>> {
+        http_shortcut(&state, &session, "POST", url, options, Some(body))
+            .map_err(|err| state.set_error(err))
+            .map(|resp| resp.into())
+    }));
+    optional_args(lua, "http_post", 4)
+}
+
 pub fn http_request(lua: &mut hlua::Lua, state: State) {
     lua.set("http_request", hlua::function4(move |session: String, method: String, url: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
-        RequestOptions::try_from(options)
+        let options = RequestOptions::try_from(options)
             .context("Invalid request options")
+            .map_err(|err| state.set_error(err))?;
+        state.http_request(&session, method, url, options)
             .map_err(|err| state.set_error(err))
-            .map(|options| {
-                state.http_request(&session, method, url, options).into()
-            })
-    }))
+            .map(|req| req.into())
+    }));
+    optional_args(lua, "http_request", 4)
 }
 
 pub fn http_send(lua: &mut hlua::Lua, state: State) {
@@ -259,6 +637,28 @@ pub fn http_send(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn http_set_cookie(lua: &mut hlua::Lua, state: State) {
+    lua.set("http_set_cookie", hlua::function3(move |session: String, name: String, value: String| {
+        state.register_in_jar(&session, vec![(name, value)]);
+    }))
+}
+
+pub fn info(lua: &mut hlua::Lua, _: State) {
+    lua.set("info", hlua::function1(move |val: AnyLuaValue| {
+        logfile::script(Level::Info, log_message(&val));
+    }));
+    optional_args(lua, "info", 1)
+}
+
+pub fn jitter(lua: &mut hlua::Lua, state: State) {
+    lua.set("jitter", hlua::function2(move |min: u32, max: u32| -> Result<u32> {
+        let ms = state.rand_range(min, max.saturating_add(1))
+            .map_err(|err| state.set_error(err))?;
+        limits::sleep(Duration::from_millis(u64::from(ms)));
+        Ok(ms)
+    }))
+}
+
 pub fn json_decode(lua: &mut hlua::Lua, state: State) {
     lua.set("json_decode", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
         json::decode(&x)
@@ -273,6 +673,60 @@ pub fn json_encode(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn jwt_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("jwt_decode", hlua::function1(move |token: String| -> Result<AnyLuaValue> {
+        let token = jwt::decode(&token)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut map = LuaMap::new();
+        map.insert("header", json::LuaJsonValue::from(token.header));
+        map.insert("claims", json::LuaJsonValue::from(token.claims));
+        Ok(map.into())
+    }))
+}
+
+pub fn jwt_sign(lua: &mut hlua::Lua, state: State) {
+    lua.set("jwt_sign", hlua::function3(move |claims: AnyLuaValue, key: AnyLuaValue, alg: String| -> Result<String> {
+        let claims: serde_json::Value = json::LuaJsonValue::from(claims).into();
+        let key = byte_array(key)
+            .map_err(|err| state.set_error(err))?;
+        let alg = alg.parse()
+            .map_err(|err| state.set_error(err))?;
+
+        jwt::sign(&claims, &key, alg)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn jwt_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("jwt_verify", hlua::function3(move |token: String, key: AnyLuaValue, alg: String| -> Result<bool> {
+        let key = byte_array(key)
+            .map_err(|err| state.set_error(err))?;
+        let alg = alg.parse()
+            .map_err(|err| state.set_error(err))?;
+
+        jwt::verify(&token, &key, alg)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn kv_get(lua: &mut hlua::Lua, state: State) {
+    lua.set("kv_get", hlua::function1(move |key: String| -> AnyLuaValue {
+        match state.kv().get(&key) {
+            Some(value) => json::LuaJsonValue::from(value).into(),
+            None => AnyLuaValue::LuaNil,
+        }
+    }))
+}
+
+pub fn kv_set(lua: &mut hlua::Lua, state: State) {
+    lua.set("kv_set", hlua::function2(move |key: String, value: AnyLuaValue| -> Result<()> {
+        let value: serde_json::Value = json::LuaJsonValue::from(value).into();
+        state.kv().set(&key, value)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn last_err(lua: &mut hlua::Lua, state: State) {
     lua.set("last_err", hlua::function0(move || -> AnyLuaValue {
         match state.last_error() {
@@ -282,15 +736,56 @@ pub fn last_err(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+// plain ldap urls are resolved with the dns cache, ldaps keeps the hostname
+// so the certificate is verified against it
+fn ldap_resolve(url: &str) -> Result<String> {
+    let mut parsed = reqwest::Url::parse(url)
+        .context("invalid ldap url")?;
+    if parsed.scheme() != "ldap" {
+        return Ok(url.to_string());
+    }
+    let host = match parsed.host_str() {
+        Some(host) if !host.starts_with('[') && host.parse::<IpAddr>().is_err() => host.to_string(),
+        _ => return Ok(url.to_string()),
+    };
+
+    let ip = match dns::lookup_ip(&host)?.first() {
+        Some(ip) => *ip,
+        None => bail!("no dns records found"),
+    };
+    parsed.set_ip_host(ip)
+        .map_err(|_| format_err!("invalid ldap url"))?;
+    Ok(parsed.into())
+}
+
+// the connection and every operation don't take longer than the time the
+// script has left, see limits.rs
+fn ldap_connect(url: &str) -> Result<ldap3::LdapConn> {
+    let url = ldap_resolve(url)?;
+    let mut settings = ldap3::LdapConnSettings::new();
+    if let Some(timeout) = limits::remaining() {
+        settings = settings.set_conn_timeout(timeout);
+    }
+    let sock = ldap3::LdapConn::with_settings(settings, &url)
+        .context("ldap connection failed")?;
+    Ok(sock)
+}
+
+fn ldap_timeout(sock: &mut ldap3::LdapConn) -> &mut ldap3::LdapConn {
+    match limits::remaining() {
+        Some(timeout) => sock.with_timeout(timeout),
+        None => sock,
+    }
+}
+
 pub fn ldap_bind(lua: &mut hlua::Lua, state: State) {
     lua.set("ldap_bind", hlua::function3(move |url: String, dn: String, password: String| -> Result<bool> {
-        let sock = match ldap3::LdapConn::new(&url)
-                        .context("ldap connection failed") {
+        let mut sock = match ldap_connect(&url) {
             Ok(sock) => sock,
             Err(err) => return Err(state.set_error(err)),
         };
 
-        sock.simple_bind(&dn, &password)
+        ldap_timeout(&mut sock).simple_bind(&dn, &password)
             .context("Fatal error during simple_bind")
             .map_err(|err| state.set_error(err))
             .map(|result| {
@@ -308,12 +803,11 @@ pub fn ldap_escape(lua: &mut hlua::Lua, _: State) {
 
 pub fn ldap_search_bind(lua: &mut hlua::Lua, state: State) {
     lua.set("ldap_search_bind", hlua::function6(move |url: String, search_user: String, search_pw: String, base_dn: String, user: String, password: String| -> Result<bool> {
-        let sock = ldap3::LdapConn::new(&url)
-            .context("ldap connection failed")
+        let mut sock = ldap_connect(&url)
             .map_err(|err| state.set_error(err))?;
 
 
-        let result = sock.simple_bind(&search_user, &search_pw)
+        let result = ldap_timeout(&mut sock).simple_bind(&search_user, &search_pw)
             .context("Fatal error during simple_bind with search user")
             .map_err(|err| state.set_error(err))?;
 
@@ -322,7 +816,7 @@ pub fn ldap_search_bind(lua: &mut hlua::Lua, state: State) {
         }
 
         let search = format!("uid={}", ldap3::dn_escape(user));
-        let result = sock.search(&base_dn, ldap3::Scope::Subtree, &search, vec!["*"])
+        let result = ldap_timeout(&mut sock).search(&base_dn, ldap3::Scope::Subtree, &search, vec!["*"])
             .context("Fatal error during ldap search")
             .map_err(|err| state.set_error(err))?;
 
@@ -336,7 +830,7 @@ pub fn ldap_search_bind(lua: &mut hlua::Lua, state: State) {
             let entry = ldap3::SearchEntry::construct(entry);
 
             // we got the DN, try to login
-            let result = sock.simple_bind(&entry.dn, &password)
+            let result = ldap_timeout(&mut sock).simple_bind(&entry.dn, &password)
                 .context("Fatal error during simple_bind")
                 .map_err(|err| state.set_error(err))?;
 
@@ -349,6 +843,28 @@ pub fn ldap_search_bind(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
+pub fn lm_hash(lua: &mut hlua::Lua, state: State) {
+    lua.set("lm_hash", hlua::function1(move |password: String| -> Result<AnyLuaValue> {
+        ntlm::lm_hash(&password)
+            .map_err(|err| state.set_error(err))
+            .map(|hash| lua_bytes(&hash))
+    }))
+}
+
+pub fn lower(lua: &mut hlua::Lua, _: State) {
+    lua.set("lower", hlua::function1(move |x: String| -> String {
+        x.to_lowercase()
+    }))
+}
+
+pub fn md4(lua: &mut hlua::Lua, state: State) {
+    lua.set("md4", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&ntlm::md4(&bytes)))
+    }))
+}
+
 pub fn md5(lua: &mut hlua::Lua, state: State) {
     lua.set("md5", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
         byte_array(bytes)
@@ -358,17 +874,35 @@ pub fn md5(lua: &mut hlua::Lua, state: State) {
 }
 
 pub fn mysql_connect(lua: &mut hlua::Lua, state: State) {
-    lua.set("mysql_connect", hlua::function4(move |host: String, port: u16, user: String, password: String| -> Result<String> {
-        let mut builder = mysql::OptsBuilder::new();
-        builder.ip_or_hostname(Some(host))
-               .tcp_port(port)
-               .prefer_socket(false)
-               .user(Some(user))
-               .pass(Some(password));
+    lua.set("mysql_connect", hlua::function5(move |host: String, port: u16, user: String, password: String, options: AnyLuaValue| -> Result<String> {
+        let options = db::mysql::MysqlOptions::try_from(options)
+            .context("invalid mysql options")
+            .map_err(|err| state.set_error(err))?;
+
+        // resolved with the dns cache
+        let ip = match dns::lookup_ip(&host) {
+            Ok(ref ips) if ips.is_empty() => return Err(state.set_error(format_err!("no dns records found"))),
+            Ok(ips) => ips[0],
+            Err(err) => return Err(state.set_error(err)),
+        };
+
+        // the certificate is verified against the hostname, not the ip
+        let addr = if options.tls_ca.is_some() && !options.disable_tls_verify {
+            host
+        } else {
+            ip.to_string()
+        };
 
-        mysql::Conn::new(builder)
+        db::mysql::connect(&addr, port, &user, &password, &options)
             .map_err(|err| state.set_error(err))
             .map(|sock| state.mysql_register(sock))
+    }));
+    optional_args(lua, "mysql_connect", 5)
+}
+
+pub fn mysql_native_password(lua: &mut hlua::Lua, _: State) {
+    lua.set("mysql_native_password", hlua::function1(move |password: String| -> String {
+        pwhash::mysql_native_password(&password)
     }))
 }
 
@@ -376,7 +910,8 @@ pub fn mysql_query(lua: &mut hlua::Lua, state: State) {
     lua.set("mysql_query", hlua::function3(move |session: String, query: String, params: HashMap<AnyHashableLuaValue, AnyLuaValue>| -> Result<Vec<AnyLuaValue>> {
         let params = LuaMap::from(params);
 
-        let sock = state.mysql_session(&session);
+        let sock = state.mysql_session(&session)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
         let rows = sock.prep_exec(query, params)
             .context("Failed to execute query")
@@ -400,12 +935,92 @@ pub fn mysql_query(lua: &mut hlua::Lua, state: State) {
     }))
 }
 
-fn format_lua(out: &mut String, x: &AnyLuaValue) {
-    match *x {
-        AnyLuaValue::LuaNil => out.push_str("null"),
-        AnyLuaValue::LuaString(ref x) => out.push_str(&format!("{:?}", x)),
-        AnyLuaValue::LuaNumber(ref x) => out.push_str(&format!("{:?}", x)),
-        AnyLuaValue::LuaAnyString(ref x) => out.push_str(&format!("{:?}", x.0)),
+pub fn ntlm_decode_challenge(lua: &mut hlua::Lua, state: State) {
+    lua.set("ntlm_decode_challenge", hlua::function1(move |msg: String| -> Result<AnyLuaValue> {
+        ntlm::decode_challenge(&msg)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn ntlm_hash(lua: &mut hlua::Lua, _: State) {
+    lua.set("ntlm_hash", hlua::function1(move |password: String| -> AnyLuaValue {
+        lua_bytes(&ntlm::ntlm_hash(&password))
+    }))
+}
+
+pub fn ntlmv1_response(lua: &mut hlua::Lua, state: State) {
+    lua.set("ntlmv1_response", hlua::function1(move |options: AnyLuaValue| -> Result<AnyLuaValue> {
+        ntlm::ResponseOptions::try_from(options)
+            .and_then(|options| ntlm::ntlmv1_response(&options))
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn ntlmv2_response(lua: &mut hlua::Lua, state: State) {
+    lua.set("ntlmv2_response", hlua::function1(move |options: AnyLuaValue| -> Result<AnyLuaValue> {
+        ntlm::ResponseOptions::try_from(options)
+            .and_then(|options| ntlm::ntlmv2_response(&options))
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn oauth1_sign(lua: &mut hlua::Lua, state: State) {
+    lua.set("oauth1_sign", hlua::function7(move |method: String, url: String, params: AnyLuaValue, consumer_key: String, consumer_secret: String, token: String, token_secret: String| -> Result<String> {
+        let params = match params {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => HashMap::new(),
+            params => serde_json::from_value(json::LuaJsonValue::from(params).into())
+                .context("Invalid oauth1 params")
+                .map_err(|err| state.set_error(err))?,
+        };
+
+        let oauth = oauth::OAuth1 {
+            consumer_key: &consumer_key,
+            consumer_secret: &consumer_secret,
+            token: &token,
+            token_secret: &token_secret,
+        };
+
+        oauth.sign(&method, &url, &params)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn oauth2_password_grant(lua: &mut hlua::Lua, state: State) {
+    lua.set("oauth2_password_grant", hlua::function5(move |token_url: String, client_id: String, client_secret: String, user: String, password: String| -> Result<(bool, AnyLuaValue)> {
+        oauth::password_grant(&state, &token_url, &client_id, &client_secret, &user, &password)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn pack(lua: &mut hlua::Lua, state: State) {
+    lua.set("pack", hlua::function2(move |fmt: String, values: Vec<AnyLuaValue>| -> Result<AnyLuaValue> {
+        pack::pack(&fmt, values)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
+
+// strings are logged as they are, everything else like print
+fn log_message(x: &AnyLuaValue) -> String {
+    match *x {
+        AnyLuaValue::LuaString(ref x) => x.to_string(),
+        _ => {
+            let mut out = String::new();
+            format_lua(&mut out, x);
+            out
+        },
+    }
+}
+
+pub fn format_lua(out: &mut String, x: &AnyLuaValue) {
+    match *x {
+        AnyLuaValue::LuaNil => out.push_str("null"),
+        AnyLuaValue::LuaString(ref x) => out.push_str(&format!("{:?}", x)),
+        AnyLuaValue::LuaNumber(ref x) => out.push_str(&format!("{:?}", x)),
+        AnyLuaValue::LuaAnyString(ref x) => out.push_str(&format!("{:?}", x.0)),
         AnyLuaValue::LuaBoolean(ref x) => out.push_str(&format!("{:?}", x)),
         AnyLuaValue::LuaArray(ref x) => {
             out.push_str("{");
@@ -432,21 +1047,91 @@ fn format_lua(out: &mut String, x: &AnyLuaValue) {
     }
 }
 
+pub fn pbkdf2(lua: &mut hlua::Lua, state: State) {
+    lua.set("pbkdf2", hlua::function5(move |password: AnyLuaValue, salt: AnyLuaValue, iterations: u32, len: u32, hash: AnyLuaValue| -> Result<AnyLuaValue> {
+        let hash = match hash {
+            AnyLuaValue::LuaString(hash) => hash,
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => String::from("sha1"),
+            hash => return Err(state.set_error(format_err!("Invalid hash: {:?}", hash))),
+        };
+
+        let password = byte_array(password)
+            .map_err(|err| state.set_error(err))?;
+        let salt = byte_array(salt)
+            .map_err(|err| state.set_error(err))?;
+
+        pwhash::pbkdf2(&password, &salt, iterations as usize, len as usize, &hash)
+            .map_err(|err| state.set_error(err))
+            .map(|key| lua_bytes(&key))
+    }));
+    optional_args(lua, "pbkdf2", 5)
+}
+
+pub fn postgres_md5(lua: &mut hlua::Lua, _: State) {
+    lua.set("postgres_md5", hlua::function2(move |user: String, password: String| -> String {
+        pwhash::postgres_md5(&user, &password)
+    }))
+}
+
+pub fn postgres_scram_hash(lua: &mut hlua::Lua, state: State) {
+    lua.set("postgres_scram_hash", hlua::function1(move |password: String| -> Result<String> {
+        pwhash::postgres_scram_hash(&password)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn postgres_scram_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("postgres_scram_verify", hlua::function2(move |password: String, hashed: String| -> Result<bool> {
+        pwhash::postgres_scram_verify(&password, &hashed)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn port_open(lua: &mut hlua::Lua, state: State) {
+    lua.set("port_open", hlua::function3(move |host: String, port: u16, timeout: u32| -> Result<bool> {
+        sockets::port_open(&host, port, timeout)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn print(lua: &mut hlua::Lua, _: State) {
-    // this function doesn't print to the terminal safely
-    // only use this for debugging
+    // same as info, but strings are quoted
     lua.set("print", hlua::function1(move |val: AnyLuaValue| {
-        // println!("{:?}", val);
         let mut out = String::new();
         format_lua(&mut out, &val);
-        println!("{}", out);
+        logfile::script(Level::Info, out);
+    }));
+    optional_args(lua, "print", 1)
+}
+
+pub fn rand(lua: &mut hlua::Lua, state: State) {
+    lua.set("rand", hlua::function2(move |min: u32, max: u32| -> Result<u32> {
+        state.rand_range(min, max)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn rand_seed(lua: &mut hlua::Lua, state: State) {
+    lua.set("rand_seed", hlua::function1(move |seed: u32| {
+        state.rand_seed(u64::from(seed))
+    }))
+}
+
+pub fn random_alphanumeric(lua: &mut hlua::Lua, _: State) {
+    lua.set("random_alphanumeric", hlua::function1(move |len: u32| -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(len as usize)
+            .collect()
     }))
 }
 
-pub fn rand(lua: &mut hlua::Lua, _: State) {
-    lua.set("rand", hlua::function2(move |min: u32, max: u32| -> u32 {
+pub fn random_bytes(lua: &mut hlua::Lua, _: State) {
+    lua.set("random_bytes", hlua::function1(move |num: u32| -> AnyLuaValue {
+        let mut x = vec![0; num as usize];
         let mut rng = rand::thread_rng();
-        (rng.next_u32() + min) % max
+        rng.fill_bytes(x.as_mut_slice());
+        lua_bytes(&x)
     }))
 }
 
@@ -459,6 +1144,130 @@ pub fn randombytes(lua: &mut hlua::Lua, _: State) {
     }))
 }
 
+// the whole match is at index 1, followed by the capture groups. Named groups
+// are also available by name.
+fn lua_captures(regex: &Regex, caps: &Captures) -> AnyLuaValue {
+    let mut list = Vec::new();
+    for (i, cap) in caps.iter().enumerate() {
+        if let Some(cap) = cap {
+            list.push((AnyLuaValue::LuaNumber((i + 1) as f64), LuaString(cap.as_str().to_string())));
+        }
+    }
+    for name in regex.capture_names().filter_map(|x| x) {
+        if let Some(cap) = caps.name(name) {
+            list.push((LuaString(name.to_string()), LuaString(cap.as_str().to_string())));
+        }
+    }
+    AnyLuaValue::LuaArray(list)
+}
+
+pub fn regex_find(lua: &mut hlua::Lua, state: State) {
+    lua.set("regex_find", hlua::function2(move |regex: String, text: String| -> Result<AnyLuaValue> {
+        let regex = Regex::new(&regex)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(match regex.captures(&text) {
+            Some(caps) => lua_captures(&regex, &caps),
+            None => AnyLuaValue::LuaNil,
+        })
+    }))
+}
+
+pub fn regex_find_all(lua: &mut hlua::Lua, state: State) {
+    lua.set("regex_find_all", hlua::function2(move |regex: String, text: String| -> Result<Vec<AnyLuaValue>> {
+        let regex = Regex::new(&regex)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(regex.captures_iter(&text)
+            .map(|caps| lua_captures(&regex, &caps))
+            .collect())
+    }))
+}
+
+pub fn regex_match(lua: &mut hlua::Lua, state: State) {
+    lua.set("regex_match", hlua::function2(move |regex: String, text: String| -> Result<bool> {
+        let regex = Regex::new(&regex)
+            .map_err(|err| state.set_error(err))?;
+        Ok(regex.is_match(&text))
+    }))
+}
+
+pub fn regex_replace(lua: &mut hlua::Lua, state: State) {
+    lua.set("regex_replace", hlua::function3(move |regex: String, text: String, replacement: String| -> Result<String> {
+        let regex = Regex::new(&regex)
+            .map_err(|err| state.set_error(err))?;
+        Ok(regex.replace_all(&text, replacement.as_str()).into_owned())
+    }))
+}
+
+pub fn rsa_encrypt(lua: &mut hlua::Lua, state: State) {
+    lua.set("rsa_encrypt", hlua::function3(move |key: String, bytes: AnyLuaValue, padding: AnyLuaValue| -> Result<AnyLuaValue> {
+        let padding = match padding {
+            AnyLuaValue::LuaString(padding) => padding,
+            _ => String::from("pkcs1"),
+        };
+
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        let key = state.get_rsa(&key)
+            .map_err(|err| state.set_error(err))?;
+        key.encrypt(&bytes, &padding)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }));
+    optional_args(lua, "rsa_encrypt", 3)
+}
+
+pub fn rsa_load_pem(lua: &mut hlua::Lua, state: State) {
+    lua.set("rsa_load_pem", hlua::function1(move |pem: String| -> Result<String> {
+        RsaKey::load_pem(&pem)
+            .map_err(|err| state.set_error(err))
+            .map(|key| state.rsa_register(key))
+    }))
+}
+
+pub fn rsa_sign(lua: &mut hlua::Lua, state: State) {
+    lua.set("rsa_sign", hlua::function3(move |key: String, bytes: AnyLuaValue, alg: AnyLuaValue| -> Result<AnyLuaValue> {
+        let alg = match alg {
+            AnyLuaValue::LuaString(alg) => alg,
+            _ => String::from("sha256"),
+        };
+
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        let key = state.get_rsa(&key)
+            .map_err(|err| state.set_error(err))?;
+        key.sign(&bytes, &alg)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }));
+    optional_args(lua, "rsa_sign", 3)
+}
+
+pub fn saml_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("saml_decode", hlua::function1(move |blob: String| -> Result<AnyLuaValue> {
+        saml::decode(&blob)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn scrypt_hash(lua: &mut hlua::Lua, state: State) {
+    lua.set("scrypt_hash", hlua::function1(move |password: String| -> Result<String> {
+        pwhash::scrypt_hash(&password)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn scrypt_verify(lua: &mut hlua::Lua, state: State) {
+    lua.set("scrypt_verify", hlua::function2(move |password: String, hashed: String| -> Result<bool> {
+        pwhash::scrypt_verify(&password, &hashed)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn sha1(lua: &mut hlua::Lua, state: State) {
     lua.set("sha1", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
         byte_array(bytes)
@@ -501,21 +1310,79 @@ pub fn sha3_512(lua: &mut hlua::Lua, state: State) {
 
 pub fn sleep(lua: &mut hlua::Lua, _: State) {
     lua.set("sleep", hlua::function1(move |n: i32| {
-        thread::sleep(Duration::from_secs(n as u64));
+        limits::sleep(Duration::from_secs(n as u64));
+        0
+    }))
+}
+
+pub fn sleep_ms(lua: &mut hlua::Lua, _: State) {
+    lua.set("sleep_ms", hlua::function1(move |n: u32| {
+        limits::sleep(Duration::from_millis(u64::from(n)));
         0
     }))
 }
 
 pub fn sock_connect(lua: &mut hlua::Lua, state: State) {
-    lua.set("sock_connect", hlua::function3(move |host: String, port: u16, _settings: AnyLuaValue| -> Result<String> {
-        state.sock_connect(&host, port)
+    lua.set("sock_connect", hlua::function3(move |host: String, port: u16, options: AnyLuaValue| -> Result<String> {
+        let options = SocketOptions::try_from(options)
+            .context("invalid socket options")
+            .map_err(|err| state.set_error(err))?;
+
+        state.sock_connect(&host, port, &options)
             .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "sock_connect", 3)
+}
+
+pub fn sock_connect_tls(lua: &mut hlua::Lua, state: State) {
+    lua.set("sock_connect_tls", hlua::function3(move |host: String, port: u16, options: AnyLuaValue| -> Result<String> {
+        let mut options = SocketOptions::try_from(options)
+            .context("invalid socket options")
+            .map_err(|err| state.set_error(err))?;
+        options.tls = true;
+
+        state.sock_connect(&host, port, &options)
+            .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "sock_connect_tls", 3)
+}
+
+pub fn sock_starttls(lua: &mut hlua::Lua, state: State) {
+    lua.set("sock_starttls", hlua::function2(move |sock: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
+        let mut sock = sock.lock().unwrap();
+
+        let options = SocketOptions::try_from(options)
+            .context("invalid socket options")
+            .map_err(|err| state.set_error(err))?;
+
+        sock.starttls(&options)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(sock.peer_cert()
+            .map(|cert| cert.clone().into())
+            .unwrap_or(AnyLuaValue::LuaNil))
+    }));
+    optional_args(lua, "sock_starttls", 2)
+}
+
+pub fn sock_peer_cert(lua: &mut hlua::Lua, state: State) {
+    lua.set("sock_peer_cert", hlua::function1(move |sock: String| -> Result<AnyLuaValue> {
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
+        let sock = sock.lock().unwrap();
+
+        Ok(sock.peer_cert()
+            .map(|cert| cert.clone().into())
+            .unwrap_or(AnyLuaValue::LuaNil))
     }))
 }
 
 pub fn sock_send(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_send", hlua::function2(move |sock: String, bytes: AnyLuaValue| -> Result<()> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let bytes = byte_array(bytes)
@@ -530,7 +1397,8 @@ pub fn sock_send(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recv(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recv", hlua::function1(move |sock: String| -> Result<AnyLuaValue> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let bytes = sock.recv()
@@ -542,7 +1410,8 @@ pub fn sock_recv(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_sendline(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_sendline", hlua::function2(move |sock: String, line: String| -> Result<()> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         sock.sendline(&line)
@@ -554,7 +1423,8 @@ pub fn sock_sendline(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvline(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvline", hlua::function1(move |sock: String| -> Result<String> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let line = sock.recvline()
@@ -566,7 +1436,8 @@ pub fn sock_recvline(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvall(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvall", hlua::function1(move |sock: String| -> Result<AnyLuaValue> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let bytes = sock.recvall()
@@ -578,7 +1449,8 @@ pub fn sock_recvall(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvline_contains(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvline_contains", hlua::function2(move |sock: String, needle: String| -> Result<String> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let line = sock.recvline_contains(&needle)
@@ -590,7 +1462,8 @@ pub fn sock_recvline_contains(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvline_regex(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvline_regex", hlua::function2(move |sock: String, regex: String| -> Result<String> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let line = sock.recvline_regex(&regex)
@@ -602,7 +1475,8 @@ pub fn sock_recvline_regex(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvn(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvn", hlua::function2(move |sock: String, n: u32| -> Result<AnyLuaValue> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let bytes = sock.recvn(n)
@@ -614,7 +1488,8 @@ pub fn sock_recvn(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_recvuntil(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_recvuntil", hlua::function2(move |sock: String, delim: AnyLuaValue| -> Result<AnyLuaValue> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let delim = byte_array(delim)
@@ -629,7 +1504,8 @@ pub fn sock_recvuntil(lua: &mut hlua::Lua, state: State) {
 
 pub fn sock_sendafter(lua: &mut hlua::Lua, state: State) {
     lua.set("sock_sendafter", hlua::function3(move |sock: String, delim: AnyLuaValue, bytes: AnyLuaValue| -> Result<()> {
-        let sock = state.get_sock(&sock);
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         let delim = byte_array(delim)
@@ -646,10 +1522,271 @@ pub fn sock_sendafter(lua: &mut hlua::Lua, state: State) {
 }
 
 pub fn sock_newline(lua: &mut hlua::Lua, state: State) {
-    lua.set("sock_newline", hlua::function2(move |sock: String, newline: String| -> () {
-        let sock = state.get_sock(&sock);
+    lua.set("sock_newline", hlua::function2(move |sock: String, newline: String| -> Result<()> {
+        let sock = state.get_sock(&sock)
+            .map_err(|err| state.set_error(err))?;
         let mut sock = sock.lock().unwrap();
 
         sock.newline(newline);
+        Ok(())
+    }))
+}
+
+pub fn startswith(lua: &mut hlua::Lua, _: State) {
+    lua.set("startswith", hlua::function2(move |x: String, prefix: String| -> bool {
+        x.starts_with(&prefix)
+    }))
+}
+
+pub fn strftime(lua: &mut hlua::Lua, state: State) {
+    lua.set("strftime", hlua::function2(move |fmt: String, ts: AnyLuaValue| -> Result<String> {
+        let ts = match ts {
+            AnyLuaValue::LuaNumber(ts) => time::Timespec::new(ts as i64, 0),
+            _ => time::get_time(),
+        };
+
+        time::strftime(&fmt, &time::at_utc(ts))
+            .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "strftime", 2)
+}
+
+pub fn strsplit(lua: &mut hlua::Lua, state: State) {
+    lua.set("strsplit", hlua::function2(move |x: String, sep: String| -> Result<Vec<AnyLuaValue>> {
+        if sep.is_empty() {
+            return Err(state.set_error(format_err!("separator can not be empty")));
+        }
+
+        Ok(x.split(sep.as_str())
+            .map(|x| LuaString(x.to_string()))
+            .collect())
+    }))
+}
+
+pub fn target_dead(lua: &mut hlua::Lua, state: State) {
+    lua.set("target_dead", hlua::function1(move |msg: String| -> Result<()> {
+        Err(state.set_error_kind(ErrorKind::TargetDead, msg))
+    }))
+}
+
+pub fn target_throttled(lua: &mut hlua::Lua, state: State) {
+    lua.set("target_throttled", hlua::function0(move || {
+        state.set_lockout(Lockout::Target)
+    }))
+}
+
+pub fn time_unix(lua: &mut hlua::Lua, _: State) {
+    lua.set("time_unix", hlua::function0(move || -> f64 {
+        time::get_time().sec as f64
+    }))
+}
+
+pub fn totp(lua: &mut hlua::Lua, state: State) {
+    lua.set("totp", hlua::function2(move |secret: String, options: AnyLuaValue| -> Result<String> {
+        let options = TotpOptions::try_from(options)
+            .context("invalid totp options")
+            .map_err(|err| state.set_error(err))?;
+
+        otp::totp(&secret, &options)
+            .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "totp", 2)
+}
+
+pub fn transient_error(lua: &mut hlua::Lua, state: State) {
+    lua.set("transient_error", hlua::function1(move |msg: String| -> Result<()> {
+        Err(state.set_error_kind(ErrorKind::Transient, msg))
+    }))
+}
+
+pub fn trim(lua: &mut hlua::Lua, _: State) {
+    lua.set("trim", hlua::function1(move |x: String| -> String {
+        x.trim().to_string()
+    }))
+}
+
+pub fn udp_send(lua: &mut hlua::Lua, state: State) {
+    lua.set("udp_send", hlua::function3(move |host: String, port: u16, bytes: AnyLuaValue| -> Result<String> {
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        let id = state.udp_connect(&host, port)
+            .map_err(|err| state.set_error(err))?;
+
+        let sock = state.get_udp(&id)
+            .map_err(|err| state.set_error(err))?;
+        sock.send(&bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(id)
+    }))
+}
+
+pub fn udp_recv(lua: &mut hlua::Lua, state: State) {
+    lua.set("udp_recv", hlua::function2(move |sock: String, timeout: u32| -> Result<AnyLuaValue> {
+        let sock = state.get_udp(&sock)
+            .map_err(|err| state.set_error(err))?;
+
+        let timeout = if timeout > 0 {
+            Some(Duration::from_millis(u64::from(timeout)))
+        } else {
+            None
+        };
+
+        let bytes = sock.recv(timeout)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(lua_bytes(&bytes))
+    }))
+}
+
+pub fn unpack(lua: &mut hlua::Lua, state: State) {
+    lua.set("unpack", hlua::function2(move |fmt: String, bytes: AnyLuaValue| -> Result<Vec<AnyLuaValue>> {
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        pack::unpack(&fmt, &bytes)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn upper(lua: &mut hlua::Lua, _: State) {
+    lua.set("upper", hlua::function1(move |x: String| -> String {
+        x.to_uppercase()
+    }))
+}
+
+pub fn url_build(lua: &mut hlua::Lua, state: State) {
+    lua.set("url_build", hlua::function1(move |parts: AnyLuaValue| -> Result<String> {
+        UrlParts::try_from(parts)
+            .and_then(|parts| url::build(&parts))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn url_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("url_decode", hlua::function1(move |x: String| -> Result<String> {
+        url::decode(&x)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn url_encode(lua: &mut hlua::Lua, _: State) {
+    lua.set("url_encode", hlua::function1(move |x: String| -> String {
+        url::encode(&x)
+    }))
+}
+
+pub fn url_parse(lua: &mut hlua::Lua, state: State) {
+    lua.set("url_parse", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
+        url::parse(&x)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn uuid4(lua: &mut hlua::Lua, _: State) {
+    lua.set("uuid4", hlua::function0(move || -> String {
+        let mut x = [0; 16];
+        rand::thread_rng().fill_bytes(&mut x);
+
+        // set the version and the rfc4122 variant
+        x[6] = (x[6] & 0x0f) | 0x40;
+        x[8] = (x[8] & 0x3f) | 0x80;
+
+        let x = x.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        format!("{}-{}-{}-{}-{}", &x[..8], &x[8..12], &x[12..16], &x[16..20], &x[20..])
+    }))
+}
+
+pub fn warn(lua: &mut hlua::Lua, _: State) {
+    lua.set("warn", hlua::function1(move |val: AnyLuaValue| {
+        logfile::script(Level::Warn, log_message(&val));
+    }));
+    optional_args(lua, "warn", 1)
+}
+
+pub fn ws_close(lua: &mut hlua::Lua, state: State) {
+    lua.set("ws_close", hlua::function1(move |ws: String| -> Result<()> {
+        let ws = state.get_ws(&ws)
+            .map_err(|err| state.set_error(err))?;
+        let mut ws = ws.lock().unwrap();
+
+        ws.close()
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn ws_connect(lua: &mut hlua::Lua, state: State) {
+    lua.set("ws_connect", hlua::function2(move |url: String, options: AnyLuaValue| -> Result<String> {
+        let options = WebSocketOptions::try_from(options)
+            .context("Invalid websocket options")
+            .map_err(|err| state.set_error(err))?;
+
+        state.ws_connect(&url, &options)
+            .map_err(|err| state.set_error(err))
+    }));
+    optional_args(lua, "ws_connect", 2)
+}
+
+pub fn ws_recv(lua: &mut hlua::Lua, state: State) {
+    lua.set("ws_recv", hlua::function1(move |ws: String| -> Result<AnyLuaValue> {
+        let ws = state.get_ws(&ws)
+            .map_err(|err| state.set_error(err))?;
+        let mut ws = ws.lock().unwrap();
+
+        let msg = ws.recv()
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(match msg {
+            Some(Message::Text(text)) => AnyLuaValue::LuaString(text),
+            Some(Message::Binary(bytes)) => lua_bytes(&bytes),
+            _ => AnyLuaValue::LuaNil,
+        })
+    }))
+}
+
+pub fn ws_send(lua: &mut hlua::Lua, state: State) {
+    lua.set("ws_send", hlua::function2(move |ws: String, data: AnyLuaValue| -> Result<()> {
+        let ws = state.get_ws(&ws)
+            .map_err(|err| state.set_error(err))?;
+        let mut ws = ws.lock().unwrap();
+
+        let msg = match data {
+            AnyLuaValue::LuaString(text) => Message::Text(text),
+            data => Message::Binary(byte_array(data)
+                .map_err(|err| state.set_error(err))?),
+        };
+
+        ws.send(msg)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn x509_parse(lua: &mut hlua::Lua, state: State) {
+    lua.set("x509_parse", hlua::function1(move |cert: AnyLuaValue| -> Result<AnyLuaValue> {
+        let cert = byte_array(cert)
+            .map_err(|err| state.set_error(err))?;
+
+        Certificate::parse(&cert)
+            .map_err(|err| state.set_error(err))
+            .map(|cert| cert.into())
+    }))
+}
+
+pub fn xml_decode(lua: &mut hlua::Lua, state: State) {
+    lua.set("xml_decode", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
+        xml::xml_decode(&x)
+            .map_err(|err| state.set_error(err))
+            .map(|x| x.into())
+    }))
+}
+
+pub fn xml_xpath(lua: &mut hlua::Lua, state: State) {
+    lua.set("xml_xpath", hlua::function2(move |x: String, expr: String| -> Result<AnyLuaValue> {
+        xml::xml_xpath(&x, &expr)
+            .map_err(|err| state.set_error(err))
     }))
 }
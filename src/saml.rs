@@ -0,0 +1,57 @@
+use errors::*;
+
+use base64;
+use flate2::read::DeflateDecoder;
+use xml::{self, XmlElement};
+
+use std::io::Read;
+
+
+// SAMLRequest/SAMLResponse parameters are base64 encoded, the redirect binding
+// additionally compresses them with raw deflate
+pub fn decode(blob: &str) -> Result<XmlElement> {
+    let blob = blob.split_whitespace().collect::<String>();
+    let bytes = base64::decode(&blob)
+                    .context("Invalid base64")?;
+
+    let is_xml = bytes.iter()
+                    .find(|x| !(**x as char).is_whitespace())
+                    .map_or(false, |x| *x == b'<');
+
+    let xml = if is_xml {
+        String::from_utf8(bytes)?
+    } else {
+        let mut xml = String::new();
+        DeflateDecoder::new(&bytes[..]).read_to_string(&mut xml)
+            .context("Failed to inflate saml message")?;
+        xml
+    };
+
+    xml::xml_decode(&xml)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    const RESPONSE: &str = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_8e8dc5f69a98cc4c1ff3427e5ce34606fd672f91e6" Version="2.0"><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status></samlp:Response>"#;
+
+    #[test]
+    fn test_decode_post_binding() {
+        let root = decode(&base64::encode(RESPONSE)).unwrap();
+        assert_eq!(root, xml::xml_decode(RESPONSE).unwrap());
+    }
+
+    #[test]
+    fn test_decode_redirect_binding() {
+        let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+        e.write_all(RESPONSE.as_bytes()).unwrap();
+        let blob = base64::encode(&e.finish().unwrap());
+
+        assert_eq!(decode(&blob).unwrap(), xml::xml_decode(RESPONSE).unwrap());
+    }
+}
@@ -0,0 +1,199 @@
+// --sandbox and --deny remove groups of dangerous functions from the lua
+// environment, so untrusted scripts can't run commands, touch files or open
+// raw sockets. A script that calls a removed function fails with an error.
+//
+// Scripts can also declare the capabilities they need in their descriptor
+// with caps = {"http", "exec"}, everything else is removed for them. Scripts
+// that don't declare caps can use everything and are confirmed like a script
+// that declares all of them.
+
+use errors::*;
+
+use hlua::{self, AnyLuaValue};
+use std::fmt;
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    Dns,
+    Exec,
+    Fs,
+    Http,
+    Ldap,
+    Mysql,
+    RawSockets,
+}
+
+pub const ALL: &[Capability] = &[
+    Capability::Dns,
+    Capability::Exec,
+    Capability::Fs,
+    Capability::Http,
+    Capability::Ldap,
+    Capability::Mysql,
+    Capability::RawSockets,
+];
+
+// everything that is denied with --sandbox
+pub const DANGEROUS: &[Capability] = &[
+    Capability::Exec,
+    Capability::Fs,
+    Capability::RawSockets,
+];
+
+// the user is asked before a script that declares these is run
+pub const CONFIRM: &[Capability] = &[
+    Capability::Exec,
+    Capability::Fs,
+];
+
+impl Capability {
+    pub fn functions(self) -> &'static [&'static str] {
+        match self {
+            Capability::Dns => &["dns_resolve", "dns_reverse"],
+            Capability::Exec => &["exec", "execve"],
+            Capability::Fs => &["file_read", "file_write"],
+            Capability::Http => &[
+                "http_basic_auth",
+                "http_get",
+                "http_mksession",
+                "http_post",
+                "http_request",
+                "http_send",
+                "oauth2_password_grant",
+                "ws_close",
+                "ws_connect",
+                "ws_recv",
+                "ws_send",
+            ],
+            Capability::Ldap => &["ldap_bind", "ldap_search_bind"],
+            Capability::Mysql => &["mysql_connect", "mysql_query"],
+            Capability::RawSockets => &[
+                "banner_grab",
+                "port_open",
+                "sock_connect",
+                "sock_connect_tls",
+                "sock_newline",
+                "sock_peer_cert",
+                "sock_recv",
+                "sock_recvall",
+                "sock_recvline",
+                "sock_recvline_contains",
+                "sock_recvline_regex",
+                "sock_recvn",
+                "sock_recvuntil",
+                "sock_send",
+                "sock_sendafter",
+                "sock_sendline",
+                "sock_starttls",
+                "udp_recv",
+                "udp_send",
+            ],
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Capability> {
+        match s {
+            "dns" => Ok(Capability::Dns),
+            "exec" => Ok(Capability::Exec),
+            "fs" => Ok(Capability::Fs),
+            "http" => Ok(Capability::Http),
+            "ldap" => Ok(Capability::Ldap),
+            "mysql" => Ok(Capability::Mysql),
+            "raw-sockets" => Ok(Capability::RawSockets),
+            _ => bail!("unknown capability: {:?}", s),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Capability::Dns => "dns",
+            Capability::Exec => "exec",
+            Capability::Fs => "fs",
+            Capability::Http => "http",
+            Capability::Ldap => "ldap",
+            Capability::Mysql => "mysql",
+            Capability::RawSockets => "raw-sockets",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// the functions of a script are limited to the capabilities it declares, if
+// it declares any. Capabilities that are denied by the user stay denied.
+pub fn denied(declared: Option<&[Capability]>, deny: &[Capability]) -> Vec<Capability> {
+    let mut denied = deny.to_vec();
+    if let Some(declared) = declared {
+        denied.extend(ALL.iter()
+            .filter(|cap| !declared.contains(cap)));
+    }
+    denied.sort();
+    denied.dedup();
+    denied
+}
+
+// capabilities that have to be confirmed before the script is run, a script
+// without caps may use all of them
+pub fn needs_confirmation(declared: Option<&[Capability]>, deny: &[Capability]) -> Vec<Capability> {
+    let declared = declared.unwrap_or(ALL);
+    CONFIRM.iter()
+        .filter(|cap| declared.contains(cap) && !deny.contains(cap))
+        .cloned()
+        .collect()
+}
+
+// has to run after the runtime functions are registered
+pub fn deny(lua: &mut hlua::Lua, denied: &[Capability]) {
+    for cap in denied {
+        for name in cap.functions() {
+            lua.set(*name, AnyLuaValue::LuaNil);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for cap in ALL {
+            assert_eq!(cap.to_string().parse::<Capability>().unwrap(), *cap);
+        }
+        assert!("network".parse::<Capability>().is_err());
+    }
+
+    #[test]
+    fn test_denied() {
+        assert_eq!(denied(None, &[]), vec![]);
+        assert_eq!(denied(None, &[Capability::Exec]), vec![Capability::Exec]);
+        assert_eq!(denied(Some(&[Capability::Http, Capability::Exec]), &[Capability::Exec]), vec![
+            Capability::Dns,
+            Capability::Exec,
+            Capability::Fs,
+            Capability::Ldap,
+            Capability::Mysql,
+            Capability::RawSockets,
+        ]);
+    }
+
+    #[test]
+    fn test_needs_confirmation() {
+        assert_eq!(needs_confirmation(Some(&[Capability::Http]), &[]), vec![]);
+        assert_eq!(needs_confirmation(Some(&[Capability::Fs, Capability::Exec]), &[]),
+            vec![Capability::Exec, Capability::Fs]);
+        assert_eq!(needs_confirmation(Some(&[Capability::Fs, Capability::Exec]), &[Capability::Exec]),
+            vec![Capability::Fs]);
+        assert_eq!(needs_confirmation(None, &[]), vec![Capability::Exec, Capability::Fs]);
+        assert_eq!(needs_confirmation(None, &[Capability::Exec, Capability::Fs]), vec![]);
+    }
+}
@@ -1,11 +1,21 @@
 use std::str;
-use ctx::Script;
+use ctx::{Script, Lockout};
+use results::Known;
+use session::{Session, Valid};
 use threadpool::ThreadPool;
+use rand::{thread_rng, Rng};
 use keyboard;
-use errors::Result;
+use logfile;
+use errors::*;
+use std::cmp;
+use std::mem;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{mpsc, Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Creds {
     Tuple((Arc<String>, Arc<String>)),
     Bytes(Arc<Vec<u8>>),
@@ -45,8 +55,10 @@ impl Creds {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Attempt {
+    // position in the order the attempts were generated
+    pub id: usize,
     pub creds: Creds,
     pub script: Arc<Script>,
     pub ttl: u8,
@@ -56,6 +68,7 @@ impl Attempt {
     #[inline]
     pub fn new(user: &Arc<String>, password: &Arc<String>, script: &Arc<Script>) -> Attempt {
         Attempt {
+            id: 0,
             creds: Creds::Tuple((user.clone(), password.clone())),
             script: script.clone(),
             ttl: 5,
@@ -65,6 +78,7 @@ impl Attempt {
     #[inline]
     pub fn bytes(bytes: &Arc<Vec<u8>>, script: &Arc<Script>) -> Attempt {
         Attempt {
+            id: 0,
             creds: Creds::Bytes(bytes.clone()),
             script: script.clone(),
             ttl: 5,
@@ -74,6 +88,7 @@ impl Attempt {
     #[inline]
     pub fn enumerate(user: &Arc<String>, script: &Arc<Script>) -> Attempt {
         Attempt {
+            id: 0,
             creds: Creds::Enum(user.clone()),
             script: script.clone(),
             ttl: 5,
@@ -91,19 +106,190 @@ impl Attempt {
     }
 
     #[inline]
-    pub fn run(self, tx: &mpsc::Sender<Msg>) {
+    pub fn run(self, tx: &mpsc::Sender<Msg>, quarantine: &Quarantine) {
+        if let Some(until) = quarantine.until(&self) {
+            tx.send(Msg::Delayed(Box::new(self), until)).expect("failed to send result");
+            return;
+        }
+
+        let start = Instant::now();
+        let result = self.run_script();
+        let latency = start.elapsed();
+        tx.send(Msg::Attempt(Box::new(self), result, latency)).expect("failed to send result");
+    }
+
+    // --script-timeout is enforced by the limits of the lua state, see limits.rs
+    fn run_script(&self) -> Result<bool> {
+        logfile::set_context(self.id, self.script.descr(), self.user());
         let result = match self.creds {
             Creds::Enum(_) => self.script.run_enum(self.user()),
             _ => self.script.run_creds(self.user(), self.password()),
         };
-        tx.send(Msg::Attempt(Box::new(self), result)).expect("failed to send result");
+        logfile::clear_context();
+        result
+    }
+}
+
+// part of the attempts with --skip, --limit and --shard, so a job can be split
+// across multiple machines. Attempts are numbered in the order they are
+// generated, --shard i/n takes every n-th attempt of the range.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Slice {
+    pub skip: usize,
+    pub limit: Option<usize>,
+    // (i, n), starting at 1
+    pub shard: Option<(usize, usize)>,
+}
+
+impl Slice {
+    pub fn contains(&self, id: usize) -> bool {
+        if id < self.skip {
+            return false;
+        }
+
+        if let Some(limit) = self.limit {
+            if id - self.skip >= limit {
+                return false;
+            }
+        }
+
+        match self.shard {
+            Some((i, n)) => (id - self.skip) % n == i - 1,
+            None => true,
+        }
+    }
+
+    // the number of attempts in the slice if total attempts are generated
+    pub fn count(&self, total: usize) -> usize {
+        let len = total.saturating_sub(self.skip);
+        let len = match self.limit {
+            Some(limit) => cmp::min(len, limit),
+            None => len,
+        };
+
+        match self.shard {
+            Some((i, n)) if len >= i => (len - i) / n + 1,
+            Some(_) => 0,
+            None => len,
+        }
+    }
+}
+
+// targets that were reported with target_dead() and users that don't need
+// more attempts, their attempts are dropped. users are tracked per target.
+#[derive(Debug, Default)]
+pub struct Dropped {
+    targets: HashSet<String>,
+    users: HashSet<(String, String)>,
+}
+
+impl Dropped {
+    pub fn contains(&self, attempt: &Attempt) -> bool {
+        let target = attempt.script.descr();
+        self.targets.contains(target) ||
+            self.users.contains(&(target.to_string(), attempt.user().to_string()))
+    }
+}
+
+// users and targets that reported a lockout, their attempts are held back
+// until the quarantine is over. users are tracked per target.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    users: Mutex<HashMap<(String, String), Instant>>,
+    targets: Mutex<HashMap<String, Instant>>,
+}
+
+impl Quarantine {
+    pub fn add(&self, lockout: Lockout, attempt: &Attempt, duration: Duration) -> Instant {
+        let until = Instant::now() + duration;
+        let target = attempt.script.descr().to_string();
+        match lockout {
+            Lockout::User => {
+                let mut users = self.users.lock().unwrap();
+                users.insert((target, attempt.user().to_string()), until);
+            },
+            Lockout::Target => {
+                let mut targets = self.targets.lock().unwrap();
+                targets.insert(target, until);
+            },
+        }
+        until
+    }
+
+    pub fn until(&self, attempt: &Attempt) -> Option<Instant> {
+        let now = Instant::now();
+        let target = attempt.script.descr();
+
+        let user = {
+            let users = self.users.lock().unwrap();
+            users.get(&(target.to_string(), attempt.user().to_string())).cloned()
+        };
+        let target = {
+            let targets = self.targets.lock().unwrap();
+            targets.get(target).cloned()
+        };
+
+        match (user, target) {
+            (Some(a), Some(b)) => Some(cmp::max(a, b)),
+            (a, b) => a.or(b),
+        }.filter(|until| *until > now)
     }
 }
 
 #[derive(Debug)]
 pub enum Msg {
-    Attempt(Box<Attempt>, Result<bool>),
+    Attempt(Box<Attempt>, Result<bool>, Duration),
+    // the attempt wasn't executed because of a quarantine
+    Delayed(Box<Attempt>, Instant),
+    // the attempt wasn't executed because we are shutting down or the attempt
+    // was dropped
+    Cancelled(Box<Attempt>),
     Key(keyboard::Key),
+    // a line that a script logged
+    Log(logfile::ScriptLine),
+    Shutdown,
+}
+
+// attempts that are generated while the run progresses, eg. from a wordlist
+// that is read from disk
+pub type Feed = Box<dyn Iterator<Item=Result<Attempt>>>;
+// the rounds of --spray, all users with one password
+pub type Rounds = Box<dyn Iterator<Item=Result<Vec<Attempt>>>>;
+
+// attempts that are taken from the feed per worker before their results are in
+const FEED_WINDOW: usize = 64;
+// attempts a worker in distributed mode holds back for ranges that are handed
+// out to other workers, older ones are dropped
+const MAX_HELD: usize = 100_000;
+
+// spaces the start of the attempts, shared by all threads of the pool
+struct RateLimit {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(per_second: f64) -> RateLimit {
+        RateLimit {
+            interval: Duration::from_nanos((1_000_000_000.0 / per_second) as u64),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // blocks until the next attempt may start
+    fn wait(&self) {
+        let until = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let until = cmp::max(*next, now);
+            *next = until + self.interval;
+            until
+        };
+        let now = Instant::now();
+        if until > now {
+            thread::sleep(until - now);
+        }
+    }
 }
 
 pub struct Scheduler {
@@ -113,6 +299,35 @@ pub struct Scheduler {
     num_threads: usize,
     inflight: usize,
     pause_trigger: Arc<(Mutex<bool>, Condvar)>,
+    // attempts that are held back until the current round is done
+    rounds: VecDeque<Vec<Attempt>>,
+    round_feed: Option<Rounds>,
+    quarantine: Arc<Quarantine>,
+    delayed: Vec<(Instant, Attempt)>,
+    next_id: usize,
+    session: Option<Session>,
+    // attempts that have a result in the results database
+    known: Option<Known>,
+    retries: u8,
+    slice: Slice,
+    feed: Option<Feed>,
+    // with --shuffle attempts are held back until all of them are generated,
+    // in distributed mode until their range is handed out
+    shuffle: bool,
+    hold: bool,
+    pending: BTreeMap<usize, Attempt>,
+    // the lowest id that can still be released with run_range
+    floor: usize,
+    rate: Option<Arc<RateLimit>>,
+    // with a per target limit, attempts over the limit wait here until an
+    // attempt for the same target is done
+    per_target: Option<usize>,
+    active: HashMap<String, usize>,
+    waiting: HashMap<String, VecDeque<Attempt>>,
+    skipped: usize,
+    draining: Arc<AtomicBool>,
+    dropped_attempts: Arc<Mutex<Dropped>>,
+    dropped: usize,
 }
 
 impl Scheduler {
@@ -126,6 +341,28 @@ impl Scheduler {
             num_threads: workers,
             inflight: 0,
             pause_trigger: Arc::new((Mutex::new(true), Condvar::new())),
+            rounds: VecDeque::new(),
+            round_feed: None,
+            quarantine: Arc::new(Quarantine::default()),
+            delayed: Vec::new(),
+            next_id: 0,
+            session: None,
+            known: None,
+            retries: 5,
+            slice: Slice::default(),
+            feed: None,
+            shuffle: false,
+            hold: false,
+            pending: BTreeMap::new(),
+            floor: 0,
+            rate: None,
+            per_target: None,
+            active: HashMap::new(),
+            waiting: HashMap::new(),
+            skipped: 0,
+            draining: Arc::new(AtomicBool::new(false)),
+            dropped_attempts: Arc::new(Mutex::new(Dropped::default())),
+            dropped: 0,
         }
     }
 
@@ -136,6 +373,11 @@ impl Scheduler {
         *paused = true;
     }
 
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        *self.pause_trigger.0.lock().unwrap()
+    }
+
     #[inline]
     pub fn resume(&mut self) {
         let &(ref lock, ref cvar) = &*self.pause_trigger;
@@ -162,6 +404,21 @@ impl Scheduler {
         self.num_threads
     }
 
+    #[inline]
+    pub fn set_workers(&mut self, workers: usize) {
+        self.num_threads = cmp::max(workers, 1);
+        self.pool.set_num_threads(self.num_threads);
+    }
+
+    // limit the attempts that are started per second, by all workers
+    pub fn set_rate(&mut self, per_second: f64) -> Result<()> {
+        if !(per_second > 0.0) {
+            bail!("The rate has to be greater than 0");
+        }
+        self.rate = Some(Arc::new(RateLimit::new(per_second)));
+        Ok(())
+    }
+
     #[inline]
     pub fn tx(&self) -> mpsc::Sender<Msg> {
         self.tx.clone()
@@ -174,13 +431,149 @@ impl Scheduler {
 
     #[inline]
     pub fn has_work(&self) -> bool {
-        self.inflight > 0
+        self.inflight > 0 || !self.delayed.is_empty()
+    }
+
+    // there is nothing to do until the next delayed attempt is ready
+    #[inline]
+    pub fn next_delayed(&self) -> Option<Instant> {
+        if self.inflight > 0 {
+            return None;
+        }
+        self.delayed.iter()
+            .map(|x| x.0)
+            .min()
     }
 
     #[inline]
+    pub fn quarantine(&self, lockout: Lockout, attempt: &Attempt, duration: Duration) -> Instant {
+        self.quarantine.add(lockout, attempt, duration)
+    }
+
+    #[inline]
+    pub fn delay(&mut self, attempt: Attempt, until: Instant) {
+        if !self.is_draining() {
+            self.delayed.push((until, attempt));
+        }
+    }
+
+    // stop dispatching new attempts, attempts that are already queued are
+    // cancelled and only the running attempts are waited for
+    pub fn drain(&mut self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.feed = None;
+        self.delayed.clear();
+        self.rounds.clear();
+        self.round_feed = None;
+        self.waiting.clear();
+        // paused workers would never pick up the cancelled attempts
+        self.resume();
+    }
+
+    // attempts on this target that are queued or submitted later are dropped
+    pub fn drop_target(&mut self, target: &str) {
+        self.dropped_attempts.lock().unwrap().targets.insert(target.to_string());
+        if let Some(waiting) = self.waiting.remove(target) {
+            self.dropped += waiting.len();
+        }
+    }
+
+    // attempts for this user on this target that are queued or submitted
+    // later are dropped
+    pub fn drop_user(&mut self, target: &str, user: &str) {
+        self.dropped_attempts.lock().unwrap().users.insert((target.to_string(), user.to_string()));
+        if let Some(waiting) = self.waiting.get_mut(target) {
+            let before = waiting.len();
+            waiting.retain(|attempt| attempt.user() != user);
+            self.dropped += before - waiting.len();
+        }
+    }
+
+    // number of attempts that were dropped because their target is dead or
+    // their user doesn't need more attempts
+    #[inline]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn inflight(&self) -> usize {
+        self.inflight
+    }
+
+    // attempts that are submitted, delayed, waiting or held back
+    pub fn queued(&self) -> usize {
+        self.inflight + self.delayed.len() + self.pending.len() +
+            self.waiting.values().map(|x| x.len()).sum::<usize>()
+    }
+
+    // submits the delayed attempts that are ready
+    pub fn run_delayed(&mut self) {
+        let now = Instant::now();
+        let (ready, delayed) = self.delayed.drain(..)
+            .partition(|x| x.0 <= now);
+        self.delayed = delayed;
+
+        for (_, attempt) in ready {
+            self.run(attempt);
+        }
+    }
+
+    #[inline]
+    pub fn set_per_target(&mut self, limit: usize) {
+        self.per_target = Some(limit);
+    }
+
     pub fn run(&mut self, attempt: Attempt) {
+        if self.dropped_attempts.lock().unwrap().contains(&attempt) {
+            self.dropped += 1;
+            return;
+        }
+
+        if let Some(limit) = self.per_target {
+            let target = attempt.script.descr().to_string();
+            let active = self.active.entry(target.clone()).or_insert(0);
+            if *active >= limit {
+                self.waiting.entry(target)
+                    .or_default()
+                    .push_back(attempt);
+                return;
+            }
+            *active += 1;
+        }
+
+        self.execute(attempt);
+    }
+
+    // an attempt for this target is done, start the next one that is waiting
+    fn release(&mut self, target: &str) {
+        if self.per_target.is_none() {
+            return;
+        }
+
+        let next = self.waiting.get_mut(target)
+            .and_then(|waiting| waiting.pop_front());
+        match next {
+            // the slot is passed on to the next attempt
+            Some(attempt) => self.execute(attempt),
+            None => if let Some(active) = self.active.get_mut(target) {
+                *active -= 1;
+            },
+        }
+    }
+
+    fn execute(&mut self, attempt: Attempt) {
         let tx = self.tx.clone();
         let pause_trigger = self.pause_trigger.clone();
+        let quarantine = self.quarantine.clone();
+        let draining = self.draining.clone();
+        let dropped = self.dropped_attempts.clone();
+        let rate = self.rate.clone();
         self.inflight += 1;
 
         self.pool.execute(move || {
@@ -193,13 +586,451 @@ impl Scheduler {
                     paused = cvar.wait(paused).unwrap();
                 }
             }
-            attempt.run(&tx);
+
+            let cancelled = draining.load(Ordering::SeqCst) ||
+                dropped.lock().unwrap().contains(&attempt);
+
+            if cancelled {
+                tx.send(Msg::Cancelled(Box::new(attempt))).expect("failed to send result");
+            } else {
+                if let Some(rate) = rate {
+                    rate.wait();
+                }
+                attempt.run(&tx, &quarantine);
+            }
         });
     }
 
     #[inline]
-    pub fn recv(&mut self) -> Msg {
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    #[inline]
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    #[inline]
+    pub fn set_total(&mut self, total: usize) {
+        if let Some(ref mut session) = self.session {
+            session.total = total;
+        }
+    }
+
+    #[inline]
+    pub fn skip_known(&mut self, known: Known) {
+        self.known = Some(known);
+    }
+
+    #[inline]
+    pub fn mark_done(&mut self, id: usize) {
+        if let Some(ref mut session) = self.session {
+            session.mark_done(id);
+        }
+    }
+
+    #[inline]
+    pub fn add_valid(&mut self, valid: Valid) {
+        if let Some(ref mut session) = self.session {
+            session.valid.push(valid);
+        }
+    }
+
+    // number of attempts that were skipped because the session has them as done
+    #[inline]
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    #[inline]
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
+    #[inline]
+    pub fn retries(&self) -> u8 {
+        self.retries
+    }
+
+    #[inline]
+    pub fn set_slice(&mut self, slice: Slice) {
+        self.slice = slice;
+    }
+
+    // number of attempts that are part of the slice
+    #[inline]
+    pub fn included(&self, total: usize) -> usize {
+        self.slice.count(total)
+    }
+
+    // assigns the next id, returns false if the attempt is outside of the
+    // slice, already done or known
+    fn prepare(&mut self, attempt: &mut Attempt) -> bool {
+        attempt.id = self.next_id;
+        attempt.ttl = self.retries;
+        self.next_id += 1;
+
+        if !self.slice.contains(attempt.id) {
+            return false;
+        }
+
+        let done = match self.session {
+            Some(ref session) => session.is_done(attempt.id),
+            None => false,
+        };
+        let known = match self.known {
+            Some(ref known) => known.contains(&(attempt.script.descr().to_string(),
+                                                attempt.user().to_string(),
+                                                attempt.password().to_string())),
+            None => false,
+        };
+
+        if done || known {
+            self.skipped += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    #[inline]
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    // run a new attempt, use run to retry an attempt
+    #[inline]
+    pub fn submit(&mut self, mut attempt: Attempt) {
+        if self.prepare(&mut attempt) {
+            if self.shuffle || self.hold {
+                self.pending.insert(attempt.id, attempt);
+            } else {
+                self.run(attempt);
+            }
+        }
+    }
+
+    // attempts are taken from the feed as the previous ones finish. With
+    // --shuffle all of them are generated right away, in distributed mode
+    // when their range is handed out.
+    pub fn set_feed(&mut self, feed: Feed) -> Result<()> {
+        self.feed = Some(feed);
+        if self.shuffle && !self.hold {
+            self.refill_until(usize::MAX)?;
+        }
+        Ok(())
+    }
+
+    // submits attempts from the feed until enough of them are queued
+    #[inline]
+    pub fn refill(&mut self) -> Result<()> {
+        self.refill_rounds()?;
+        if self.hold {
+            return Ok(());
+        }
+        let window = self.num_threads * FEED_WINDOW;
+        self.refill_until(window)
+    }
+
+    fn refill_until(&mut self, window: usize) -> Result<()> {
+        while self.inflight + self.waiting.values().map(|x| x.len()).sum::<usize>() < window {
+            let next = match self.feed {
+                Some(ref mut feed) => feed.next(),
+                None => return Ok(()),
+            };
+            match next {
+                Some(attempt) => self.submit(attempt?),
+                None => {
+                    self.feed = None;
+                    break;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // run the attempts that were held back by shuffle in random order. The
+    // ids are assigned before, so sessions can still be resumed.
+    pub fn flush(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        self.run_pending(pending);
+    }
+
+    // hold back all attempts until they are released with run_range
+    #[inline]
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    // the lowest id that can still be released with run_range
+    #[inline]
+    pub fn floor(&self) -> usize {
+        self.floor
+    }
+
+    // run the attempts with ids from start to end, excluding end. The feed is
+    // read until end, attempts for other ranges are held back. Returns the
+    // number of attempts.
+    pub fn run_range(&mut self, start: usize, end: usize) -> Result<usize> {
+        if start < self.floor {
+            bail!("The attempts before {} were already dropped", self.floor);
+        }
+
+        while self.next_id < end {
+            let next = match self.feed {
+                Some(ref mut feed) => feed.next(),
+                None => break,
+            };
+            match next {
+                Some(attempt) => self.submit(attempt?),
+                None => {
+                    self.feed = None;
+                    break;
+                },
+            }
+        }
+
+        let mut range = self.pending.split_off(&start);
+        let mut rest = range.split_off(&end);
+        self.pending.append(&mut rest);
+
+        while self.pending.len() > MAX_HELD {
+            let id = *self.pending.keys().next().unwrap();
+            self.pending.remove(&id);
+            self.floor = id + 1;
+        }
+
+        let num = range.len();
+        self.run_pending(range);
+        Ok(num)
+    }
+
+    fn run_pending(&mut self, pending: BTreeMap<usize, Attempt>) {
+        let mut pending = pending.into_values().collect::<Vec<_>>();
+        if self.shuffle {
+            thread_rng().shuffle(&mut pending);
+        }
+        for attempt in pending {
+            self.run(attempt);
+        }
+    }
+
+    pub fn queue_round(&mut self, attempts: Vec<Attempt>) {
+        let mut round = Vec::new();
+        for mut attempt in attempts {
+            if self.prepare(&mut attempt) {
+                round.push(attempt);
+            }
+        }
+
+        if self.shuffle {
+            thread_rng().shuffle(&mut round);
+        }
+
+        if !round.is_empty() {
+            self.rounds.push_back(round);
+        }
+    }
+
+    // rounds are taken from the feed one at a time, only the round after the
+    // current one is kept in memory
+    pub fn set_rounds(&mut self, rounds: Rounds) -> Result<()> {
+        self.round_feed = Some(rounds);
+        self.refill_rounds()
+    }
+
+    fn refill_rounds(&mut self) -> Result<()> {
+        while self.rounds.is_empty() {
+            let next = match self.round_feed {
+                Some(ref mut feed) => feed.next(),
+                None => return Ok(()),
+            };
+            match next {
+                // empty rounds aren't queued
+                Some(round) => self.queue_round(round?),
+                None => self.round_feed = None,
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn has_rounds(&self) -> bool {
+        !self.rounds.is_empty()
+    }
+
+    // submits the next round, returns the number of attempts
+    pub fn start_round(&mut self) -> usize {
+        match self.rounds.pop_front() {
+            Some(attempts) => {
+                let num = attempts.len();
+                for attempt in attempts {
+                    self.run(attempt);
+                }
+                num
+            },
+            None => 0,
+        }
+    }
+
+    fn received(&mut self, msg: &Msg) {
+        let attempt = match *msg {
+            Msg::Attempt(ref attempt, ..) => attempt,
+            Msg::Delayed(ref attempt, _) => attempt,
+            Msg::Cancelled(ref attempt) => {
+                if !self.is_draining() {
+                    self.dropped += 1;
+                }
+                attempt
+            },
+            Msg::Key(_) | Msg::Log(_) | Msg::Shutdown => return,
+        };
         self.inflight -= 1;
-        self.rx.recv().unwrap()
+        self.release(attempt.script.descr());
+    }
+
+    #[inline]
+    pub fn recv(&mut self) -> Msg {
+        let msg = self.rx.recv().unwrap();
+        self.received(&msg);
+        msg
+    }
+
+    // returns None if nothing was received before the timeout
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Msg> {
+        let msg = self.rx.recv_timeout(timeout).ok()?;
+        self.received(&msg);
+        Some(msg)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use retry;
+
+    fn ids(slice: Slice) -> Vec<usize> {
+        (0..10).filter(|id| slice.contains(*id)).collect()
+    }
+
+    #[test]
+    fn test_slice_default() {
+        assert_eq!(ids(Slice::default()), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_slice_skip_limit() {
+        assert_eq!(ids(Slice { skip: 3, limit: Some(4), shard: None }), vec![3, 4, 5, 6]);
+        assert_eq!(ids(Slice { skip: 8, limit: None, shard: None }), vec![8, 9]);
+    }
+
+    #[test]
+    fn test_slice_shard() {
+        assert_eq!(ids(Slice { skip: 0, limit: None, shard: Some((1, 3)) }), vec![0, 3, 6, 9]);
+        assert_eq!(ids(Slice { skip: 0, limit: None, shard: Some((3, 3)) }), vec![2, 5, 8]);
+        assert_eq!(ids(Slice { skip: 2, limit: Some(6), shard: Some((2, 2)) }), vec![3, 5, 7]);
+    }
+
+    #[test]
+    fn test_slice_count() {
+        for skip in 0..4 {
+            for limit in vec![None, Some(0), Some(3), Some(20)] {
+                for shard in vec![None, Some((1, 1)), Some((1, 3)), Some((3, 3)), Some((4, 4))] {
+                    let slice = Slice { skip, limit, shard };
+                    assert_eq!(slice.count(10), ids(slice).len(), "{:?}", slice);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_feed() {
+        let script = Arc::new(Script::load_from(r#"
+        descr = "feed"
+
+        function verify(user, password)
+            return false
+        end
+        "#.as_bytes(), Arc::new(Config::default())).unwrap());
+        let user = Arc::new("admin".to_string());
+        let attempts = (0..1000)
+            .map(move |i| Ok(Attempt::new(&user, &Arc::new(i.to_string()), &script)));
+
+        // the workers are paused until resume is called
+        let mut pool = Scheduler::new(2);
+        pool.set_feed(Box::new(attempts)).unwrap();
+        assert_eq!(pool.inflight(), 0);
+        pool.refill().unwrap();
+        assert_eq!(pool.inflight(), 2 * FEED_WINDOW);
+        pool.refill().unwrap();
+        assert_eq!(pool.inflight(), 2 * FEED_WINDOW);
+
+        pool.drain();
+        pool.refill().unwrap();
+        assert_eq!(pool.inflight(), 2 * FEED_WINDOW);
+    }
+
+    #[test]
+    fn test_run_range() {
+        let script = Arc::new(Script::load_from(r#"
+        descr = "range"
+
+        function verify(user, password)
+            return false
+        end
+        "#.as_bytes(), Arc::new(Config::default())).unwrap());
+        let user = Arc::new("admin".to_string());
+        let attempts = (0..MAX_HELD + 100)
+            .map(move |i| Ok(Attempt::new(&user, &Arc::new(i.to_string()), &script)));
+
+        let mut pool = Scheduler::new(2);
+        pool.set_hold(true);
+        pool.set_feed(Box::new(attempts)).unwrap();
+        pool.refill().unwrap();
+        // nothing is generated before a range is handed out
+        assert_eq!(pool.next_id, 0);
+
+        assert_eq!(pool.run_range(10, 20).unwrap(), 10);
+        assert_eq!(pool.next_id, 20);
+        assert_eq!(pool.pending.len(), 10);
+
+        assert_eq!(pool.run_range(MAX_HELD + 90, MAX_HELD + 100).unwrap(), 10);
+        assert_eq!(pool.pending.len(), MAX_HELD);
+        assert_eq!(pool.floor(), 90);
+        assert!(pool.run_range(0, 10).is_err());
+        assert_eq!(pool.run_range(90, 100).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_script_timeout() {
+        let mut config = Config::default();
+        config.runtime.script_timeout = Some(Duration::from_millis(100));
+        let script = Script::load_from(r#"
+        descr = "hang"
+
+        function verify(user, password)
+            if user == "hang" then sleep(5) end
+            if user == "loop" then while true do end end
+            return true
+        end
+        "#.as_bytes(), Arc::new(config)).unwrap();
+        let script = Arc::new(script);
+        let password = Arc::new("x".to_string());
+
+        for user in &["hang", "loop"] {
+            let start = Instant::now();
+            let attempt = Attempt::new(&Arc::new(user.to_string()), &password, &script);
+            let err = attempt.run_script().unwrap_err();
+            assert_eq!(err.to_string(), "script timed out after 100ms");
+            assert!(retry::is_transient(&err));
+            assert!(start.elapsed() < Duration::from_secs(2));
+        }
+
+        // the state of the worker is reused after a timeout
+        let attempt = Attempt::new(&Arc::new("admin".to_string()), &password, &script);
+        assert!(attempt.run_script().unwrap());
     }
 }
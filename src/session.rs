@@ -0,0 +1,129 @@
+// progress of a run, so it can be resumed after a crash or ctrl-c
+//
+// attempts are numbered in the order they are generated, which is the same
+// for the same command. Everything below the watermark is done, attempts that
+// finished out of order are kept in a set until the watermark catches up.
+
+use errors::*;
+
+use serde_json;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::path::Path;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Valid {
+    pub script: String,
+    pub user: String,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    // the subcommand, a session can only be resumed with the same command
+    pub command: String,
+    // number of attempts of the command
+    #[serde(default)]
+    pub total: usize,
+    watermark: usize,
+    done: BTreeSet<usize>,
+    pub valid: Vec<Valid>,
+}
+
+impl Session {
+    pub fn new(command: String) -> Session {
+        Session {
+            command,
+            ..Default::default()
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session> {
+        let file = File::open(path)?;
+        let session = serde_json::from_reader(file)
+            .context("Failed to parse session file")?;
+        Ok(session)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let file = File::create(&tmp)?;
+        serde_json::to_writer(file, self)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_done(&self, id: usize) -> bool {
+        id < self.watermark || self.done.contains(&id)
+    }
+
+    pub fn mark_done(&mut self, id: usize) {
+        if id < self.watermark {
+            return;
+        }
+
+        self.done.insert(id);
+        while self.done.remove(&self.watermark) {
+            self.watermark += 1;
+        }
+    }
+
+    // number of attempts that are done
+    #[inline]
+    pub fn progress(&self) -> usize {
+        self.watermark + self.done.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_mark_done() {
+        let mut session = Session::default();
+        session.mark_done(1);
+        session.mark_done(3);
+        assert_eq!(session.watermark, 0);
+        assert!(!session.is_done(0));
+        assert!(session.is_done(1));
+
+        session.mark_done(0);
+        assert_eq!(session.watermark, 2);
+        assert_eq!(session.done.len(), 1);
+        assert!(session.is_done(0));
+        assert!(!session.is_done(2));
+        assert!(session.is_done(3));
+
+        session.mark_done(2);
+        session.mark_done(1);
+        assert_eq!(session.watermark, 4);
+        assert!(session.done.is_empty());
+        assert_eq!(session.progress(), 4);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = env::temp_dir().join(format!("badtouch-session-{}.json", process::id()));
+
+        let mut session = Session::new("Dict".to_string());
+        session.mark_done(0);
+        session.mark_done(5);
+        session.valid.push(Valid {
+            script: "example.com".to_string(),
+            user: "admin".to_string(),
+            password: Some("hunter2".to_string()),
+        });
+        session.save(&path).unwrap();
+
+        let loaded = Session::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(session, loaded);
+    }
+}
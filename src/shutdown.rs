@@ -0,0 +1,59 @@
+// graceful shutdown on SIGINT and SIGTERM
+//
+// the first signal asks the main loop to stop dispatching new attempts and to
+// wait for the running ones, so the results and the session can be saved. A
+// second signal exits immediately.
+
+use errors::*;
+
+use keyboard::Keyboard;
+use libc::c_int;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use scheduler::Msg;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handler(_: c_int) {
+    SIGNALS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn requested() -> bool {
+    SIGNALS.load(Ordering::SeqCst) > 0
+}
+
+// the handler only counts the signals, a thread watches the counter and
+// wakes up the main loop with Msg::Shutdown
+pub fn setup(tx: mpsc::Sender<Msg>) -> Result<()> {
+    let action = SigAction::new(SigHandler::Handler(handler), SaFlags::SA_RESTART, SigSet::empty());
+    for sig in &[Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { signal::sigaction(*sig, &action) }?;
+    }
+
+    thread::spawn(move || {
+        let mut notified = false;
+        loop {
+            match SIGNALS.load(Ordering::SeqCst) {
+                0 => (),
+                1 => if !notified {
+                    // the main loop might be gone already
+                    tx.send(Msg::Shutdown).ok();
+                    notified = true;
+                },
+                _ => {
+                    Keyboard::reset();
+                    process::exit(130);
+                },
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+
+    Ok(())
+}
@@ -2,49 +2,238 @@ use errors::*;
 
 use bufstream::BufStream;
 use regex::Regex;
+use hlua::AnyLuaValue;
+use json::LuaJsonValue;
+use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode};
+use serde_json;
+use x509::Certificate;
+use dns;
+use limits;
+use proxy;
 
 use std::str;
 use std::io;
 use std::io::prelude::*;
 use std::io::BufRead;
 use std::net::TcpStream;
-use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::time::Duration;
 
 
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SocketOptions {
+    #[serde(default)]
+    pub tls: bool,
+    // overwrite the hostname that is sent with sni
+    pub sni_value: Option<String>,
+    #[serde(default)]
+    pub disable_sni: bool,
+    #[serde(default)]
+    pub disable_tls_verify: bool,
+    pub proxy: Option<String>,
+}
+
+impl SocketOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<SocketOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(SocketOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
 #[derive(Debug)]
-pub struct Socket {
-    stream: BufStream<TcpStream>,
-    newline: String,
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(SslStream<TcpStream>),
 }
 
-impl Socket {
-    pub fn connect(host: &str, port: u16) -> Result<Socket> {
-        let addrs = (host, port).to_socket_addrs()?;
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.read(buf),
+            Stream::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
 
-        let mut errors = Vec::new();
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.write(buf),
+            Stream::Tls(ref mut s) => s.write(buf),
+        }
+    }
 
-        for addr in addrs {
-            debug!("connecting to {:?}", addr);
-            match TcpStream::connect(&addr) {
-                Ok(socket) => {
-                    debug!("successfully connected to {:?}", addr);
-                    let stream = BufStream::new(socket);
-
-                    return Ok(Socket {
-                        stream,
-                        newline: String::from("\n"),
-                    });
-                },
-                Err(err) => errors.push((addr, err)),
-            }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.flush(),
+            Stream::Tls(ref mut s) => s.flush(),
         }
+    }
+}
 
-        if errors.is_empty() {
-            bail!("no dns records found");
+impl Stream {
+    pub fn connect(host: &str, port: u16, options: &SocketOptions) -> Result<(Stream, Option<Certificate>)> {
+        let socket = match options.proxy {
+            Some(ref proxy) => proxy::connect(proxy, host, port)?,
+            None => tcp_connect(host, port, limits::remaining())?,
+        };
+        // reads and writes don't block longer than the script has left
+        socket.set_read_timeout(limits::remaining())?;
+        socket.set_write_timeout(limits::remaining())?;
+
+        if options.tls {
+            let (stream, cert) = tls_wrap(socket, host, options)?;
+            Ok((Stream::Tls(stream), cert))
         } else {
-            bail!("couldn't connect: {:?}", errors);
+            Ok((Stream::Tcp(socket), None))
         }
     }
+}
+
+fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    let addrs = dns::socket_addrs(host, port)?;
+
+    let mut errors = Vec::new();
+
+    for addr in addrs {
+        debug!("connecting to {:?}", addr);
+        let socket = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(&addr),
+        };
+        match socket {
+            Ok(socket) => {
+                debug!("successfully connected to {:?}", addr);
+                return Ok(socket);
+            },
+            Err(err) => errors.push((addr, err)),
+        }
+    }
+
+    if errors.is_empty() {
+        bail!("no dns records found");
+    } else {
+        bail!("couldn't connect: {:?}", errors);
+    }
+}
+
+// a timeout of 0 uses the timeout of the operating system
+fn timeout_ms(ms: u32) -> Option<Duration> {
+    if ms > 0 {
+        Some(Duration::from_millis(u64::from(ms)))
+    } else {
+        None
+    }
+}
+
+// only fails if the host can't be resolved
+pub fn port_open(host: &str, port: u16, timeout: u32) -> Result<bool> {
+    let addrs = dns::socket_addrs(host, port)?;
+    if addrs.is_empty() {
+        bail!("no dns records found");
+    }
+
+    let timeout = limits::timeout(timeout_ms(timeout));
+    Ok(addrs.iter().any(|addr| {
+        let socket = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        socket.is_ok()
+    }))
+}
+
+// returns the first bytes the service sends, the result is empty if the
+// service waits for the client to send something first
+pub fn banner_grab(host: &str, port: u16, timeout: u32) -> Result<Vec<u8>> {
+    let timeout = limits::timeout(timeout_ms(timeout));
+    let mut socket = tcp_connect(host, port, timeout)?;
+    socket.set_read_timeout(timeout)?;
+
+    let mut buf = [0; 4096];
+    let n = match socket.read(&mut buf) {
+        Ok(n) => n,
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                        err.kind() == io::ErrorKind::TimedOut => 0,
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(buf[..n].to_vec())
+}
+
+fn tls_wrap(stream: TcpStream, host: &str, options: &SocketOptions) -> Result<(SslStream<TcpStream>, Option<Certificate>)> {
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    if options.disable_tls_verify {
+        connector.set_verify(SslVerifyMode::NONE);
+    }
+    let connector = connector.build();
+
+    let mut config = connector.configure()?;
+    config.set_use_server_name_indication(!options.disable_sni);
+    config.set_verify_hostname(!options.disable_tls_verify);
+
+    let sni = options.sni_value.as_ref()
+                    .map(|x| x.as_str())
+                    .unwrap_or(host);
+
+    debug!("starting tls handshake with {:?}", sni);
+    let stream = config.connect(sni, stream)
+                    .map_err(|err| format_err!("tls handshake failed: {}", err))?;
+    debug!("tls handshake finished");
+
+    let cert = match stream.ssl().peer_certificate() {
+        Some(cert) => Some(Certificate::from_x509(&cert)?),
+        None => None,
+    };
+
+    Ok((stream, cert))
+}
+
+#[derive(Debug)]
+pub struct Socket {
+    stream: BufStream<Stream>,
+    newline: String,
+    host: String,
+    peer_cert: Option<Certificate>,
+}
+
+impl Socket {
+    pub fn connect(host: &str, port: u16, options: &SocketOptions) -> Result<Socket> {
+        let (stream, peer_cert) = Stream::connect(host, port, options)?;
+
+        Ok(Socket {
+            stream: BufStream::new(stream),
+            newline: String::from("\n"),
+            host: host.to_string(),
+            peer_cert,
+        })
+    }
+
+    pub fn starttls(&mut self, options: &SocketOptions) -> Result<()> {
+        self.stream.flush()?;
+
+        let socket = match *self.stream.get_ref() {
+            Stream::Tcp(ref socket) => socket.try_clone()?,
+            Stream::Tls(_) => bail!("connection is already encrypted"),
+        };
+
+        let (stream, cert) = tls_wrap(socket, &self.host, options)?;
+        self.stream = BufStream::new(Stream::Tls(stream));
+        self.peer_cert = cert;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn peer_cert(&self) -> Option<&Certificate> {
+        self.peer_cert.as_ref()
+    }
 
     pub fn send(&mut self, data: &[u8]) -> Result<()> {
         match str::from_utf8(&data) {
@@ -164,3 +353,119 @@ impl Socket {
         self.newline = delim.into();
     }
 }
+
+#[derive(Debug)]
+pub struct Datagram {
+    socket: UdpSocket,
+}
+
+impl Datagram {
+    pub fn connect(host: &str, port: u16) -> Result<Datagram> {
+        let addrs = dns::socket_addrs(host, port)?;
+
+        let mut errors = Vec::new();
+
+        for addr in addrs {
+            let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+            let socket = match UdpSocket::bind(bind) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    errors.push((addr, err));
+                    continue;
+                },
+            };
+
+            debug!("connecting udp socket to {:?}", addr);
+            match socket.connect(&addr) {
+                Ok(_) => return Ok(Datagram {
+                    socket,
+                }),
+                Err(err) => errors.push((addr, err)),
+            }
+        }
+
+        if errors.is_empty() {
+            bail!("no dns records found");
+        } else {
+            bail!("couldn't connect: {:?}", errors);
+        }
+    }
+
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        debug!("udp send: {:?}", data);
+        self.socket.send(data)?;
+        Ok(())
+    }
+
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Vec<u8>> {
+        self.socket.set_read_timeout(limits::timeout(timeout))?;
+
+        let mut buf = [0; 65535];
+        let n = match self.socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::TimedOut => bail!("udp recv timed out"),
+            Err(err) => return Err(err.into()),
+        };
+
+        let data = buf[..n].to_vec();
+        debug!("udp recv: {:?}", data);
+        Ok(data)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_port_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let t = thread::spawn(move || {
+            listener.accept().unwrap();
+        });
+
+        assert!(port_open("127.0.0.1", port, 1000).unwrap());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_grab() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let t = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"220 smtp.example.com ESMTP\r\n").unwrap();
+        });
+
+        let banner = banner_grab("127.0.0.1", port, 1000).unwrap();
+        assert_eq!(banner, b"220 smtp.example.com ESMTP\r\n");
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_banner_grab_silent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let banner = banner_grab("127.0.0.1", port, 100).unwrap();
+        assert!(banner.is_empty());
+        drop(listener);
+    }
+
+    #[test]
+    fn test_port_closed() {
+        // bind and drop to get a port that is very likely closed
+        let port = TcpListener::bind("127.0.0.1:0").unwrap()
+            .local_addr().unwrap()
+            .port();
+        assert!(!port_open("127.0.0.1", port, 1000).unwrap());
+    }
+}
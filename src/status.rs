@@ -0,0 +1,163 @@
+// machine readable progress with --status-json
+//
+// a json object is written every second, either to stderr or to every client
+// of a unix socket, so wrappers don't have to parse the progress bar.
+
+use errors::*;
+
+use serde_json;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Status {
+    pub elapsed_secs: u64,
+    pub done: usize,
+    pub total: usize,
+    // attempts per second
+    pub rate: f64,
+    pub eta_secs: Option<u64>,
+    pub valid: usize,
+    pub errors: usize,
+    pub retries: usize,
+    pub queued: usize,
+}
+
+impl Status {
+    pub fn new(elapsed: Duration, done: usize, total: usize) -> Status {
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+        let rate = if secs > 0.0 {
+            done as f64 / secs
+        } else {
+            0.0
+        };
+        let eta_secs = if rate > 0.0 {
+            Some((total.saturating_sub(done) as f64 / rate).ceil() as u64)
+        } else {
+            None
+        };
+
+        Status {
+            elapsed_secs: elapsed.as_secs(),
+            done,
+            total,
+            rate: (rate * 100.0).round() / 100.0,
+            eta_secs,
+            valid: 0,
+            errors: 0,
+            retries: 0,
+            queued: 0,
+        }
+    }
+}
+
+enum Output {
+    Stderr,
+    Socket(PathBuf, Arc<Mutex<Vec<UnixStream>>>),
+}
+
+pub struct StatusWriter {
+    output: Output,
+    last: Instant,
+}
+
+impl StatusWriter {
+    // "stderr" or the path of a unix socket
+    pub fn open(dest: &str) -> Result<StatusWriter> {
+        let output = if dest == "stderr" {
+            Output::Stderr
+        } else {
+            let path = PathBuf::from(dest);
+            // a socket of an earlier run
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)
+                    .context("Failed to remove old status socket")?;
+            }
+            let listener = UnixListener::bind(&path)
+                .context(format!("Failed to listen on {:?}", dest))?;
+
+            let clients = Arc::new(Mutex::new(Vec::new()));
+            let c = clients.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    c.lock().unwrap().push(stream);
+                }
+            });
+            Output::Socket(path, clients)
+        };
+
+        Ok(StatusWriter {
+            output,
+            last: Instant::now(),
+        })
+    }
+
+    // time until the next status is due
+    #[inline]
+    pub fn timeout(&self) -> Duration {
+        INTERVAL.checked_sub(self.last.elapsed())
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    #[inline]
+    pub fn is_due(&self) -> bool {
+        self.last.elapsed() >= INTERVAL
+    }
+
+    pub fn write(&mut self, status: &Status) -> Result<()> {
+        let mut line = serde_json::to_string(status)?;
+        line.push('\n');
+
+        match self.output {
+            Output::Stderr => io::stderr().write_all(line.as_bytes())?,
+            Output::Socket(_, ref clients) => {
+                // clients that went away are dropped
+                clients.lock().unwrap()
+                    .retain(|mut client| client.write_all(line.as_bytes()).is_ok());
+            },
+        }
+
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for StatusWriter {
+    fn drop(&mut self) {
+        if let Output::Socket(ref path, _) = self.output {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let status = Status::new(Duration::from_secs(10), 25, 100);
+        assert_eq!(status.rate, 2.5);
+        assert_eq!(status.eta_secs, Some(30));
+
+        let status = Status::new(Duration::from_secs(0), 0, 100);
+        assert_eq!(status.rate, 0.0);
+        assert_eq!(status.eta_secs, None);
+    }
+
+    #[test]
+    fn test_status_json() {
+        let status = Status::new(Duration::from_secs(3), 3, 3);
+        let x = serde_json::to_string(&status).unwrap();
+        assert_eq!(x, r#"{"elapsed_secs":3,"done":3,"total":3,"rate":1.0,"eta_secs":0,"valid":0,"errors":0,"retries":0,"queued":0}"#);
+    }
+}
@@ -18,6 +18,11 @@ impl LuaMap {
         self.0.is_empty()
     }
 
+    #[inline]
+    pub fn get(&self, k: &str) -> Option<&AnyLuaValue> {
+        self.0.get(&AnyHashableLuaValue::LuaString(k.to_string()))
+    }
+
     #[inline]
     pub fn insert<K: Into<String>, V: Into<AnyLuaValue>>(&mut self, k: K, v: V) {
         self.0.insert(AnyHashableLuaValue::LuaString(k.into()), v.into());
@@ -1,7 +1,9 @@
 use errors::*;
 
 use std::str;
+use std::collections::HashSet;
 use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::io::{self, BufReader};
 use std::io::prelude::*;
@@ -10,6 +12,15 @@ use config::Config;
 use ctx;
 
 
+// compares secrets without returning early on the first difference, only the
+// length can be told from the time it takes
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 pub fn load_list(path: &str) -> Result<Vec<Arc<String>>> {
     let f = File::open(path)?;
     let file = BufReader::new(&f);
@@ -19,45 +30,217 @@ pub fn load_list(path: &str) -> Result<Vec<Arc<String>>> {
     Ok(lines?)
 }
 
-pub fn load_creds(path: &str) -> Result<Vec<Arc<Vec<u8>>>> {
-    let f = File::open(path)?;
-    let mut file = BufReader::new(&f);
+// merges lists in order, only the first occurrence of an entry is kept
+pub fn merge_lists(lists: Vec<Vec<Arc<String>>>) -> Vec<Arc<String>> {
+    let mut seen = HashSet::new();
+    let mut merged = Vec::new();
+
+    for list in lists {
+        for x in list {
+            if seen.insert(x.clone()) {
+                merged.push(x);
+            }
+        }
+    }
+
+    merged
+}
+
+// reads a creds list line by line while the attempts are generated
+pub struct CredsList {
+    file: BufReader<File>,
+    buf: Vec<u8>,
+}
 
-    let mut creds = Vec::new();
+impl CredsList {
+    pub fn open(path: &str) -> Result<CredsList> {
+        let file = File::open(path)?;
+        Ok(CredsList {
+            file: BufReader::new(file),
+            buf: Vec::new(),
+        })
+    }
+
+    // validates the list without keeping it in memory
+    pub fn count(path: &str) -> Result<usize> {
+        CredsList::open(path)?
+            .try_fold(0, |num, cred| cred.map(|_| num + 1))
+    }
 
-    let mut buf = Vec::new();
-    const DELIM: u8 = b'\n';
+    fn read(&mut self) -> Result<Option<Arc<Vec<u8>>>> {
+        const DELIM: u8 = b'\n';
 
-    while 0 < file.read_until(DELIM, &mut buf)? {
-        if buf[buf.len() - 1] == DELIM {
-            buf.pop();
+        self.buf.clear();
+        if self.file.read_until(DELIM, &mut self.buf)? == 0 {
+            return Ok(None);
+        }
+        if self.buf[self.buf.len() - 1] == DELIM {
+            self.buf.pop();
         }
 
         // ensure line is valid utf8
-        str::from_utf8(&buf)
+        str::from_utf8(&self.buf)
             .context("Failed to decode utf8")?;
 
-        if buf.iter().any(|x| *x == b':') {
-            creds.push(Arc::new(buf.clone()));
+        if self.buf.iter().any(|x| *x == b':') {
+            Ok(Some(Arc::new(self.buf.clone())))
         } else {
-            return Err(format_err!("Invalid list format: {:?}", buf))
+            bail!("Invalid list format: {:?}", self.buf)
+        }
+    }
+}
+
+impl Iterator for CredsList {
+    type Item = Result<Arc<Vec<u8>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+// splits a line at the first separator. If escaping is enabled, `\\` and an
+// escaped separator are unescaped and don't split the line.
+pub fn split_combo(line: &str, separator: &str, escape: bool) -> Option<(String, String)> {
+    if !escape {
+        let idx = line.find(separator)?;
+        return Some((line[..idx].to_string(), line[idx+separator.len()..].to_string()));
+    }
+
+    let mut user = None;
+    let mut buf = String::new();
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(next) = rest.strip_prefix('\\') {
+            if let Some(next) = next.strip_prefix(separator) {
+                buf.push_str(separator);
+                rest = next;
+                continue;
+            } else if let Some(next) = next.strip_prefix('\\') {
+                buf.push('\\');
+                rest = next;
+                continue;
+            }
+        } else if user.is_none() {
+            if let Some(next) = rest.strip_prefix(separator) {
+                user = Some(buf.clone());
+                buf.clear();
+                rest = next;
+                continue;
+            }
+        }
+
+        buf.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    user.map(|user| (user, buf))
+}
+
+pub type Combo = (Arc<String>, Arc<String>);
+
+// reads a combo list line by line while the attempts are generated, lines
+// that can't be split are skipped and counted
+pub struct ComboList {
+    file: BufReader<File>,
+    separator: String,
+    escape: bool,
+    buf: Vec<u8>,
+    pub skipped: usize,
+}
+
+impl ComboList {
+    pub fn open(path: &str, separator: &str, escape: bool) -> Result<ComboList> {
+        if separator.is_empty() {
+            bail!("Separator can't be empty");
+        }
+
+        let file = File::open(path)?;
+        Ok(ComboList {
+            file: BufReader::new(file),
+            separator: separator.to_string(),
+            escape,
+            buf: Vec::new(),
+            skipped: 0,
+        })
+    }
+
+    // returns the number of valid and skipped lines without keeping the list
+    // in memory
+    pub fn count(path: &str, separator: &str, escape: bool) -> Result<(usize, usize)> {
+        let mut list = ComboList::open(path, separator, escape)?;
+        let mut num = 0;
+        for combo in &mut list {
+            combo?;
+            num += 1;
+        }
+        Ok((num, list.skipped))
+    }
+
+    fn read(&mut self) -> Result<Option<Combo>> {
+        loop {
+            self.buf.clear();
+            if self.file.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+
+            // leaked lists are rarely clean utf8
+            let line = String::from_utf8_lossy(&self.buf);
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+
+            match split_combo(line, &self.separator, self.escape) {
+                Some((user, password)) => return Ok(Some((Arc::new(user), Arc::new(password)))),
+                None => self.skipped += 1,
+            }
+        }
+    }
+}
+
+impl Iterator for ComboList {
+    type Item = Result<Combo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+// lua files in a directory and its subdirectories
+pub fn find_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|x| x == "lua") {
+            scripts.push(path);
         }
+    }
+    Ok(())
+}
 
-        buf.clear();
+// scripts that don't exist are looked up in the scripts folder of the config
+pub fn find_script(path: &str, config: &Config) -> String {
+    if Path::new(path).exists() {
+        return path.to_string();
     }
 
-    Ok(creds)
+    match config.general.scripts_dir() {
+        Some(ref dir) if dir.join(path).exists() => dir.join(path).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    }
 }
 
 pub fn load_scripts(paths: Vec<String>, config: &Arc<Config>) -> Result<Vec<Arc<ctx::Script>>> {
     let mut scripts = Vec::new();
 
     for path in paths {
+        let path = find_script(&path, config);
         let meta = fs::metadata(&path)?;
 
         if meta.is_dir() {
-            for path in fs::read_dir(path)? {
-                let path = path?.path();
+            // repositories from `badtouch scripts` keep scripts in subfolders
+            let mut paths = Vec::new();
+            find_scripts(Path::new(&path), &mut paths)?;
+            for path in paths {
                 let path = path.to_str().unwrap();
                 let script = Arc::new(ctx::Script::load(path, config.clone())?);
                 scripts.push(script);
@@ -68,5 +251,130 @@ pub fn load_scripts(paths: Vec<String>, config: &Arc<Config>) -> Result<Vec<Arc<
         }
     }
 
+    validate_options(&scripts, config)?;
+
     Ok(scripts)
 }
+
+// scripts that don't declare their options may read any option, so they are
+// only validated if every script has a list of options in its descriptor
+pub fn validate_options(scripts: &[Arc<ctx::Script>], config: &Config) -> Result<()> {
+    if scripts.iter().any(|x| x.descriptor().options.is_empty()) {
+        return Ok(());
+    }
+
+    for key in config.runtime.options.keys() {
+        if !scripts.iter().any(|x| x.descriptor().options.contains_key(key)) {
+            bail!("Unknown option {:?}, see list-scripts for supported options", key);
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn script(options: &str) -> Arc<ctx::Script> {
+        let code = format!(r#"
+        descriptor = {{
+            name = "options",
+            options = {{ {} }},
+        }}
+
+        function verify(user, password)
+            return true
+        end
+        "#, options);
+        Arc::new(ctx::Script::load_from(code.as_bytes(), Arc::new(Config::default())).unwrap())
+    }
+
+    fn config(key: &str) -> Config {
+        let mut config = Config::default();
+        config.runtime.options.insert(key.to_string(), "1".to_string());
+        config
+    }
+
+    fn list(x: &[&str]) -> Vec<Arc<String>> {
+        x.iter()
+            .map(|x| Arc::new(x.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_lists() {
+        let merged = merge_lists(vec![list(&["root", "admin"]), list(&["admin", "guest", "root"]), list(&[])]);
+        assert_eq!(merged, list(&["root", "admin", "guest"]));
+    }
+
+    #[test]
+    fn test_split_combo() {
+        assert_eq!(split_combo("admin:hunter2", ":", false), Some(("admin".to_string(), "hunter2".to_string())));
+        assert_eq!(split_combo("admin:a:b", ":", false), Some(("admin".to_string(), "a:b".to_string())));
+        assert_eq!(split_combo("admin;;pw", ";;", false), Some(("admin".to_string(), "pw".to_string())));
+        assert_eq!(split_combo("admin", ":", false), None);
+    }
+
+    #[test]
+    fn test_split_combo_escape() {
+        assert_eq!(split_combo("a\\:b:c\\:d", ":", true), Some(("a:b".to_string(), "c:d".to_string())));
+        assert_eq!(split_combo("a\\\\:b", ":", true), Some(("a\\".to_string(), "b".to_string())));
+        assert_eq!(split_combo("a\\b:\\x", ":", true), Some(("a\\b".to_string(), "\\x".to_string())));
+        assert_eq!(split_combo("a\\:b", ":", true), None);
+    }
+
+    #[test]
+    fn test_combo_list() {
+        let path = env::temp_dir().join(format!("badtouch-combo-{}.txt", process::id()));
+        fs::write(&path, b"admin:hunter2\r\ninvalid\nroot:\xff\n").unwrap();
+
+        let path = path.to_str().unwrap();
+        let count = ComboList::count(path, ":", false).unwrap();
+        let combos = ComboList::open(path, ":", false).unwrap()
+            .collect::<Result<Vec<_>>>();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(count, (2, 1));
+        let combos = combos.unwrap();
+        assert_eq!(combos, vec![
+            (Arc::new("admin".to_string()), Arc::new("hunter2".to_string())),
+            (Arc::new("root".to_string()), Arc::new("\u{fffd}".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let scripts = vec![script("domain = 'the domain'"), script("realm = 'the realm'")];
+        assert!(validate_options(&scripts, &config("domain")).is_ok());
+        assert!(validate_options(&scripts, &config("realm")).is_ok());
+        assert!(validate_options(&scripts, &config("foo")).is_err());
+    }
+
+    #[test]
+    fn test_validate_options_undeclared() {
+        let scripts = vec![script("domain = 'the domain'"), script("")];
+        assert!(validate_options(&scripts, &config("foo")).is_ok());
+    }
+
+    #[test]
+    fn test_find_script() {
+        let mut config = Config::default();
+        assert_eq!(find_script("basic_auth.lua", &config), "basic_auth.lua");
+        config.general.scripts = Some("scripts".to_string());
+        assert_eq!(find_script("basic_auth.lua", &config), "scripts/basic_auth.lua");
+        assert_eq!(find_script("scripts/binary.lua", &config), "scripts/binary.lua");
+        assert_eq!(find_script("missing.lua", &config), "missing.lua");
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
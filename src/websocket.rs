@@ -0,0 +1,110 @@
+use errors::*;
+
+use hlua::AnyLuaValue;
+use json::LuaJsonValue;
+use reqwest::Url;
+use serde_json;
+use sockets::{Stream, SocketOptions};
+use tungstenite::{self, Message};
+use tungstenite::http::Request;
+
+use std::collections::HashMap;
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WebSocketOptions {
+    // copy cookies and proxy from this http session
+    pub session: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub disable_tls_verify: bool,
+}
+
+impl WebSocketOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<WebSocketOptions> {
+        match x {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaOther => return Ok(WebSocketOptions::default()),
+            _ => (),
+        }
+
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+#[derive(Debug)]
+pub struct WebSocket {
+    ws: tungstenite::WebSocket<Stream>,
+}
+
+impl WebSocket {
+    pub fn connect(url: &str, cookies: Option<String>, proxy: Option<String>, options: &WebSocketOptions) -> Result<WebSocket> {
+        let url = Url::parse(url)
+                    .context("Invalid url")?;
+
+        let tls = match url.scheme() {
+            "ws" => false,
+            "wss" => true,
+            scheme => bail!("unsupported websocket scheme: {:?}", scheme),
+        };
+        let host = url.host_str()
+                    .ok_or_else(|| format_err!("url is missing a host"))?;
+        let port = url.port_or_known_default()
+                    .ok_or_else(|| format_err!("url is missing a port"))?;
+
+        let (stream, _) = Stream::connect(host, port, &SocketOptions {
+            tls,
+            disable_tls_verify: options.disable_tls_verify,
+            proxy,
+            ..Default::default()
+        })?;
+
+        let mut req = Request::builder()
+                        .uri(url.as_str());
+
+        if let Some(cookies) = cookies {
+            req = req.header("Cookie", cookies);
+        }
+
+        if let Some(ref headers) = options.headers {
+            for (k, v) in headers {
+                req = req.header(k.as_str(), v.as_str());
+            }
+        }
+
+        let req = req.body(())?;
+        let (ws, resp) = tungstenite::client(req, stream)
+                            .map_err(|err| format_err!("websocket handshake failed: {}", err))?;
+        debug!("websocket handshake finished: {:?}", resp.status());
+
+        Ok(WebSocket {
+            ws,
+        })
+    }
+
+    pub fn send(&mut self, msg: Message) -> Result<()> {
+        self.ws.write_message(msg)?;
+        Ok(())
+    }
+
+    // returns None if the connection has been closed
+    pub fn recv(&mut self) -> Result<Option<Message>> {
+        loop {
+            match self.ws.read_message() {
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => continue,
+                Ok(Message::Close(_)) => return Ok(None),
+                Ok(msg) => return Ok(Some(msg)),
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.ws.close(None)?;
+        self.ws.write_pending()?;
+        Ok(())
+    }
+}
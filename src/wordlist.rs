@@ -0,0 +1,219 @@
+// user and password lists that are read from disk while the attempts are
+// generated, so a list of a few gigabytes doesn't have to fit into memory.
+//
+// only the lines are counted up front to know the number of attempts, the
+// list is read again every time it's iterated. Lists that are merged or
+// transformed are kept in memory.
+//
+// the lines are checked for invalid utf-8 while they are counted, so a bad
+// line is reported before the run starts instead of aborting it.
+
+use errors::*;
+
+use std::fs::File;
+use std::str;
+use std::io::{self, BufReader};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+
+const BUF_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum Wordlist {
+    Memory(Arc<Vec<Arc<String>>>),
+    File {
+        path: PathBuf,
+        len: usize,
+    },
+}
+
+impl Wordlist {
+    // counts the lines without loading them
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Wordlist> {
+        let path = path.as_ref();
+        let len = count_lines(File::open(path)?)?;
+        Ok(Wordlist::File {
+            path: path.to_path_buf(),
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        match *self {
+            Wordlist::Memory(ref list) => list.len(),
+            Wordlist::File { len, .. } => len,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Result<Words> {
+        match *self {
+            Wordlist::Memory(ref list) => Ok(Words::Memory(list.clone(), 0)),
+            Wordlist::File { ref path, .. } => {
+                let file = File::open(path)
+                    .context(format!("Failed to open {:?}", path))?;
+                Ok(Words::File(BufReader::with_capacity(BUF_SIZE, file).lines()))
+            },
+        }
+    }
+
+    // reads the whole list into memory
+    pub fn load(&self) -> Result<Vec<Arc<String>>> {
+        match *self {
+            Wordlist::Memory(ref list) => Ok(list.to_vec()),
+            Wordlist::File { .. } => self.iter()?.collect(),
+        }
+    }
+}
+
+impl From<Vec<Arc<String>>> for Wordlist {
+    fn from(list: Vec<Arc<String>>) -> Wordlist {
+        Wordlist::Memory(Arc::new(list))
+    }
+}
+
+pub enum Words {
+    Memory(Arc<Vec<Arc<String>>>, usize),
+    File(io::Lines<BufReader<File>>),
+}
+
+impl Iterator for Words {
+    type Item = Result<Arc<String>>;
+
+    fn next(&mut self) -> Option<Result<Arc<String>>> {
+        match *self {
+            Words::Memory(ref list, ref mut idx) => {
+                let word = list.get(*idx).cloned();
+                *idx += 1;
+                word.map(Ok)
+            },
+            Words::File(ref mut lines) => lines.next()
+                .map(|line| Ok(Arc::new(line?))),
+        }
+    }
+}
+
+// the number of lines BufRead::lines would return, without keeping them.
+// Fails on the first line BufRead::lines would fail on.
+pub fn count_lines<R: Read>(r: R) -> Result<usize> {
+    let mut r = BufReader::with_capacity(BUF_SIZE, r);
+    let mut line = Vec::new();
+    let mut lines = 0;
+
+    loop {
+        line.clear();
+        if r.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        lines += 1;
+        if str::from_utf8(&line).is_err() {
+            bail!("line {} is not valid utf-8", lines);
+        }
+    }
+    Ok(lines)
+}
+
+// every user with every password, the password list is read again for
+// every user
+pub struct Product {
+    passwords: Wordlist,
+    users: Words,
+    user: Option<Arc<String>>,
+    current: Option<Words>,
+}
+
+impl Product {
+    pub fn new(users: &Wordlist, passwords: &Wordlist) -> Result<Product> {
+        Ok(Product {
+            passwords: passwords.clone(),
+            users: users.iter()?,
+            user: None,
+            current: None,
+        })
+    }
+}
+
+impl Iterator for Product {
+    type Item = Result<(Arc<String>, Arc<String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (Some(user), Some(passwords)) = (&self.user, &mut self.current) {
+                match passwords.next() {
+                    Some(Ok(password)) => return Some(Ok((user.clone(), password))),
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => (),
+                }
+            }
+
+            match self.users.next()? {
+                Ok(user) => self.user = Some(user),
+                Err(err) => return Some(Err(err)),
+            }
+            match self.passwords.iter() {
+                Ok(passwords) => self.current = Some(passwords),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn list(x: &[&str]) -> Wordlist {
+        Wordlist::from(x.iter()
+            .map(|x| Arc::new(x.to_string()))
+            .collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_count_lines() {
+        for x in &["", "a", "a\n", "a\nb", "a\r\nb\r\n", "\n\n", "a\n\nb\n"] {
+            let expected = BufReader::new(x.as_bytes()).lines().count();
+            assert_eq!(count_lines(x.as_bytes()).unwrap(), expected, "{:?}", x);
+        }
+    }
+
+    #[test]
+    fn test_count_lines_invalid_utf8() {
+        let err = count_lines(&b"admin\nro\xffot\nguest\n"[..]).unwrap_err();
+        assert_eq!(err.to_string(), "line 2 is not valid utf-8");
+    }
+
+    #[test]
+    fn test_file() {
+        let path = env::temp_dir().join(format!("badtouch-wordlist-{}.txt", ::std::process::id()));
+        fs::write(&path, "admin\r\nroot\n\nguest").unwrap();
+        let words = Wordlist::open(&path).unwrap();
+        let loaded = words.load();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(words.len(), 4);
+        assert_eq!(loaded.unwrap().iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+            vec!["admin", "root", "", "guest"]);
+    }
+
+    #[test]
+    fn test_product() {
+        let pairs = Product::new(&list(&["admin", "root"]), &list(&["123456", "hunter2"])).unwrap()
+            .map(|x| x.map(|(user, password)| format!("{}:{}", user, password)))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(pairs, vec!["admin:123456", "admin:hunter2", "root:123456", "root:hunter2"]);
+
+        assert_eq!(Product::new(&list(&["admin"]), &list(&[])).unwrap().count(), 0);
+        assert_eq!(Product::new(&list(&[]), &list(&["123456"])).unwrap().count(), 0);
+    }
+}
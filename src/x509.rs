@@ -0,0 +1,163 @@
+use errors::*;
+
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::x509::{X509, X509NameRef};
+use hlua::AnyLuaValue;
+use structs::LuaMap;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    pub san: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+    pub fingerprint_sha1: String,
+    pub fingerprint_sha256: String,
+}
+
+impl Certificate {
+    pub fn from_x509(cert: &X509) -> Result<Certificate> {
+        let mut san = Vec::new();
+        if let Some(names) = cert.subject_alt_names() {
+            for name in &names {
+                if let Some(dns) = name.dnsname() {
+                    san.push(dns.to_string());
+                } else if let Some(ip) = name.ipaddress() {
+                    san.push(format_ip(ip));
+                }
+            }
+        }
+
+        Ok(Certificate {
+            subject: format_name(cert.subject_name())?,
+            issuer: format_name(cert.issuer_name())?,
+            san,
+            not_before: cert.not_before().to_string(),
+            not_after: cert.not_after().to_string(),
+            fingerprint_sha1: fingerprint(cert, MessageDigest::sha1())?,
+            fingerprint_sha256: fingerprint(cert, MessageDigest::sha256())?,
+        })
+    }
+
+    // pem is detected by its armor, everything else is parsed as der
+    pub fn parse(bytes: &[u8]) -> Result<Certificate> {
+        let cert = if bytes.starts_with(b"-----BEGIN") {
+            X509::from_pem(bytes)
+        } else {
+            X509::from_der(bytes)
+        }.context("Failed to parse certificate")?;
+        Certificate::from_x509(&cert)
+    }
+}
+
+impl Into<AnyLuaValue> for Certificate {
+    fn into(self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        map.insert_str("subject", self.subject);
+        map.insert_str("issuer", self.issuer);
+        map.insert("san", AnyLuaValue::LuaArray(self.san.into_iter()
+            .enumerate()
+            .map(|(i, x)| (AnyLuaValue::LuaNumber((i + 1) as f64), AnyLuaValue::LuaString(x)))
+            .collect()));
+        map.insert_str("not_before", self.not_before);
+        map.insert_str("not_after", self.not_after);
+        map.insert_str("fingerprint_sha1", self.fingerprint_sha1);
+        map.insert_str("fingerprint_sha256", self.fingerprint_sha256);
+
+        map.into()
+    }
+}
+
+fn format_name(name: &X509NameRef) -> Result<String> {
+    let mut parts = Vec::new();
+
+    for entry in name.entries() {
+        let key = match entry.object().nid() {
+            Nid::COMMONNAME => "CN",
+            Nid::COUNTRYNAME => "C",
+            Nid::LOCALITYNAME => "L",
+            Nid::STATEORPROVINCENAME => "ST",
+            Nid::ORGANIZATIONNAME => "O",
+            Nid::ORGANIZATIONALUNITNAME => "OU",
+            nid => nid.short_name()?,
+        };
+        let value = entry.data().as_utf8()?;
+        parts.push(format!("{}={}", key, value));
+    }
+
+    Ok(parts.join(", "))
+}
+
+fn format_ip(ip: &[u8]) -> String {
+    match ip.len() {
+        4 => ip.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+        _ => ip.chunks(2)
+                .map(|x| format!("{:x}", (u16::from(x[0]) << 8) | u16::from(x[1])))
+                .collect::<Vec<_>>()
+                .join(":"),
+    }
+}
+
+fn fingerprint(cert: &X509, digest: MessageDigest) -> Result<String> {
+    let digest = cert.digest(digest)?;
+    let hex = digest.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>();
+    Ok(hex.join(":"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIICZjCCAc+gAwIBAgIBATANBgkqhkiG9w0BAQsFADA2MQswCQYDVQQGEwJERTER
+MA8GA1UECgwIYmFkdG91Y2gxFDASBgNVBAMMC2V4YW1wbGUuY29tMB4XDTI2MTAx
+NjEyMzE0M1oXDTM2MTAxMzEyMzE0M1owNjELMAkGA1UEBhMCREUxETAPBgNVBAoM
+CGJhZHRvdWNoMRQwEgYDVQQDDAtleGFtcGxlLmNvbTCBnzANBgkqhkiG9w0BAQEF
+AAOBjQAwgYkCgYEA3yOwKm6+DdJzto9ZWWbIoK9T7ATmXpu88f2y/gOcPfB8//GY
+X2VXXljc28YXn1mcBR651UggdpjbArzsURsneeR1uuYp9kFDyzpl9weYIsvNroIv
+hqKwZdRH2oDD9pk6rXvwdj28mWzHC71ZR0zQiIh0bhY3qjpqYLEb/D6LrTECAwEA
+AaOBgzCBgDAdBgNVHQ4EFgQUsulIC8Q5nKMdMoPACflxh4aJaoEwHwYDVR0jBBgw
+FoAUsulIC8Q5nKMdMoPACflxh4aJaoEwDwYDVR0TAQH/BAUwAwEB/zAtBgNVHREE
+JjAkggtleGFtcGxlLmNvbYIPd3d3LmV4YW1wbGUuY29thwR/AAABMA0GCSqGSIb3
+DQEBCwUAA4GBAJcQST7+l/3jhJh/A82I6ajlLN6ciVcWXZ8ahOJ0xlD9AvZRTGFP
+qLZk3qUWiuxzhe6O0SGKVNrZ54ePMlZrVpEehbok6CJSx89IgnVEktQtU+cLrp5z
+Tgveks8Tu9A38P9A54byk1jJH2u3Slr0yRmPWaLTSalHgEEaSZKJ4MnN
+-----END CERTIFICATE-----";
+
+    #[test]
+    fn test_parse_pem() {
+        let cert = Certificate::parse(CERT.as_bytes()).unwrap();
+        assert_eq!(cert, Certificate {
+            subject: "C=DE, O=badtouch, CN=example.com".into(),
+            issuer: "C=DE, O=badtouch, CN=example.com".into(),
+            san: vec!["example.com".into(), "www.example.com".into(), "127.0.0.1".into()],
+            not_before: "Oct 16 12:31:43 2026 GMT".into(),
+            not_after: "Oct 13 12:31:43 2036 GMT".into(),
+            fingerprint_sha1: cert.fingerprint_sha1.clone(),
+            fingerprint_sha256: "a3:e5:66:6c:be:6e:61:e8:51:2a:f9:3d:f4:fb:ce:49:36:38:1e:0b:6e:01:3c:2d:fa:10:e9:57:1a:1c:ef:36".into(),
+        });
+    }
+
+    #[test]
+    fn test_parse_der() {
+        let der = X509::from_pem(CERT.as_bytes()).unwrap().to_der().unwrap();
+        let cert = Certificate::parse(&der).unwrap();
+        assert_eq!(cert, Certificate::parse(CERT.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Certificate::parse(b"-----BEGIN CERTIFICATE-----").is_err());
+        assert!(Certificate::parse(b"\x30\x00").is_err());
+    }
+}
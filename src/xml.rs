@@ -0,0 +1,175 @@
+use errors::*;
+
+use hlua::AnyLuaValue;
+use structs::LuaMap;
+use sxd_document::dom;
+use sxd_document::parser;
+use sxd_xpath::{Context, Factory, Value};
+use sxd_xpath::nodeset::Node;
+
+use std::collections::HashMap;
+
+
+#[derive(Debug, PartialEq)]
+pub struct XmlElement {
+    name: String,
+    attrs: HashMap<String, String>,
+    text: Option<String>,
+    children: Vec<XmlElement>,
+}
+
+impl Into<AnyLuaValue> for XmlElement {
+    fn into(self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        map.insert_str("name", self.name);
+        map.insert("attrs", LuaMap::from(self.attrs));
+        if let Some(text) = self.text {
+            map.insert_str("text", text);
+        }
+        map.insert("children", to_array(self.children.into_iter()
+            .map(|x| x.into())
+            .collect()));
+
+        map.into()
+    }
+}
+
+fn to_array(list: Vec<AnyLuaValue>) -> AnyLuaValue {
+    AnyLuaValue::LuaArray(list.into_iter()
+        .enumerate()
+        .map(|(i, x)| (AnyLuaValue::LuaNumber((i + 1) as f64), x))
+        .collect())
+}
+
+fn transform_element(element: dom::Element) -> XmlElement {
+    let attrs = element.attributes().into_iter()
+        .map(|x| (x.name().local_part().to_string(), x.value().to_string()))
+        .collect();
+
+    let mut text = String::new();
+    let mut children = Vec::new();
+    for child in element.children() {
+        match child {
+            dom::ChildOfElement::Element(x) => children.push(transform_element(x)),
+            dom::ChildOfElement::Text(x) => text.push_str(x.text()),
+            _ => (),
+        }
+    }
+
+    // whitespace between child elements is only formatting
+    let text = text.trim();
+    let text = if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    };
+
+    XmlElement {
+        name: element.name().local_part().to_string(),
+        attrs,
+        text,
+        children,
+    }
+}
+
+fn root_element<'d>(doc: &dom::Document<'d>) -> Result<dom::Element<'d>> {
+    doc.root().children().into_iter()
+        .filter_map(|x| x.element())
+        .next()
+        .ok_or_else(|| format_err!("xml document has no root element"))
+}
+
+pub fn xml_decode(xml: &str) -> Result<XmlElement> {
+    let package = parser::parse(xml)?;
+    let doc = package.as_document();
+    let root = root_element(&doc)?;
+    Ok(transform_element(root))
+}
+
+// elements are returned as tables, every other node as its string value
+pub fn xml_xpath(xml: &str, expr: &str) -> Result<AnyLuaValue> {
+    let package = parser::parse(xml)?;
+    let doc = package.as_document();
+    let root = root_element(&doc)?;
+
+    let xpath = match Factory::new().build(expr)? {
+        Some(xpath) => xpath,
+        None => bail!("xpath expression is empty"),
+    };
+
+    // make the prefixes of the document available to the expression
+    let mut context = Context::new();
+    for ns in root.namespaces_in_scope() {
+        context.set_namespace(ns.prefix(), ns.uri());
+    }
+
+    let value = xpath.evaluate(&context, doc.root())?;
+    Ok(match value {
+        Value::Boolean(x) => AnyLuaValue::LuaBoolean(x),
+        Value::Number(x) => AnyLuaValue::LuaNumber(x),
+        Value::String(x) => AnyLuaValue::LuaString(x),
+        Value::Nodeset(nodes) => to_array(nodes.document_order().into_iter()
+            .map(|node| match node {
+                Node::Element(x) => transform_element(x).into(),
+                node => AnyLuaValue::LuaString(node.string_value()),
+            })
+            .collect()),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOAP: &str = r#"<?xml version="1.0"?>
+<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope">
+    <soap:Body>
+        <LoginResponse result="ok">
+            <token>abc123</token>
+        </LoginResponse>
+    </soap:Body>
+</soap:Envelope>"#;
+
+    #[test]
+    fn test_xml_decode() {
+        let root = xml_decode(SOAP).unwrap();
+        assert_eq!(root.name, "Envelope");
+        assert_eq!(root.text, None);
+
+        let resp = &root.children[0].children[0];
+        assert_eq!(resp, &XmlElement {
+            name: "LoginResponse".into(),
+            attrs: vec![(String::from("result"), String::from("ok"))].into_iter().collect(),
+            text: None,
+            children: vec![
+                XmlElement {
+                    name: "token".into(),
+                    attrs: HashMap::new(),
+                    text: Some("abc123".into()),
+                    children: Vec::new(),
+                },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_xml_xpath() {
+        let x = xml_xpath(SOAP, "string(/soap:Envelope/soap:Body/LoginResponse/token)").unwrap();
+        assert_eq!(x, AnyLuaValue::LuaString("abc123".into()));
+
+        let x = xml_xpath(SOAP, "//LoginResponse/@result").unwrap();
+        assert_eq!(x, AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaString("ok".into())),
+        ]));
+
+        let x = xml_xpath(SOAP, "count(//token)").unwrap();
+        assert_eq!(x, AnyLuaValue::LuaNumber(1.0));
+    }
+
+    #[test]
+    fn test_xml_invalid() {
+        assert!(xml_decode("<a><b></a>").is_err());
+    }
+}